@@ -0,0 +1,35 @@
+use std::path::Path;
+use std::process::Command;
+
+/// First line of a command's stdout, if it ran successfully
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    stdout.lines().next().map(|line| line.trim().to_string()).filter(|line| !line.is_empty())
+}
+
+fn main() {
+    // Release pipelines building from a source tarball can pass the commit explicitly
+    let commit = std::env::var("FE_PHP_GIT_COMMIT")
+        .ok()
+        .or_else(|| command_output("git", &["rev-parse", "--short=12", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=FE_PHP_GIT_COMMIT={}", commit);
+
+    // "rustc 1.75.0 (82e1608df 2023-12-21)" -> "1.75.0"
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"])
+        .and_then(|v| v.split_whitespace().nth(1).map(String::from))
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=FE_PHP_RUSTC_VERSION={}", rustc_version);
+
+    println!("cargo:rerun-if-env-changed=FE_PHP_GIT_COMMIT");
+    for git_file in [".git/HEAD", ".git/index"] {
+        if Path::new(git_file).exists() {
+            println!("cargo:rerun-if-changed={}", git_file);
+        }
+    }
+}
@@ -2,10 +2,12 @@ use serde::{Deserialize, Serialize};
 use crate::metrics::MetricsCollector;
 use crate::metrics::collector::BackendStats;
 use crate::monitor::analyzer::{LogAnalyzer, LogAnalysisResult};
+use crate::load_balancing::LoadBalancingManager;
 use crate::server::ip_blocker::IpBlocker;
+use crate::waf::{WafEngine, WafSample};
 use std::sync::Arc;
 use std::collections::HashMap;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
 use parking_lot::RwLock;
 use thiserror::Error;
 
@@ -19,6 +21,26 @@ pub enum AdminError {
     /// Failed to send command through channel
     #[error("Failed to send command: {0}")]
     SendError(String),
+
+    /// WAF is disabled on this server
+    #[error("WAF is not enabled")]
+    WafNotEnabled,
+
+    /// New WAF rules could not be loaded; the previous rules remain active
+    #[error("{0}")]
+    WafReload(String),
+
+    /// Load balancing is disabled on this server
+    #[error("Load balancing is not enabled")]
+    LoadBalancingNotEnabled,
+
+    /// No upstream has the given name
+    #[error("Unknown upstream '{0}'")]
+    UnknownUpstream(String),
+
+    /// The server could not apply the new configuration; the previous one remains active
+    #[error("{0}")]
+    Reload(String),
 }
 
 impl From<mpsc::error::SendError<AdminCommand>> for AdminError {
@@ -54,10 +76,13 @@ pub struct HealthCheckResponse {
 }
 
 /// Admin command for server operations
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub enum AdminCommand {
-    ReloadConfig,
+    /// Reload the configuration; the handler replies with the reloaded file or the failure
+    ReloadConfig(oneshot::Sender<Result<String, String>>),
     RestartWorkers,
+    ResetOpcache,
+    SetMaintenance { enabled: bool, retry_after: Option<u64> },
     BlockIp(String),
     UnblockIp(String),
 }
@@ -72,6 +97,10 @@ pub struct AdminApi {
     ip_blocker: Option<Arc<IpBlocker>>,
     // Worker pool size (for worker status reporting)
     worker_pool_size: usize,
+    // WAF engine whose rules can be reloaded
+    waf_engine: Option<Arc<WafEngine>>,
+    // Load balancer whose upstreams can be drained
+    load_balancer: Option<Arc<LoadBalancingManager>>,
 }
 
 impl AdminApi {
@@ -82,6 +111,8 @@ impl AdminApi {
             log_analyzer: Arc::new(RwLock::new(LogAnalyzer::new())),
             ip_blocker: None,
             worker_pool_size: 0,
+            waf_engine: None,
+            load_balancer: None,
         }
     }
 
@@ -98,9 +129,23 @@ impl AdminApi {
             log_analyzer: Arc::new(RwLock::new(LogAnalyzer::new())),
             ip_blocker: Some(ip_blocker),
             worker_pool_size,
+            waf_engine: None,
+            load_balancer: None,
         }
     }
 
+    /// Enable the `reload_waf` command for this engine
+    pub fn with_waf_engine(mut self, waf_engine: Option<Arc<WafEngine>>) -> Self {
+        self.waf_engine = waf_engine;
+        self
+    }
+
+    /// Enable the `drain_upstream` and `enable_upstream` commands for this load balancer
+    pub fn with_load_balancer(mut self, load_balancer: Option<Arc<LoadBalancingManager>>) -> Self {
+        self.load_balancer = load_balancer;
+        self
+    }
+
     /// Get current server status
     pub fn get_status(&self) -> ServerStatus {
         let uptime = self.metrics.get_uptime_seconds();
@@ -141,18 +186,23 @@ impl AdminApi {
         }
     }
 
-    /// Reload configuration
+    /// Reload configuration and wait for the result; returns the reloaded file
     ///
     /// # Errors
     /// Returns `AdminError::NoCommandChannel` if the command channel is not available,
-    /// or `AdminError::SendError` if sending the command fails.
-    pub fn reload_config(&self) -> Result<(), AdminError> {
+    /// `AdminError::SendError` if sending the command fails, or `AdminError::Reload`
+    /// if the new configuration could not be loaded.
+    pub async fn reload_config(&self) -> Result<String, AdminError> {
         let tx = self.command_tx.as_ref().ok_or_else(|| {
             AdminError::NoCommandChannel("Configuration reload not supported".to_string())
         })?;
 
-        tx.send(AdminCommand::ReloadConfig)?;
-        Ok(())
+        let (reply_tx, reply_rx) = oneshot::channel();
+        tx.send(AdminCommand::ReloadConfig(reply_tx))?;
+        reply_rx
+            .await
+            .map_err(|_| AdminError::SendError("Command handler dropped the reload request".to_string()))?
+            .map_err(AdminError::Reload)
     }
 
     /// Restart workers
@@ -169,6 +219,34 @@ impl AdminApi {
         Ok(())
     }
 
+    /// Reset OPcache on all embedded workers
+    ///
+    /// # Errors
+    /// Returns `AdminError::NoCommandChannel` if the command channel is not available,
+    /// or `AdminError::SendError` if sending the command fails.
+    pub fn reset_opcache(&self) -> Result<(), AdminError> {
+        let tx = self.command_tx.as_ref().ok_or_else(|| {
+            AdminError::NoCommandChannel("OPcache reset not supported".to_string())
+        })?;
+
+        tx.send(AdminCommand::ResetOpcache)?;
+        Ok(())
+    }
+
+    /// Turn maintenance mode on or off
+    ///
+    /// # Errors
+    /// Returns `AdminError::NoCommandChannel` if the command channel is not available,
+    /// or `AdminError::SendError` if sending the command fails.
+    pub fn set_maintenance(&self, enabled: bool, retry_after: Option<u64>) -> Result<(), AdminError> {
+        let tx = self.command_tx.as_ref().ok_or_else(|| {
+            AdminError::NoCommandChannel("Maintenance mode not supported".to_string())
+        })?;
+
+        tx.send(AdminCommand::SetMaintenance { enabled, retry_after })?;
+        Ok(())
+    }
+
     /// Block IP address
     ///
     /// # Errors
@@ -197,6 +275,51 @@ impl AdminApi {
         Ok(())
     }
 
+    /// Take an upstream out of rotation; requests already sent to it finish
+    ///
+    /// # Errors
+    /// Returns `AdminError::LoadBalancingNotEnabled`, or `AdminError::UnknownUpstream`
+    /// if there is no such upstream.
+    pub async fn drain_upstream(&self, name: String) -> Result<(), AdminError> {
+        self.set_upstream_enabled(name, false).await
+    }
+
+    /// Put a drained upstream back into rotation
+    ///
+    /// # Errors
+    /// As for [`Self::drain_upstream`].
+    pub async fn enable_upstream(&self, name: String) -> Result<(), AdminError> {
+        self.set_upstream_enabled(name, true).await
+    }
+
+    async fn set_upstream_enabled(&self, name: String, enabled: bool) -> Result<(), AdminError> {
+        let load_balancer = self.load_balancer.as_ref().ok_or(AdminError::LoadBalancingNotEnabled)?;
+        load_balancer
+            .set_enabled(&name, enabled)
+            .await
+            .map_err(|_| AdminError::UnknownUpstream(name))
+    }
+
+    /// Re-read the WAF rules file and swap in the new rules, returning how many loaded
+    ///
+    /// # Errors
+    /// Returns `AdminError::WafNotEnabled` without a WAF engine, or `AdminError::WafReload`
+    /// if the rules file cannot be read or compiled (the current rules stay active).
+    pub fn reload_waf(&self) -> Result<usize, AdminError> {
+        let waf = self.waf_engine.as_ref().ok_or(AdminError::WafNotEnabled)?;
+        waf.reload_rules()
+            .map_err(|e| AdminError::WafReload(format!("{:#}", e)))
+    }
+
+    /// Requests the WAF would have blocked while in learn mode
+    ///
+    /// # Errors
+    /// Returns `AdminError::WafNotEnabled` without a WAF engine.
+    pub fn waf_samples(&self) -> Result<Vec<WafSample>, AdminError> {
+        let waf = self.waf_engine.as_ref().ok_or(AdminError::WafNotEnabled)?;
+        Ok(waf.learned_samples())
+    }
+
     /// Get metrics in Prometheus format
     pub fn get_metrics_text(&self) -> String {
         use prometheus::Encoder;
@@ -209,6 +332,18 @@ impl AdminApi {
         String::from_utf8(buffer).unwrap_or_default()
     }
 
+    /// Get cached metrics as structured JSON-friendly data
+    pub fn get_metrics_snapshot(&self) -> crate::metrics::MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Reset cached counters for a fresh measurement window
+    pub fn reset_metrics(&self) -> crate::metrics::MetricsSnapshot {
+        let previous = self.metrics.snapshot();
+        self.metrics.reset_cached();
+        previous
+    }
+
     /// Get log analysis result
     pub fn get_log_analysis(&self) -> LogAnalysisResult {
         let analyzer = self.log_analyzer.read();
@@ -1,10 +1,13 @@
 use anyhow::Result;
 use axum::{
-    extract::State,
-    response::{IntoResponse, Json},
+    body::Body,
+    extract::{Request, State},
+    middleware::{self, Next},
+    response::{IntoResponse, Json, Response},
     routing::get,
     Router,
 };
+use crate::server::compression::CompressionConfig;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::net::TcpListener;
@@ -147,30 +150,65 @@ async fn api_health() -> impl IntoResponse {
     }))
 }
 
-/// JSON API: Prometheus metrics
-async fn api_metrics(State(state): State<Arc<AdminState>>) -> impl IntoResponse {
-    use prometheus::Encoder;
-    let encoder = prometheus::TextEncoder::new();
-    let metric_families = state.metrics_collector.registry().gather();
-
-    let mut buffer = Vec::new();
-    encoder.encode(&metric_families, &mut buffer).unwrap_or_default();
+/// JSON API: Prometheus metrics (OpenMetrics when the scraper asks for it)
+async fn api_metrics(
+    State(state): State<Arc<AdminState>>,
+    headers: axum::http::HeaderMap,
+) -> impl IntoResponse {
+    let accept = headers.get(axum::http::header::ACCEPT).and_then(|v| v.to_str().ok());
+    match crate::metrics::export_registry(&state.metrics_collector.registry(), accept) {
+        Ok((body, content_type)) => ([(axum::http::header::CONTENT_TYPE, content_type)], body).into_response(),
+        Err(e) => (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
+    }
+}
 
-    String::from_utf8(buffer).unwrap_or_default()
+/// Middleware: gzip responses for clients sending `Accept-Encoding: gzip`
+async fn compress_response(
+    State(compression): State<Arc<CompressionConfig>>,
+    req: Request,
+    next: Next,
+) -> Response {
+    // Keep only the headers needed for content negotiation
+    let mut negotiation = axum::http::Request::new(());
+    *negotiation.headers_mut() = req.headers().clone();
+
+    let (parts, body) = next.run(req).await.into_parts();
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!("Failed to buffer admin API response: {}", e);
+            return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let response = Response::from_parts(parts, ());
+    match compression.compress_response(&negotiation, response, body.to_vec()) {
+        Ok(response) => response.map(Body::from),
+        Err(e) => {
+            tracing::warn!("Failed to compress admin API response: {}", e);
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
 }
 
 pub async fn serve(
     addr: &str,
     metrics_collector: Arc<crate::metrics::MetricsCollector>,
+    compression: bool,
 ) -> Result<()> {
     let state = Arc::new(AdminState::new(metrics_collector));
 
-    let app = Router::new()
+    let mut app = Router::new()
         .route("/api/status", get(api_status))
         .route("/api/health", get(api_health))
         .route("/metrics", get(api_metrics))
         .with_state(state);
 
+    if compression {
+        let compression = Arc::new(CompressionConfig::gzip_only());
+        app = app.layer(middleware::from_fn_with_state(compression, compress_response));
+    }
+
     let listener = TcpListener::bind(addr).await?;
     tracing::info!("Admin JSON API server listening on {}", addr);
 
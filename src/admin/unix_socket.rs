@@ -8,18 +8,57 @@ use tracing::{debug, error, info};
 
 use crate::admin::api::AdminApi;
 
+/// Admin socket protocol version, bumped whenever commands are added or changed
+pub const PROTOCOL_VERSION: u32 = 7;
+
+/// Commands understood by this server, reported back for unsupported ones
+pub const SUPPORTED_COMMANDS: &[&str] = &[
+    "status",
+    "health",
+    "metrics",
+    "metrics_snapshot",
+    "reset_metrics",
+    "analysis",
+    "blocked_ips",
+    "reload_config",
+    "reload_waf",
+    "waf_samples",
+    "restart_workers",
+    "reset_opcache",
+    "set_maintenance",
+    "block_ip",
+    "unblock_ip",
+    "drain_upstream",
+    "enable_upstream",
+];
+
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "command", rename_all = "snake_case")]
 pub enum Command {
     Status,
     Health,
     Metrics,
+    MetricsSnapshot,
+    ResetMetrics,
     Analysis,  // ログ解析結果を取得
     BlockedIps,  // ブロックされているIPリスト取得
     ReloadConfig { config_path: Option<String> },
+    ReloadWaf,
+    WafSamples,  // learnモードで記録されたリクエスト
     RestartWorkers,
+    ResetOpcache,
+    SetMaintenance {
+        enabled: bool,
+        #[serde(default)]
+        retry_after: Option<u64>,
+    },
     BlockIp { ip: String },
     UnblockIp { ip: String },
+    DrainUpstream { name: String },
+    EnableUpstream { name: String },
+    /// Any command this server does not know (e.g. sent by a newer client)
+    #[serde(other)]
+    Unknown,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -29,6 +68,9 @@ pub struct Response {
     pub data: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// Server protocol version (absent from servers predating versioning)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
 }
 
 impl Response {
@@ -37,6 +79,7 @@ impl Response {
             status: "ok".to_string(),
             data: Some(data),
             error: None,
+            version: Some(PROTOCOL_VERSION),
         }
     }
 
@@ -45,6 +88,24 @@ impl Response {
             status: "error".to_string(),
             data: None,
             error: Some(message),
+            version: Some(PROTOCOL_VERSION),
+        }
+    }
+
+    /// Structured reply for commands this server does not implement
+    pub fn unsupported(command: &str) -> Self {
+        Self {
+            status: "unsupported".to_string(),
+            data: Some(serde_json::json!({
+                "command": command,
+                "protocol_version": PROTOCOL_VERSION,
+                "supported_commands": SUPPORTED_COMMANDS,
+            })),
+            error: Some(format!(
+                "Unsupported command: '{}' (server protocol version {})",
+                command, PROTOCOL_VERSION
+            )),
+            version: Some(PROTOCOL_VERSION),
         }
     }
 }
@@ -133,19 +194,38 @@ async fn handle_connection(stream: UnixStream, admin_api: Arc<AdminApi>) -> Resu
 async fn process_command(line: &str, admin_api: &AdminApi) -> Result<Response> {
     // Try to parse as JSON command first
     let command: Command = if line.starts_with('{') {
-        serde_json::from_str(line)?
+        let value: serde_json::Value =
+            serde_json::from_str(line).context("Invalid JSON command")?;
+        let name = value
+            .get("command")
+            .and_then(|c| c.as_str())
+            .unwrap_or_default()
+            .to_string();
+
+        match serde_json::from_value(value) {
+            Ok(Command::Unknown) => return Ok(Response::unsupported(&name)),
+            Ok(command) => command,
+            Err(e) => return Ok(Response::error(format!("Invalid '{}' command: {}", name, e))),
+        }
     } else {
         // Simple text protocol fallback
         match line.to_lowercase().as_str() {
             "status" => Command::Status,
             "health" => Command::Health,
             "metrics" => Command::Metrics,
+            "metrics_snapshot" | "snapshot" => Command::MetricsSnapshot,
+            "reset_metrics" => Command::ResetMetrics,
             "analysis" => Command::Analysis,
             "blocked_ips" | "blocked" => Command::BlockedIps,
+            "reload_waf" | "waf_reload" => Command::ReloadWaf,
+            "waf_samples" => Command::WafSamples,
             cmd if cmd.starts_with("reload") => Command::ReloadConfig {
                 config_path: None,
             },
             cmd if cmd.starts_with("restart") => Command::RestartWorkers,
+            "reset_opcache" | "opcache_reset" => Command::ResetOpcache,
+            "maintenance on" => Command::SetMaintenance { enabled: true, retry_after: None },
+            "maintenance off" => Command::SetMaintenance { enabled: false, retry_after: None },
             cmd if cmd.starts_with("block ") => {
                 let ip = cmd.strip_prefix("block ").unwrap_or("").trim().to_string();
                 Command::BlockIp { ip }
@@ -154,8 +234,15 @@ async fn process_command(line: &str, admin_api: &AdminApi) -> Result<Response> {
                 let ip = cmd.strip_prefix("unblock ").unwrap_or("").trim().to_string();
                 Command::UnblockIp { ip }
             }
+            // Upstream names keep their case
+            cmd if cmd.starts_with("drain ") => Command::DrainUpstream {
+                name: line["drain ".len()..].trim().to_string(),
+            },
+            cmd if cmd.starts_with("enable ") => Command::EnableUpstream {
+                name: line["enable ".len()..].trim().to_string(),
+            },
             _ => {
-                return Ok(Response::error(format!("Unknown command: {}", line)));
+                return Ok(Response::unsupported(line));
             }
         }
     };
@@ -179,6 +266,17 @@ async fn execute_command(command: Command, admin_api: &AdminApi) -> Result<Respo
                 "prometheus": metrics
             })))
         }
+        Command::MetricsSnapshot => {
+            let snapshot = admin_api.get_metrics_snapshot();
+            Ok(Response::success(serde_json::to_value(snapshot)?))
+        }
+        Command::ResetMetrics => {
+            let previous = admin_api.reset_metrics();
+            Ok(Response::success(serde_json::json!({
+                "message": "Cached metrics reset",
+                "previous": previous,
+            })))
+        }
         Command::Analysis => {
             let analysis = admin_api.get_log_analysis();
             Ok(Response::success(serde_json::to_value(analysis)?))
@@ -190,19 +288,57 @@ async fn execute_command(command: Command, admin_api: &AdminApi) -> Result<Respo
                 "count": blocked_ips.len()
             })))
         }
-        Command::ReloadConfig { config_path } => {
-            match admin_api.reload_config() {
-                Ok(()) => Ok(Response::success(serde_json::json!({
-                    "message": "Configuration reload request sent",
+        // The server always reloads the files it was started with; a client-side
+        // config_path only selects the socket and what the client validated
+        Command::ReloadConfig { .. } => {
+            match admin_api.reload_config().await {
+                Ok(config_path) => Ok(Response::success(serde_json::json!({
+                    "message": format!("Configuration reloaded from {}", config_path),
                     "config_path": config_path,
                 }))),
                 Err(e) => Ok(Response::error(e.to_string())),
             }
         }
+        Command::ReloadWaf => {
+            match admin_api.reload_waf() {
+                Ok(rules) => Ok(Response::success(serde_json::json!({
+                    "message": format!("Loaded {} WAF rules", rules),
+                    "rules": rules,
+                }))),
+                Err(e) => Ok(Response::error(e.to_string())),
+            }
+        }
+        Command::WafSamples => {
+            match admin_api.waf_samples() {
+                Ok(samples) => Ok(Response::success(serde_json::json!({
+                    "count": samples.len(),
+                    "samples": samples,
+                }))),
+                Err(e) => Ok(Response::error(e.to_string())),
+            }
+        }
         Command::RestartWorkers => {
             match admin_api.restart_workers() {
                 Ok(()) => Ok(Response::success(serde_json::json!({
-                    "message": "Worker restart request sent"
+                    "message": "Workers will be replaced as they pick up their next request"
+                }))),
+                Err(e) => Ok(Response::error(e.to_string())),
+            }
+        }
+        Command::ResetOpcache => {
+            match admin_api.reset_opcache() {
+                Ok(()) => Ok(Response::success(serde_json::json!({
+                    "message": "OPcache reset request sent"
+                }))),
+                Err(e) => Ok(Response::error(e.to_string())),
+            }
+        }
+        Command::SetMaintenance { enabled, retry_after } => {
+            match admin_api.set_maintenance(enabled, retry_after) {
+                Ok(()) => Ok(Response::success(serde_json::json!({
+                    "message": format!("Maintenance mode {} request sent", if enabled { "on" } else { "off" }),
+                    "enabled": enabled,
+                    "retry_after": retry_after,
                 }))),
                 Err(e) => Ok(Response::error(e.to_string())),
             }
@@ -223,6 +359,29 @@ async fn execute_command(command: Command, admin_api: &AdminApi) -> Result<Respo
                 Err(e) => Ok(Response::error(e.to_string())),
             }
         }
+        Command::DrainUpstream { name } => {
+            match admin_api.drain_upstream(name.clone()).await {
+                Ok(()) => {
+                    info!("Upstream {} draining", name);
+                    Ok(Response::success(serde_json::json!({
+                        "message": format!("Upstream {} draining", name)
+                    })))
+                }
+                Err(e) => Ok(Response::error(e.to_string())),
+            }
+        }
+        Command::EnableUpstream { name } => {
+            match admin_api.enable_upstream(name.clone()).await {
+                Ok(()) => {
+                    info!("Upstream {} enabled", name);
+                    Ok(Response::success(serde_json::json!({
+                        "message": format!("Upstream {} enabled", name)
+                    })))
+                }
+                Err(e) => Ok(Response::error(e.to_string())),
+            }
+        }
+        Command::Unknown => Ok(Response::unsupported("unknown")),
     }
 }
 
@@ -234,3 +393,230 @@ impl Drop for UnixSocketServer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::MetricsCollector;
+
+    fn admin_api() -> AdminApi {
+        AdminApi::new(Arc::new(MetricsCollector::new()))
+    }
+
+    #[tokio::test]
+    async fn test_unknown_json_command_is_unsupported() {
+        let response = process_command(r#"{"command":"rotate_logs","keep":3}"#, &admin_api())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status, "unsupported");
+        assert_eq!(response.version, Some(PROTOCOL_VERSION));
+        let data = response.data.unwrap();
+        assert_eq!(data["command"], "rotate_logs");
+        assert!(data["supported_commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|c| c == "status"));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_text_command_is_unsupported() {
+        let response = process_command("frobnicate", &admin_api()).await.unwrap();
+        assert_eq!(response.status, "unsupported");
+        assert!(response.error.unwrap().contains("frobnicate"));
+    }
+
+    #[tokio::test]
+    async fn test_newer_client_fields_are_ignored() {
+        // A newer client may attach fields this server doesn't know about
+        let response = process_command(r#"{"command":"health","client_version":9}"#, &admin_api())
+            .await
+            .unwrap();
+        assert_eq!(response.status, "ok");
+        assert_eq!(response.version, Some(PROTOCOL_VERSION));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_command_returns_error_response() {
+        let response = process_command(r#"{"command":"block_ip"}"#, &admin_api())
+            .await
+            .unwrap();
+        assert_eq!(response.status, "error");
+        assert!(response.error.unwrap().contains("block_ip"));
+    }
+
+    #[tokio::test]
+    async fn test_reset_opcache_dispatches_admin_command() {
+        use crate::admin::api::AdminCommand;
+        use crate::server::ip_blocker::IpBlocker;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let api = AdminApi::with_command_channel(
+            Arc::new(MetricsCollector::new()),
+            tx,
+            Arc::new(IpBlocker::new()),
+            2,
+        );
+
+        for line in [r#"{"command":"reset_opcache"}"#, "reset_opcache"] {
+            let response = process_command(line, &api).await.unwrap();
+            assert_eq!(response.status, "ok");
+            assert!(matches!(rx.try_recv(), Ok(AdminCommand::ResetOpcache)));
+        }
+
+        // Without a command channel the request is rejected, not dropped
+        let response = process_command("reset_opcache", &admin_api()).await.unwrap();
+        assert_eq!(response.status, "error");
+    }
+
+    #[tokio::test]
+    async fn test_reload_config_waits_for_result() {
+        use crate::admin::api::AdminCommand;
+        use crate::server::ip_blocker::IpBlocker;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let api = AdminApi::with_command_channel(
+            Arc::new(MetricsCollector::new()),
+            tx,
+            Arc::new(IpBlocker::new()),
+            2,
+        );
+        tokio::spawn(async move {
+            let mut results = vec![
+                Ok("/etc/fe-php.toml".to_string()),
+                Err("Configuration validation failed: bad port".to_string()),
+            ]
+            .into_iter();
+            while let Some(AdminCommand::ReloadConfig(reply)) = rx.recv().await {
+                let _ = reply.send(results.next().unwrap());
+            }
+        });
+
+        let response = process_command(r#"{"command":"reload_config","config_path":"/tmp/other.toml"}"#, &api)
+            .await
+            .unwrap();
+        assert_eq!(response.status, "ok");
+        assert_eq!(response.data.unwrap()["config_path"], "/etc/fe-php.toml");
+
+        let response = process_command("reload", &api).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert!(response.error.unwrap().contains("bad port"));
+    }
+
+    #[tokio::test]
+    async fn test_set_maintenance_dispatches_admin_command() {
+        use crate::admin::api::AdminCommand;
+        use crate::server::ip_blocker::IpBlocker;
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let api = AdminApi::with_command_channel(
+            Arc::new(MetricsCollector::new()),
+            tx,
+            Arc::new(IpBlocker::new()),
+            2,
+        );
+
+        let line = r#"{"command":"set_maintenance","enabled":true,"retry_after":60}"#;
+        assert_eq!(process_command(line, &api).await.unwrap().status, "ok");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(AdminCommand::SetMaintenance { enabled: true, retry_after: Some(60) })
+        ));
+
+        assert_eq!(process_command("maintenance off", &api).await.unwrap().status, "ok");
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(AdminCommand::SetMaintenance { enabled: false, retry_after: None })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_drain_upstream_updates_load_balancer() {
+        use crate::server::ip_blocker::IpBlocker;
+
+        let upstreams = vec![crate::config::UpstreamConfig {
+            name: "App-1".to_string(),
+            url: "http://127.0.0.1:1".to_string(),
+            weight: 1,
+            enabled: true,
+        }];
+        let load_balancer = Arc::new(
+            crate::load_balancing::LoadBalancingManager::new(
+                upstreams,
+                crate::config::LoadBalancingAlgorithm::RoundRobin,
+                &crate::config::CircuitBreakerConfig::default(),
+            )
+            .unwrap(),
+        );
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let api = AdminApi::with_command_channel(
+            Arc::new(MetricsCollector::new()),
+            tx,
+            Arc::new(IpBlocker::new()),
+            2,
+        )
+        .with_load_balancer(Some(load_balancer.clone()));
+        let enabled = || async { load_balancer.get_upstreams_status().await[0].enabled };
+
+        let response = process_command(r#"{"command":"drain_upstream","name":"App-1"}"#, &api).await.unwrap();
+        assert_eq!(response.status, "ok");
+        assert!(!enabled().await);
+
+        assert_eq!(process_command("enable App-1", &api).await.unwrap().status, "ok");
+        assert!(enabled().await);
+
+        let response = process_command("drain app-2", &api).await.unwrap();
+        assert_eq!(response.error.as_deref(), Some("Unknown upstream 'app-2'"));
+        assert!(enabled().await);
+        assert!(rx.try_recv().is_err());
+
+        let response = process_command("drain App-1", &admin_api()).await.unwrap();
+        assert_eq!(response.error.as_deref(), Some("Load balancing is not enabled"));
+    }
+
+    #[tokio::test]
+    async fn test_reload_waf_reports_rule_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.toml");
+        std::fs::write(&path, "[[rules]]\nid = \"T-001\"\ndescription = \"test\"\npattern = \"^/x\"\naction = \"Block\"\nseverity = \"High\"\n").unwrap();
+
+        let metrics = Arc::new(MetricsCollector::new());
+        let waf = crate::waf::WafEngine::new(Vec::new(), "block".to_string(), Arc::clone(&metrics))
+            .with_rules_path(path.clone());
+        let api = AdminApi::new(metrics).with_waf_engine(Some(Arc::new(waf)));
+
+        let response = process_command(r#"{"command":"reload_waf"}"#, &api).await.unwrap();
+        assert_eq!(response.status, "ok");
+        assert_eq!(response.data.unwrap()["rules"], 1);
+
+        std::fs::write(&path, "not toml [").unwrap();
+        let response = process_command("reload_waf", &api).await.unwrap();
+        assert_eq!(response.status, "error");
+        assert!(response.error.unwrap().contains("keeping the current rules"));
+
+        let response = process_command("reload_waf", &admin_api()).await.unwrap();
+        assert_eq!(response.error.as_deref(), Some("WAF is not enabled"));
+    }
+
+    #[tokio::test]
+    async fn test_waf_samples_lists_learned_requests() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let waf = crate::waf::WafEngine::new(
+            crate::waf::rules::default_rules(),
+            "learn".to_string(),
+            Arc::clone(&metrics),
+        );
+        waf.check_request("GET", "/search", "q=1 UNION SELECT 1", &std::collections::HashMap::new(), b"");
+        let api = AdminApi::new(metrics).with_waf_engine(Some(Arc::new(waf)));
+
+        let response = process_command(r#"{"command":"waf_samples"}"#, &api).await.unwrap();
+        assert_eq!(response.status, "ok");
+        let data = response.data.unwrap();
+        assert_eq!(data["count"], 1);
+        assert_eq!(data["samples"][0]["rule_id"], "SQL-001");
+
+        let response = process_command("waf_samples", &admin_api()).await.unwrap();
+        assert_eq!(response.error.as_deref(), Some("WAF is not enabled"));
+    }
+}
@@ -1,4 +1,4 @@
-use super::{Backend, BackendError, BackendType, HealthStatus};
+use super::{Backend, BackendError, BackendType, Deadline, HealthStatus};
 use crate::php::{WorkerPool, PhpRequest, PhpResponse};
 use anyhow::Result;
 use std::sync::Arc;
@@ -16,12 +16,29 @@ impl EmbeddedBackend {
 
 impl Backend for EmbeddedBackend {
     fn execute(&self, request: PhpRequest) -> Result<PhpResponse, BackendError> {
+        self.execute_with_deadline(request, None)
+    }
+
+    /// Stops waiting for a worker once `deadline` passes
+    fn execute_with_deadline(
+        &self,
+        request: PhpRequest,
+        deadline: Option<Deadline>,
+    ) -> Result<PhpResponse, BackendError> {
         tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(
-                self.worker_pool.execute(request)
-            )
+            tokio::runtime::Handle::current().block_on(async {
+                let execution = async {
+                    self.worker_pool.execute(request).await
+                        .map_err(|e| BackendError::PhpError(e.to_string()))
+                };
+                match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline.instant().into(), execution)
+                        .await
+                        .unwrap_or(Err(BackendError::Timeout)),
+                    None => execution.await,
+                }
+            })
         })
-        .map_err(|e| BackendError::PhpError(e.to_string()))
     }
 
     fn health_check(&self) -> Result<HealthStatus> {
@@ -1,28 +1,92 @@
-use super::{Backend, BackendError, BackendType, HealthStatus};
-use crate::php::fastcgi::FastCgiClient;
-use crate::php::{PhpRequest, PhpResponse};
+use super::{Backend, BackendError, BackendType, Deadline, HealthStatus};
+use crate::config::ContentTypeConfig;
+use crate::metrics::MetricsCollector;
+use crate::php::connection_pool::{PoolConfig, PoolTimeout};
+use crate::php::fastcgi::{request_not_sent, stderr_response, BodyLimitExceeded, FastCgiClient, FastCgiError, FastCgiValues, RequestHead, SpooledBody};
+use crate::php::{content_type, PhpRequest, PhpResponse, ResponseHeaders};
 use anyhow::Result;
 use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Upper bound for the `FCGI_GET_VALUES` round trip
+const GET_VALUES_TIMEOUT: Duration = Duration::from_secs(5);
 
 pub struct FastCGIBackend {
-    client: Arc<FastCgiClient>,
+    client: FastCgiClient,
     document_root: PathBuf,
+    max_retries: u32,
+    retry_backoff: Duration,
+    retry_non_idempotent: bool,
+    expose_errors: bool,
+    content_type: ContentTypeConfig,
 }
 
 impl FastCGIBackend {
     pub fn new(fpm_socket: String, document_root: PathBuf) -> Self {
+        Self::with_pool_config(fpm_socket, document_root, PoolConfig::default())
+    }
+
+    /// Like `new`, with `pool_config` bounding the connections to PHP-FPM
+    pub fn with_pool_config(fpm_socket: String, document_root: PathBuf, pool_config: PoolConfig) -> Self {
+        // Resolved scripts are canonical, so the root must be too for the traversal check
+        let document_root = document_root.canonicalize().unwrap_or(document_root);
         Self {
-            client: Arc::new(FastCgiClient::new(fpm_socket)),
+            client: FastCgiClient::with_pool_config(fpm_socket, pool_config),
             document_root,
+            max_retries: 0,
+            retry_backoff: Duration::ZERO,
+            retry_non_idempotent: false,
+            expose_errors: false,
+            content_type: ContentTypeConfig::default(),
         }
     }
 
-    fn resolve_script_path(&self, uri: &str) -> Result<PathBuf, BackendError> {
-        let path = uri.split('?').next().unwrap_or(uri);
+    /// Retry requests PHP-FPM never received and `FCGI_OVERLOADED` up to `max_retries` times,
+    /// waiting `backoff` before the first retry and doubling it after each one.
+    /// Only idempotent methods are retried unless `non_idempotent` is set.
+    pub fn with_retries(mut self, max_retries: u32, backoff: Duration, non_idempotent: bool) -> Self {
+        self.max_retries = max_retries;
+        self.retry_backoff = backoff;
+        self.retry_non_idempotent = non_idempotent;
+        self
+    }
+
+    /// Give up on PHP-FPM when no response data arrives for `read_timeout`
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.client = self.client.with_read_timeout(read_timeout);
+        self
+    }
+
+    /// Record connection pool waits
+    pub fn with_metrics(mut self, metrics: Option<Arc<MetricsCollector>>) -> Self {
+        self.client = self.client.with_metrics(metrics);
+        self
+    }
+
+    /// Send PHP-FPM's STDERR as the body of the `502` returned when a script prints nothing
+    pub fn with_expose_errors(mut self, expose: bool) -> Self {
+        self.expose_errors = expose;
+        self
+    }
+
+    /// `Content-Type` for responses PHP sends without one
+    pub fn with_content_type(mut self, content_type: ContentTypeConfig) -> Self {
+        self.content_type = content_type;
+        self
+    }
 
+    /// Pass the request id as this FastCGI param as well as `HTTP_X_REQUEST_ID`
+    pub fn with_request_id_param(mut self, name: Option<String>) -> Self {
+        self.client = self.client.with_request_id_param(name);
+        self
+    }
+
+    fn resolve_script_path(&self, uri: &str) -> Result<PathBuf, BackendError> {
+        let path = crate::utils::decode_path(uri)
+            .map_err(|e| BackendError::Other(anyhow::anyhow!("{}: {}", e, uri)))?;
         let path = path.trim_start_matches('/');
 
         let path = if path.is_empty() || path.ends_with('/') {
@@ -53,19 +117,79 @@ impl FastCGIBackend {
         Ok(canonical)
     }
 
-    fn parse_fastcgi_response(&self, data: &[u8]) -> Result<(u16, HashMap<String, String>, Vec<u8>), BackendError> {
+    /// Attempts allowed for a request with this method
+    fn attempts_for(&self, method: &str) -> u32 {
+        if crate::php::fastcgi::is_idempotent(method) || self.retry_non_idempotent {
+            self.max_retries + 1
+        } else {
+            1
+        }
+    }
+
+    /// Connection and request limits reported by PHP-FPM via `FCGI_GET_VALUES`
+    pub async fn get_values(&self) -> Result<FastCgiValues> {
+        tokio::time::timeout(GET_VALUES_TIMEOUT, self.client.get_values())
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for FCGI_GET_VALUES_RESULT"))?
+    }
+
+    /// Read a chunked upload in full, failing with `BodyTooLarge` past `max_body`
+    pub async fn spool_body<B>(&self, mut body: B, max_body: usize) -> Result<SpooledBody, BackendError>
+    where
+        B: hyper::body::Body + Unpin,
+        B::Error: std::fmt::Display,
+    {
+        crate::php::fastcgi::spool_body(&mut body, max_body).await.map_err(client_error)
+    }
+
+    /// Execute with a body read by `spool_body`; `request.body` is ignored
+    pub async fn execute_spooled(&self, request: &PhpRequest, body: SpooledBody) -> Result<PhpResponse, BackendError> {
+        let start = Instant::now();
+
+        let script_path = self.resolve_script_path(&request.uri)?;
+        let head = RequestHead {
+            script_path: script_path.to_str()
+                .ok_or_else(|| BackendError::Other(anyhow::anyhow!("Script path contains invalid UTF-8")))?,
+            method: &request.method,
+            uri: &request.uri,
+            query_string: &request.query_string,
+            headers: &request.headers,
+            remote_addr: &request.remote_addr,
+        };
+
+        let (stdout, stderr) = self.client.execute_spooled(&head, body).await
+            .map_err(client_error)?;
+
+        let execution_time_ms = start.elapsed().as_millis() as u64;
+
+        if let Some(response) = stderr_response(head.script_path, &stdout, &stderr, self.expose_errors) {
+            return Ok(PhpResponse { execution_time_ms, ..response });
+        }
+
+        let (status_code, headers, body) = self.parse_fastcgi_response(&stdout)?;
+
+        Ok(PhpResponse {
+            status_code,
+            headers,
+            body,
+            execution_time_ms,
+            memory_peak_mb: 0.0,
+        })
+    }
+
+    fn parse_fastcgi_response(&self, data: &[u8]) -> Result<(u16, ResponseHeaders, Vec<u8>), BackendError> {
         use memchr::memmem;
 
         let mut status_code = 200u16;
-        let mut headers = HashMap::with_capacity(8);
+        let mut headers = ResponseHeaders::with_capacity(8);
 
         let (separator, body_start) = if let Some(pos) = memmem::find(data, b"\r\n\r\n") {
             (b"\r\n" as &[u8], pos + 4)
         } else if let Some(pos) = memmem::find(data, b"\n\n") {
             (b"\n" as &[u8], pos + 2)
         } else {
-            let mut headers = HashMap::with_capacity(1);
-            headers.insert("Content-Type".to_string(), "text/html; charset=UTF-8".to_string());
+            let mut headers = ResponseHeaders::new();
+            content_type::apply(&mut headers, data, &self.content_type);
             return Ok((200, headers, data.to_vec()));
         };
 
@@ -86,48 +210,114 @@ impl FastCGIBackend {
                         status_code = code_str.parse().unwrap_or(200);
                     }
                 } else if !name.is_empty() {
-                    headers.insert(name.to_string(), value.to_string());
+                    headers.append(name, value);
                 }
             }
         }
 
-        if !headers.contains_key("Content-Type") && !headers.contains_key("content-type") {
-            headers.insert("Content-Type".to_string(), "text/html; charset=UTF-8".to_string());
-        }
-
         let body = if body_start < data.len() {
             data[body_start..].to_vec()
         } else {
             Vec::new()
         };
+        content_type::apply(&mut headers, &body, &self.content_type);
 
         Ok((status_code, headers, body))
     }
 }
 
+/// Map a FastCGI client failure to the backend error callers act on
+fn client_error(e: anyhow::Error) -> BackendError {
+    if let Some(exceeded) = e.downcast_ref::<BodyLimitExceeded>() {
+        return BackendError::BodyTooLarge(exceeded.limit);
+    }
+    if let Some(timeout) = e.downcast_ref::<PoolTimeout>() {
+        warn!("{}", timeout);
+        return BackendError::Timeout;
+    }
+    match e.downcast_ref::<FastCgiError>() {
+        Some(FastCgiError::Overloaded) => BackendError::Overloaded(e.to_string()),
+        Some(FastCgiError::ReadTimeout(_)) => BackendError::Timeout,
+        Some(_) => BackendError::ProtocolError(e.to_string()),
+        None => BackendError::ConnectionFailed(e.to_string()),
+    }
+}
+
+/// Failures where PHP-FPM never ran the script, so trying again is safe: the
+/// request never reached it, or it answered `FCGI_OVERLOADED`. A connection
+/// lost after the request was sent may have run the script and is not retried.
+fn is_retryable(e: &anyhow::Error) -> bool {
+    request_not_sent(e) || matches!(e.downcast_ref::<FastCgiError>(), Some(FastCgiError::Overloaded))
+}
+
 impl Backend for FastCGIBackend {
     fn execute(&self, request: PhpRequest) -> Result<PhpResponse, BackendError> {
+        self.execute_with_deadline(request, None)
+    }
+
+    /// Gives up with `Timeout` once `deadline` passes, including between retries
+    fn execute_with_deadline(
+        &self,
+        request: PhpRequest,
+        deadline: Option<Deadline>,
+    ) -> Result<PhpResponse, BackendError> {
         let start = Instant::now();
 
         let script_path = self.resolve_script_path(&request.uri)?;
 
-        let (stdout, _stderr) = tokio::task::block_in_place(|| {
-            tokio::runtime::Handle::current().block_on(
-                self.client.execute(
-                    script_path.to_str()
-                        .ok_or_else(|| BackendError::Other(anyhow::anyhow!("Script path contains invalid UTF-8")))?,
-                    &request.method,
-                    &request.uri,
-                    &request.query_string,
-                    &request.headers,
-                    &request.body,
-                    &request.remote_addr,
-                )
-            )
-        }).map_err(|e| BackendError::ConnectionFailed(e.to_string()))?;
+        let script_path = script_path.to_str()
+            .ok_or_else(|| BackendError::Other(anyhow::anyhow!("Script path contains invalid UTF-8")))?;
+        let attempts = self.attempts_for(&request.method);
+
+        let (stdout, stderr) = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async {
+                let mut attempt = 1;
+                loop {
+                    // Each failure carries whether it is safe to retry
+                    let call = async {
+                        self.client.execute(
+                            script_path,
+                            &request.method,
+                            &request.uri,
+                            &request.query_string,
+                            &request.headers,
+                            &request.body,
+                            &request.remote_addr,
+                        ).await.map_err(|e| (is_retryable(&e), client_error(e)))
+                    };
+                    let result = match deadline {
+                        Some(deadline) => tokio::time::timeout_at(deadline.instant().into(), call)
+                            .await
+                            .unwrap_or(Err((false, BackendError::Timeout))),
+                        None => call.await,
+                    };
+
+                    match result {
+                        Err((true, e)) if attempt < attempts => {
+                            let delay = self.retry_backoff.saturating_mul(1 << (attempt - 1).min(16));
+                            // A retry that cannot finish in time is wasted work
+                            if deadline.is_some_and(|d| d.remaining() <= delay) {
+                                return Err(e);
+                            }
+                            warn!(
+                                "FastCGI attempt {}/{} for {} {} failed, retrying in {:?}: {}",
+                                attempt, attempts, request.method, request.uri, delay, e
+                            );
+                            tokio::time::sleep(delay).await;
+                            attempt += 1;
+                        }
+                        result => return result.map_err(|(_, e)| e),
+                    }
+                }
+            })
+        })?;
 
         let execution_time_ms = start.elapsed().as_millis() as u64;
 
+        if let Some(response) = stderr_response(script_path, &stdout, &stderr, self.expose_errors) {
+            return Ok(PhpResponse { execution_time_ms, ..response });
+        }
+
         let (status_code, headers, body) = self.parse_fastcgi_response(&stdout)?;
 
         Ok(PhpResponse {
@@ -142,6 +332,15 @@ impl Backend for FastCGIBackend {
     fn health_check(&self) -> Result<HealthStatus> {
         let start = Instant::now();
 
+        // Also proves FPM is reachable when there is no health check script
+        let values = tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(self.get_values())
+        });
+        let values = match values {
+            Ok(values) => values,
+            Err(e) => return Ok(HealthStatus::unhealthy(format!("FastCGI backend unreachable: {}", e))),
+        };
+
         let check_request = PhpRequest {
             method: "GET".to_string(),
             uri: "/_health.php".to_string(),
@@ -154,13 +353,16 @@ impl Backend for FastCGIBackend {
         match self.execute(check_request) {
             Ok(_) => {
                 let latency = start.elapsed();
-                Ok(HealthStatus::healthy("FastCGI backend is healthy")
+                Ok(HealthStatus::healthy(format!("FastCGI backend is healthy ({})", values))
                     .with_latency(latency))
             }
             Err(BackendError::NotFound(_)) => {
                 let latency = start.elapsed();
-                Ok(HealthStatus::healthy("FastCGI backend is reachable (no health check script)")
-                    .with_latency(latency))
+                Ok(HealthStatus::healthy(format!(
+                    "FastCGI backend is reachable (no health check script; {})",
+                    values
+                ))
+                .with_latency(latency))
             }
             Err(e) => {
                 Ok(HealthStatus::unhealthy(format!("FastCGI backend error: {}", e)))
@@ -171,4 +373,144 @@ impl Backend for FastCGIBackend {
     fn backend_type(&self) -> BackendType {
         BackendType::FastCGI
     }
+
+    fn as_fastcgi(&self) -> Option<&FastCGIBackend> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::php::fastcgi::mock::{self, record, MockFpm, MockFpmServer, Reply};
+    use tokio::net::TcpListener;
+
+    /// PHP-FPM stand-in that resets the connection of the first request it
+    /// receives and answers every later one. It starts listening after `delay`,
+    /// so connections attempted before then are refused.
+    async fn flaky_fpm(delay: Duration) -> MockFpmServer {
+        MockFpm::new(|index, _| match index {
+            0 => Reply::Reset,
+            _ => Reply::Send(mock::ok()),
+        })
+        .listen_after(delay)
+        .start()
+        .await
+    }
+
+    fn request(method: &str) -> PhpRequest {
+        PhpRequest {
+            method: method.to_string(),
+            uri: "/index.php".to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            query_string: String::new(),
+            remote_addr: "127.0.0.1".to_string(),
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_connection_error_is_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php echo 'ok';").unwrap();
+        // POST is retried only when opted in, even though it never reached PHP-FPM
+        for (method, non_idempotent) in [("GET", false), ("POST", true)] {
+            let fpm = MockFpm::new(|_, _| Reply::Send(mock::ok()))
+                .listen_after(Duration::from_millis(50))
+                .start()
+                .await;
+
+            // Refused until the server listens; the retry after 300ms gets through
+            let backend = FastCGIBackend::new(fpm.addr.clone(), dir.path().to_path_buf())
+                .with_retries(2, Duration::from_millis(300), non_idempotent);
+            let response = backend.execute(request(method)).unwrap();
+
+            assert_eq!(response.status_code, 200);
+            assert_eq!(response.body, b"ok");
+            assert_eq!(fpm.requests().len(), 1);
+        }
+
+        let fpm = flaky_fpm(Duration::from_millis(50)).await;
+        let backend = FastCGIBackend::new(fpm.addr.clone(), dir.path().to_path_buf())
+            .with_retries(2, Duration::from_millis(300), false);
+        let err = backend.execute(request("POST")).unwrap_err();
+        assert!(matches!(err, BackendError::ConnectionFailed(_)), "{}", err);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_request_lost_after_sending_is_not_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php echo 'ok';").unwrap();
+
+        // PHP-FPM may have run the script before the connection was reset
+        for (method, non_idempotent) in [("GET", false), ("POST", true)] {
+            let fpm = flaky_fpm(Duration::ZERO).await;
+            let backend = FastCGIBackend::new(fpm.addr.clone(), dir.path().to_path_buf())
+                .with_retries(2, Duration::from_millis(10), non_idempotent);
+            let err = backend.execute(request(method)).unwrap_err();
+            assert!(matches!(err, BackendError::ConnectionFailed(_)), "{}", err);
+            assert_eq!(fpm.requests().len(), 1);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_fpm_errors_without_output_are_a_bad_gateway() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php undefined();").unwrap();
+        let fatal = b"PHP Fatal error:  Call to undefined function undefined()";
+        let fpm = MockFpm::new(move |_, _| Reply::SendAndClose([record(7, fatal), mock::end_request(0)].concat()))
+            .start()
+            .await;
+
+        let backend = FastCGIBackend::new(fpm.addr.clone(), dir.path().to_path_buf())
+            .with_expose_errors(true);
+        let response = backend.execute(request("GET")).unwrap();
+        assert_eq!(response.status_code, 502);
+        assert!(response.body.starts_with(fatal));
+
+        let backend = FastCGIBackend::new(fpm.addr.clone(), dir.path().to_path_buf());
+        let response = backend.execute(request("GET")).unwrap();
+        assert_eq!(response.status_code, 502);
+        assert!(!response.body.starts_with(fatal));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_parser_adds_default_content_type() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php echo '[1]';").unwrap();
+        let fpm = MockFpm::replying(b"Status: 200\r\n\r\n[1]").start().await;
+
+        let backend = FastCGIBackend::new(fpm.addr.clone(), dir.path().to_path_buf());
+        let response = backend.execute(request("GET")).unwrap();
+        assert_eq!(response.headers.get("Content-Type"), Some("text/html; charset=UTF-8"));
+
+        let sniff = ContentTypeConfig { sniff: true, ..ContentTypeConfig::default() };
+        let backend = FastCGIBackend::new(fpm.addr.clone(), dir.path().to_path_buf()).with_content_type(sniff);
+        let response = backend.execute(request("GET")).unwrap();
+        assert_eq!(response.headers.get("Content-Type"), Some("application/json"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_deadline_bounds_fastcgi_request() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php sleep(10);").unwrap();
+        // Accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+
+        let backend = FastCGIBackend::new(addr, dir.path().to_path_buf());
+        let start = Instant::now();
+        let err = backend
+            .execute_with_deadline(request("GET"), Some(Deadline::after(Duration::from_millis(200))))
+            .unwrap_err();
+
+        assert!(matches!(err, BackendError::Timeout), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+    }
 }
@@ -0,0 +1,150 @@
+//! Small LRU cache of resolved static file paths and contents
+//!
+//! Entries are re-validated with a single `stat` on every hit: a changed
+//! modification time or size drops the entry so the file is read again.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// A cached lookup: where a request path resolved to and, for small files, its bytes
+#[derive(Clone)]
+pub struct CachedFile {
+    pub path: PathBuf,
+    pub metadata: Metadata,
+    pub content: Option<Arc<Vec<u8>>>,
+}
+
+struct Entry {
+    file: CachedFile,
+    modified: Option<SystemTime>,
+    last_used: u64,
+}
+
+struct State {
+    entries: HashMap<String, Entry>,
+    clock: u64,
+}
+
+pub struct FileCache {
+    capacity: usize,
+    max_file_size: u64,
+    state: Mutex<State>,
+}
+
+impl FileCache {
+    /// `capacity` entries at most; only files up to `max_file_size` bytes keep their contents
+    pub fn new(capacity: usize, max_file_size: u64) -> Self {
+        Self {
+            capacity,
+            max_file_size,
+            state: Mutex::new(State { entries: HashMap::new(), clock: 0 }),
+        }
+    }
+
+    /// Cached file for `key` if it has not changed on disk since it was stored
+    pub fn get(&self, key: &str) -> Option<CachedFile> {
+        let path = {
+            let state = self.state.lock();
+            state.entries.get(key)?.file.path.clone()
+        };
+
+        // Stat outside the lock so a slow filesystem does not serialize requests
+        let current = std::fs::metadata(&path).ok();
+
+        let mut state = self.state.lock();
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.entries.get_mut(key)?;
+        match current {
+            Some(metadata)
+                if metadata.is_file()
+                    && metadata.len() == entry.file.metadata.len()
+                    && metadata.modified().ok() == entry.modified =>
+            {
+                entry.last_used = clock;
+                Some(entry.file.clone())
+            }
+            _ => {
+                state.entries.remove(key);
+                None
+            }
+        }
+    }
+
+    /// Remember that `key` resolved to `path`; `content` is kept only for small files
+    pub fn insert(&self, key: &str, path: &Path, metadata: &Metadata, content: Option<&[u8]>) -> CachedFile {
+        let content = content
+            .filter(|c| self.caches_content(c.len() as u64))
+            .map(|c| Arc::new(c.to_vec()));
+        let file = CachedFile {
+            path: path.to_path_buf(),
+            metadata: metadata.clone(),
+            content,
+        };
+
+        let mut state = self.state.lock();
+        if !state.entries.contains_key(key) && state.entries.len() >= self.capacity {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                state.entries.remove(&oldest);
+            }
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.entries.insert(
+            key.to_string(),
+            Entry { file: file.clone(), modified: metadata.modified().ok(), last_used },
+        );
+        file
+    }
+
+    /// Whether a file of `len` bytes is small enough to keep in memory
+    pub fn caches_content(&self, len: u64) -> bool {
+        len <= self.max_file_size
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = FileCache::new(2, 1024);
+        for name in ["a", "b", "c"] {
+            std::fs::write(dir.path().join(name), name).unwrap();
+        }
+        let insert = |name: &str| {
+            let path = dir.path().join(name);
+            let metadata = std::fs::metadata(&path).unwrap();
+            cache.insert(name, &path, &metadata, Some(name.as_bytes()));
+        };
+
+        insert("a");
+        insert("b");
+        assert!(cache.get("a").is_some());
+        insert("c");
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+    }
+}
@@ -1,19 +1,63 @@
 pub mod embedded;
+pub mod file_cache;
+pub mod page_cache;
 pub mod fastcgi;
 pub mod static_files;
 pub mod router;
 
 use crate::php::{PhpRequest, PhpResponse};
-use anyhow::Result;
+use anyhow::{Context, Result};
 use std::fmt;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 pub trait Backend: Send + Sync {
     fn execute(&self, request: PhpRequest) -> Result<PhpResponse, BackendError>;
 
+    /// Execute within the request's remaining time budget. Backends that can bound
+    /// their own work override this; the default only refuses already-expired requests.
+    fn execute_with_deadline(
+        &self,
+        request: PhpRequest,
+        deadline: Option<Deadline>,
+    ) -> Result<PhpResponse, BackendError> {
+        if deadline.is_some_and(|d| d.is_expired()) {
+            return Err(BackendError::Timeout);
+        }
+        self.execute(request)
+    }
+
     fn health_check(&self) -> Result<HealthStatus>;
 
     fn backend_type(&self) -> BackendType;
+
+    /// FastCGI backends can stream request bodies instead of buffering them
+    fn as_fastcgi(&self) -> Option<&fastcgi::FastCGIBackend> {
+        None
+    }
+}
+
+/// Point by which a request must be answered, derived from `server.request_timeout_ms`.
+/// Travels with the request as an extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self(Instant::now() + timeout)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Time left, zero once the deadline has passed
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -21,6 +65,7 @@ pub enum BackendType {
     Embedded,
     FastCGI,
     Static,
+    Cache,
 }
 
 impl fmt::Display for BackendType {
@@ -29,6 +74,7 @@ impl fmt::Display for BackendType {
             Self::Embedded => write!(f, "embedded"),
             Self::FastCGI => write!(f, "fastcgi"),
             Self::Static => write!(f, "static"),
+            Self::Cache => write!(f, "cache"),
         }
     }
 }
@@ -41,6 +87,7 @@ impl std::str::FromStr for BackendType {
             "embedded" => Ok(Self::Embedded),
             "fastcgi" => Ok(Self::FastCGI),
             "static" => Ok(Self::Static),
+            "cache" => Ok(Self::Cache),
             _ => Err(anyhow::anyhow!("Invalid backend type: '{}'", s)),
         }
     }
@@ -54,6 +101,13 @@ pub enum BackendError {
     PhpError(String),
     IoError(std::io::Error),
     NotFound(String),
+    BodyTooLarge(usize),
+    /// The backend refused the request because it is at capacity
+    Overloaded(String),
+    /// The backend panicked while executing the request
+    Panic(String),
+    /// The routed backend failed its last health check and no fallback was available
+    Unavailable(BackendType),
     Other(anyhow::Error),
 }
 
@@ -66,6 +120,10 @@ impl fmt::Display for BackendError {
             Self::PhpError(msg) => write!(f, "PHP error: {}", msg),
             Self::IoError(e) => write!(f, "IO error: {}", e),
             Self::NotFound(path) => write!(f, "Not found: {}", path),
+            Self::BodyTooLarge(limit) => write!(f, "Request body exceeds limit of {} bytes", limit),
+            Self::Overloaded(msg) => write!(f, "Backend overloaded: {}", msg),
+            Self::Panic(msg) => write!(f, "Backend panicked: {}", msg),
+            Self::Unavailable(backend) => write!(f, "Backend {} is unhealthy", backend),
             Self::Other(e) => write!(f, "{}", e),
         }
     }
@@ -125,6 +183,26 @@ pub enum PathPattern {
 }
 
 impl PathPattern {
+    /// Compile a configured pattern, optionally matching case-insensitively.
+    /// Case-insensitive patterns expect callers to lowercase the path first.
+    pub fn from_config(config: &crate::config::PathPatternConfig, case_insensitive: bool) -> Result<Self> {
+        use crate::config::PathPatternConfig;
+
+        let fold = |s: &String| if case_insensitive { s.to_lowercase() } else { s.clone() };
+        match config {
+            PathPatternConfig::Exact(s) => Ok(Self::Exact(fold(s))),
+            PathPatternConfig::Prefix(s) => Ok(Self::Prefix(fold(s))),
+            PathPatternConfig::Suffix(s) => Ok(Self::Suffix(fold(s))),
+            PathPatternConfig::Regex(s) => {
+                let regex = regex::RegexBuilder::new(s)
+                    .case_insensitive(case_insensitive)
+                    .build()
+                    .with_context(|| format!("Invalid regex pattern: {}", s))?;
+                Ok(Self::Regex(regex))
+            }
+        }
+    }
+
     pub fn matches(&self, path: &str) -> bool {
         match self {
             Self::Exact(pattern) => path == pattern,
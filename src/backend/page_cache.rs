@@ -0,0 +1,647 @@
+//! Reverse cache in front of a PHP backend
+//!
+//! Successful `GET` responses from the origin are stored on disk or in Redis. Within
+//! `ttl` they are served as is; for another `stale` period they are still served
+//! while a single background request refreshes them from the origin.
+//!
+//! Pages are shared by every client, so requests with credentials (`Authorization`
+//! or a session cookie) bypass the cache, and a stored page is only served to
+//! requests that agree on the headers its `Vary` names.
+
+use super::{Backend, BackendError, BackendType, Deadline, HealthStatus};
+use crate::php::{PhpRequest, PhpResponse, ResponseHeaders};
+use anyhow::{Context, Result};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Page as kept in storage
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedPage {
+    pub key: String,
+    pub stored_at_ms: u64,
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    /// Headers named by the response's `Vary` and the values the page was rendered for
+    #[serde(default)]
+    pub vary: Vec<(String, String)>,
+    #[serde(skip)]
+    pub body: Vec<u8>,
+}
+
+impl CachedPage {
+    /// JSON metadata line followed by the raw body
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut bytes = serde_json::to_vec(self)?;
+        bytes.push(b'\n');
+        bytes.extend_from_slice(&self.body);
+        Ok(bytes)
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let split = bytes.iter().position(|&b| b == b'\n').context("Cache entry without metadata")?;
+        let mut page: CachedPage = serde_json::from_slice(&bytes[..split])?;
+        page.body = bytes[split + 1..].to_vec();
+        Ok(page)
+    }
+
+    /// Whether this page was rendered for the same `Vary` header values as `request`
+    fn varies_with(&self, request: &PhpRequest) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request_header(request, name).unwrap_or("") == value)
+    }
+
+    fn age(&self) -> Duration {
+        Duration::from_millis(now_ms().saturating_sub(self.stored_at_ms))
+    }
+
+    fn to_response(&self, cache_status: &str) -> PhpResponse {
+        let mut headers: ResponseHeaders = self.headers.iter().cloned().collect();
+        headers.insert("X-Cache", cache_status);
+        headers.insert("Age", self.age().as_secs().to_string());
+        PhpResponse {
+            status_code: self.status,
+            headers,
+            body: self.body.clone(),
+            execution_time_ms: 0,
+            memory_peak_mb: 0.0,
+        }
+    }
+}
+
+/// Where cached pages live
+pub trait PageStore: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<CachedPage>>;
+
+    /// Keep `page` for at least `lifetime`
+    fn put(&self, page: &CachedPage, lifetime: Duration) -> Result<()>;
+}
+
+/// Shortest time between two sweeps of the disk store
+const DISK_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// One file per page under a directory. Writes sweep out pages (and abandoned
+/// temporary files) older than the lifetime, at most once a minute.
+pub struct DiskStore {
+    dir: PathBuf,
+    /// Numbers temporary files so concurrent writes of one page never share one
+    writes: AtomicU64,
+    last_sweep: Mutex<Instant>,
+}
+
+impl DiskStore {
+    pub fn new(dir: PathBuf) -> Result<Self> {
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create cache directory: {}", dir.display()))?;
+        Ok(Self { dir, writes: AtomicU64::new(0), last_sweep: Mutex::new(Instant::now()) })
+    }
+
+    /// Delete files not written within `lifetime`; returns how many were removed
+    fn sweep(&self, lifetime: Duration) -> Result<usize> {
+        let mut removed = 0;
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let expired = entry
+                .metadata()
+                .and_then(|meta| meta.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > lifetime);
+            // A concurrent write may have replaced the file meanwhile
+            if expired && std::fs::remove_file(entry.path()).is_ok() {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn maybe_sweep(&self, lifetime: Duration) {
+        {
+            let mut last_sweep = self.last_sweep.lock();
+            if last_sweep.elapsed() < DISK_SWEEP_INTERVAL {
+                return;
+            }
+            *last_sweep = Instant::now();
+        }
+        match self.sweep(lifetime) {
+            Ok(0) => {}
+            Ok(removed) => debug!("Removed {} expired pages from {}", removed, self.dir.display()),
+            Err(e) => warn!("Sweeping page cache directory {} failed: {}", self.dir.display(), e),
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.dir.join(format!("{:016x}.page", hasher.finish()))
+    }
+}
+
+impl PageStore for DiskStore {
+    fn get(&self, key: &str) -> Result<Option<CachedPage>> {
+        let bytes = match std::fs::read(self.path(key)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        // A hash collision leaves another key's page in the file
+        Ok(Some(CachedPage::decode(&bytes)?).filter(|page| page.key == key))
+    }
+
+    fn put(&self, page: &CachedPage, lifetime: Duration) -> Result<()> {
+        // Write then rename so readers never see a partial page
+        let path = self.path(&page.key);
+        let write = self.writes.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("tmp.{}.{}", std::process::id(), write));
+        let written = std::fs::write(&tmp, page.encode()?).and_then(|()| std::fs::rename(&tmp, &path));
+        if let Err(e) = written {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        self.maybe_sweep(lifetime);
+        Ok(())
+    }
+}
+
+/// Pages stored as Redis strings that expire once they are too stale to serve.
+/// One multiplexed connection is opened on first use and shared; every
+/// command, connecting included, gives up after `timeout`.
+pub struct RedisStore {
+    client: redis::Client,
+    connection: tokio::sync::OnceCell<redis::aio::ConnectionManager>,
+    key_prefix: String,
+    timeout: Duration,
+}
+
+impl RedisStore {
+    pub fn new(url: &str, key_prefix: String, timeout: Duration) -> Result<Self> {
+        let client = redis::Client::open(url).context("Failed to create Redis client")?;
+        Ok(Self { client, connection: tokio::sync::OnceCell::new(), key_prefix, timeout })
+    }
+
+    /// Run `cmd` on the shared connection, from the blocking thread a backend runs on
+    fn query<T: redis::FromRedisValue>(&self, cmd: &redis::Cmd) -> Result<T> {
+        let query = async {
+            let connection = self
+                .connection
+                .get_or_try_init(|| redis::aio::ConnectionManager::new(self.client.clone()))
+                .await?;
+            cmd.query_async(&mut connection.clone()).await
+        };
+        tokio::task::block_in_place(|| {
+            tokio::runtime::Handle::current().block_on(async {
+                tokio::time::timeout(self.timeout, query)
+                    .await
+                    .map_err(|_| anyhow::anyhow!("Redis page cache timed out after {:?}", self.timeout))?
+                    .map_err(Into::into)
+            })
+        })
+    }
+}
+
+impl PageStore for RedisStore {
+    fn get(&self, key: &str) -> Result<Option<CachedPage>> {
+        let bytes: Option<Vec<u8>> = self.query(redis::cmd("GET").arg(format!("{}{}", self.key_prefix, key)))?;
+        bytes.map(|b| CachedPage::decode(&b)).transpose()
+    }
+
+    fn put(&self, page: &CachedPage, lifetime: Duration) -> Result<()> {
+        self.query::<()>(
+            redis::cmd("SET")
+                .arg(format!("{}{}", self.key_prefix, page.key))
+                .arg(page.encode()?)
+                .arg("PX")
+                .arg(lifetime.as_millis().max(1) as u64),
+        )
+    }
+}
+
+pub struct CacheBackend {
+    origin: Arc<dyn Backend>,
+    store: Arc<dyn PageStore>,
+    ttl: Duration,
+    stale: Duration,
+    /// Keys with a background refresh in flight
+    refreshing: Arc<Mutex<HashSet<String>>>,
+    /// Cookies that make a request bypass the cache; empty means any cookie does
+    bypass_cookies: Vec<String>,
+}
+
+impl CacheBackend {
+    pub fn new(origin: Arc<dyn Backend>, store: Arc<dyn PageStore>, ttl: Duration, stale: Duration) -> Self {
+        Self {
+            origin,
+            store,
+            ttl,
+            stale,
+            refreshing: Arc::new(Mutex::new(HashSet::new())),
+            bypass_cookies: Vec::new(),
+        }
+    }
+
+    /// Only these cookies (e.g. the session cookie) make a request bypass the cache
+    pub fn with_bypass_cookies(mut self, cookies: Vec<String>) -> Self {
+        self.bypass_cookies = cookies;
+        self
+    }
+
+    /// Requests with credentials get per-user pages, which must neither be
+    /// served from nor stored in the shared cache
+    fn bypasses(&self, request: &PhpRequest) -> bool {
+        if request_header(request, "authorization").is_some() {
+            return true;
+        }
+        let Some(cookies) = request_header(request, "cookie") else {
+            return false;
+        };
+        if self.bypass_cookies.is_empty() {
+            return true;
+        }
+        cookies
+            .split(';')
+            .filter_map(|cookie| cookie.split_once('=').map(|(name, _)| name.trim()))
+            .any(|name| self.bypass_cookies.iter().any(|bypass| bypass == name))
+    }
+
+    fn lookup(&self, key: &str, request: &PhpRequest) -> Option<CachedPage> {
+        let page = self.store.get(key).unwrap_or_else(|e| {
+            warn!("Page cache read for {} failed: {}", key, e);
+            None
+        })?;
+        page.varies_with(request).then_some(page)
+    }
+
+    /// Re-run `request` on the origin in the background unless a refresh is already running
+    fn spawn_refresh(&self, key: String, request: PhpRequest) -> bool {
+        let Ok(handle) = tokio::runtime::Handle::try_current() else {
+            return false;
+        };
+        if !self.refreshing.lock().insert(key.clone()) {
+            return true;
+        }
+
+        let origin = Arc::clone(&self.origin);
+        let store = Arc::clone(&self.store);
+        let refreshing = Arc::clone(&self.refreshing);
+        let lifetime = self.ttl + self.stale;
+        handle.spawn_blocking(move || {
+            match origin.execute(request.clone()) {
+                Ok(response) => store_response(store.as_ref(), &key, &request, &response, lifetime),
+                Err(e) => warn!("Background refresh of {} failed: {}", key, e),
+            }
+            refreshing.lock().remove(&key);
+        });
+        true
+    }
+}
+
+impl Backend for CacheBackend {
+    fn execute(&self, request: PhpRequest) -> Result<PhpResponse, BackendError> {
+        self.execute_with_deadline(request, None)
+    }
+
+    fn execute_with_deadline(
+        &self,
+        request: PhpRequest,
+        deadline: Option<Deadline>,
+    ) -> Result<PhpResponse, BackendError> {
+        if request.method != "GET" || self.bypasses(&request) {
+            return self.origin.execute_with_deadline(request, deadline);
+        }
+
+        let key = cache_key(&request);
+        if let Some(page) = self.lookup(&key, &request) {
+            let age = page.age();
+            if age < self.ttl {
+                return Ok(page.to_response("HIT"));
+            }
+            if age < self.ttl + self.stale && self.spawn_refresh(key.clone(), request.clone()) {
+                debug!("Serving stale {} while it is refreshed", key);
+                return Ok(page.to_response("STALE"));
+            }
+        }
+
+        let mut response = self.origin.execute_with_deadline(request.clone(), deadline)?;
+        store_response(self.store.as_ref(), &key, &request, &response, self.ttl + self.stale);
+        response.headers.insert("X-Cache", "MISS");
+        Ok(response)
+    }
+
+    fn health_check(&self) -> Result<HealthStatus> {
+        self.origin.health_check()
+    }
+
+    fn backend_type(&self) -> BackendType {
+        BackendType::Cache
+    }
+}
+
+/// Pages are shared between clients, so per-user and uncacheable responses are skipped
+fn is_cacheable(response: &PhpResponse) -> bool {
+    let no_store = response.headers.get("cache-control").is_some_and(|value| {
+        let value = value.to_ascii_lowercase();
+        value.contains("no-store") || value.contains("private")
+    });
+    let vary_any = vary_headers(response).iter().any(|name| name == "*");
+    response.status_code == 200 && !no_store && !vary_any && !response.headers.contains("set-cookie")
+}
+
+/// Lowercased header names listed in the response's `Vary` headers
+fn vary_headers(response: &PhpResponse) -> Vec<String> {
+    response
+        .headers
+        .iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("vary"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+fn request_header<'a>(request: &'a PhpRequest, name: &str) -> Option<&'a str> {
+    request
+        .headers
+        .iter()
+        .find(|(header, _)| header.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn store_response(store: &dyn PageStore, key: &str, request: &PhpRequest, response: &PhpResponse, lifetime: Duration) {
+    if !is_cacheable(response) {
+        return;
+    }
+    let vary = vary_headers(response)
+        .into_iter()
+        .map(|name| {
+            let value = request_header(request, &name).unwrap_or("").to_string();
+            (name, value)
+        })
+        .collect();
+    let page = CachedPage {
+        key: key.to_string(),
+        stored_at_ms: now_ms(),
+        status: response.status_code,
+        headers: response.headers.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect(),
+        vary,
+        body: response.body.clone(),
+    };
+    if let Err(e) = store.put(&page, lifetime) {
+        warn!("Page cache write for {} failed: {}", key, e);
+    }
+}
+
+fn cache_key(request: &PhpRequest) -> String {
+    let host = request.headers.get("host").map(String::as_str).unwrap_or("");
+    format!("{}{}", host, request.uri)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers `v1`, `v2`, ... so each origin hit is visible in the body
+    struct CountingOrigin {
+        calls: AtomicUsize,
+        vary: Option<&'static str>,
+    }
+
+    impl Backend for CountingOrigin {
+        fn execute(&self, _request: PhpRequest) -> Result<PhpResponse, BackendError> {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst) + 1;
+            let mut headers: ResponseHeaders = [("Content-Type", "text/html")].into_iter().collect();
+            if let Some(vary) = self.vary {
+                headers.insert("Vary", vary);
+            }
+            Ok(PhpResponse {
+                status_code: 200,
+                headers,
+                body: format!("v{}", n).into_bytes(),
+                execution_time_ms: 1,
+                memory_peak_mb: 0.0,
+            })
+        }
+
+        fn health_check(&self) -> Result<HealthStatus> {
+            Ok(HealthStatus::healthy("Counting origin"))
+        }
+
+        fn backend_type(&self) -> BackendType {
+            BackendType::Embedded
+        }
+    }
+
+    fn request() -> PhpRequest {
+        PhpRequest {
+            method: "GET".to_string(),
+            uri: "/products?page=2".to_string(),
+            headers: [("host".to_string(), "shop.example".to_string())].into_iter().collect(),
+            body: Vec::new(),
+            query_string: "page=2".to_string(),
+            remote_addr: "127.0.0.1:5000".to_string(),
+        }
+    }
+
+    fn cache(dir: &std::path::Path, ttl: Duration, stale: Duration) -> (CacheBackend, Arc<CountingOrigin>) {
+        varying_cache(dir, ttl, stale, None)
+    }
+
+    fn varying_cache(
+        dir: &std::path::Path,
+        ttl: Duration,
+        stale: Duration,
+        vary: Option<&'static str>,
+    ) -> (CacheBackend, Arc<CountingOrigin>) {
+        let origin = Arc::new(CountingOrigin { calls: AtomicUsize::new(0), vary });
+        let store = Arc::new(DiskStore::new(dir.join("pages")).unwrap());
+        let backend = CacheBackend::new(Arc::clone(&origin) as Arc<dyn Backend>, store, ttl, stale);
+        (backend, origin)
+    }
+
+    fn served(response: &PhpResponse) -> (String, Option<&str>) {
+        (String::from_utf8_lossy(&response.body).into_owned(), response.headers.get("X-Cache"))
+    }
+
+    #[test]
+    fn test_miss_populates_and_hit_serves_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, origin) = cache(dir.path(), Duration::from_secs(60), Duration::ZERO);
+
+        let miss = backend.execute(request()).unwrap();
+        assert_eq!(served(&miss), ("v1".to_string(), Some("MISS")));
+
+        let hit = backend.execute(request()).unwrap();
+        assert_eq!(served(&hit), ("v1".to_string(), Some("HIT")));
+        assert_eq!(hit.headers.get("content-type"), Some("text/html"));
+        assert_eq!(origin.calls.load(Ordering::SeqCst), 1);
+
+        // Other pages and non-GET requests go to the origin
+        let mut other = request();
+        other.uri = "/products?page=3".to_string();
+        assert_eq!(served(&backend.execute(other).unwrap()).1, Some("MISS"));
+        let mut post = request();
+        post.method = "POST".to_string();
+        assert_eq!(served(&backend.execute(post).unwrap()).1, None);
+        assert_eq!(origin.calls.load(Ordering::SeqCst), 3);
+    }
+
+    fn with_header(name: &str, value: &str) -> PhpRequest {
+        let mut request = request();
+        request.headers.insert(name.to_string(), value.to_string());
+        request
+    }
+
+    #[test]
+    fn test_requests_with_credentials_bypass_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, origin) = cache(dir.path(), Duration::from_secs(60), Duration::ZERO);
+
+        // Personalized responses are neither stored nor served from the cache
+        let private = backend.execute(with_header("authorization", "Bearer alice")).unwrap();
+        assert_eq!(served(&private), ("v1".to_string(), None));
+        let private = backend.execute(with_header("Cookie", "PHPSESSID=alice")).unwrap();
+        assert_eq!(served(&private), ("v2".to_string(), None));
+
+        assert_eq!(served(&backend.execute(request()).unwrap()), ("v3".to_string(), Some("MISS")));
+        assert_eq!(served(&backend.execute(request()).unwrap()), ("v3".to_string(), Some("HIT")));
+        let private = backend.execute(with_header("cookie", "PHPSESSID=bob")).unwrap();
+        assert_eq!(served(&private), ("v4".to_string(), None));
+        assert_eq!(origin.calls.load(Ordering::SeqCst), 4);
+
+        // With bypass_cookies, only the session cookie bypasses
+        let backend = backend.with_bypass_cookies(vec!["PHPSESSID".to_string()]);
+        assert_eq!(served(&backend.execute(with_header("cookie", "_ga=1")).unwrap()).1, Some("HIT"));
+        assert_eq!(served(&backend.execute(with_header("cookie", "_ga=1; PHPSESSID=bob")).unwrap()).1, None);
+    }
+
+    #[test]
+    fn test_vary_headers_are_honored() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, origin) = varying_cache(
+            dir.path(),
+            Duration::from_secs(60),
+            Duration::ZERO,
+            Some("Accept-Encoding, Accept-Language"),
+        );
+        let french = || with_header("accept-language", "fr");
+
+        assert_eq!(served(&backend.execute(french()).unwrap()), ("v1".to_string(), Some("MISS")));
+        assert_eq!(served(&backend.execute(french()).unwrap()), ("v1".to_string(), Some("HIT")));
+
+        // A request that differs in a Vary header never gets the stored page
+        assert_eq!(served(&backend.execute(request()).unwrap()), ("v2".to_string(), Some("MISS")));
+        assert_eq!(served(&backend.execute(with_header("Accept-Language", "de")).unwrap()).0, "v3");
+        assert_eq!(origin.calls.load(Ordering::SeqCst), 3);
+
+        // `Vary: *` responses are not stored at all
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, origin) = varying_cache(dir.path(), Duration::from_secs(60), Duration::ZERO, Some("*"));
+        backend.execute(request()).unwrap();
+        assert_eq!(served(&backend.execute(request()).unwrap()), ("v2".to_string(), Some("MISS")));
+        assert_eq!(origin.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_stale_while_revalidate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (backend, origin) = cache(dir.path(), Duration::ZERO, Duration::from_secs(60));
+
+        assert_eq!(served(&backend.execute(request()).unwrap()), ("v1".to_string(), Some("MISS")));
+
+        // Expired but within the stale window: old page now, refresh in the background
+        let stale = backend.execute(request()).unwrap();
+        assert_eq!(served(&stale), ("v1".to_string(), Some("STALE")));
+
+        let deadline = std::time::Instant::now() + Duration::from_secs(5);
+        while origin.calls.load(Ordering::SeqCst) < 2 || !backend.refreshing.lock().is_empty() {
+            assert!(std::time::Instant::now() < deadline, "refresh never finished");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let refreshed = backend.execute(request()).unwrap();
+        assert_eq!(served(&refreshed), ("v2".to_string(), Some("STALE")));
+    }
+
+    #[test]
+    fn test_disk_store_concurrent_writes_and_sweep() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = Arc::new(DiskStore::new(dir.path().to_path_buf()).unwrap());
+        let page = |n: usize| CachedPage {
+            key: "shop.example/".to_string(),
+            stored_at_ms: now_ms(),
+            status: 200,
+            headers: Vec::new(),
+            vary: Vec::new(),
+            body: format!("v{}", n).into_bytes(),
+        };
+
+        // Writers of the same page never trip over each other's temporary file
+        let writers: Vec<_> = (0..8)
+            .map(|n| {
+                let store = Arc::clone(&store);
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        store.put(&page(n), Duration::from_secs(60)).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert!(store.get("shop.example/").unwrap().is_some());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        // Pages past their lifetime are removed
+        assert_eq!(store.sweep(Duration::from_secs(60)).unwrap(), 0);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(store.sweep(Duration::from_millis(10)).unwrap(), 1);
+        assert!(store.get("shop.example/").unwrap().is_none());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_redis_store_unreachable_fails_fast() {
+        let store = RedisStore::new("redis://192.0.2.1:6379", "page:".to_string(), Duration::from_millis(200)).unwrap();
+        let start = std::time::Instant::now();
+        assert!(store.get("shop.example/").is_err());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_uncacheable_responses_skipped() {
+        let mut response = PhpResponse {
+            status_code: 200,
+            headers: ResponseHeaders::new(),
+            body: Vec::new(),
+            execution_time_ms: 0,
+            memory_peak_mb: 0.0,
+        };
+        assert!(is_cacheable(&response));
+
+        response.headers.insert("Set-Cookie", "sid=1");
+        assert!(!is_cacheable(&response));
+
+        response.headers.remove("Set-Cookie");
+        response.headers.insert("Cache-Control", "private, max-age=0");
+        assert!(!is_cacheable(&response));
+
+        response.headers.remove("Cache-Control");
+        response.headers.insert("Vary", "Accept-Encoding, *");
+        assert!(!is_cacheable(&response));
+
+        response.headers.remove("Vary");
+        response.status_code = 404;
+        assert!(!is_cacheable(&response));
+    }
+}
@@ -1,22 +1,53 @@
-use super::{Backend, BackendError, BackendType, PathPattern};
+use super::{Backend, BackendError, BackendType, Deadline, HealthStatus, PathPattern};
 use crate::config::{PathPatternConfig, RoutingRule};
 use crate::metrics::MetricsCollector;
 use crate::php::{PhpRequest, PhpResponse};
 use anyhow::{Context, Result};
+use parking_lot::RwLock;
 use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
 
 pub struct BackendRouter {
     backends: HashMap<BackendType, Arc<dyn Backend>>,
     rules: Vec<CompiledRoutingRule>,
     default_backend: BackendType,
+    /// Outcome of the most recent health check per backend; unchecked backends count as healthy
+    health: RwLock<HashMap<BackendType, bool>>,
+    skip_unhealthy: bool,
+    unhealthy_fallback: Option<BackendType>,
+    default_slo: Option<Duration>,
+}
+
+/// Where `route` sent a request and which rule decided it
+pub struct Route {
+    pub backend: Arc<dyn Backend>,
+    /// Pattern of the matched rule (`prefix:/static/*`), or `None` for the default backend
+    pub rule: Option<String>,
+    /// Latency objective of the matched rule (or `backend.default_slo_ms`)
+    pub slo: Option<Duration>,
+}
+
+impl Route {
+    /// `prefix:/static/* -> static`, or `default -> embedded`
+    pub fn describe(&self) -> String {
+        format!("{} -> {}", self.label(), self.backend.backend_type())
+    }
+
+    /// Matched rule pattern or `default`; the `route` label of the SLO metrics
+    pub fn label(&self) -> &str {
+        self.rule.as_deref().unwrap_or("default")
+    }
 }
 
 struct CompiledRoutingRule {
     pattern: PathPattern,
     backend_type: BackendType,
     priority: u32,
+    max_body_size: Option<usize>,
+    slo: Option<Duration>,
 }
 
 impl BackendRouter {
@@ -29,13 +60,17 @@ impl BackendRouter {
 
         for rule in routing_rules {
             let pattern = Self::compile_pattern(&rule.pattern)?;
-            let backend_type = rule.backend.parse::<BackendType>()
+            let backend_type = rule
+                .backend
+                .parse::<BackendType>()
                 .with_context(|| format!("Invalid backend type: {}", rule.backend))?;
 
             rules.push(CompiledRoutingRule {
                 pattern,
                 backend_type,
                 priority: rule.priority,
+                max_body_size: rule.max_body_size,
+                slo: rule.slo_ms.map(Duration::from_millis),
             });
         }
 
@@ -45,37 +80,108 @@ impl BackendRouter {
             backends,
             rules,
             default_backend,
+            health: RwLock::new(HashMap::new()),
+            skip_unhealthy: false,
+            unhealthy_fallback: None,
+            default_slo: None,
         })
     }
 
+    /// Latency objective for requests that match no routing rule
+    pub fn with_default_slo(mut self, slo: Option<Duration>) -> Self {
+        self.default_slo = slo;
+        self
+    }
+
+    /// Whether to route around backends that failed their last health check, and
+    /// which backend takes over for them (503 when `None` or also unhealthy)
+    pub fn with_unhealthy_policy(
+        mut self,
+        skip_unhealthy: bool,
+        fallback: Option<BackendType>,
+    ) -> Self {
+        self.skip_unhealthy = skip_unhealthy;
+        self.unhealthy_fallback = fallback;
+        self
+    }
+
     fn compile_pattern(config: &PathPatternConfig) -> Result<PathPattern> {
-        match config {
-            PathPatternConfig::Exact(s) => Ok(PathPattern::Exact(s.clone())),
-            PathPatternConfig::Prefix(s) => Ok(PathPattern::Prefix(s.clone())),
-            PathPatternConfig::Suffix(s) => Ok(PathPattern::Suffix(s.clone())),
-            PathPatternConfig::Regex(s) => {
-                let regex = regex::Regex::new(s)
-                    .with_context(|| format!("Invalid regex pattern: {}", s))?;
-                Ok(PathPattern::Regex(regex))
-            }
-        }
+        PathPattern::from_config(config, false)
     }
 
-    pub fn route(&self, path: &str) -> Arc<dyn Backend> {
-        for rule in &self.rules {
-            if rule.pattern.matches(path) {
-                if let Some(backend) = self.backends.get(&rule.backend_type) {
-                    return Arc::clone(backend);
-                }
+    /// Backend for a path and the rule that chose it, skipping a backend whose
+    /// last health check failed
+    pub fn route(&self, path: &str) -> Result<Route, BackendError> {
+        let matched = self.matching_rule(path);
+        let rule = matched.map(|rule| describe_pattern(&rule.pattern));
+        let slo = match matched {
+            Some(rule) => rule.slo,
+            None => self.default_slo,
+        };
+        let backend_type = matched
+            .map(|rule| rule.backend_type)
+            .unwrap_or(self.default_backend);
+
+        if !self.skip_unhealthy || self.is_healthy(backend_type) {
+            return Ok(Route {
+                backend: self.backend(backend_type),
+                rule,
+                slo,
+            });
+        }
+
+        match self.unhealthy_fallback {
+            Some(fallback)
+                if self.backends.contains_key(&fallback) && self.is_healthy(fallback) =>
+            {
+                debug!(
+                    "Backend {} is unhealthy, routing {} to {}",
+                    backend_type, path, fallback
+                );
+                Ok(Route {
+                    backend: self.backend(fallback),
+                    rule,
+                    slo,
+                })
             }
+            _ => Err(BackendError::Unavailable(backend_type)),
         }
+    }
 
+    fn backend(&self, backend_type: BackendType) -> Arc<dyn Backend> {
         self.backends
-            .get(&self.default_backend)
-            .expect("Default backend must exist")
+            .get(&backend_type)
+            .expect("Routed backend must exist")
             .clone()
     }
 
+    /// Result of the last health check for `backend_type`; `true` until one has run
+    pub fn is_healthy(&self, backend_type: BackendType) -> bool {
+        self.health
+            .read()
+            .get(&backend_type)
+            .copied()
+            .unwrap_or(true)
+    }
+
+    /// Maximum request body size for a path
+    pub fn body_limit(&self, path: &str) -> usize {
+        self.matching_rule(path)
+            .and_then(|rule| rule.max_body_size)
+            .unwrap_or(crate::utils::MAX_BODY_SIZE)
+    }
+
+    fn matching_rule(&self, path: &str) -> Option<&CompiledRoutingRule> {
+        self.rules.iter().find(|rule| {
+            rule.pattern.matches(path) && self.backends.contains_key(&rule.backend_type)
+        })
+    }
+
+    /// Whether a routing rule, rather than the default backend, claims `path`
+    pub fn has_rule(&self, path: &str) -> bool {
+        self.matching_rule(path).is_some()
+    }
+
     pub fn backends(&self) -> &HashMap<BackendType, Arc<dyn Backend>> {
         &self.backends
     }
@@ -84,54 +190,142 @@ impl BackendRouter {
         self.rules
             .iter()
             .map(|rule| {
-                let pattern_desc = match &rule.pattern {
-                    PathPattern::Exact(s) => format!("exact:{}", s),
-                    PathPattern::Prefix(s) => format!("prefix:{}", s),
-                    PathPattern::Suffix(s) => format!("suffix:{}", s),
-                    PathPattern::Regex(r) => format!("regex:{}", r.as_str()),
-                };
-                (pattern_desc, rule.backend_type, rule.priority)
+                (
+                    describe_pattern(&rule.pattern),
+                    rule.backend_type,
+                    rule.priority,
+                )
+            })
+            .collect()
+    }
+
+    /// Run every backend's health check, recording `backend_up` and its duration
+    /// and updating the status `route` consults
+    pub fn check_health(
+        &self,
+        metrics: Option<&MetricsCollector>,
+    ) -> Vec<(BackendType, Result<HealthStatus>)> {
+        self.backends
+            .iter()
+            .map(|(backend_type, backend)| {
+                let start = Instant::now();
+                let result = backend.health_check();
+                let duration = start.elapsed().as_secs_f64();
+
+                let up = matches!(result, Ok(ref status) if status.healthy);
+                self.health.write().insert(*backend_type, up);
+                if let Some(metrics) = metrics {
+                    metrics.record_backend_health(&backend_type.to_string(), up, duration);
+                }
+
+                (*backend_type, result)
             })
             .collect()
     }
 
+    /// Re-check backend health every `interval` so `backend_up` stays current
+    /// without anyone requesting `/_health`, until `shutdown` fires
+    pub fn spawn_health_checks(
+        self: &Arc<Self>,
+        metrics: Arc<MetricsCollector>,
+        interval: Duration,
+        mut shutdown: tokio::sync::broadcast::Receiver<()>,
+    ) -> tokio::task::JoinHandle<()> {
+        let router = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    _ = shutdown.recv() => return,
+                }
+                // Health checks may block on backend I/O
+                let router = Arc::clone(&router);
+                let metrics = Arc::clone(&metrics);
+                let checks = tokio::task::spawn_blocking(move || {
+                    for (backend_type, result) in router.check_health(Some(&metrics)) {
+                        match result {
+                            Ok(status) if !status.healthy => {
+                                warn!("Backend {} is unhealthy: {}", backend_type, status.message);
+                            }
+                            Err(e) => warn!("Backend {} health check failed: {}", backend_type, e),
+                            Ok(_) => {}
+                        }
+                    }
+                });
+                if let Err(e) = checks.await {
+                    warn!("Backend health check task failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Route and execute `request`, passing `deadline` on to the backend
     pub fn execute_with_metrics(
         &self,
         request: PhpRequest,
+        deadline: Option<Deadline>,
         metrics: Option<&MetricsCollector>,
     ) -> Result<PhpResponse, BackendError> {
         let path = &request.uri.clone();
-        let backend = self.route(path);
+        let backend = self.route(path)?.backend;
         let backend_type = backend.backend_type();
         let backend_name = backend_type.to_string();
 
         let start = Instant::now();
-        let result = backend.execute(request);
+        // A panicking backend becomes an error response instead of tearing down the connection
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            backend.execute_with_deadline(request, deadline)
+        }))
+        .unwrap_or_else(|payload| Err(BackendError::Panic(crate::utils::panic_message(&*payload))));
         let duration = start.elapsed().as_secs_f64();
 
         if let Some(metrics) = metrics {
-            match &result {
-                Ok(_) => {
-                    metrics.record_backend_request(&backend_name, "success", duration);
-                }
-                Err(e) => {
-                    let error_type = match e {
-                        BackendError::NotFound(_) => "not_found",
-                        BackendError::PhpError(_) => "php_error",
-                        BackendError::ConnectionFailed(_) => "connection_failed",
-                        BackendError::ProtocolError(_) => "protocol_error",
-                        BackendError::IoError(_) => "io_error",
-                        BackendError::Timeout => "timeout",
-                        BackendError::Other(_) => "other",
-                    };
-                    metrics.record_backend_request(&backend_name, "error", duration);
-                    metrics.record_backend_error(&backend_name, error_type);
-                }
-            }
+            Self::record_metrics(metrics, &backend_name, duration, &result);
         }
 
         result
     }
+
+    /// Record the outcome of a backend call
+    pub fn record_metrics(
+        metrics: &MetricsCollector,
+        backend_name: &str,
+        duration: f64,
+        result: &Result<PhpResponse, BackendError>,
+    ) {
+        match result {
+            Ok(_) => {
+                metrics.record_backend_request(backend_name, "success", duration);
+            }
+            Err(e) => {
+                let error_type = match e {
+                    BackendError::NotFound(_) => "not_found",
+                    BackendError::PhpError(_) => "php_error",
+                    BackendError::ConnectionFailed(_) => "connection_failed",
+                    BackendError::ProtocolError(_) => "protocol_error",
+                    BackendError::IoError(_) => "io_error",
+                    BackendError::Timeout => "timeout",
+                    BackendError::BodyTooLarge(_) => "body_too_large",
+                    BackendError::Overloaded(_) => "overloaded",
+                    BackendError::Panic(_) => "panic",
+                    BackendError::Unavailable(_) => "unavailable",
+                    BackendError::Other(_) => "other",
+                };
+                metrics.record_backend_request(backend_name, "error", duration);
+                metrics.record_backend_error(backend_name, error_type);
+            }
+        }
+    }
+}
+
+fn describe_pattern(pattern: &PathPattern) -> String {
+    match pattern {
+        PathPattern::Exact(s) => format!("exact:{}", s),
+        PathPattern::Prefix(s) => format!("prefix:{}", s),
+        PathPattern::Suffix(s) => format!("suffix:{}", s),
+        PathPattern::Regex(r) => format!("regex:{}", r.as_str()),
+    }
 }
 
 #[cfg(test)]
@@ -184,19 +378,26 @@ mod tests {
             pattern: PathPatternConfig::Prefix("/static/*".to_string()),
             backend: "static".to_string(),
             priority: 100,
+            max_body_size: Some(1024),
+            slo_ms: None,
         }];
 
-        let router =
-            BackendRouter::new(backends, rules, BackendType::Embedded).unwrap();
+        let router = BackendRouter::new(backends, rules, BackendType::Embedded).unwrap();
 
         assert_eq!(
-            router.route("/static/image.png").backend_type(),
+            router
+                .route("/static/image.png")
+                .unwrap()
+                .backend
+                .backend_type(),
             BackendType::Static
         );
         assert_eq!(
-            router.route("/api/user").backend_type(),
+            router.route("/api/user").unwrap().backend.backend_type(),
             BackendType::Embedded
         );
+        assert_eq!(router.body_limit("/static/image.png"), 1024);
+        assert_eq!(router.body_limit("/api/user"), crate::utils::MAX_BODY_SIZE);
     }
 
     #[test]
@@ -220,20 +421,251 @@ mod tests {
                 pattern: PathPatternConfig::Prefix("/api/*".to_string()),
                 backend: "embedded".to_string(),
                 priority: 100,
+                max_body_size: None,
+                slo_ms: None,
             },
             RoutingRule {
                 pattern: PathPatternConfig::Prefix("/api/*".to_string()),
                 backend: "fastcgi".to_string(),
                 priority: 50,
+                max_body_size: None,
+                slo_ms: None,
             },
         ];
 
+        let router = BackendRouter::new(backends, rules, BackendType::Embedded).unwrap();
+
+        assert_eq!(
+            router.route("/api/user").unwrap().backend.backend_type(),
+            BackendType::Embedded
+        );
+    }
+
+    struct FlippingBackend {
+        healthy: Arc<std::sync::atomic::AtomicBool>,
+    }
+
+    impl Backend for FlippingBackend {
+        fn execute(&self, _request: PhpRequest) -> Result<PhpResponse, BackendError> {
+            Err(BackendError::Timeout)
+        }
+
+        fn health_check(&self) -> Result<HealthStatus> {
+            if self.healthy.load(std::sync::atomic::Ordering::SeqCst) {
+                Ok(HealthStatus::healthy("up"))
+            } else {
+                Ok(HealthStatus::unhealthy("down"))
+            }
+        }
+
+        fn backend_type(&self) -> BackendType {
+            BackendType::Embedded
+        }
+    }
+
+    #[tokio::test]
+    async fn test_periodic_health_checks_update_backend_up() {
+        let healthy = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let mut backends = HashMap::new();
+        backends.insert(
+            BackendType::Embedded,
+            Arc::new(FlippingBackend {
+                healthy: Arc::clone(&healthy),
+            }) as Arc<dyn Backend>,
+        );
         let router =
-            BackendRouter::new(backends, rules, BackendType::Embedded).unwrap();
+            Arc::new(BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap());
+        let metrics = Arc::new(MetricsCollector::new());
+
+        let (shutdown_tx, shutdown_rx) = tokio::sync::broadcast::channel(1);
+        let task = router.spawn_health_checks(
+            Arc::clone(&metrics),
+            Duration::from_millis(20),
+            shutdown_rx,
+        );
+
+        let wait_for = |up: bool| {
+            let metrics = Arc::clone(&metrics);
+            async move {
+                for _ in 0..100 {
+                    if metrics.get_backend_up("embedded") == up {
+                        return true;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                false
+            }
+        };
+
+        assert!(wait_for(true).await, "backend_up never became 1");
+        healthy.store(false, std::sync::atomic::Ordering::SeqCst);
+        assert!(wait_for(false).await, "backend_up never became 0");
+
+        let exported = crate::metrics::export_registry(&metrics.registry(), None)
+            .unwrap()
+            .0;
+        assert!(
+            exported.contains("backend_up{backend=\"embedded\"} 0"),
+            "{}",
+            exported
+        );
+        assert!(exported.contains("backend_health_check_duration_seconds{backend=\"embedded\"}"));
+
+        shutdown_tx.send(()).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), task)
+            .await
+            .expect("health checks kept running after shutdown")
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unhealthy_backend_bypassed_for_fallback() {
+        let healthy = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let mut backends = HashMap::new();
+        backends.insert(
+            BackendType::Embedded,
+            Arc::new(FlippingBackend {
+                healthy: Arc::clone(&healthy),
+            }) as Arc<dyn Backend>,
+        );
+        backends.insert(
+            BackendType::Static,
+            Arc::new(MockBackend {
+                backend_type: BackendType::Static,
+            }) as Arc<dyn Backend>,
+        );
+        let router = BackendRouter::new(backends, Vec::new(), BackendType::Embedded)
+            .unwrap()
+            .with_unhealthy_policy(true, Some(BackendType::Static));
+
+        // Nothing is known before the first check
+        assert_eq!(
+            router.route("/index.php").unwrap().backend.backend_type(),
+            BackendType::Embedded
+        );
+
+        router.check_health(None);
+        assert!(!router.is_healthy(BackendType::Embedded));
+        assert_eq!(
+            router.route("/index.php").unwrap().backend.backend_type(),
+            BackendType::Static
+        );
 
+        let request = PhpRequest {
+            method: "GET".to_string(),
+            uri: "/index.php".to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            query_string: String::new(),
+            remote_addr: "127.0.0.1".to_string(),
+        };
         assert_eq!(
-            router.route("/api/user").backend_type(),
+            router
+                .execute_with_metrics(request, None, None)
+                .unwrap()
+                .status_code,
+            200
+        );
+
+        let router = router.with_unhealthy_policy(true, None);
+        assert!(matches!(
+            router.route("/index.php"),
+            Err(BackendError::Unavailable(BackendType::Embedded))
+        ));
+
+        let router = router.with_unhealthy_policy(false, None);
+        assert_eq!(
+            router.route("/index.php").unwrap().backend.backend_type(),
             BackendType::Embedded
         );
+
+        healthy.store(true, std::sync::atomic::Ordering::SeqCst);
+        let router = router.with_unhealthy_policy(true, None);
+        router.check_health(None);
+        assert_eq!(
+            router.route("/index.php").unwrap().backend.backend_type(),
+            BackendType::Embedded
+        );
+    }
+
+    /// Reports the budget it was handed and gives up when it runs out
+    struct DeadlineBackend {
+        remaining: parking_lot::Mutex<Option<Duration>>,
+    }
+
+    impl Backend for DeadlineBackend {
+        fn execute(&self, request: PhpRequest) -> Result<PhpResponse, BackendError> {
+            self.execute_with_deadline(request, None)
+        }
+
+        fn execute_with_deadline(
+            &self,
+            _request: PhpRequest,
+            deadline: Option<Deadline>,
+        ) -> Result<PhpResponse, BackendError> {
+            let deadline = deadline.expect("deadline should be propagated");
+            *self.remaining.lock() = Some(deadline.remaining());
+            std::thread::sleep(deadline.remaining());
+            Err(BackendError::Timeout)
+        }
+
+        fn health_check(&self) -> Result<HealthStatus> {
+            Ok(HealthStatus::healthy("up"))
+        }
+
+        fn backend_type(&self) -> BackendType {
+            BackendType::Embedded
+        }
+    }
+
+    #[test]
+    fn test_deadline_reaches_backend() {
+        let backend = Arc::new(DeadlineBackend {
+            remaining: parking_lot::Mutex::new(None),
+        });
+        let mut backends = HashMap::new();
+        backends.insert(
+            BackendType::Embedded,
+            Arc::clone(&backend) as Arc<dyn Backend>,
+        );
+        backends.insert(
+            BackendType::Static,
+            Arc::new(MockBackend {
+                backend_type: BackendType::Static,
+            }) as Arc<dyn Backend>,
+        );
+        let rules = vec![RoutingRule {
+            pattern: PathPatternConfig::Prefix("/static/*".to_string()),
+            backend: "static".to_string(),
+            priority: 100,
+            max_body_size: None,
+            slo_ms: None,
+        }];
+        let router = BackendRouter::new(backends, rules, BackendType::Embedded).unwrap();
+        let request = |uri: &str| PhpRequest {
+            method: "GET".to_string(),
+            uri: uri.to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            query_string: String::new(),
+            remote_addr: "127.0.0.1".to_string(),
+        };
+
+        let deadline = Deadline::after(Duration::from_millis(300));
+        std::thread::sleep(Duration::from_millis(100));
+        let start = Instant::now();
+        let result = router.execute_with_metrics(request("/index.php"), Some(deadline), None);
+
+        assert!(matches!(result, Err(BackendError::Timeout)));
+        let remaining = backend.remaining.lock().unwrap();
+        assert!(remaining <= Duration::from_millis(200), "{:?}", remaining);
+        assert!(start.elapsed() < Duration::from_millis(300));
+        assert!(deadline.is_expired());
+
+        // Backends without their own handling refuse already-expired work
+        let result = router.execute_with_metrics(request("/static/app.css"), Some(deadline), None);
+        assert!(matches!(result, Err(BackendError::Timeout)));
+        let result = router.execute_with_metrics(request("/static/app.css"), None, None);
+        assert_eq!(result.unwrap().status_code, 200);
     }
 }
@@ -1,13 +1,20 @@
-use super::{Backend, BackendError, BackendType, HealthStatus};
-use crate::php::{PhpRequest, PhpResponse};
+use super::file_cache::{CachedFile, FileCache};
+use super::{Backend, BackendError, BackendType, HealthStatus, PathPattern};
+use crate::php::{PhpRequest, PhpResponse, ResponseHeaders};
+use crate::server::range::{RangeHandler, RangeOutcome};
 use anyhow::Result;
-use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 
+/// Separates the parts of `multipart/byteranges` responses
+const BYTERANGES_BOUNDARY: &str = "fe-php-byteranges";
+
 pub struct StaticBackend {
     root: PathBuf,
     index_files: Vec<String>,
+    max_ranges: usize,
+    range_disabled: Vec<PathPattern>,
+    cache: Option<FileCache>,
 }
 
 impl StaticBackend {
@@ -15,6 +22,9 @@ impl StaticBackend {
         Self {
             root,
             index_files: vec!["index.html".to_string(), "index.htm".to_string()],
+            max_ranges: 16,
+            range_disabled: Vec::new(),
+            cache: None,
         }
     }
 
@@ -23,14 +33,63 @@ impl StaticBackend {
         self
     }
 
-    fn sanitize_path(&self, uri: &str) -> Result<PathBuf, BackendError> {
+    /// Limit sub-ranges per request (`0` disables ranges) and turn ranges off for some paths
+    pub fn with_ranges(mut self, max_ranges: usize, range_disabled: Vec<PathPattern>) -> Self {
+        self.max_ranges = max_ranges;
+        self.range_disabled = range_disabled;
+        self
+    }
+
+    /// Cache up to `entries` resolved paths and the contents of files up to
+    /// `max_file_size` bytes (`0` entries disables the cache)
+    pub fn with_cache(mut self, entries: usize, max_file_size: u64) -> Self {
+        self.cache = (entries > 0).then(|| FileCache::new(entries, max_file_size));
+        self
+    }
+
+    /// Resolve a request URI to a regular file, consulting the cache first
+    fn resolve(&self, uri: &str) -> Result<CachedFile, BackendError> {
+        if let Some(file) = self.cache.as_ref().and_then(|cache| cache.get(uri)) {
+            return Ok(file);
+        }
+
+        let mut file_path = self.sanitize_path(uri)?;
+
+        if file_path.is_dir() {
+            file_path = self.find_index_file(&file_path)?;
+        }
+
+        if !file_path.exists() || !file_path.is_file() {
+            return Err(BackendError::NotFound(uri.to_string()));
+        }
+
+        let metadata = std::fs::metadata(&file_path)
+            .map_err(|e| BackendError::IoError(e))?;
+
+        match &self.cache {
+            Some(cache) => {
+                let content = if cache.caches_content(metadata.len()) {
+                    Some(std::fs::read(&file_path).map_err(BackendError::IoError)?)
+                } else {
+                    None
+                };
+                Ok(cache.insert(uri, &file_path, &metadata, content.as_deref()))
+            }
+            None => Ok(CachedFile { path: file_path, metadata, content: None }),
+        }
+    }
+
+    fn ranges_enabled(&self, uri: &str) -> bool {
         let path = uri.split('?').next().unwrap_or(uri);
+        self.max_ranges > 0 && !self.range_disabled.iter().any(|p| p.matches(path))
+    }
 
+    fn sanitize_path(&self, uri: &str) -> Result<PathBuf, BackendError> {
+        let path = crate::utils::decode_path(uri)
+            .map_err(|e| BackendError::Other(anyhow::anyhow!("{}: {}", e, uri)))?;
         let path = path.trim_start_matches('/');
-        let path = urlencoding::decode(path)
-            .map_err(|e| BackendError::Other(anyhow::anyhow!("Invalid URL encoding: {}", e)))?;
 
-        let full_path = self.root.join(path.as_ref());
+        let full_path = self.root.join(path);
 
         let canonical = full_path.canonicalize()
             .map_err(|_| BackendError::NotFound(path.to_string()))?;
@@ -116,7 +175,7 @@ impl Backend for StaticBackend {
             return Ok(PhpResponse {
                 status_code: 405,
                 headers: {
-                    let mut h = HashMap::new();
+                    let mut h = ResponseHeaders::new();
                     h.insert("Allow".to_string(), "GET, HEAD".to_string());
                     h.insert("Content-Type".to_string(), "text/plain".to_string());
                     h
@@ -127,30 +186,25 @@ impl Backend for StaticBackend {
             });
         }
 
-        let mut file_path = self.sanitize_path(&request.uri)?;
-
-        if file_path.is_dir() {
-            file_path = self.find_index_file(&file_path)?;
-        }
-
-        if !file_path.exists() || !file_path.is_file() {
-            return Err(BackendError::NotFound(request.uri.clone()));
-        }
-
-        let metadata = std::fs::metadata(&file_path)
-            .map_err(|e| BackendError::IoError(e))?;
+        let resolved = self.resolve(&request.uri)?;
+        let file_path = resolved.path;
+        let metadata = resolved.metadata;
 
         let file_size = metadata.len();
 
         let mime_type = self.guess_mime_type(&file_path);
 
         let cache_control = self.get_cache_control(&file_path);
+        let ranges_enabled = self.ranges_enabled(&request.uri);
 
         if request.method == "HEAD" {
-            let mut headers = HashMap::new();
+            let mut headers = ResponseHeaders::new();
             headers.insert("Content-Type".to_string(), mime_type.to_string());
             headers.insert("Content-Length".to_string(), file_size.to_string());
             headers.insert("Cache-Control".to_string(), cache_control);
+            if ranges_enabled {
+                headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+            }
 
             return Ok(PhpResponse {
                 status_code: 200,
@@ -161,10 +215,13 @@ impl Backend for StaticBackend {
             });
         }
 
-        let content = std::fs::read(&file_path)
-            .map_err(|e| BackendError::IoError(e))?;
+        let content = match resolved.content {
+            Some(content) => content.as_ref().clone(),
+            None => std::fs::read(&file_path)
+                .map_err(|e| BackendError::IoError(e))?,
+        };
 
-        let mut headers = HashMap::new();
+        let mut headers = ResponseHeaders::new();
         headers.insert("Content-Type".to_string(), mime_type.to_string());
         headers.insert("Content-Length".to_string(), content.len().to_string());
         headers.insert("Cache-Control".to_string(), cache_control);
@@ -179,12 +236,45 @@ impl Backend for StaticBackend {
         );
         headers.insert("ETag".to_string(), etag);
 
+        let mut status_code = 200;
+        let mut body = content;
+        if ranges_enabled {
+            headers.insert("Accept-Ranges".to_string(), "bytes".to_string());
+
+            let range_header = request.headers.get("range").map(String::as_str);
+            match RangeHandler::evaluate(range_header, file_size, self.max_ranges) {
+                RangeOutcome::Full => {}
+                RangeOutcome::Partial(range) => {
+                    status_code = 206;
+                    body = body[range.start as usize..=range.end as usize].to_vec();
+                    headers.insert(
+                        "Content-Range".to_string(),
+                        format!("bytes {}-{}/{}", range.start, range.end, file_size),
+                    );
+                }
+                RangeOutcome::Multipart(ranges) => {
+                    status_code = 206;
+                    body = RangeHandler::multipart_body(&ranges, &body, mime_type, BYTERANGES_BOUNDARY);
+                    headers.insert(
+                        "Content-Type".to_string(),
+                        format!("multipart/byteranges; boundary={}", BYTERANGES_BOUNDARY),
+                    );
+                }
+                RangeOutcome::NotSatisfiable => {
+                    status_code = 416;
+                    body = Vec::new();
+                    headers.insert("Content-Range".to_string(), format!("bytes */{}", file_size));
+                }
+            }
+            headers.insert("Content-Length".to_string(), body.len().to_string());
+        }
+
         let execution_time_ms = start.elapsed().as_millis() as u64;
 
         Ok(PhpResponse {
-            status_code: 200,
+            status_code,
             headers,
-            body: content,
+            body,
             execution_time_ms,
             memory_peak_mb: 0.0,
         })
@@ -208,3 +298,61 @@ impl Backend for StaticBackend {
         BackendType::Static
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::{Duration, SystemTime};
+
+    fn get(uri: &str) -> PhpRequest {
+        PhpRequest {
+            method: "GET".to_string(),
+            uri: uri.to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            query_string: String::new(),
+            remote_addr: "127.0.0.1".to_string(),
+        }
+    }
+
+    fn set_mtime(path: &Path, mtime: SystemTime) {
+        std::fs::File::options().write(true).open(path).unwrap().set_modified(mtime).unwrap();
+    }
+
+    #[test]
+    fn test_cache_hit_served_from_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let file = root.join("app.css");
+        std::fs::write(&file, "body{}").unwrap();
+        let mtime = std::fs::metadata(&file).unwrap().modified().unwrap();
+
+        let backend = StaticBackend::new(root).with_cache(8, 1024);
+        assert_eq!(backend.execute(get("/app.css")).unwrap().body, b"body{}");
+
+        // Same size and mtime: the stat check passes and the old bytes come from memory
+        std::fs::write(&file, "p{ a }").unwrap();
+        set_mtime(&file, mtime);
+        assert_eq!(backend.execute(get("/app.css")).unwrap().body, b"body{}");
+    }
+
+    #[test]
+    fn test_cache_invalidated_on_mtime_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        let file = root.join("index.html");
+        std::fs::write(&file, "v1").unwrap();
+        let mtime = std::fs::metadata(&file).unwrap().modified().unwrap();
+
+        let backend = StaticBackend::new(root).with_cache(8, 1024);
+        assert_eq!(backend.execute(get("/")).unwrap().body, b"v1");
+
+        std::fs::write(&file, "v2").unwrap();
+        set_mtime(&file, mtime + Duration::from_secs(10));
+        assert_eq!(backend.execute(get("/")).unwrap().body, b"v2");
+
+        std::fs::remove_file(&file).unwrap();
+        assert!(matches!(backend.execute(get("/")), Err(BackendError::NotFound(_))));
+    }
+}
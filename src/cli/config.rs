@@ -14,6 +14,10 @@ pub enum ConfigCommand {
     Check {
         #[arg(short, long)]
         config: PathBuf,
+
+        /// Override file deep-merged over --config; repeat to apply several in order
+        #[arg(long = "override", value_name = "FILE")]
+        overrides: Vec<PathBuf>,
     },
 
     Save {
@@ -33,10 +37,10 @@ pub enum ConfigCommand {
 
 pub async fn run(args: ConfigArgs) -> Result<()> {
     match args.command {
-        ConfigCommand::Check { config } => {
+        ConfigCommand::Check { config, overrides } => {
             println!("Checking configuration: {}", config.display());
 
-            let cfg = Config::from_file(&config)?;
+            let cfg = Config::from_files(&config, &overrides)?;
             let warnings = cfg.validate()?;
 
             if warnings.is_empty() {
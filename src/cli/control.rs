@@ -0,0 +1,324 @@
+//! One-shot commands sent to a running server over the admin Unix socket
+
+use anyhow::{Context, Result};
+use clap::Args;
+use crate::tui::client::TuiClient;
+use crate::Config;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How to reach the admin socket of a running server
+#[derive(Args, Debug)]
+pub struct ConnectionArgs {
+    /// Configuration file; its admin.unix_socket is used when --socket is not given
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Admin Unix socket path of the running server
+    #[arg(short, long)]
+    socket: Option<PathBuf>,
+
+    /// Timeout in seconds for each admin socket operation
+    #[arg(long, default_value = "5")]
+    timeout: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct ReloadArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct RestartWorkersArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct IpArgs {
+    /// IP address to block or unblock
+    ip: String,
+
+    #[command(flatten)]
+    connection: ConnectionArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct UpstreamArgs {
+    /// Name of the upstream in [[load_balancing.upstreams]]
+    name: String,
+
+    #[command(flatten)]
+    connection: ConnectionArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct WafSamplesArgs {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+}
+
+impl ConnectionArgs {
+    fn load_config(&self) -> Result<Option<Config>> {
+        self.config
+            .as_ref()
+            .map(|path| Config::from_file(path).with_context(|| format!("Failed to load {}", path.display())))
+            .transpose()
+    }
+
+    fn client(&self, config: Option<&Config>) -> TuiClient {
+        let socket_path = self
+            .socket
+            .clone()
+            .or_else(|| config.map(|c| c.admin.unix_socket.clone()))
+            .unwrap_or_else(|| crate::config::AdminConfig::default().unix_socket);
+        TuiClient::new(socket_path).with_timeout(Duration::from_secs(self.timeout))
+    }
+}
+
+/// Validate the configuration (when given) and ask the server to reload it.
+/// Validation errors are reported without contacting the server.
+pub async fn reload(args: ReloadArgs) -> Result<()> {
+    let config = args.connection.load_config()?;
+
+    if let Some(ref config) = config {
+        let warnings = config.validate()?;
+        for warning in &warnings {
+            println!("{}", warning);
+        }
+        let errors: Vec<&str> = warnings
+            .iter()
+            .filter(|w| w.starts_with("[X]"))
+            .map(|w| w.as_str())
+            .collect();
+        if !errors.is_empty() {
+            anyhow::bail!(
+                "Configuration has {} error(s), not reloading:\n{}",
+                errors.len(),
+                errors.join("\n")
+            );
+        }
+    }
+
+    let config_path = args.connection.config.as_ref().map(|p| p.display().to_string());
+    let message = args
+        .connection
+        .client(config.as_ref())
+        .reload_config(config_path)
+        .await
+        .context("Reload failed")?;
+    println!("{}", message);
+    Ok(())
+}
+
+pub async fn restart_workers(args: RestartWorkersArgs) -> Result<()> {
+    let config = args.connection.load_config()?;
+    let message = args
+        .connection
+        .client(config.as_ref())
+        .restart_workers()
+        .await
+        .context("Worker restart failed")?;
+    println!("{}", message);
+    Ok(())
+}
+
+pub async fn block_ip(args: IpArgs) -> Result<()> {
+    let config = args.connection.load_config()?;
+    let message = args
+        .connection
+        .client(config.as_ref())
+        .block_ip(args.ip)
+        .await
+        .context("Block failed")?;
+    println!("{}", message);
+    Ok(())
+}
+
+pub async fn unblock_ip(args: IpArgs) -> Result<()> {
+    let config = args.connection.load_config()?;
+    let message = args
+        .connection
+        .client(config.as_ref())
+        .unblock_ip(args.ip)
+        .await
+        .context("Unblock failed")?;
+    println!("{}", message);
+    Ok(())
+}
+
+/// Stop sending new requests to an upstream; requests in flight finish
+pub async fn drain_upstream(args: UpstreamArgs) -> Result<()> {
+    let config = args.connection.load_config()?;
+    let message = args
+        .connection
+        .client(config.as_ref())
+        .drain_upstream(args.name)
+        .await
+        .context("Drain failed")?;
+    println!("{}", message);
+    Ok(())
+}
+
+pub async fn enable_upstream(args: UpstreamArgs) -> Result<()> {
+    let config = args.connection.load_config()?;
+    let message = args
+        .connection
+        .client(config.as_ref())
+        .enable_upstream(args.name)
+        .await
+        .context("Enable failed")?;
+    println!("{}", message);
+    Ok(())
+}
+
+/// Print the requests the WAF would have blocked in learn mode, with a per-rule tally
+pub async fn waf_samples(args: WafSamplesArgs) -> Result<()> {
+    let config = args.connection.load_config()?;
+    let samples = args
+        .connection
+        .client(config.as_ref())
+        .waf_samples()
+        .await
+        .context("Fetching WAF samples failed")?;
+
+    if samples.is_empty() {
+        println!("No WAF samples recorded (samples are only kept with waf.mode = \"learn\")");
+        return Ok(());
+    }
+
+    let mut per_rule: std::collections::BTreeMap<&str, usize> = std::collections::BTreeMap::new();
+    for sample in &samples {
+        let time = chrono::DateTime::from_timestamp(sample.timestamp, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        let query = if sample.query_string.is_empty() { String::new() } else { format!("?{}", sample.query_string) };
+        println!("{}  {:<12} {} {}{}", time, sample.rule_id, sample.method, sample.uri, query);
+        *per_rule.entry(sample.rule_id.as_str()).or_default() += 1;
+    }
+
+    println!();
+    println!("{} samples:", samples.len());
+    for (rule_id, count) in per_rule {
+        println!("  {}: {}", rule_id, count);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixListener;
+
+    /// Record every command received; `block_ip` is refused, everything else succeeds
+    fn spawn_mock_server(path: &std::path::Path) -> Arc<Mutex<Vec<serde_json::Value>>> {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let listener = UnixListener::bind(path).unwrap();
+        let log = Arc::clone(&received);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let log = Arc::clone(&log);
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command: serde_json::Value = serde_json::from_str(&line).unwrap();
+                        let response = match command["command"].as_str().unwrap() {
+                            "block_ip" => serde_json::json!({ "status": "error", "error": "Invalid IP address" }),
+                            "waf_samples" => serde_json::json!({ "status": "ok", "data": { "count": 1, "samples": [{
+                                "rule_id": "SQL-001", "description": "SQL Injection", "method": "GET",
+                                "uri": "/search", "query_string": "q=union+select", "timestamp": 0
+                            }] } }),
+                            name => serde_json::json!({ "status": "ok", "data": { "message": format!("{} done", name) } }),
+                        };
+                        log.lock().push(command);
+                        writer.write_all(format!("{}\n", response).as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        received
+    }
+
+    fn connection(socket: PathBuf, config: Option<PathBuf>) -> ConnectionArgs {
+        ConnectionArgs { config, socket: Some(socket), timeout: 1 }
+    }
+
+    #[tokio::test]
+    async fn test_reload_sends_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("admin.sock");
+        let received = spawn_mock_server(&socket);
+
+        reload(ReloadArgs { connection: connection(socket.clone(), None) }).await.unwrap();
+        restart_workers(RestartWorkersArgs { connection: connection(socket.clone(), None) }).await.unwrap();
+        unblock_ip(IpArgs { ip: "192.0.2.1".to_string(), connection: connection(socket.clone(), None) }).await.unwrap();
+        drain_upstream(UpstreamArgs { name: "app-1".to_string(), connection: connection(socket.clone(), None) }).await.unwrap();
+        waf_samples(WafSamplesArgs { connection: connection(socket, None) }).await.unwrap();
+
+        let received = received.lock();
+        assert_eq!(received[0]["command"], "reload_config");
+        assert_eq!(received[1]["command"], "restart_workers");
+        assert_eq!(received[2]["command"], "unblock_ip");
+        assert_eq!(received[2]["ip"], "192.0.2.1");
+        assert_eq!(received[3]["command"], "drain_upstream");
+        assert_eq!(received[3]["name"], "app-1");
+        assert_eq!(received[4]["command"], "waf_samples");
+    }
+
+    #[tokio::test]
+    async fn test_server_error_is_surfaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("admin.sock");
+        spawn_mock_server(&socket);
+
+        let err = block_ip(IpArgs { ip: "nope".to_string(), connection: connection(socket, None) })
+            .await
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("Invalid IP address"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_config_is_not_reloaded() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("admin.sock");
+        let received = spawn_mock_server(&socket);
+
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(&config_path, format!(r#"
+[server]
+port = 8080
+
+[php]
+libphp_path = "/nonexistent/libphp.so"
+document_root = "{}"
+
+[logging]
+level = "info"
+
+[metrics]
+enable = false
+
+[security]
+allowlist = ["not-an-ip"]
+"#, dir.path().display())).unwrap();
+
+        let err = reload(ReloadArgs { connection: connection(socket, Some(config_path)) })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid security.allowlist entry"));
+        assert!(received.lock().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let args = RestartWorkersArgs { connection: connection(dir.path().join("missing.sock"), None) };
+        assert!(restart_workers(args).await.is_err());
+    }
+}
@@ -5,6 +5,7 @@ pub mod sandbox;
 pub mod compare;
 pub mod waf;
 pub mod monitor;
+pub mod control;
 
 pub use serve::ServeArgs;
 pub use bench::BenchArgs;
@@ -13,3 +14,4 @@ pub use sandbox::SandboxArgs;
 pub use compare::CompareArgs;
 pub use waf::WafArgs;
 pub use monitor::MonitorArgs;
+pub use control::{IpArgs, ReloadArgs, RestartWorkersArgs};
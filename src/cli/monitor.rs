@@ -1,12 +1,14 @@
 use anyhow::Result;
 use clap::Args;
-use crate::admin::api::AdminApi;
-use crate::metrics::MetricsCollector;
+use crate::admin::api::{AdminApi, ServerStatus};
+use crate::metrics::{MetricsCollector, MetricsSnapshot};
 use crate::monitor::MonitorCollector;
 use crate::tui;
 use crate::tui::client::TuiClient;
+use serde::Serialize;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
 
 #[derive(Args, Debug)]
 pub struct MonitorArgs {
@@ -18,16 +20,108 @@ pub struct MonitorArgs {
     #[arg(short, long, default_value = "1")]
     refresh: u64,
 
+    /// Refresh interval in milliseconds (only for TUI mode, overrides --refresh)
+    #[arg(long)]
+    refresh_ms: Option<u64>,
+
     /// Unix socket path to connect to running server
     #[arg(short, long)]
     socket: Option<String>,
+
+    /// Fetch one status+metrics snapshot as JSON and exit
+    /// (exit code 0: healthy, 1: unhealthy, 2: unreachable)
+    #[arg(long)]
+    json: bool,
+
+    /// Timeout in seconds for each admin socket operation
+    #[arg(long, default_value = "5")]
+    timeout: u64,
+}
+
+/// Result of a one-shot `--json` check, printed to stdout
+#[derive(Debug, Serialize)]
+pub struct OneShotReport {
+    pub reachable: bool,
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<ServerStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<MetricsSnapshot>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl OneShotReport {
+    fn unreachable(error: anyhow::Error) -> Self {
+        Self {
+            reachable: false,
+            healthy: false,
+            health: None,
+            status: None,
+            metrics: None,
+            error: Some(error.to_string()),
+        }
+    }
+
+    /// Process exit code for health-gating scripts
+    pub fn exit_code(&self) -> i32 {
+        match (self.reachable, self.healthy) {
+            (true, true) => 0,
+            (true, false) => 1,
+            (false, _) => 2,
+        }
+    }
+}
+
+/// Fetch health, status and metrics once from a running server
+pub async fn one_shot(client: &TuiClient) -> OneShotReport {
+    let health = match client.health_check().await {
+        Ok(health) => health,
+        Err(e) => return OneShotReport::unreachable(e),
+    };
+    let healthy = health.get("status").and_then(|s| s.as_str()) == Some("healthy");
+
+    let (status, metrics) = tokio::join!(client.get_status(), client.get_metrics_snapshot());
+    let error = status.as_ref().err().map(|e| e.to_string());
+
+    OneShotReport {
+        reachable: true,
+        healthy: healthy && status.is_ok(),
+        health: Some(health),
+        status: status.ok(),
+        // Older servers may not support metrics snapshots; omit rather than fail
+        metrics: metrics.ok(),
+        error,
+    }
 }
 
 pub async fn run(args: MonitorArgs) -> Result<()> {
+    if args.json {
+        let socket_path = args
+            .socket
+            .map(PathBuf::from)
+            .unwrap_or_else(|| crate::config::AdminConfig::default().unix_socket);
+        let client = TuiClient::new(socket_path).with_timeout(Duration::from_secs(args.timeout));
+
+        let report = one_shot(&client).await;
+        println!("{}", serde_json::to_string_pretty(&report)?);
+
+        let code = report.exit_code();
+        if code != 0 {
+            std::process::exit(code);
+        }
+        return Ok(());
+    }
+
     // Create monitor collector based on whether socket path is provided
     let (monitor, client) = if let Some(socket_path) = args.socket {
         // Remote mode: Connect to Unix socket
-        let client = Arc::new(TuiClient::new(PathBuf::from(socket_path)));
+        let client = Arc::new(
+            TuiClient::new(PathBuf::from(socket_path))
+                .with_timeout(Duration::from_secs(args.timeout)),
+        );
         let monitor = MonitorCollector::new_remote(client.clone());
         (monitor, Some(client))
     } else {
@@ -46,6 +140,8 @@ pub async fn run(args: MonitorArgs) -> Result<()> {
             } else {
                 tui::app::App::new(monitor)
             };
+            let refresh_ms = args.refresh_ms.unwrap_or(args.refresh.saturating_mul(1000));
+            let app = app.with_refresh_interval(Duration::from_millis(refresh_ms));
             tui::run_tui(app).await?;
         }
         "json" => {
@@ -95,3 +191,95 @@ fn print_text_status(snapshot: &crate::monitor::collector::MonitorSnapshot) {
         println!("    Avg Response Time: {:.2}ms", stats.avg_response_ms);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::UnixListener;
+
+    /// Answer admin commands with canned responses, reporting the given health
+    fn spawn_mock_server(path: &std::path::Path, health: &'static str) {
+        let listener = UnixListener::bind(path).unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    let mut lines = BufReader::new(reader).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let command: serde_json::Value = serde_json::from_str(&line).unwrap();
+                        let data = match command["command"].as_str().unwrap() {
+                            "health" => serde_json::json!({
+                                "status": health,
+                                "uptime_seconds": 42,
+                                "version": "test",
+                            }),
+                            "status" => serde_json::json!({
+                                "uptime_seconds": 42,
+                                "active_connections": 1,
+                                "total_requests": 7,
+                                "workers": [],
+                                "backends": {},
+                            }),
+                            "metrics_snapshot" => serde_json::json!({
+                                "timestamp": 0,
+                                "uptime_seconds": 42,
+                                "window_seconds": 42.0,
+                                "total_requests": 7,
+                                "active_connections": 1,
+                                "backends": {},
+                            }),
+                            _ => serde_json::Value::Null,
+                        };
+                        let response = serde_json::json!({ "status": "ok", "data": data });
+                        writer.write_all(format!("{}\n", response).as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_one_shot_healthy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("admin.sock");
+        spawn_mock_server(&path, "healthy");
+
+        let client = TuiClient::new(path).with_timeout(Duration::from_secs(1));
+        let report = one_shot(&client).await;
+        assert_eq!(report.exit_code(), 0);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["healthy"], true);
+        assert_eq!(json["status"]["total_requests"], 7);
+        assert_eq!(json["metrics"]["total_requests"], 7);
+        assert!(json.get("error").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_one_shot_unhealthy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("admin.sock");
+        spawn_mock_server(&path, "unhealthy");
+
+        let client = TuiClient::new(path).with_timeout(Duration::from_secs(1));
+        let report = one_shot(&client).await;
+        assert!(report.reachable);
+        assert_eq!(report.exit_code(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_one_shot_unreachable() {
+        let dir = tempfile::tempdir().unwrap();
+        let client = TuiClient::new(dir.path().join("missing.sock"))
+            .with_timeout(Duration::from_secs(1));
+
+        let report = one_shot(&client).await;
+        assert_eq!(report.exit_code(), 2);
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["reachable"], false);
+        assert!(json["error"].as_str().unwrap().contains("Failed to connect"));
+    }
+}
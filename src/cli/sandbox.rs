@@ -1,9 +1,18 @@
-use clap::Args;
-use anyhow::Result;
-use std::path::PathBuf;
+use clap::{Args, Subcommand, ValueEnum};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use crate::backend::{embedded::EmbeddedBackend, fastcgi::FastCGIBackend, Backend};
+use crate::config::Config;
+use crate::php::{PhpConfig, PhpRequest, PhpResponse, WorkerPool, WorkerPoolConfig};
 
 #[derive(Args)]
 pub struct SandboxArgs {
+    #[command(subcommand)]
+    pub command: Option<SandboxCommand>,
+
     #[arg(short, long)]
     pub config: PathBuf,
 
@@ -14,7 +23,123 @@ pub struct SandboxArgs {
     pub log_file: Option<PathBuf>,
 }
 
+#[derive(Subcommand)]
+pub enum SandboxCommand {
+    /// Send one captured request through a PHP backend and print the response
+    Replay(ReplayArgs),
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// JSON file with `method`, `uri`, `headers` and `body`
+    pub request: PathBuf,
+
+    /// Backend to execute on; `fastcgi` when `php.use_fpm` is set, otherwise `embedded`
+    #[arg(short, long)]
+    pub backend: Option<ReplayBackend>,
+
+    /// Fail unless the response has this status
+    #[arg(long)]
+    pub expect_status: Option<u16>,
+
+    /// Fail unless the response body contains this text
+    #[arg(long)]
+    pub expect_contains: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReplayBackend {
+    Embedded,
+    Fastcgi,
+}
+
+/// Request captured to a file for replay
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "CapturedRequestFile")]
+pub struct CapturedRequest {
+    pub method: String,
+    /// Request target including any query string
+    pub uri: String,
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+    pub remote_addr: String,
+}
+
+/// On-disk form of [`CapturedRequest`]: a text body in `body`, or any bytes
+/// base64-encoded in `body_base64`
+#[derive(Deserialize)]
+struct CapturedRequestFile {
+    #[serde(default = "default_method")]
+    method: String,
+    uri: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    #[serde(default)]
+    body: Option<String>,
+    #[serde(default)]
+    body_base64: Option<String>,
+    #[serde(default = "default_remote_addr")]
+    remote_addr: String,
+}
+
+impl TryFrom<CapturedRequestFile> for CapturedRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(file: CapturedRequestFile) -> Result<Self> {
+        use base64::Engine;
+
+        let body = match (file.body, file.body_base64) {
+            (Some(_), Some(_)) => bail!("Set either body or body_base64, not both"),
+            (Some(text), None) => text.into_bytes(),
+            (None, Some(encoded)) => base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .context("Invalid body_base64")?,
+            (None, None) => Vec::new(),
+        };
+        Ok(Self {
+            method: file.method,
+            uri: file.uri,
+            headers: file.headers,
+            body,
+            remote_addr: file.remote_addr,
+        })
+    }
+}
+
+fn default_method() -> String {
+    "GET".to_string()
+}
+
+fn default_remote_addr() -> String {
+    "127.0.0.1:0".to_string()
+}
+
+impl CapturedRequest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read captured request: {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Invalid captured request: {}", path.display()))
+    }
+
+    pub fn into_php_request(self) -> PhpRequest {
+        let query_string = self.uri.split_once('?').map(|(_, q)| q.to_string()).unwrap_or_default();
+        PhpRequest {
+            method: self.method.to_ascii_uppercase(),
+            uri: self.uri,
+            headers: self.headers,
+            body: self.body,
+            query_string,
+            remote_addr: self.remote_addr,
+        }
+    }
+}
+
 pub async fn run(args: SandboxArgs) -> Result<()> {
+    if let Some(SandboxCommand::Replay(replay_args)) = args.command {
+        return replay(&args.config, replay_args).await;
+    }
+
     println!("=== Sandbox Test ===");
     println!("Config: {}", args.config.display());
     println!("Duration: {}s", args.duration);
@@ -36,3 +161,195 @@ pub async fn run(args: SandboxArgs) -> Result<()> {
 
     Ok(())
 }
+
+async fn replay(config_path: &Path, args: ReplayArgs) -> Result<()> {
+    let config = Config::from_file(config_path)?;
+    let request = CapturedRequest::load(&args.request)?;
+    let kind = args.backend.unwrap_or(if config.php.use_fpm {
+        ReplayBackend::Fastcgi
+    } else {
+        ReplayBackend::Embedded
+    });
+
+    println!("=== Sandbox Replay ===");
+    println!("Request: {} {}", request.method, request.uri);
+    println!("Backend: {:?}", kind);
+    println!();
+
+    let response = execute(&config, kind, request).await?;
+
+    print_response(&response);
+    check_expectations(&response, args.expect_status, args.expect_contains.as_deref())
+}
+
+/// Run `request` once on the `kind` backend built from `config`
+async fn execute(config: &Config, kind: ReplayBackend, request: CapturedRequest) -> Result<PhpResponse> {
+    let backend = build_backend(config, kind)?;
+    let response = tokio::task::spawn_blocking(move || backend.execute(request.into_php_request()))
+        .await
+        .context("Replay task failed")?
+        .context("Backend execution failed")?;
+    Ok(response)
+}
+
+fn build_backend(config: &Config, kind: ReplayBackend) -> Result<Arc<dyn Backend>> {
+    match kind {
+        ReplayBackend::Fastcgi => {
+            if config.php.fpm_socket.is_empty() {
+                bail!("FastCGI replay requires php.fpm_socket");
+            }
+            Ok(Arc::new(
+                FastCGIBackend::new(config.php.fpm_socket.clone(), config.fastcgi_document_root().to_path_buf())
+                    .with_read_timeout(std::time::Duration::from_secs(config.php.fpm_read_timeout_secs))
+                    .with_request_id_param(config.php.request_id_param())
+                    .with_expose_errors(config.php.expose_fpm_errors)
+                    .with_content_type(config.php.content_type.clone()),
+            ))
+        }
+        ReplayBackend::Embedded => {
+            let php_config = PhpConfig {
+                libphp_path: config.php.libphp_path.clone(),
+                document_root: config.embedded_document_root().to_path_buf(),
+                worker_pool_size: 1,
+                worker_max_requests: config.php.worker_max_requests,
+                use_fpm: false,
+                fpm_socket: String::new(),
+                request_id_param: None,
+                expose_fpm_errors: false,
+                content_type: config.php.content_type.clone(),
+            };
+            let pool_config = WorkerPoolConfig {
+                pool_size: 1,
+                max_requests: config.php.worker_max_requests,
+                affinity: false,
+            };
+            let worker_pool = WorkerPool::new(php_config, pool_config)?;
+            Ok(Arc::new(EmbeddedBackend::new(Arc::new(worker_pool))))
+        }
+    }
+}
+
+fn print_response(response: &PhpResponse) {
+    println!("Status: {}", response.status_code);
+    for (name, value) in response.headers.iter() {
+        println!("{}: {}", name, value);
+    }
+    println!();
+    println!("{}", String::from_utf8_lossy(&response.body));
+    println!();
+    println!("Execution time: {}ms", response.execution_time_ms);
+}
+
+/// Compare the replayed response with `--expect-status` / `--expect-contains`
+fn check_expectations(response: &PhpResponse, status: Option<u16>, contains: Option<&str>) -> Result<()> {
+    if let Some(expected) = status.filter(|&s| s != response.status_code) {
+        bail!("Expected status {}, got {}", expected, response.status_code);
+    }
+    if let Some(expected) = contains {
+        if !String::from_utf8_lossy(&response.body).contains(expected) {
+            bail!("Response body does not contain {:?}", expected);
+        }
+    }
+    if status.is_some() || contains.is_some() {
+        println!("[OK] Response matches expectations");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::php::fastcgi::mock::{self, MockFpm, Reply};
+
+    fn fastcgi_config(root: &Path, fpm_socket: &str) -> Config {
+        toml::from_str(&format!(
+            r#"
+[server]
+host = "127.0.0.1"
+port = 0
+
+[php]
+libphp_path = "/nonexistent/libphp.so"
+document_root = "{root}"
+use_fpm = true
+fpm_socket = "{fpm_socket}"
+
+[logging]
+level = "info"
+
+[metrics]
+enable = false
+"#,
+            root = root.display(),
+        ))
+        .unwrap()
+    }
+
+    /// PHP-FPM stand-in for a script that echoes the request back
+    async fn echo_fpm() -> String {
+        let fpm = MockFpm::new(|_, request| {
+            let param = |name: &str| request.params.get(name).cloned().unwrap_or_default();
+            let status = if param("REQUEST_METHOD") == "POST" { 201 } else { 200 };
+            let head = format!(
+                "Status: {}\r\nContent-Type: application/octet-stream\r\n\r\n{} {} query={} cookie={} body=",
+                status,
+                param("REQUEST_METHOD"),
+                param("REQUEST_URI"),
+                param("QUERY_STRING"),
+                param("HTTP_COOKIE"),
+            );
+            Reply::Send(mock::response(&[head.as_bytes(), &request.stdin].concat()))
+        });
+        fpm.start().await.addr
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replay_captured_request() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("cart.php"), "<?php").unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php").unwrap();
+        let config = fastcgi_config(dir.path(), &echo_fpm().await);
+        let path = dir.path().join("request.json");
+        std::fs::write(
+            &path,
+            r#"{"method":"post","uri":"/cart.php?item=42","headers":{"Cookie":"sid=abc"},"body":"qty=2"}"#,
+        )
+        .unwrap();
+
+        let request = CapturedRequest::load(&path).unwrap();
+        let response = execute(&config, ReplayBackend::Fastcgi, request).await.unwrap();
+
+        assert_eq!(
+            String::from_utf8_lossy(&response.body),
+            "POST /cart.php?item=42 query=item=42 cookie=sid=abc body=qty=2"
+        );
+        check_expectations(&response, Some(201), Some("qty=2")).unwrap();
+        assert!(check_expectations(&response, Some(200), None).is_err());
+        assert!(check_expectations(&response, None, Some("qty=3")).is_err());
+
+        // Everything but the target is optional
+        std::fs::write(&path, r#"{"uri":"/index.php"}"#).unwrap();
+        let request = CapturedRequest::load(&path).unwrap().into_php_request();
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.query_string, "");
+        assert!(request.body.is_empty());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_replay_binary_body() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("upload.php"), "<?php").unwrap();
+        let config = fastcgi_config(dir.path(), &echo_fpm().await);
+        let path = dir.path().join("request.json");
+        // 0x00 0xff 0xfe 0x80 'x', which is not UTF-8
+        std::fs::write(&path, r#"{"method":"PUT","uri":"/upload.php","body_base64":"AP/+gHg="}"#).unwrap();
+
+        let request = CapturedRequest::load(&path).unwrap();
+        assert_eq!(request.body, [0x00, 0xff, 0xfe, 0x80, b'x']);
+        let response = execute(&config, ReplayBackend::Fastcgi, request).await.unwrap();
+        assert!(response.body.ends_with(b"body=\x00\xff\xfe\x80x"));
+
+        std::fs::write(&path, r#"{"uri":"/upload.php","body":"a","body_base64":"YQ=="}"#).unwrap();
+        assert!(CapturedRequest::load(&path).is_err());
+    }
+}
@@ -1,26 +1,44 @@
 use clap::Args;
-use anyhow::Result;
+use anyhow::{Context, Result};
 use crate::{Config, Server};
 use crate::server::config_reload::ConfigReloadManager;
 use crate::admin::api::AdminCommand;
-use std::path::PathBuf;
+use crate::server::compression::CompressionConfig;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::mpsc;
-use tracing::{info, error};
+use tracing::{info, warn, error};
 
 #[derive(Args)]
 pub struct ServeArgs {
     #[arg(short, long, default_value = "fe-php.toml")]
     pub config: PathBuf,
+
+    /// Override file deep-merged over --config; repeat to apply several in order
+    #[arg(long = "override", value_name = "FILE")]
+    pub overrides: Vec<PathBuf>,
+
+    /// Run all startup initialization (TLS, GeoIP, libphp, backends), report the result and exit
+    #[arg(long)]
+    pub check: bool,
 }
 
 pub async fn run(args: ServeArgs) -> Result<()> {
-    let config = Config::from_file(&args.config)?;
+    if args.check {
+        return check(&args.config, &args.overrides).await;
+    }
+
+    let config = Config::from_files(&args.config, &args.overrides)?;
 
     crate::logging::init_logging(&config.logging.level, &config.logging.format)?;
 
     info!("Starting fe-php server v{}", crate::VERSION);
     info!("Loading configuration from: {}", args.config.display());
+    for path in &args.overrides {
+        info!("Applying configuration override: {}", path.display());
+    }
 
     let warnings = config.validate()?;
     for warning in warnings {
@@ -32,15 +50,18 @@ pub async fn run(args: ServeArgs) -> Result<()> {
     crate::metrics::init_metrics();
 
     // Create config reload manager
-    let config_reload_manager = Arc::new(ConfigReloadManager::new(
-        args.config.clone(),
-        config.clone(),
-    ));
+    let config_reload_manager = Arc::new(
+        ConfigReloadManager::new(args.config.clone(), config.clone())
+            .with_overrides(args.overrides.clone()),
+    );
 
     // Create server first to get metrics collector and ip blocker
     let mut server = Server::new(config.clone()).await?;
     let metrics_collector = server.metrics_collector();
     let ip_blocker = server.ip_blocker();
+    let worker_pool = server.worker_pool();
+    let maintenance = server.maintenance();
+    let load_balancer = server.load_balancer();
 
     // Create admin command channel
     let (admin_tx, mut admin_rx) = mpsc::unbounded_channel::<AdminCommand>();
@@ -51,17 +72,33 @@ pub async fn run(args: ServeArgs) -> Result<()> {
     tokio::spawn(async move {
         while let Some(command) = admin_rx.recv().await {
             match command {
-                AdminCommand::ReloadConfig => {
+                AdminCommand::ReloadConfig(reply) => {
                     info!("Received config reload request");
-                    if let Err(e) = reload_manager.reload() {
-                        error!("Failed to reload configuration: {}", e);
-                    } else {
-                        info!("Configuration reloaded successfully");
-                    }
+                    let result = match reload_manager.reload() {
+                        Ok(()) => {
+                            if reload_manager.config().read().php.opcache.reset_on_reload {
+                                worker_pool.reset_opcache();
+                            }
+                            Ok(reload_manager.config_path().display().to_string())
+                        }
+                        Err(e) => {
+                            error!("Failed to reload configuration: {:#}", e);
+                            Err(format!("{:#}", e))
+                        }
+                    };
+                    let _ = reply.send(result);
                 }
                 AdminCommand::RestartWorkers => {
-                    info!("Received worker restart request (not yet implemented)");
-                    // TODO: Implement worker restart
+                    info!("Received worker restart request");
+                    worker_pool.restart_workers();
+                }
+                AdminCommand::ResetOpcache => {
+                    info!("Received OPcache reset request");
+                    worker_pool.reset_opcache();
+                }
+                AdminCommand::SetMaintenance { enabled, retry_after } => {
+                    warn!("Maintenance mode {}", if enabled { "enabled" } else { "disabled" });
+                    maintenance.set(enabled, retry_after);
                 }
                 AdminCommand::BlockIp(ip) => {
                     info!("Received request to block IP: {}", ip);
@@ -93,8 +130,16 @@ pub async fn run(args: ServeArgs) -> Result<()> {
         let metrics_port = config.metrics.port;
         let metrics_endpoint = config.metrics.endpoint.clone();
         let metrics_for_server = metrics_collector.clone();
+        let metrics_compression = config.metrics.compression;
+        let server_header = config.server.server_header.clone();
         tokio::spawn(async move {
-            if let Err(e) = start_metrics_server(metrics_port, &metrics_endpoint, metrics_for_server).await {
+            if let Err(e) = start_metrics_server(
+                metrics_port,
+                &metrics_endpoint,
+                metrics_for_server,
+                metrics_compression,
+                server_header,
+            ).await {
                 tracing::error!("Metrics server error: {}", e);
             }
         });
@@ -109,15 +154,17 @@ pub async fn run(args: ServeArgs) -> Result<()> {
             admin_tx.clone(),
             ip_blocker.clone(),
             worker_pool_size,
-        ));
+        ).with_waf_engine(server.waf_engine())
+        .with_load_balancer(load_balancer.clone()));
 
         // Start HTTP JSON API (optional, for external tools)
         let admin_host = config.admin.host.clone();
         let admin_port = config.admin.http_port;
         let metrics_for_admin = metrics_collector.clone();
+        let admin_compression = config.admin.compression;
         tokio::spawn(async move {
             let addr = format!("{}:{}", admin_host, admin_port);
-            if let Err(e) = crate::admin::serve_json_api(&addr, metrics_for_admin).await {
+            if let Err(e) = crate::admin::serve_json_api(&addr, metrics_for_admin, admin_compression).await {
                 error!("Admin JSON API server error: {}", e);
             }
         });
@@ -145,50 +192,71 @@ pub async fn run(args: ServeArgs) -> Result<()> {
     Ok(())
 }
 
-async fn start_metrics_server(port: u16, endpoint: &str, metrics_collector: Arc<crate::metrics::MetricsCollector>) -> Result<()> {
+/// Dry run for `serve --check`: fails on validation errors or any startup failure
+pub async fn check(config_path: &Path, overrides: &[PathBuf]) -> Result<()> {
+    println!("Checking configuration: {}", config_path.display());
+
+    let config = Config::from_files(config_path, overrides)?;
+
+    let warnings = config.validate()?;
+    for warning in &warnings {
+        println!("{}", warning);
+    }
+    let errors: Vec<&String> = warnings.iter().filter(|w| w.starts_with("[X]")).collect();
+    if !errors.is_empty() {
+        anyhow::bail!(
+            "Configuration has {} error(s):\n{}",
+            errors.len(),
+            errors.iter().map(|e| e.as_str()).collect::<Vec<_>>().join("\n")
+        );
+    }
+
+    let server = Server::new(config).await.context("Startup initialization failed")?;
+    drop(server);
+
+    println!(" Startup check passed: {}", config_path.display());
+    Ok(())
+}
+
+async fn start_metrics_server(
+    port: u16,
+    endpoint: &str,
+    metrics_collector: Arc<crate::metrics::MetricsCollector>,
+    compression: bool,
+    server_header: String,
+) -> Result<()> {
     use hyper::service::service_fn;
-    use hyper::{Request, Response, body::{Incoming, Bytes}};
+    use hyper::{Request, body::Incoming};
     use hyper::server::conn::http1;
     use hyper_util::rt::TokioIo;
     use tokio::net::TcpListener;
-    use http_body_util::Full;
-    use prometheus::Encoder;
 
     let addr = format!("0.0.0.0:{}", port);
     let listener = TcpListener::bind(&addr).await?;
     let endpoint_path = endpoint.to_string();
+    let compression = compression.then(|| Arc::new(CompressionConfig::gzip_only()));
 
     loop {
         let (stream, _) = listener.accept().await?;
         let io = TokioIo::new(stream);
         let endpoint_path = endpoint_path.clone();
         let metrics_collector = Arc::clone(&metrics_collector);
+        let compression = compression.clone();
+        let server_header = server_header.clone();
 
         tokio::spawn(async move {
             let service = service_fn(move |req: Request<Incoming>| {
-                let endpoint_path = endpoint_path.clone();
-                let metrics_collector = Arc::clone(&metrics_collector);
-                async move {
-                    if req.uri().path() == endpoint_path {
-                        // Use MetricsCollector's registry instead of global registry
-                        let encoder = prometheus::TextEncoder::new();
-                        let metric_families = metrics_collector.registry().gather();
-                        let mut buffer = Vec::new();
-
-                        match encoder.encode(&metric_families, &mut buffer) {
-                            Ok(_) => Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(buffer)))),
-                            Err(_) => Ok(Response::builder()
-                                .status(500)
-                                .body(Full::new(Bytes::from("Error exporting metrics")))
-                                .unwrap()),
-                        }
-                    } else {
-                        Ok(Response::builder()
-                            .status(404)
-                            .body(Full::new(Bytes::from("Not Found")))
-                            .unwrap())
-                    }
-                }
+                let mut response = metrics_response(
+                    &req,
+                    &endpoint_path,
+                    &metrics_collector,
+                    compression.as_deref(),
+                );
+                crate::server::headers::apply_standard_headers(
+                    response.headers_mut(),
+                    Some(server_header.as_str()).filter(|s| !s.is_empty()),
+                );
+                async move { Ok::<_, hyper::Error>(response) }
             });
 
             if let Err(e) = http1::Builder::new().serve_connection(io, service).await {
@@ -197,3 +265,190 @@ async fn start_metrics_server(port: u16, endpoint: &str, metrics_collector: Arc<
         });
     }
 }
+
+/// Render the metrics endpoint, gzip-encoding it for clients that accept it
+fn metrics_response<B>(
+    req: &hyper::Request<B>,
+    endpoint: &str,
+    metrics_collector: &crate::metrics::MetricsCollector,
+    compression: Option<&CompressionConfig>,
+) -> hyper::Response<Full<Bytes>> {
+    use hyper::Response;
+
+    if req.uri().path() != endpoint {
+        return Response::builder()
+            .status(404)
+            .body(Full::new(Bytes::from("Not Found")))
+            .unwrap();
+    }
+
+    // Use MetricsCollector's registry instead of global registry
+    let accept = req.headers().get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok());
+    let (body, content_type) = match crate::metrics::export_registry(&metrics_collector.registry(), accept) {
+        Ok(exported) => exported,
+        Err(_) => {
+            return Response::builder()
+                .status(500)
+                .body(Full::new(Bytes::from("Error exporting metrics")))
+                .unwrap();
+        }
+    };
+    let buffer = body.into_bytes();
+
+    let response = Response::builder()
+        .header(hyper::header::CONTENT_TYPE, content_type)
+        .body(())
+        .unwrap();
+
+    let response = match compression {
+        Some(compression) => match compression.compress_response(req, response, buffer) {
+            Ok(response) => response,
+            Err(e) => {
+                tracing::warn!("Failed to compress metrics response: {}", e);
+                return Response::builder()
+                    .status(500)
+                    .body(Full::new(Bytes::from("Error exporting metrics")))
+                    .unwrap();
+            }
+        },
+        None => response.map(|_| buffer),
+    };
+
+    response.map(|body| Full::new(Bytes::from(body)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn metrics_request(accept_encoding: Option<&str>) -> hyper::Request<()> {
+        let mut builder = hyper::Request::builder().uri("/_metrics");
+        if let Some(value) = accept_encoding {
+            builder = builder.header(hyper::header::ACCEPT_ENCODING, value);
+        }
+        builder.body(()).unwrap()
+    }
+
+    async fn body_bytes(response: hyper::Response<Full<Bytes>>) -> Vec<u8> {
+        use http_body_util::BodyExt;
+        response.into_body().collect().await.unwrap().to_bytes().to_vec()
+    }
+
+    fn write_config(dir: &Path, extra: &str) -> PathBuf {
+        let path = dir.join("fe-php.toml");
+        std::fs::write(&path, format!(r#"
+[server]
+host = "127.0.0.1"
+port = 8080
+
+[php]
+libphp_path = "/nonexistent/libphp.so"
+document_root = "{root}"
+use_fpm = true
+fpm_socket = ""
+
+[logging]
+level = "info"
+
+[metrics]
+enable = false
+
+[backend]
+enable_hybrid = true
+default_backend = "static"
+
+[backend.static_files]
+enable = true
+root = "{root}"
+{extra}
+"#, root = dir.display())).unwrap();
+        path
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_check_good_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_config(dir.path(), "");
+        check(&path, &[]).await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_check_reports_startup_errors() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Caught by validation
+        let path = write_config(dir.path(), "\n[security]\nallowlist = [\"not-an-ip\"]\n");
+        let err = check(&path, &[]).await.unwrap_err().to_string();
+        assert!(err.contains("Invalid security.allowlist entry: not-an-ip"), "{}", err);
+
+        // Only caught when TLS is actually initialized
+        let missing = dir.path().join("missing.pem");
+        let path = write_config(dir.path(), &format!(
+            "\n[tls]\nenable = true\ncert_path = \"{0}\"\nkey_path = \"{0}\"\n",
+            missing.display()
+        ));
+        let err = format!("{:#}", check(&path, &[]).await.unwrap_err());
+        assert!(err.contains("Failed to initialize TLS"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_gzip_negotiation() {
+        let metrics = crate::metrics::MetricsCollector::new();
+        for status in [200, 404, 500] {
+            metrics.record_request("GET", status, 0.01);
+        }
+        let compression = CompressionConfig::gzip_only();
+
+        // gzip-accepting client gets an encoded payload
+        let response = metrics_response(
+            &metrics_request(Some("gzip, deflate")),
+            "/_metrics",
+            &metrics,
+            Some(&compression),
+        );
+        assert_eq!(response.headers()[hyper::header::CONTENT_ENCODING], "gzip");
+        let mut decoded = String::new();
+        GzDecoder::new(body_bytes(response).await.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert!(decoded.contains("http_requests_total"));
+
+        // Other clients get plain text
+        let response = metrics_response(&metrics_request(None), "/_metrics", &metrics, Some(&compression));
+        assert!(response.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+        let plain = String::from_utf8(body_bytes(response).await).unwrap();
+        assert!(plain.contains("http_requests_total"));
+
+        // Compression can be disabled entirely
+        let response = metrics_response(&metrics_request(Some("gzip")), "/_metrics", &metrics, None);
+        assert!(response.headers().get(hyper::header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_openmetrics_negotiation() {
+        let metrics = crate::metrics::MetricsCollector::new();
+        metrics.record_request("GET", 200, 0.01);
+
+        let mut request = metrics_request(None);
+        request.headers_mut().insert(
+            hyper::header::ACCEPT,
+            hyper::header::HeaderValue::from_static("application/openmetrics-text; version=1.0.0"),
+        );
+        let response = metrics_response(&request, "/_metrics", &metrics, None);
+        assert_eq!(
+            response.headers()[hyper::header::CONTENT_TYPE],
+            crate::metrics::OPENMETRICS_CONTENT_TYPE
+        );
+        let body = String::from_utf8(body_bytes(response).await).unwrap();
+        assert!(body.ends_with("# EOF\n"), "{}", body);
+        assert!(body.contains("# TYPE http_requests counter"));
+
+        // Scrapers without OpenMetrics support keep the classic format
+        let response = metrics_response(&metrics_request(None), "/_metrics", &metrics, None);
+        assert_eq!(response.headers()[hyper::header::CONTENT_TYPE], prometheus::TEXT_FORMAT);
+        let body = String::from_utf8(body_bytes(response).await).unwrap();
+        assert!(!body.contains("# EOF"));
+    }
+}
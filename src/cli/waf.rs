@@ -1,6 +1,7 @@
 use clap::{Args, Subcommand};
 use anyhow::Result;
 use std::path::PathBuf;
+use super::control::WafSamplesArgs;
 
 #[derive(Args)]
 pub struct WafArgs {
@@ -31,6 +32,9 @@ pub enum WafCommand {
         #[arg(short, long, default_value = "waf_rules.toml")]
         output: PathBuf,
     },
+
+    /// Show requests a running server in learn mode would have blocked
+    Samples(WafSamplesArgs),
 }
 
 pub async fn run(args: WafArgs) -> Result<()> {
@@ -90,5 +94,7 @@ pub async fn run(args: WafArgs) -> Result<()> {
 
             Ok(())
         }
+
+        WafCommand::Samples(args) => super::control::waf_samples(args).await,
     }
 }
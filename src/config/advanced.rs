@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 use super::defaults::*;
-use super::types::{LoadBalancingAlgorithm, DeploymentStrategy};
+use super::types::{LoadBalancingAlgorithm, DeploymentStrategy, SessionDecodeErrorPolicy, StickyMode};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RedisConfig {
@@ -8,12 +8,38 @@ pub struct RedisConfig {
     pub enable: bool,
     #[serde(default = "default_redis_url")]
     pub url: String,
+    /// ACL username; overrides one embedded in `url`
+    #[serde(default)]
+    pub username: Option<String>,
+    /// AUTH password; overrides one embedded in `url`
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Connections session operations are spread over
     #[serde(default = "default_redis_pool_size")]
     pub pool_size: u32,
+    /// Connection timeout
     #[serde(default = "default_redis_timeout")]
     pub timeout_ms: u64,
+    /// TTL of sessions stored without one of their own
+    #[serde(default = "default_redis_session_ttl")]
+    pub session_ttl_secs: u64,
     #[serde(default = "default_redis_prefix")]
     pub key_prefix: String,
+    /// Handling of stored sessions that fail to deserialize
+    #[serde(default)]
+    pub on_deserialize_error: SessionDecodeErrorPolicy,
+    /// Seconds between keepalive pings that detect dead connections (0 disables)
+    #[serde(default = "default_redis_keepalive_interval")]
+    pub keepalive_interval_secs: u64,
+    /// Reconnect attempts before an operation fails
+    #[serde(default = "default_redis_reconnect_retries")]
+    pub reconnect_retries: usize,
+    /// Backoff factor: reconnect attempt `n` waits up to `reconnect_backoff_ms * 2^n`
+    #[serde(default = "default_redis_reconnect_backoff")]
+    pub reconnect_backoff_ms: u64,
+    /// `COUNT` hint for the `SCAN` calls that list and clear sessions
+    #[serde(default = "default_redis_scan_count")]
+    pub scan_count: usize,
 }
 
 impl Default for RedisConfig {
@@ -21,9 +47,17 @@ impl Default for RedisConfig {
         Self {
             enable: false,
             url: default_redis_url(),
+            username: None,
+            password: None,
             pool_size: default_redis_pool_size(),
             timeout_ms: default_redis_timeout(),
+            session_ttl_secs: default_redis_session_ttl(),
             key_prefix: default_redis_prefix(),
+            on_deserialize_error: SessionDecodeErrorPolicy::default(),
+            keepalive_interval_secs: default_redis_keepalive_interval(),
+            reconnect_retries: default_redis_reconnect_retries(),
+            reconnect_backoff_ms: default_redis_reconnect_backoff(),
+            scan_count: default_redis_scan_count(),
         }
     }
 }
@@ -63,6 +97,15 @@ pub struct LoadBalancingConfig {
     pub health_check: HealthCheckConfig,
     #[serde(default)]
     pub circuit_breaker: CircuitBreakerConfig,
+    /// Upstreams tried for one request before giving up; all of them when unset
+    #[serde(default)]
+    pub max_tries: Option<usize>,
+    /// Pin each client to one upstream; off when the block is absent
+    #[serde(default)]
+    pub sticky: Option<StickyConfig>,
+    /// Request header `consistent_hash` keys on; the request URI when unset
+    #[serde(default)]
+    pub hash_header: Option<String>,
 }
 
 impl Default for LoadBalancingConfig {
@@ -73,10 +116,21 @@ impl Default for LoadBalancingConfig {
             algorithm: LoadBalancingAlgorithm::default(),
             health_check: HealthCheckConfig::default(),
             circuit_breaker: CircuitBreakerConfig::default(),
+            max_tries: None,
+            sticky: None,
+            hash_header: None,
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StickyConfig {
+    pub mode: StickyMode,
+    /// Cookie naming the pinned upstream in `cookie` mode
+    #[serde(default = "default_sticky_cookie_name")]
+    pub cookie_name: String,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UpstreamConfig {
     pub name: String,
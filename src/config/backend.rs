@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use super::defaults::*;
-use super::types::PathPatternConfig;
+use super::types::{PageCacheStorage, PathPatternConfig};
 use super::advanced::CircuitBreakerConfig;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +16,25 @@ pub struct BackendConfig {
     pub static_files: StaticFilesConfig,
     #[serde(default)]
     pub connection_pool: ConnectionPoolConfig,
+    #[serde(default)]
+    pub embedded: BackendRootConfig,
+    #[serde(default)]
+    pub fastcgi: FastCgiBackendConfig,
+    #[serde(default)]
+    pub cache: PageCacheConfig,
+    /// Seconds between background health checks feeding `backend_up`; 0 disables them
+    #[serde(default = "default_backend_health_check_interval")]
+    pub health_check_interval_secs: u64,
+    /// Stop routing to a backend whose last health check failed; off by default
+    /// because a single failed check would turn the backend's traffic into 503s
+    #[serde(default)]
+    pub skip_unhealthy: bool,
+    /// Backend that takes over for an unhealthy one; such requests get 503 when unset
+    #[serde(default)]
+    pub unhealthy_fallback: Option<String>,
+    /// Latency objective in milliseconds for requests no routing rule matched
+    #[serde(default)]
+    pub default_slo_ms: Option<u64>,
 }
 
 impl Default for BackendConfig {
@@ -26,6 +45,94 @@ impl Default for BackendConfig {
             routing_rules: Vec::new(),
             static_files: StaticFilesConfig::default(),
             connection_pool: ConnectionPoolConfig::default(),
+            embedded: BackendRootConfig::default(),
+            fastcgi: FastCgiBackendConfig::default(),
+            cache: PageCacheConfig::default(),
+            health_check_interval_secs: default_backend_health_check_interval(),
+            skip_unhealthy: false,
+            unhealthy_fallback: None,
+            default_slo_ms: None,
+        }
+    }
+}
+
+/// Per-backend overrides of `php.document_root`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackendRootConfig {
+    #[serde(default)]
+    pub document_root: Option<PathBuf>,
+}
+
+/// FastCGI backend settings: document root override and retries of transient PHP-FPM failures
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FastCgiBackendConfig {
+    #[serde(default)]
+    pub document_root: Option<PathBuf>,
+    /// Extra attempts after a connection failure or `FCGI_OVERLOADED`; `0` disables retries
+    #[serde(default = "default_fastcgi_max_retries")]
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further one
+    #[serde(default = "default_fastcgi_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    /// Also retry methods that are not idempotent (POST, PATCH)
+    #[serde(default)]
+    pub retry_non_idempotent: bool,
+}
+
+impl Default for FastCgiBackendConfig {
+    fn default() -> Self {
+        Self {
+            document_root: None,
+            max_retries: default_fastcgi_max_retries(),
+            retry_backoff_ms: default_fastcgi_retry_backoff_ms(),
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+/// `cache` backend: pages from `origin` stored and served with stale-while-revalidate.
+/// Routing rules choose which paths use it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PageCacheConfig {
+    #[serde(default)]
+    pub enable: bool,
+    /// Backend that renders the pages (`embedded` or `fastcgi`)
+    #[serde(default = "default_backend_type")]
+    pub origin: String,
+    /// Seconds a page is served without asking the origin
+    #[serde(default = "default_page_cache_ttl")]
+    pub ttl_secs: u64,
+    /// Further seconds an expired page is still served while it is refreshed in the background
+    #[serde(default = "default_page_cache_stale")]
+    pub stale_secs: u64,
+    #[serde(default)]
+    pub storage: PageCacheStorage,
+    /// Directory for `disk` storage
+    #[serde(default = "default_page_cache_dir")]
+    pub dir: PathBuf,
+    /// Redis for `redis` storage; `[redis].url` when unset
+    #[serde(default)]
+    pub redis_url: Option<String>,
+    #[serde(default = "default_page_cache_prefix")]
+    pub key_prefix: String,
+    /// Cookies that make a request bypass the cache (e.g. the session cookie);
+    /// when empty, any `Cookie` header does
+    #[serde(default)]
+    pub bypass_cookies: Vec<String>,
+}
+
+impl Default for PageCacheConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            origin: default_backend_type(),
+            ttl_secs: default_page_cache_ttl(),
+            stale_secs: default_page_cache_stale(),
+            storage: PageCacheStorage::default(),
+            dir: default_page_cache_dir(),
+            redis_url: None,
+            key_prefix: default_page_cache_prefix(),
+            bypass_cookies: Vec::new(),
         }
     }
 }
@@ -36,6 +143,12 @@ pub struct RoutingRule {
     pub backend: String,
     #[serde(default = "default_priority")]
     pub priority: u32,
+    /// Request body limit in bytes for matching paths (default: 10MB)
+    #[serde(default)]
+    pub max_body_size: Option<usize>,
+    /// Latency objective in milliseconds for matching requests, counted in `slo_breaches_total`
+    #[serde(default)]
+    pub slo_ms: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -45,6 +158,19 @@ pub struct StaticFilesConfig {
     pub root: Option<PathBuf>,
     #[serde(default = "default_index_files")]
     pub index_files: Vec<String>,
+    /// Sub-ranges accepted in one `Range` header; more are answered with 416.
+    /// `0` disables range requests entirely.
+    #[serde(default = "default_max_ranges")]
+    pub max_ranges: usize,
+    /// Paths served without `Accept-Ranges` whose `Range` headers are ignored
+    #[serde(default)]
+    pub range_disabled_patterns: Vec<PathPatternConfig>,
+    /// Resolved paths kept in the open-file cache; `0` disables it
+    #[serde(default)]
+    pub cache_entries: usize,
+    /// Largest file in bytes whose contents are kept in the cache
+    #[serde(default = "default_cache_max_file_size")]
+    pub cache_max_file_size: u64,
 }
 
 impl Default for StaticFilesConfig {
@@ -53,6 +179,10 @@ impl Default for StaticFilesConfig {
             enable: false,
             root: None,
             index_files: default_index_files(),
+            max_ranges: default_max_ranges(),
+            range_disabled_patterns: Vec::new(),
+            cache_entries: 0,
+            cache_max_file_size: default_cache_max_file_size(),
         }
     }
 }
@@ -67,6 +197,9 @@ pub struct ConnectionPoolConfig {
     pub max_lifetime_secs: u64,
     #[serde(default = "default_pool_connect_timeout")]
     pub connect_timeout_secs: u64,
+    /// Seconds between passes that evict stale connections and reopen idle ones
+    #[serde(default = "default_pool_maintenance_interval")]
+    pub maintenance_interval_secs: u64,
     #[serde(default)]
     pub enable_metrics: bool,
     #[serde(default)]
@@ -80,6 +213,7 @@ impl Default for ConnectionPoolConfig {
             max_idle_time_secs: default_pool_max_idle_time(),
             max_lifetime_secs: default_pool_max_lifetime(),
             connect_timeout_secs: default_pool_connect_timeout(),
+            maintenance_interval_secs: default_pool_maintenance_interval(),
             enable_metrics: true,
             circuit_breaker: CircuitBreakerConfig::default(),
         }
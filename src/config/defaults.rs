@@ -1,6 +1,7 @@
 //! Default values for configuration options
 
 use std::path::PathBuf;
+use super::types::ConnectionFilter;
 
 // Server defaults
 pub(super) fn default_host() -> String {
@@ -11,14 +12,81 @@ pub(super) fn default_port() -> u16 {
     8080
 }
 
+pub(super) fn default_geoip_cache_size() -> usize {
+    10_000
+}
+
+pub(super) fn default_geoip_cache_ttl() -> u64 {
+    3600
+}
+
+pub(super) fn default_acme_cache_dir() -> PathBuf {
+    PathBuf::from("/var/lib/fe-php/acme")
+}
+
+pub(super) fn default_tls_expiry_warning_days() -> u64 {
+    14
+}
+
+pub(super) fn default_allowed_methods() -> Vec<String> {
+    ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]
+        .iter()
+        .map(|m| m.to_string())
+        .collect()
+}
+
+pub(super) fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
 pub(super) fn default_workers() -> usize {
     num_cpus::get()
 }
 
+pub(super) fn default_server_header() -> String {
+    "fe-php".to_string()
+}
+
+pub(super) fn default_overload_status() -> u16 {
+    503
+}
+
+pub(super) fn default_overload_retry_after_secs() -> u64 {
+    5
+}
+
+pub(super) fn default_http2_max_concurrent_streams() -> u32 {
+    100
+}
+
+pub(super) fn default_http2_max_reset_streams() -> usize {
+    100
+}
+
+pub(super) fn default_http2_reset_window() -> u64 {
+    30
+}
+
+pub(super) fn default_response_stream_threshold() -> usize {
+    64 * 1024
+}
+
+pub(super) fn default_response_chunk_size() -> usize {
+    16 * 1024
+}
+
+pub(super) fn default_maintenance_retry_after() -> u64 {
+    300
+}
+
 pub(super) fn default_http_port() -> u16 {
     80
 }
 
+pub(super) fn default_tls_handshake_timeout() -> u64 {
+    10
+}
+
 // PHP defaults
 pub(super) fn default_max_requests() -> usize {
     1000
@@ -28,6 +96,18 @@ pub(super) fn default_fpm_socket() -> String {
     "127.0.0.1:9000".to_string()
 }
 
+pub(crate) fn default_fpm_read_timeout_secs() -> u64 {
+    60
+}
+
+pub(super) fn default_request_id_param() -> String {
+    "REQUEST_ID".to_string()
+}
+
+pub(super) fn default_php_content_type() -> String {
+    "text/html; charset=UTF-8".to_string()
+}
+
 // Opcache defaults
 pub(super) fn default_true() -> bool {
     true
@@ -54,6 +134,10 @@ pub(super) fn default_log_output() -> String {
     "stdout".to_string()
 }
 
+pub(super) fn default_redact_headers() -> Vec<String> {
+    ["authorization", "cookie", "x-api-key"].map(String::from).to_vec()
+}
+
 // Metrics defaults
 pub(super) fn default_metrics_endpoint() -> String {
     "/_metrics".to_string()
@@ -63,6 +147,20 @@ pub(super) fn default_metrics_port() -> u16 {
     9090
 }
 
+// Security defaults
+pub(super) fn default_filter_order() -> Vec<ConnectionFilter> {
+    vec![ConnectionFilter::IpBlocker, ConnectionFilter::Geoip]
+}
+
+// WAF defaults
+pub(super) fn default_waf_max_body_inspect_bytes() -> usize {
+    128 * 1024
+}
+
+pub(super) fn default_waf_body_exclude_content_types() -> Vec<String> {
+    vec!["multipart/form-data".to_string()]
+}
+
 // Rate limit defaults
 pub(super) fn default_rate_limit() -> u32 {
     100
@@ -99,17 +197,37 @@ pub(super) fn default_redis_url() -> String {
 }
 
 pub(super) fn default_redis_pool_size() -> u32 {
-    10
+    8
 }
 
 pub(super) fn default_redis_timeout() -> u64 {
     5000
 }
 
+pub(super) fn default_redis_session_ttl() -> u64 {
+    1440
+}
+
 pub(super) fn default_redis_prefix() -> String {
     "fe_php:".to_string()
 }
 
+pub(super) fn default_redis_keepalive_interval() -> u64 {
+    30
+}
+
+pub(super) fn default_redis_reconnect_retries() -> usize {
+    6
+}
+
+pub(super) fn default_redis_reconnect_backoff() -> u64 {
+    100
+}
+
+pub(super) fn default_redis_scan_count() -> usize {
+    1000
+}
+
 // Tracing defaults
 pub(super) fn default_otlp_endpoint() -> String {
     "http://localhost:4317".to_string()
@@ -164,6 +282,10 @@ pub(super) fn default_half_open_max_requests() -> usize {
     3
 }
 
+pub(super) fn default_sticky_cookie_name() -> String {
+    "fe_php_upstream".to_string()
+}
+
 // Deployment defaults
 pub(super) fn default_min_requests() -> u64 {
     100
@@ -186,10 +308,46 @@ pub(super) fn default_priority() -> u32 {
     50
 }
 
+pub(super) fn default_backend_health_check_interval() -> u64 {
+    30
+}
+
+pub(super) fn default_fastcgi_max_retries() -> u32 {
+    2
+}
+
+pub(super) fn default_fastcgi_retry_backoff_ms() -> u64 {
+    50
+}
+
+pub(super) fn default_page_cache_ttl() -> u64 {
+    60
+}
+
+pub(super) fn default_page_cache_stale() -> u64 {
+    300
+}
+
+pub(super) fn default_page_cache_dir() -> PathBuf {
+    PathBuf::from("/var/cache/fe-php/pages")
+}
+
+pub(super) fn default_page_cache_prefix() -> String {
+    "fe_php:page:".to_string()
+}
+
 pub(super) fn default_index_files() -> Vec<String> {
     vec!["index.html".to_string(), "index.htm".to_string()]
 }
 
+pub(super) fn default_max_ranges() -> usize {
+    16
+}
+
+pub(super) fn default_cache_max_file_size() -> u64 {
+    64 * 1024
+}
+
 // Connection pool defaults
 pub(super) fn default_pool_max_size() -> usize {
     20
@@ -206,3 +364,7 @@ pub(super) fn default_pool_max_lifetime() -> u64 {
 pub(super) fn default_pool_connect_timeout() -> u64 {
     5
 }
+
+pub(super) fn default_pool_maintenance_interval() -> u64 {
+    10
+}
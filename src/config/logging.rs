@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 use super::defaults::*;
+use super::types::StatusGranularity;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoggingConfig {
@@ -9,6 +10,18 @@ pub struct LoggingConfig {
     pub format: String,
     #[serde(default = "default_log_output")]
     pub output: String,
+    /// Keep the query string in logged URIs; when false only the path is logged
+    #[serde(default = "default_true")]
+    pub log_query_string: bool,
+    /// Query parameter names whose values are masked in logs (case-insensitive)
+    #[serde(default)]
+    pub redact_query_params: Vec<String>,
+    /// Header names whose values are masked in logs (case-insensitive); credentials by default
+    #[serde(default = "default_redact_headers")]
+    pub redact_headers: Vec<String>,
+    /// Record which routing rule and backend handled each request
+    #[serde(default)]
+    pub log_routing: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -19,4 +32,10 @@ pub struct MetricsConfig {
     pub endpoint: String,
     #[serde(default = "default_metrics_port")]
     pub port: u16,
+    /// Gzip responses for clients sending `Accept-Encoding: gzip`
+    #[serde(default = "default_true")]
+    pub compression: bool,
+    /// Record `http_requests_total` by exact status code or by class (`2xx`...)
+    #[serde(default)]
+    pub status_granularity: StatusGranularity,
 }
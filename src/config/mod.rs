@@ -12,7 +12,7 @@ mod logging;
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 // Re-export types
 pub use types::*;
@@ -22,6 +22,7 @@ pub use security::*;
 pub use advanced::*;
 pub use backend::*;
 pub use logging::*;
+pub(crate) use defaults::default_fpm_read_timeout_secs;
 
 /// Main configuration structure for the fe-php server
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,6 +32,8 @@ pub struct Config {
     pub logging: LoggingConfig,
     pub metrics: MetricsConfig,
     #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
     pub waf: WafConfig,
     #[serde(default)]
     pub admin: AdminConfig,
@@ -48,16 +51,33 @@ pub struct Config {
     pub deployment: DeploymentConfig,
     #[serde(default)]
     pub backend: BackendConfig,
+    #[serde(default)]
+    pub maintenance: MaintenanceConfig,
 }
 
 impl Config {
     /// Load configuration from a file
-    pub fn from_file(path: &PathBuf) -> Result<Self> {
+    pub fn from_file(path: &Path) -> Result<Self> {
         parser::parse_config(path)
     }
 
+    /// Load a base configuration with environment overrides deep-merged over it in order
+    pub fn from_files(base: &Path, overrides: &[PathBuf]) -> Result<Self> {
+        parser::parse_layered(base, overrides)
+    }
+
     /// Validate the configuration
     pub fn validate(&self) -> Result<Vec<String>> {
         validator::validate_config(self)
     }
+
+    /// Document root for the embedded backend (`backend.embedded.document_root` or `php.document_root`)
+    pub fn embedded_document_root(&self) -> &Path {
+        self.backend.embedded.document_root.as_deref().unwrap_or(&self.php.document_root)
+    }
+
+    /// Document root for the FastCGI backend (`backend.fastcgi.document_root` or `php.document_root`)
+    pub fn fastcgi_document_root(&self) -> &Path {
+        self.backend.fastcgi.document_root.as_deref().unwrap_or(&self.php.document_root)
+    }
 }
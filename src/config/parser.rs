@@ -1,16 +1,55 @@
 use super::Config;
 use anyhow::{Context, Result};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-pub fn parse_config(path: &PathBuf) -> Result<Config> {
+pub fn parse_config(path: &Path) -> Result<Config> {
+    parse_layered(path, &[])
+}
+
+/// Load `base` and deep-merge each of `overrides` over it in order. Tables are
+/// merged key by key; any other value, arrays included, replaces the base value.
+pub fn parse_layered(base: &Path, overrides: &[PathBuf]) -> Result<Config> {
+    let mut merged = read_table(base)?;
+    for path in overrides {
+        merge(&mut merged, read_table(path)?);
+    }
+
+    let config: Config = toml::Value::Table(merged).try_into()
+        .with_context(|| {
+            if overrides.is_empty() {
+                format!("Failed to parse config file: {}", base.display())
+            } else {
+                format!(
+                    "Failed to parse config file: {} with overrides {}",
+                    base.display(),
+                    overrides.iter().map(|p| p.display().to_string()).collect::<Vec<_>>().join(", ")
+                )
+            }
+        })?;
+
+    Ok(config)
+}
+
+fn read_table(path: &Path) -> Result<toml::Table> {
     let content = fs::read_to_string(path)
         .with_context(|| format!("Failed to read config file: {}", path.display()))?;
 
-    let config: Config = toml::from_str(&content)
-        .with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+    content.parse::<toml::Table>()
+        .with_context(|| format!("Failed to parse config file: {}", path.display()))
+}
 
-    Ok(config)
+fn merge(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base_table)), toml::Value::Table(overlay_table)) => {
+                merge(base_table, overlay_table);
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
 }
 
 pub fn save_config(config: &Config, path: &PathBuf) -> Result<()> {
@@ -55,4 +94,70 @@ enable = true
         assert_eq!(config.server.host, "127.0.0.1");
         assert_eq!(config.server.port, 8080);
     }
+
+    #[test]
+    fn test_override_is_deep_merged() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.toml");
+        let prod = dir.path().join("prod.toml");
+        std::fs::write(&base, r#"
+[server]
+host = "127.0.0.1"
+port = 8080
+
+[php]
+libphp_path = "/usr/local/lib/libphp.so"
+document_root = "/var/www/html"
+
+[php.opcache]
+enable = true
+memory_size = "128M"
+
+[logging]
+level = "debug"
+
+[metrics]
+enable = true
+
+[security]
+allowlist = ["10.0.0.0/8", "192.168.0.0/16"]
+"#).unwrap();
+        std::fs::write(&prod, r#"
+[server]
+port = 80
+
+[php.opcache]
+memory_size = "512M"
+
+[logging]
+level = "warn"
+
+[security]
+allowlist = ["10.1.0.0/16"]
+"#).unwrap();
+
+        let config = parse_layered(&base, &[prod]).unwrap();
+
+        // Overridden keys, including nested ones
+        assert_eq!(config.server.port, 80);
+        assert_eq!(config.php.opcache.memory_size, "512M");
+        assert_eq!(config.logging.level, "warn");
+        // Arrays are replaced, not appended to
+        assert_eq!(config.security.allowlist, ["10.1.0.0/16"]);
+        // Siblings only in the base are inherited
+        assert_eq!(config.server.host, "127.0.0.1");
+        assert!(config.php.opcache.enable);
+        assert_eq!(config.php.document_root, PathBuf::from("/var/www/html"));
+        assert!(config.metrics.enable);
+    }
+
+    #[test]
+    fn test_missing_override_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().join("base.toml");
+        std::fs::write(&base, "").unwrap();
+
+        let err = parse_layered(&base, &[dir.path().join("missing.toml")]).unwrap_err();
+        assert!(err.to_string().contains("missing.toml"));
+    }
 }
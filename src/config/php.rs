@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use super::defaults::*;
+use super::types::{DuplicateHeaders, HeaderPolicyMode, OversizedHeaders};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PhpConfig {
@@ -10,12 +11,100 @@ pub struct PhpConfig {
     pub worker_pool_size: usize,
     #[serde(default = "default_max_requests")]
     pub worker_max_requests: usize,
+    /// Prefer the same embedded worker for repeated requests to a script while it is idle
+    #[serde(default)]
+    pub worker_affinity: bool,
     #[serde(default)]
     pub opcache: OpcacheConfig,
     #[serde(default)]
     pub use_fpm: bool,
     #[serde(default = "default_fpm_socket")]
     pub fpm_socket: String,
+    /// Longest wait in seconds for the next bytes of a PHP-FPM response
+    #[serde(default = "default_fpm_read_timeout_secs")]
+    pub fpm_read_timeout_secs: u64,
+    /// FastCGI param carrying the request id alongside `HTTP_X_REQUEST_ID`; empty disables it
+    #[serde(default = "default_request_id_param")]
+    pub request_id_param: String,
+    /// Send PHP-FPM's STDERR to the client when the script produced no output; it is always logged
+    #[serde(default)]
+    pub expose_fpm_errors: bool,
+    /// Limit on simultaneous PHP executions across all connections; unset means unlimited
+    #[serde(default)]
+    pub max_concurrent: Option<usize>,
+    /// How long a request may queue for a free slot before getting the overload response (0 = reject immediately)
+    #[serde(default)]
+    pub max_concurrent_wait_ms: u64,
+    /// Let POST requests tell PHP they are PUT/PATCH/DELETE
+    #[serde(default)]
+    pub method_override: MethodOverrideConfig,
+    /// `Content-Type` for PHP responses that do not set one
+    #[serde(default)]
+    pub content_type: ContentTypeConfig,
+    /// Handling of response headers PHP sends more than once
+    #[serde(default)]
+    pub duplicate_headers: DuplicateHeaders,
+    #[serde(default)]
+    pub response_headers: ResponseHeaderLimits,
+    /// Which headers PHP may set on its responses
+    #[serde(default)]
+    pub response_header_policy: ResponseHeaderPolicy,
+}
+
+impl PhpConfig {
+    /// `request_id_param`, or `None` when disabled
+    pub fn request_id_param(&self) -> Option<String> {
+        Some(self.request_id_param.clone()).filter(|name| !name.is_empty())
+    }
+}
+
+/// Allowlist or denylist for headers emitted by PHP; names compare case-insensitively
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponseHeaderPolicy {
+    #[serde(default)]
+    pub mode: HeaderPolicyMode,
+    #[serde(default)]
+    pub headers: Vec<String>,
+}
+
+/// Size caps for headers emitted by PHP, in bytes of name plus value; unset means unlimited
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResponseHeaderLimits {
+    #[serde(default)]
+    pub max_header_size: Option<usize>,
+    #[serde(default)]
+    pub max_total_size: Option<usize>,
+    #[serde(default)]
+    pub on_oversized: OversizedHeaders,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MethodOverrideConfig {
+    /// Honor `X-HTTP-Method-Override` on POST requests
+    #[serde(default)]
+    pub enable: bool,
+    /// Form field (e.g. `_method`) also checked in urlencoded POST bodies
+    #[serde(default)]
+    pub form_field: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContentTypeConfig {
+    /// Used when PHP sends no `Content-Type`
+    #[serde(default = "default_php_content_type")]
+    pub default: String,
+    /// Send `application/json` instead when the body looks like a JSON object or array
+    #[serde(default)]
+    pub sniff: bool,
+}
+
+impl Default for ContentTypeConfig {
+    fn default() -> Self {
+        Self {
+            default: default_php_content_type(),
+            sniff: false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +117,9 @@ pub struct OpcacheConfig {
     pub max_files: usize,
     #[serde(default)]
     pub validate_timestamps: bool,
+    /// Reset OPcache on every embedded worker after config reload / worker restart
+    #[serde(default)]
+    pub reset_on_reload: bool,
 }
 
 impl Default for OpcacheConfig {
@@ -37,6 +129,7 @@ impl Default for OpcacheConfig {
             memory_size: default_opcache_memory(),
             max_files: default_max_files(),
             validate_timestamps: false,
+            reset_on_reload: false,
         }
     }
 }
@@ -1,7 +1,46 @@
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 use super::defaults::*;
-use super::types::WafMode;
+use super::types::{ConnectionFilter, PathPatternConfig, WafMode, WafRulesMode};
+
+/// Settings shared by the WAF, rate limiting and IP blocking layers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecurityConfig {
+    /// Client IPs/CIDRs that bypass the WAF, rate limiting, IP blocking and GeoIP
+    #[serde(default)]
+    pub allowlist: Vec<String>,
+    /// Connection filters in evaluation order; the first rejection closes the
+    /// connection and skips the rest. Omitted filters are not applied.
+    #[serde(default = "default_filter_order")]
+    pub filter_order: Vec<ConnectionFilter>,
+    /// Request paths rejected with 403 before routing
+    #[serde(default)]
+    pub denied_patterns: Vec<PathPatternConfig>,
+    /// Pass paths with bad percent-encoding, NUL bytes or root-escaping `..`
+    /// on to the backends instead of answering 400 up front
+    #[serde(default)]
+    pub allow_malformed_paths: bool,
+    /// Proxy IPs/CIDRs whose `Forwarded` / `X-Forwarded-*` headers identify the client
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Also trust forwarding headers on connections to a Unix socket listener,
+    /// which is only reachable by local processes such as a fronting proxy
+    #[serde(default)]
+    pub trust_unix_socket_peers: bool,
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            allowlist: Vec::new(),
+            filter_order: default_filter_order(),
+            denied_patterns: Vec::new(),
+            allow_malformed_paths: false,
+            trusted_proxies: Vec::new(),
+            trust_unix_socket_peers: false,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WafConfig {
@@ -11,8 +50,34 @@ pub struct WafConfig {
     pub mode: WafMode,
     #[serde(default)]
     pub rules_path: Option<PathBuf>,
+    /// Whether `rules_path` replaces the built-in rules or is merged into them
+    #[serde(default)]
+    pub rules_mode: WafRulesMode,
     #[serde(default)]
     pub rate_limit: RateLimitConfig,
+    /// Enables anomaly scoring: block only when matched rule scores sum to this value
+    #[serde(default)]
+    pub anomaly_threshold: Option<u32>,
+    /// Add an `X-WAF-Anomaly-Score` header to responses
+    #[serde(default)]
+    pub debug_headers: bool,
+    /// Only the first N bytes of a request body are scanned
+    #[serde(default = "default_waf_max_body_inspect_bytes")]
+    pub max_body_inspect_bytes: usize,
+    /// Content types (prefix match) whose bodies are not scanned at all
+    #[serde(default = "default_waf_body_exclude_content_types")]
+    pub body_exclude_content_types: Vec<String>,
+    /// Rules skipped for matching paths
+    #[serde(default)]
+    pub exclusions: Vec<WafExclusionConfig>,
+}
+
+/// Rules not applied to requests whose path matches `path_pattern`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WafExclusionConfig {
+    pub path_pattern: PathPatternConfig,
+    /// Rule ids to skip; `"*"` skips the WAF entirely
+    pub rule_ids: Vec<String>,
 }
 
 impl Default for WafConfig {
@@ -21,7 +86,13 @@ impl Default for WafConfig {
             enable: false,
             mode: WafMode::default(),
             rules_path: None,
+            rules_mode: WafRulesMode::default(),
             rate_limit: RateLimitConfig::default(),
+            anomaly_threshold: None,
+            debug_headers: false,
+            max_body_inspect_bytes: default_waf_max_body_inspect_bytes(),
+            body_exclude_content_types: default_waf_body_exclude_content_types(),
+            exclusions: Vec::new(),
         }
     }
 }
@@ -56,6 +127,18 @@ pub struct GeoIpConfig {
     pub allowed_countries: Vec<String>,
     #[serde(default)]
     pub blocked_countries: Vec<String>,
+    /// Check the database file's mtime this often and reload it when it changes (0 = never)
+    #[serde(default, alias = "reload_interval_seconds")]
+    pub reload_interval_secs: u64,
+    /// Pass the client's country, city and continent to PHP as `GEOIP_*` headers
+    #[serde(default)]
+    pub inject_headers: bool,
+    /// Addresses whose country is remembered (0 = no cache)
+    #[serde(default = "default_geoip_cache_size")]
+    pub cache_size: usize,
+    /// How long a cached country stays valid
+    #[serde(default = "default_geoip_cache_ttl")]
+    pub cache_ttl_secs: u64,
 }
 
 impl Default for GeoIpConfig {
@@ -65,6 +148,10 @@ impl Default for GeoIpConfig {
             database_path: None,
             allowed_countries: Vec::new(),
             blocked_countries: Vec::new(),
+            reload_interval_secs: 0,
+            inject_headers: false,
+            cache_size: default_geoip_cache_size(),
+            cache_ttl_secs: default_geoip_cache_ttl(),
         }
     }
 }
@@ -1,7 +1,9 @@
+use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::PathBuf;
 use super::defaults::*;
-use super::types::ListenType;
+use super::types::{ListenType, PathPatternConfig, RequireHttps, ResponseBufferMode, TrailingSlash};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServerConfig {
@@ -21,6 +23,216 @@ pub struct ServerConfig {
     pub listen_type: ListenType,
     #[serde(default)]
     pub unix_socket_path: Option<PathBuf>,
+    /// Permission bits for Unix listener sockets as an octal string (e.g. `"0660"`);
+    /// the umask default applies when unset
+    #[serde(default)]
+    pub unix_socket_mode: Option<String>,
+    /// User name or uid that should own Unix listener sockets
+    #[serde(default)]
+    pub unix_socket_owner: Option<String>,
+    /// Group name or gid that should own Unix listener sockets
+    #[serde(default)]
+    pub unix_socket_group: Option<String>,
+    /// Deadline for a whole request (body read + backend execution); unset disables it
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    /// Deadline for receiving a buffered request body; slower uploads get 408. Unset disables it
+    #[serde(default)]
+    pub body_read_timeout_ms: Option<u64>,
+    /// How long shutdown waits for open connections and background tasks to finish
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Explicit listener list; when empty a single listener is derived from host/port/listen_type
+    #[serde(default)]
+    pub listeners: Vec<ListenerConfig>,
+    /// Methods accepted from clients; anything else gets 405 before reaching the WAF
+    /// or a backend. Empty allows every method
+    #[serde(default = "default_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    #[serde(default)]
+    pub trailing_slash: TrailingSlash,
+    /// Redirect or reject requests that arrived over plain HTTP, judged by the connection
+    /// or the scheme reported by a trusted proxy. Health checks are exempt.
+    #[serde(default)]
+    pub require_https: RequireHttps,
+    /// Compare paths case-insensitively in security checks (for case-insensitive filesystems)
+    #[serde(default)]
+    pub case_insensitive_paths: bool,
+    /// `Server` header value sent on every response; empty disables it
+    #[serde(default = "default_server_header")]
+    pub server_header: String,
+    /// Send `X-Fe-Php-Version` on every response
+    #[serde(default)]
+    pub expose_version: bool,
+    #[serde(default)]
+    pub host_check: HostCheckConfig,
+    #[serde(default)]
+    pub overload: OverloadConfig,
+    #[serde(default)]
+    pub http1: Http1Config,
+    #[serde(default)]
+    pub http2: Http2Config,
+    /// Disable Nagle's algorithm on accepted TCP connections
+    #[serde(default = "default_true")]
+    pub tcp_nodelay: bool,
+    /// `SO_SNDBUF` for accepted TCP connections; OS default when unset
+    #[serde(default)]
+    pub send_buffer_size: Option<usize>,
+    /// `SO_RCVBUF` for accepted TCP connections; OS default when unset
+    #[serde(default)]
+    pub recv_buffer_size: Option<usize>,
+    #[serde(default)]
+    pub response_buffer_mode: ResponseBufferMode,
+    /// Bodies larger than this many bytes are streamed in `streaming-threshold` mode
+    #[serde(default = "default_response_stream_threshold")]
+    pub response_stream_threshold: usize,
+    /// Size of each chunk written when streaming a body
+    #[serde(default = "default_response_chunk_size")]
+    pub response_chunk_size: usize,
+}
+
+/// Handling of requests whose `Host` is missing or unexpected
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostCheckConfig {
+    /// Reject a missing or unlisted `Host` with 400 instead of routing it
+    #[serde(default)]
+    pub strict: bool,
+    /// Expected hostnames; `*.example.com` matches any subdomain
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// `Host` substituted for missing or unlisted hosts when not strict
+    #[serde(default)]
+    pub default_host: Option<String>,
+}
+
+/// Response for requests rejected by a capacity limit (e.g. `php.max_concurrent`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverloadConfig {
+    /// `503` or `429`
+    #[serde(default = "default_overload_status")]
+    pub status: u16,
+    /// Seconds sent in `Retry-After`
+    #[serde(default = "default_overload_retry_after_secs")]
+    pub retry_after_secs: u64,
+}
+
+impl Default for OverloadConfig {
+    fn default() -> Self {
+        Self {
+            status: default_overload_status(),
+            retry_after_secs: default_overload_retry_after_secs(),
+        }
+    }
+}
+
+/// HTTP/1.1 connection limits
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Http1Config {
+    /// Requests a client may queue behind the one being answered on a connection;
+    /// the one reaching the limit is answered with `Connection: close`. Unlimited when unset.
+    #[serde(default)]
+    pub max_pipelined: Option<usize>,
+}
+
+/// HTTP/2 stream limits, including Rapid Reset (CVE-2023-44487) protection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Http2Config {
+    #[serde(default = "default_http2_max_concurrent_streams")]
+    pub max_concurrent_streams: u32,
+    /// Close the connection when the client cancels more in-flight streams than this...
+    #[serde(default = "default_http2_max_reset_streams")]
+    pub max_reset_streams: usize,
+    /// ...within this many seconds
+    #[serde(default = "default_http2_reset_window")]
+    pub reset_window_secs: u64,
+}
+
+impl Default for Http2Config {
+    fn default() -> Self {
+        Self {
+            max_concurrent_streams: default_http2_max_concurrent_streams(),
+            max_reset_streams: default_http2_max_reset_streams(),
+            reset_window_secs: default_http2_reset_window(),
+        }
+    }
+}
+
+impl ServerConfig {
+    /// `unix_socket_mode` parsed as permission bits
+    pub fn unix_socket_permissions(&self) -> Result<Option<u32>> {
+        self.unix_socket_mode.as_deref().map(parse_octal_mode).transpose()
+    }
+
+    /// Whether `allowed_methods` lets `method` through
+    pub fn method_allowed(&self, method: &str) -> bool {
+        self.allowed_methods.is_empty() || self.allowed_methods.iter().any(|m| m.eq_ignore_ascii_case(method))
+    }
+
+    /// Listeners to bind, falling back to the legacy single-socket settings
+    pub fn effective_listeners(&self, tls_enabled: bool) -> Vec<ListenerConfig> {
+        if !self.listeners.is_empty() {
+            return self.listeners.clone();
+        }
+
+        vec![ListenerConfig {
+            listen_type: self.listen_type.clone(),
+            host: self.host.clone(),
+            port: self.port,
+            unix_socket_path: self.unix_socket_path.clone(),
+            // TLS was only ever applied to the TCP listener
+            tls: tls_enabled && self.listen_type == ListenType::Tcp,
+            enable_http2: None,
+        }]
+    }
+}
+
+/// Parse an octal permission string such as `"0660"`, `"660"` or `"0o660"`
+fn parse_octal_mode(mode: &str) -> Result<u32> {
+    let digits = mode.trim().trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(bits) if !digits.is_empty() && bits <= 0o7777 => Ok(bits),
+        _ => bail!("Invalid unix_socket_mode '{}': expected octal permission bits such as \"0660\"", mode),
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListenerConfig {
+    #[serde(default)]
+    pub listen_type: ListenType,
+    #[serde(default = "default_host")]
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub unix_socket_path: Option<PathBuf>,
+    /// Terminate TLS on this listener using the [tls] certificate
+    #[serde(default)]
+    pub tls: bool,
+    /// Serve HTTP/2 instead of HTTP/1.1; falls back to `server.enable_http2` when unset
+    #[serde(default)]
+    pub enable_http2: Option<bool>,
+}
+
+impl ListenerConfig {
+    /// Whether connections on this listener speak HTTP/2
+    pub fn http2_enabled(&self, server_default: bool) -> bool {
+        self.enable_http2.unwrap_or(server_default)
+    }
+}
+
+impl fmt::Display for ListenerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.listen_type {
+            ListenType::Tcp => {
+                let scheme = if self.tls { "https" } else { "http" };
+                write!(f, "{}://{}:{}", scheme, self.host, self.port)
+            }
+            ListenType::Unix => match self.unix_socket_path {
+                Some(ref path) => write!(f, "unix://{}", path.display()),
+                None => write!(f, "unix://<unset>"),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -31,14 +243,78 @@ pub struct TlsConfig {
     pub cert_path: Option<PathBuf>,
     #[serde(default)]
     pub key_path: Option<PathBuf>,
-    #[serde(default)]
+    /// CA bundle that client certificates are verified against
+    #[serde(default, alias = "client_ca_path")]
     pub ca_cert_path: Option<PathBuf>,
+    /// Fail handshakes from clients without a certificate signed by `ca_cert_path`
+    #[serde(default)]
+    pub require_client_cert: bool,
     #[serde(default)]
     pub alpn_protocols: Vec<String>,
     #[serde(default)]
     pub http_redirect: bool,
     #[serde(default = "default_http_port")]
     pub http_port: u16,
+    /// Connections that do not finish the TLS handshake in time are closed
+    #[serde(default = "default_tls_handshake_timeout")]
+    pub handshake_timeout_secs: u64,
+    /// Paths answered with 403 unless the connection presented a client certificate
+    /// signed by `ca_cert_path`
+    #[serde(default)]
+    pub client_cert_required_paths: Vec<PathPatternConfig>,
+    /// Limit on simultaneous TLS handshakes across all listeners; unset means unlimited
+    #[serde(default)]
+    pub max_concurrent_handshakes: Option<usize>,
+    /// How long a connection may queue for a handshake slot before it is dropped (0 = drop immediately)
+    #[serde(default)]
+    pub handshake_queue_wait_ms: u64,
+    /// Warn when the certificate expires within this many days
+    #[serde(default = "default_tls_expiry_warning_days")]
+    pub expiry_warning_days: u64,
+    /// Extra certificates chosen by the client's SNI name
+    #[serde(default)]
+    pub sni: Vec<SniCertConfig>,
+    #[serde(default)]
+    pub acme: AcmeConfig,
+}
+
+/// Automatic certificates from an ACME CA (Let's Encrypt) via the HTTP-01 challenge
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AcmeConfig {
+    #[serde(default)]
+    pub enable: bool,
+    /// Names on the certificate; the first one is also its CN
+    #[serde(default)]
+    pub domains: Vec<String>,
+    /// Contact address registered with the account
+    #[serde(default)]
+    pub email: Option<String>,
+    /// Account key, certificate and private key are kept here across restarts
+    #[serde(default = "default_acme_cache_dir")]
+    pub cache_dir: PathBuf,
+    /// Use the Let's Encrypt staging environment
+    #[serde(default)]
+    pub staging: bool,
+}
+
+impl Default for AcmeConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            domains: Vec::new(),
+            email: None,
+            cache_dir: default_acme_cache_dir(),
+            staging: false,
+        }
+    }
+}
+
+/// Certificate served to clients that ask for `hostname` (`*.example.com` covers one label)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SniCertConfig {
+    pub hostname: String,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
 }
 
 impl Default for TlsConfig {
@@ -48,9 +324,17 @@ impl Default for TlsConfig {
             cert_path: None,
             key_path: None,
             ca_cert_path: None,
+            require_client_cert: false,
             alpn_protocols: vec!["h2".to_string(), "http/1.1".to_string()],
             http_redirect: false,
             http_port: default_http_port(),
+            handshake_timeout_secs: default_tls_handshake_timeout(),
+            client_cert_required_paths: Vec::new(),
+            max_concurrent_handshakes: None,
+            handshake_queue_wait_ms: 0,
+            expiry_warning_days: default_tls_expiry_warning_days(),
+            sni: Vec::new(),
+            acme: AcmeConfig::default(),
         }
     }
 }
@@ -67,6 +351,33 @@ pub struct AdminConfig {
     pub http_port: u16,
     #[serde(default = "default_allowed_ips")]
     pub allowed_ips: Vec<String>,
+    /// Gzip HTTP API responses for clients sending `Accept-Encoding: gzip`
+    #[serde(default = "default_true")]
+    pub compression: bool,
+}
+
+/// 503 maintenance page served to everyone but allowlisted clients and probes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MaintenanceConfig {
+    /// Start in maintenance mode (toggle at runtime with the `set_maintenance` admin command)
+    #[serde(default)]
+    pub enabled: bool,
+    /// HTML page to serve; a built-in page is used when unset
+    #[serde(default)]
+    pub page_path: Option<PathBuf>,
+    /// `Retry-After` seconds when the admin command does not give one
+    #[serde(default = "default_maintenance_retry_after")]
+    pub retry_after_secs: u64,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            page_path: None,
+            retry_after_secs: default_maintenance_retry_after(),
+        }
+    }
 }
 
 impl Default for AdminConfig {
@@ -77,6 +388,7 @@ impl Default for AdminConfig {
             unix_socket: default_admin_socket(),
             http_port: default_admin_port(),
             allowed_ips: default_allowed_ips(),
+            compression: true,
         }
     }
 }
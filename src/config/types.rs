@@ -12,6 +12,16 @@ pub enum WafMode {
     Block,
 }
 
+/// Connection-level check run when a client connects
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionFilter {
+    /// Runtime blocklist managed with `block_ip` / `unblock_ip`
+    IpBlocker,
+    /// `[geoip]` country rules
+    Geoip,
+}
+
 impl Default for WafMode {
     fn default() -> Self {
         Self::Off
@@ -43,6 +53,26 @@ impl FromStr for WafMode {
     }
 }
 
+/// How a `waf.rules_path` file combines with the built-in rules
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WafRulesMode {
+    /// The file is the whole ruleset
+    #[default]
+    Replace,
+    /// The file is added to the built-in rules; a rule with a built-in id overrides it
+    Merge,
+}
+
+impl fmt::Display for WafRulesMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Replace => write!(f, "replace"),
+            Self::Merge => write!(f, "merge"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum DeploymentStrategy {
@@ -88,6 +118,10 @@ pub enum LoadBalancingAlgorithm {
     WeightedRoundRobin,
     /// IP hash
     IpHash,
+    /// Lowest moving average of response time
+    LeastResponseTime,
+    /// Hash ring keyed by request URI or a header
+    ConsistentHash,
 }
 
 impl Default for LoadBalancingAlgorithm {
@@ -103,10 +137,22 @@ impl fmt::Display for LoadBalancingAlgorithm {
             Self::LeastConn => write!(f, "least_conn"),
             Self::WeightedRoundRobin => write!(f, "weighted_round_robin"),
             Self::IpHash => write!(f, "ip_hash"),
+            Self::LeastResponseTime => write!(f, "least_response_time"),
+            Self::ConsistentHash => write!(f, "consistent_hash"),
         }
     }
 }
 
+/// How a client is pinned to one upstream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StickyMode {
+    /// A cookie names the upstream that served the client last
+    Cookie,
+    /// The client IP is hashed onto the available upstreams
+    IpHash,
+}
+
 impl FromStr for LoadBalancingAlgorithm {
     type Err = anyhow::Error;
 
@@ -116,8 +162,10 @@ impl FromStr for LoadBalancingAlgorithm {
             "least_conn" => Ok(Self::LeastConn),
             "weighted_round_robin" => Ok(Self::WeightedRoundRobin),
             "ip_hash" => Ok(Self::IpHash),
+            "least_response_time" => Ok(Self::LeastResponseTime),
+            "consistent_hash" => Ok(Self::ConsistentHash),
             _ => Err(anyhow::anyhow!(
-                "Invalid load balancing algorithm: '{}'. Valid values: round_robin, least_conn, weighted_round_robin, ip_hash",
+                "Invalid load balancing algorithm: '{}'. Valid values: round_robin, least_conn, weighted_round_robin, ip_hash, least_response_time, consistent_hash",
                 s
             )),
         }
@@ -137,6 +185,133 @@ impl Default for ListenType {
     }
 }
 
+/// How the `status` label of `http_requests_total` is recorded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatusGranularity {
+    /// Exact code, e.g. `404`
+    #[default]
+    Exact,
+    /// Status class, e.g. `4xx`
+    Class,
+}
+
+impl StatusGranularity {
+    /// Label value for a status code
+    pub fn label(&self, status: u16) -> String {
+        match self {
+            Self::Exact => status.to_string(),
+            Self::Class => format!("{}xx", status / 100),
+        }
+    }
+}
+
+/// What to do with a stored session that no longer deserializes (e.g. after a schema change)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionDecodeErrorPolicy {
+    /// Fail the lookup with an error
+    Error,
+    /// Treat the session as absent so a new one is created
+    #[default]
+    Missing,
+    /// Keep the fields that still deserialize and default the rest
+    Partial,
+}
+
+impl SessionDecodeErrorPolicy {
+    /// Metrics label value
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Missing => "missing",
+            Self::Partial => "partial",
+        }
+    }
+}
+
+/// Where `[backend.cache]` keeps pages
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PageCacheStorage {
+    #[default]
+    Disk,
+    Redis,
+}
+
+/// Handling of plaintext requests on the main listeners
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequireHttps {
+    #[default]
+    Off,
+    /// 301 to the same URL with `https://`
+    Redirect,
+    /// 403
+    Reject,
+}
+
+/// Canonical form enforced with a 301 redirect for extension-less paths
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    /// Serve paths as requested
+    #[default]
+    Preserve,
+    /// Redirect `/about` to `/about/`
+    Add,
+    /// Redirect `/about/` to `/about`
+    Remove,
+}
+
+/// What to do when PHP emits the same response header more than once
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateHeaders {
+    /// Send every value as its own header line
+    #[default]
+    Preserve,
+    /// Join the values into one comma-separated line (`Set-Cookie` excepted)
+    Combine,
+}
+
+/// What to do with a PHP response header over the `php.response_headers` limits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedHeaders {
+    /// Leave the header out
+    #[default]
+    Drop,
+    /// Cut the value down to `max_header_size` (headers past `max_total_size` are dropped)
+    Truncate,
+    /// Answer 500 instead of the PHP response
+    Error,
+}
+
+/// How `php.response_header_policy.headers` is applied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderPolicyMode {
+    /// PHP may send any header
+    #[default]
+    Off,
+    /// Only the listed headers (and `Content-Type`) are sent
+    Allow,
+    /// The listed headers are stripped
+    Deny,
+}
+
+/// How response bodies are handed to the connection
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ResponseBufferMode {
+    /// Write every body as one buffer
+    #[default]
+    Full,
+    /// Write headers first, then bodies above `response_stream_threshold` in chunks
+    StreamingThreshold,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type", content = "value")]
 #[serde(rename_all = "lowercase")]
@@ -1,4 +1,4 @@
-use super::{Config, WafMode};
+use super::{Config, ConnectionFilter, ListenType, WafMode};
 use anyhow::Result;
 
 pub fn validate_config(config: &Config) -> Result<Vec<String>> {
@@ -23,6 +23,36 @@ pub fn validate_config(config: &Config) -> Result<Vec<String>> {
         ));
     }
 
+    for listener in &config.server.listeners {
+        if listener.listen_type == ListenType::Unix && listener.unix_socket_path.is_none() {
+            warnings.push("[X] Unix listener configured without unix_socket_path".to_string());
+        }
+        if listener.tls && !config.tls.enable {
+            warnings.push(format!(
+                "[X] Listener {} requires TLS but [tls] is not enabled",
+                listener
+            ));
+        }
+    }
+
+    if let Err(e) = config.server.unix_socket_permissions() {
+        warnings.push(format!("[X] {}", e));
+    }
+
+    if !config.tls.client_cert_required_paths.is_empty()
+        && (!config.tls.enable || config.tls.ca_cert_path.is_none())
+    {
+        warnings.push("[X] tls.client_cert_required_paths requires [tls] enabled with ca_cert_path".to_string());
+    }
+
+    if config.tls.acme.enable && (!config.tls.enable || config.tls.acme.domains.is_empty()) {
+        warnings.push("[X] tls.acme requires [tls] enabled and at least one domain".to_string());
+    }
+
+    if config.tls.require_client_cert && config.tls.ca_cert_path.is_none() {
+        warnings.push("[X] tls.require_client_cert requires ca_cert_path".to_string());
+    }
+
     if config.backend.enable_hybrid {
         if !config.php.libphp_path.exists() {
             warnings.push(format!(
@@ -58,6 +88,42 @@ pub fn validate_config(config: &Config) -> Result<Vec<String>> {
         ));
     }
 
+    for (backend, root) in [
+        ("embedded", &config.backend.embedded.document_root),
+        ("fastcgi", &config.backend.fastcgi.document_root),
+    ] {
+        if let Some(root) = root.as_ref().filter(|root| !root.exists()) {
+            warnings.push(format!(
+                "[X] Document root for {} backend not found: {}",
+                backend,
+                root.display()
+            ));
+        }
+    }
+
+    if config.server.http1.max_pipelined == Some(0) {
+        warnings.push("[X] server.http1.max_pipelined cannot be 0".to_string());
+    }
+
+    if ![429, 503].contains(&config.server.overload.status) {
+        warnings.push(format!(
+            "[X] server.overload.status must be 429 or 503, got {}",
+            config.server.overload.status
+        ));
+    }
+
+    if config.server.host_check.strict && config.server.host_check.allowed_hosts.is_empty() {
+        warnings.push("[X] server.host_check.strict requires allowed_hosts".to_string());
+    }
+
+    if config.php.max_concurrent == Some(0) {
+        warnings.push("[X] php.max_concurrent cannot be 0".to_string());
+    }
+
+    if config.php.fpm_read_timeout_secs == 0 {
+        warnings.push("[X] php.fpm_read_timeout_secs cannot be 0".to_string());
+    }
+
     if config.php.worker_pool_size == 0 {
         warnings.push("[X] PHP worker pool size cannot be 0".to_string());
     }
@@ -80,6 +146,27 @@ pub fn validate_config(config: &Config) -> Result<Vec<String>> {
         ));
     }
 
+    for entry in &config.security.allowlist {
+        if entry.parse::<ipnetwork::IpNetwork>().is_err() {
+            warnings.push(format!("[X] Invalid security.allowlist entry: {}", entry));
+        }
+    }
+
+    for (i, filter) in config.security.filter_order.iter().enumerate() {
+        if config.security.filter_order[..i].contains(filter) {
+            warnings.push(format!("[X] security.filter_order lists {:?} more than once", filter));
+        }
+    }
+    if config.geoip.enable && !config.security.filter_order.contains(&ConnectionFilter::Geoip) {
+        warnings.push("[!] GeoIP is enabled but security.filter_order omits geoip, so it is never applied".to_string());
+    }
+
+    for entry in &config.security.trusted_proxies {
+        if entry.parse::<ipnetwork::IpNetwork>().is_err() {
+            warnings.push(format!("[X] Invalid security.trusted_proxies entry: {}", entry));
+        }
+    }
+
     if config.waf.enable {
 
         if let Some(ref rules_path) = config.waf.rules_path {
@@ -4,7 +4,8 @@ pub mod canary;
 
 use anyhow::Result;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
 use tracing::{debug, info, warn};
 
 pub use traffic_splitter::TrafficSplitter;
@@ -93,22 +94,27 @@ impl DeploymentManager {
         }
     }
 
-    pub async fn start_background_tasks(self: Arc<Self>) {
-        if let Some(ref canary) = self.canary {
-            let canary_clone = canary.clone();
-            tokio::spawn(async move {
-                loop {
-                    tokio::time::sleep(tokio::time::Duration::from_secs(10)).await;
-
-                    let mut canary = canary_clone.write().await;
-                    if let Err(e) = canary.check_and_update().await {
-                        warn!("Canary check failed: {}", e);
-                    }
+    /// Evaluate the canary periodically until `shutdown` fires; a check in progress
+    /// is finished first. `None` when there is nothing to run.
+    pub async fn start_background_tasks(self: Arc<Self>, mut shutdown: broadcast::Receiver<()>) -> Option<JoinHandle<()>> {
+        let canary_clone = self.canary.clone()?;
+        let handle = tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(tokio::time::Duration::from_secs(10)) => {}
+                    _ = shutdown.recv() => break,
                 }
-            });
-        }
+
+                let mut canary = canary_clone.write().await;
+                if let Err(e) = canary.check_and_update().await {
+                    warn!("Canary check failed: {}", e);
+                }
+            }
+            debug!("Deployment background tasks stopped");
+        });
 
         debug!("Deployment background tasks started");
+        Some(handle)
     }
 }
 
@@ -0,0 +1,124 @@
+//! Country lookups cached per address (`geoip.cache_size`)
+//!
+//! Two generations approximate LRU without bookkeeping on every hit: new entries
+//! go into the young generation and a hit in the old one moves the entry back.
+//! When the young generation fills up it becomes the old one, dropping whatever
+//! was not used since the previous turnover.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+struct Entry {
+    country: Option<String>,
+    expires: Instant,
+}
+
+#[derive(Default)]
+struct Generations {
+    young: HashMap<IpAddr, Entry>,
+    old: HashMap<IpAddr, Entry>,
+}
+
+pub struct CountryCache {
+    /// Size of each generation
+    generation: usize,
+    ttl: Duration,
+    state: Mutex<Generations>,
+}
+
+impl CountryCache {
+    /// Remember about `capacity` addresses for `ttl` each
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            generation: (capacity / 2).max(1),
+            ttl,
+            state: Mutex::new(Generations::default()),
+        }
+    }
+
+    /// Cached country for `ip`; `Some(None)` means the database had none
+    pub fn get(&self, ip: IpAddr) -> Option<Option<String>> {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        if let Some(entry) = state.young.get(&ip) {
+            if entry.expires > now {
+                return Some(entry.country.clone());
+            }
+            state.young.remove(&ip);
+            return None;
+        }
+
+        let entry = state.old.remove(&ip).filter(|entry| entry.expires > now)?;
+        let country = entry.country.clone();
+        self.promote(&mut state, ip, entry);
+        Some(country)
+    }
+
+    pub fn insert(&self, ip: IpAddr, country: Option<String>) {
+        let entry = Entry { country, expires: Instant::now() + self.ttl };
+        let mut state = self.state.lock();
+        state.old.remove(&ip);
+        self.promote(&mut state, ip, entry);
+    }
+
+    /// Forget everything, e.g. after the database changed
+    pub fn clear(&self) {
+        *self.state.lock() = Generations::default();
+    }
+
+    pub fn len(&self) -> usize {
+        let state = self.state.lock();
+        state.young.len() + state.old.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn promote(&self, state: &mut Generations, ip: IpAddr, entry: Entry) {
+        if state.young.len() >= self.generation && !state.young.contains_key(&ip) {
+            state.old = std::mem::take(&mut state.young);
+        }
+        state.young.insert(ip, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::from([192, 0, 2, last])
+    }
+
+    #[test]
+    fn test_keeps_recently_used_addresses() {
+        let cache = CountryCache::new(4, Duration::from_secs(60));
+        cache.insert(ip(1), Some("JP".to_string()));
+        cache.insert(ip(2), None);
+        // Fills the young generation; 1 and 2 age into the old one
+        cache.insert(ip(3), Some("US".to_string()));
+        assert_eq!(cache.get(ip(1)), Some(Some("JP".to_string())));
+        cache.insert(ip(4), Some("GB".to_string()));
+        cache.insert(ip(5), Some("FR".to_string()));
+
+        // 2 was never used again and is gone; 1 was, and survives
+        assert_eq!(cache.get(ip(2)), None);
+        assert_eq!(cache.get(ip(1)), Some(Some("JP".to_string())));
+        assert!(cache.len() <= 4);
+
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = CountryCache::new(10, Duration::from_millis(20));
+        cache.insert(ip(1), None);
+        assert_eq!(cache.get(ip(1)), Some(None));
+        std::thread::sleep(Duration::from_millis(40));
+        assert_eq!(cache.get(ip(1)), None);
+    }
+}
@@ -1,12 +1,56 @@
+pub mod cache;
+
+use crate::metrics::MetricsCollector;
 use anyhow::{Context, Result};
-use maxminddb::{geoip2, Reader};
+use arc_swap::ArcSwap;
+use maxminddb::{geoip2, MaxMindDBError, Reader};
 use std::net::IpAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tracing::{debug, warn};
+use std::time::{Duration, SystemTime};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+use cache::CountryCache;
+
+/// Source of country codes; the MaxMind database outside of tests
+pub trait CountryLookup: Send + Sync {
+    /// ISO country code for `ip`, `None` when the address is not in the database
+    fn country(&self, ip: IpAddr) -> Result<Option<String>>;
+}
+
+impl CountryLookup for Reader<Vec<u8>> {
+    fn country(&self, ip: IpAddr) -> Result<Option<String>> {
+        match self.lookup::<geoip2::Country>(ip) {
+            Ok(country) => Ok(country.country.and_then(|c| c.iso_code).map(|code| code.to_string())),
+            Err(MaxMindDBError::AddressNotFoundError(_)) => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("GeoIP lookup failed for {}", ip)),
+        }
+    }
+}
+
+/// Lookups of one loaded database
+struct Database {
+    reader: Option<Arc<Reader<Vec<u8>>>>,
+    countries: Arc<dyn CountryLookup>,
+}
+
+impl Database {
+    fn open(path: &Path) -> Result<Self> {
+        let reader = Arc::new(Reader::open_readfile(path)
+            .context("Failed to open GeoIP database")?);
+        Ok(Self {
+            countries: Arc::clone(&reader) as Arc<dyn CountryLookup>,
+            reader: Some(reader),
+        })
+    }
+}
 
 pub struct GeoIpManager {
-    reader: Arc<Reader<Vec<u8>>>,
+    /// Swapped whole on reload; lookups already running keep the old one
+    database: ArcSwap<Database>,
+    database_path: Option<PathBuf>,
+    cache: Option<CountryCache>,
     allowed_countries: Vec<String>,
     blocked_countries: Vec<String>,
 }
@@ -17,8 +61,7 @@ impl GeoIpManager {
         allowed_countries: Vec<String>,
         blocked_countries: Vec<String>,
     ) -> Result<Self> {
-        let reader = Reader::open_readfile(database_path)
-            .context("Failed to open GeoIP database")?;
+        let database = Database::open(database_path)?;
 
         debug!(
             "GeoIP database loaded: {} allowed countries, {} blocked countries",
@@ -26,61 +69,167 @@ impl GeoIpManager {
             blocked_countries.len()
         );
 
-        Ok(Self {
-            reader: Arc::new(reader),
+        let manager = Self {
+            database: ArcSwap::from_pointee(database),
+            database_path: Some(database_path.to_path_buf()),
+            cache: None,
             allowed_countries,
             blocked_countries,
-        })
+        };
+        info!("GeoIP database build epoch {}", manager.build_epoch().unwrap_or_default());
+        Ok(manager)
+    }
+
+    /// Filter with countries from `countries` instead of a database file;
+    /// location lookups are unavailable
+    pub fn with_lookup(
+        countries: Arc<dyn CountryLookup>,
+        allowed_countries: Vec<String>,
+        blocked_countries: Vec<String>,
+    ) -> Self {
+        Self {
+            database: ArcSwap::from_pointee(Database { reader: None, countries }),
+            database_path: None,
+            cache: None,
+            allowed_countries,
+            blocked_countries,
+        }
+    }
+
+    /// Remember the country of up to `size` addresses for `ttl`, including
+    /// addresses the database has no country for. A `size` of 0 disables caching.
+    pub fn with_cache(mut self, size: usize, ttl: Duration) -> Self {
+        self.cache = (size > 0).then(|| CountryCache::new(size, ttl));
+        self
+    }
+
+    /// Re-open the database file. On failure the loaded database stays in use.
+    pub fn reload(&self) -> Result<()> {
+        let path = self.database_path.as_ref().context("GeoIP filter has no database file")?;
+        self.database.store(Arc::new(Database::open(path)?));
+        if let Some(ref cache) = self.cache {
+            cache.clear();
+        }
+        info!("GeoIP database reloaded from {}, build epoch {}", path.display(), self.build_epoch().unwrap_or_default());
+        Ok(())
+    }
+
+    /// `build_epoch` from the loaded database's metadata
+    pub fn build_epoch(&self) -> Option<u64> {
+        self.database.load().reader.as_ref().map(|reader| reader.metadata.build_epoch)
+    }
+
+    /// Reload whenever the database file's modification time changes, checking
+    /// every `interval` until `shutdown` fires
+    pub fn spawn_reload_watcher(
+        self: Arc<Self>,
+        interval: Duration,
+        mut shutdown: broadcast::Receiver<()>,
+    ) -> Option<JoinHandle<()>> {
+        let path = self.database_path.clone()?;
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut last_modified: Option<SystemTime> = modified(&path);
+
+        Some(tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = shutdown.recv() => return,
+                }
+                let current = modified(&path);
+                if current.is_none() || current == last_modified {
+                    continue;
+                }
+                match self.reload() {
+                    Ok(()) => last_modified = current,
+                    // Possibly caught mid-write; the next tick tries again
+                    Err(e) => warn!("GeoIP database reload failed: {:#}", e),
+                }
+            }
+        }))
     }
 
     pub fn is_allowed(&self, ip: IpAddr) -> Result<bool> {
         let country = self.lookup_country(ip)?;
+        Ok(self.permits(ip, country.as_deref()))
+    }
+
+    /// Decide on a new connection and count the outcome in the `geoip_*` metrics.
+    /// Lookup failures let the connection through so a bad database does not
+    /// block legitimate traffic.
+    pub fn check(&self, ip: IpAddr, metrics: &MetricsCollector) -> bool {
+        let country = match self.cached_country(ip, Some(metrics)) {
+            Ok(country) => country,
+            Err(e) => {
+                warn!("{:#}", e);
+                metrics.inc_geoip_lookup_error();
+                return true;
+            }
+        };
+
+        if self.permits(ip, country.as_deref()) {
+            metrics.inc_geoip_allowed();
+            true
+        } else {
+            metrics.inc_geoip_blocked(country.as_deref().unwrap_or("unknown"));
+            false
+        }
+    }
 
+    fn permits(&self, ip: IpAddr, country: Option<&str>) -> bool {
         let country_code = match country {
             Some(code) => code,
             None => {
                 debug!("No country found for IP {}, allowing by default", ip);
-                return Ok(true);
+                return true;
             }
         };
 
-        if !self.blocked_countries.is_empty() {
-            if self.blocked_countries.contains(&country_code) {
-                debug!("IP {} blocked (country: {})", ip, country_code);
-                return Ok(false);
-            }
+        if self.blocked_countries.iter().any(|c| c == country_code) {
+            debug!("IP {} blocked (country: {})", ip, country_code);
+            return false;
         }
 
         if !self.allowed_countries.is_empty() {
-            let allowed = self.allowed_countries.contains(&country_code);
+            let allowed = self.allowed_countries.iter().any(|c| c == country_code);
             if !allowed {
                 debug!("IP {} not in allowed countries (country: {})", ip, country_code);
             }
-            return Ok(allowed);
+            return allowed;
         }
 
-        Ok(true)
+        true
     }
 
     pub fn lookup_country(&self, ip: IpAddr) -> Result<Option<String>> {
-        match self.reader.lookup::<geoip2::Country>(ip) {
-            Ok(country) => {
-                if let Some(c) = country.country {
-                    if let Some(iso_code) = c.iso_code {
-                        return Ok(Some(iso_code.to_string()));
-                    }
-                }
-                Ok(None)
-            }
-            Err(e) => {
-                warn!("GeoIP lookup failed for {}: {}", ip, e);
-                Ok(None)
+        self.cached_country(ip, None)
+    }
+
+    /// Country from the cache, else from the database; failed lookups are not cached
+    fn cached_country(&self, ip: IpAddr, metrics: Option<&MetricsCollector>) -> Result<Option<String>> {
+        let Some(ref cache) = self.cache else {
+            return self.database.load().countries.country(ip);
+        };
+        if let Some(country) = cache.get(ip) {
+            if let Some(metrics) = metrics {
+                metrics.inc_geoip_cache_hit();
             }
+            return Ok(country);
         }
+        if let Some(metrics) = metrics {
+            metrics.inc_geoip_cache_miss();
+        }
+        let country = self.database.load().countries.country(ip)?;
+        cache.insert(ip, country.clone());
+        Ok(country)
     }
 
     pub fn lookup_location(&self, ip: IpAddr) -> Result<Option<LocationInfo>> {
-        match self.reader.lookup::<geoip2::City>(ip) {
+        let database = self.database.load_full();
+        let Some(ref reader) = database.reader else {
+            return Ok(None);
+        };
+        match reader.lookup::<geoip2::City>(ip) {
             Ok(city) => {
                 let country = city.country.and_then(|c| c.iso_code).map(|s| s.to_string());
                 let city_name = city.city
@@ -121,4 +270,144 @@ mod tests {
     fn test_geoip_manager_requires_database() {
 
     }
+
+    /// Fixed table of addresses; 192.0.2.99 fails like a corrupt database would
+    struct TableLookup;
+
+    impl CountryLookup for TableLookup {
+        fn country(&self, ip: IpAddr) -> Result<Option<String>> {
+            match ip.to_string().as_str() {
+                "192.0.2.1" => Ok(Some("JP".to_string())),
+                "192.0.2.2" => Ok(Some("XA".to_string())),
+                "192.0.2.99" => Err(anyhow::anyhow!("corrupt record")),
+                _ => Ok(None),
+            }
+        }
+    }
+
+    #[test]
+    fn test_decisions_are_counted() {
+        let metrics = MetricsCollector::new();
+        let geoip = GeoIpManager::with_lookup(Arc::new(TableLookup), Vec::new(), vec!["XA".to_string()]);
+        let blocked = metrics.get_geoip_blocked("XA");
+        let allowed = metrics.get_geoip_allowed();
+        let errors = metrics.get_geoip_lookup_errors();
+
+        assert!(!geoip.check("192.0.2.2".parse().unwrap(), &metrics));
+        assert_eq!(metrics.get_geoip_blocked("XA"), blocked + 1);
+
+        assert!(geoip.check("192.0.2.1".parse().unwrap(), &metrics));
+        assert!(geoip.check("198.51.100.7".parse().unwrap(), &metrics));
+        assert_eq!(metrics.get_geoip_allowed(), allowed + 2);
+
+        // Lookup failures let the connection through
+        assert!(geoip.check("192.0.2.99".parse().unwrap(), &metrics));
+        assert_eq!(metrics.get_geoip_lookup_errors(), errors + 1);
+        assert_eq!(metrics.get_geoip_blocked("XA"), blocked + 1);
+    }
+
+    #[test]
+    fn test_cached_lookups() {
+        struct CountingLookup(std::sync::atomic::AtomicUsize);
+
+        impl CountryLookup for CountingLookup {
+            fn country(&self, ip: IpAddr) -> Result<Option<String>> {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                TableLookup.country(ip)
+            }
+        }
+
+        let metrics = MetricsCollector::new();
+        let lookup = Arc::new(CountingLookup(Default::default()));
+        let geoip = GeoIpManager::with_lookup(Arc::clone(&lookup) as Arc<dyn CountryLookup>, Vec::new(), vec!["XA".to_string()])
+            .with_cache(100, Duration::from_secs(60));
+        let hits = metrics.get_geoip_cache_hits();
+        let misses = metrics.get_geoip_cache_misses();
+
+        // Blocked, unknown (cached as such) and failing addresses
+        for _ in 0..3 {
+            assert!(!geoip.check("192.0.2.2".parse().unwrap(), &metrics));
+            assert!(geoip.check("198.51.100.7".parse().unwrap(), &metrics));
+            assert!(geoip.check("192.0.2.99".parse().unwrap(), &metrics));
+        }
+        // The failing address is looked up every time
+        assert_eq!(lookup.0.load(std::sync::atomic::Ordering::Relaxed), 2 + 3);
+        assert_eq!(metrics.get_geoip_cache_hits(), hits + 4);
+        assert_eq!(metrics.get_geoip_cache_misses(), misses + 5);
+    }
+
+    #[test]
+    fn test_allowlist_blocks_other_countries() {
+        let geoip = GeoIpManager::with_lookup(Arc::new(TableLookup), vec!["JP".to_string()], Vec::new());
+
+        assert!(geoip.is_allowed("192.0.2.1".parse().unwrap()).unwrap());
+        assert!(!geoip.is_allowed("192.0.2.2".parse().unwrap()).unwrap());
+        // Addresses without a country are allowed
+        assert!(geoip.is_allowed("198.51.100.7".parse().unwrap()).unwrap());
+    }
+
+    /// Smallest valid IPv4 database: one search node that maps everything to
+    /// "not found", then the metadata
+    fn empty_database(build_epoch: u64) -> Vec<u8> {
+        let string = |s: &str| [&[0x40 | s.len() as u8][..], s.as_bytes()].concat();
+        let uint16 = |n: u16| vec![0xa2, (n >> 8) as u8, n as u8];
+        let entries: [(&str, Vec<u8>); 9] = [
+            ("binary_format_major_version", uint16(2)),
+            ("binary_format_minor_version", uint16(0)),
+            ("build_epoch", [&[0x08, 0x02][..], &build_epoch.to_be_bytes()].concat()),
+            ("database_type", string("Test-Country")),
+            ("description", vec![0xe0]),
+            ("ip_version", uint16(4)),
+            ("languages", vec![0x00, 0x04]),
+            ("node_count", vec![0xc1, 1]),
+            ("record_size", uint16(24)),
+        ];
+
+        let mut db = vec![0, 0, 1, 0, 0, 1];
+        db.extend([0u8; 16]);
+        db.extend(b"\xab\xcd\xefMaxMind.com");
+        db.push(0xe0 | entries.len() as u8);
+        for (key, value) in entries {
+            db.extend(string(key));
+            db.extend(value);
+        }
+        db
+    }
+
+    #[tokio::test]
+    async fn test_reload_picks_up_new_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("GeoLite2-Country.mmdb");
+        std::fs::write(&path, empty_database(1_700_000_000)).unwrap();
+        let geoip = Arc::new(GeoIpManager::new(&path, Vec::new(), Vec::new()).unwrap());
+        assert_eq!(geoip.build_epoch(), Some(1_700_000_000));
+
+        // A lookup holding the old database is unaffected by a reload
+        let old = geoip.database.load_full();
+        std::fs::write(&path, empty_database(1_700_600_000)).unwrap();
+        geoip.reload().unwrap();
+        assert_eq!(geoip.build_epoch(), Some(1_700_600_000));
+        assert_eq!(old.reader.as_ref().unwrap().metadata.build_epoch, 1_700_000_000);
+        assert!(old.countries.country("192.0.2.1".parse().unwrap()).unwrap().is_none());
+
+        // A broken file keeps the current database
+        std::fs::write(&path, b"not a database").unwrap();
+        assert!(geoip.reload().is_err());
+        assert_eq!(geoip.build_epoch(), Some(1_700_600_000));
+
+        // The watcher notices the file changing
+        let (shutdown, rx) = broadcast::channel(1);
+        let watcher = Arc::clone(&geoip).spawn_reload_watcher(Duration::from_millis(20), rx).unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        std::fs::write(&path, empty_database(1_701_200_000)).unwrap();
+        // Make sure the mtime moves even on coarse-grained filesystems
+        let later = SystemTime::now() + Duration::from_secs(5);
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(later).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(geoip.build_epoch(), Some(1_701_200_000));
+
+        shutdown.send(()).unwrap();
+        watcher.await.unwrap();
+        assert!(GeoIpManager::with_lookup(Arc::new(TableLookup), Vec::new(), Vec::new()).reload().is_err());
+    }
 }
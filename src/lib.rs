@@ -24,3 +24,9 @@ pub use server::Server;
 
 /// Library version
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Git commit the binary was built from (`unknown` outside a checkout)
+pub const GIT_COMMIT: &str = env!("FE_PHP_GIT_COMMIT");
+
+/// Version of the compiler that built the binary
+pub const RUSTC_VERSION: &str = env!("FE_PHP_RUSTC_VERSION");
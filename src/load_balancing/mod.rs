@@ -1,8 +1,14 @@
-use anyhow::Result;
+use crate::backend::Deadline;
+use crate::config::{StickyConfig, StickyMode};
+use crate::metrics::MetricsCollector;
+use crate::php::{PhpRequest, PhpResponse, ResponseHeaders};
+use anyhow::{Context, Result};
+use std::fmt;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
 use tracing::debug;
 
 #[derive(Debug)]
@@ -20,10 +26,31 @@ struct SimpleCircuitBreaker {
     success_threshold: u32,
     last_failure_time: Arc<RwLock<Option<Instant>>>,
     timeout: Duration,
+    probes_in_flight: Arc<AtomicUsize>,
+    max_probes: usize,
+}
+
+/// Why the breaker refused a request
+enum Rejection {
+    /// Still open; the next probe is allowed after this long
+    Open(Duration),
+    /// Half-open with every probe slot taken
+    ProbesBusy,
+}
+
+/// Slot held by a half-open trial request; released on drop
+struct ProbePermit {
+    probes_in_flight: Arc<AtomicUsize>,
+}
+
+impl Drop for ProbePermit {
+    fn drop(&mut self) {
+        self.probes_in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
 }
 
 impl SimpleCircuitBreaker {
-    fn new(failure_threshold: u32, success_threshold: u32, timeout: Duration) -> Self {
+    fn new(failure_threshold: u32, success_threshold: u32, timeout: Duration, max_probes: usize) -> Self {
         Self {
             state: Arc::new(RwLock::new(CircuitState::Closed)),
             failure_count: Arc::new(AtomicUsize::new(0)),
@@ -32,11 +59,42 @@ impl SimpleCircuitBreaker {
             success_threshold,
             last_failure_time: Arc::new(RwLock::new(None)),
             timeout,
+            probes_in_flight: Arc::new(AtomicUsize::new(0)),
+            max_probes: max_probes.max(1),
         }
     }
 
-    async fn is_open(&self) -> bool {
-        matches!(*self.state.read().await, CircuitState::Open)
+    /// Admit a request; in half-open only `max_probes` trial requests run at once
+    async fn acquire(&self) -> std::result::Result<Option<ProbePermit>, Rejection> {
+        self.try_reset().await;
+
+        let state = self.state.read().await;
+        match *state {
+            CircuitState::Closed => Ok(None),
+            CircuitState::Open => Err(Rejection::Open(self.remaining_timeout().await)),
+            CircuitState::HalfOpen => self
+                .probes_in_flight
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < self.max_probes).then_some(n + 1))
+                .map(|_| {
+                    Some(ProbePermit {
+                        probes_in_flight: Arc::clone(&self.probes_in_flight),
+                    })
+                })
+                .map_err(|_| Rejection::ProbesBusy),
+        }
+    }
+
+    /// Time left before an open breaker lets a probe through; `None` unless open
+    async fn time_until_probe(&self) -> Option<Duration> {
+        if !matches!(*self.state.read().await, CircuitState::Open) {
+            return None;
+        }
+        Some(self.remaining_timeout().await)
+    }
+
+    async fn remaining_timeout(&self) -> Duration {
+        let last_failure = *self.last_failure_time.read().await;
+        last_failure.map_or(Duration::ZERO, |time| self.timeout.saturating_sub(time.elapsed()))
     }
 
     async fn record_success(&self) {
@@ -79,10 +137,197 @@ impl SimpleCircuitBreaker {
     }
 }
 
+/// No upstream could take the request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoUpstream {
+    /// Every enabled upstream failed its health checks
+    AllUnhealthy,
+    /// Healthy upstreams exist, but all of their circuit breakers are open
+    AllCircuitsOpen,
+}
+
+impl NoUpstream {
+    /// Label for `upstream_unavailable_total`
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::AllUnhealthy => "unhealthy",
+            Self::AllCircuitsOpen => "circuit_open",
+        }
+    }
+}
+
+impl fmt::Display for NoUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AllUnhealthy => write!(f, "No healthy upstreams available"),
+            Self::AllCircuitsOpen => write!(f, "All healthy upstreams have an open circuit breaker"),
+        }
+    }
+}
+
+impl std::error::Error for NoUpstream {}
+
+/// Largest upstream response body buffered before it is relayed to the client
+pub const MAX_UPSTREAM_RESPONSE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The upstream's response body is larger than [`MAX_UPSTREAM_RESPONSE_SIZE`]
+#[derive(Debug)]
+pub struct ResponseTooLarge {
+    pub upstream: String,
+}
+
+impl fmt::Display for ResponseTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Response from upstream '{}' exceeds {} bytes",
+            self.upstream, MAX_UPSTREAM_RESPONSE_SIZE
+        )
+    }
+}
+
+impl std::error::Error for ResponseTooLarge {}
+
+/// Headers that describe a single hop and are not forwarded in either direction
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn is_hop_by_hop(name: &str) -> bool {
+    HOP_BY_HOP_HEADERS.iter().any(|h| h.eq_ignore_ascii_case(name))
+}
+
+/// Methods that may be replayed on another upstream after any failure; other
+/// requests are only retried when the connection could not be established
+fn is_idempotent(method: &str) -> bool {
+    matches!(
+        method.to_ascii_uppercase().as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "PUT" | "DELETE" | "TRACE"
+    )
+}
+
+fn client_ip(remote_addr: &str) -> String {
+    remote_addr
+        .parse::<std::net::SocketAddr>()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_else(|_| remote_addr.to_string())
+}
+
+/// Value of cookie `name` in the request's `Cookie` header
+fn request_cookie(headers: &std::collections::HashMap<String, String>, name: &str) -> Option<String> {
+    let (_, cookies) = headers.iter().find(|(header, _)| header.eq_ignore_ascii_case("cookie"))?;
+    cookies.split(';').find_map(|pair| {
+        let (key, value) = pair.trim().split_once('=')?;
+        (key == name).then(|| value.to_string())
+    })
+}
+
+/// Stable 64-bit hash of a pair of strings, the same in every process:
+/// FNV-1a, then the SplitMix64 finalizer to spread the bits
+fn stable_hash(first: &str, second: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in first.bytes().chain(std::iter::once(0xff)).chain(second.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+/// Weighted rendezvous hashing: every upstream scores the key and the highest
+/// score wins, so adding or removing one upstream only moves the keys it wins
+/// or loses. Weights skew the scores the way `select_weighted` skews turns.
+fn select_rendezvous<'a>(available: &[&'a UpstreamServer], key: &str) -> Option<&'a UpstreamServer> {
+    let score = |upstream: &UpstreamServer| {
+        // Uniform in (0, 1) from the top 53 bits
+        let unit = ((stable_hash(key, &upstream.name) >> 11) as f64 + 0.5) / (1u64 << 53) as f64;
+        upstream.weight as f64 / -unit.ln()
+    };
+    available
+        .iter()
+        .copied()
+        .max_by(|a, b| score(a).total_cmp(&score(b)))
+}
+
+/// Consistent hash ring over the upstreams that can take requests, with
+/// `VIRTUAL_NODES` points per unit of weight. Removing an upstream only moves
+/// the keys that landed on its points.
+#[derive(Default)]
+struct HashRing {
+    points: Vec<(u64, String)>,
+}
+
+impl HashRing {
+    const VIRTUAL_NODES: u32 = 160;
+
+    fn build(upstreams: &[UpstreamServer]) -> Self {
+        let mut points: Vec<(u64, String)> = upstreams
+            .iter()
+            .filter(|u| u.is_enabled() && u.is_healthy())
+            .flat_map(|u| {
+                (0..u.weight.max(1) * Self::VIRTUAL_NODES)
+                    .map(move |i| (stable_hash(&u.name, &i.to_string()), u.name.clone()))
+            })
+            .collect();
+        points.sort_unstable();
+        Self { points }
+    }
+
+    /// Upstream names met walking clockwise from `key`'s position
+    fn walk<'a>(&'a self, key: &str) -> impl Iterator<Item = &'a str> {
+        let hash = stable_hash(key, "");
+        let start = self.points.partition_point(|(point, _)| *point < hash);
+        self.points[start..]
+            .iter()
+            .chain(&self.points[..start])
+            .map(|(_, name)| name.as_str())
+    }
+}
+
+/// Request attributes that steer upstream selection
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SelectionKey<'a> {
+    /// Sticky session key: an upstream name in `cookie` mode, the client IP in `ip_hash` mode
+    pub sticky: Option<&'a str>,
+    /// Placed on the ring by the `consistent_hash` algorithm
+    pub hash: Option<&'a str>,
+}
+
+/// Counts a request against an upstream's active connections while alive
+struct ActiveConnection<'a>(&'a UpstreamServer);
+
+impl<'a> ActiveConnection<'a> {
+    fn new(upstream: &'a UpstreamServer) -> Self {
+        upstream.increment_connections();
+        Self(upstream)
+    }
+}
+
+impl Drop for ActiveConnection<'_> {
+    fn drop(&mut self) {
+        self.0.decrement_connections();
+    }
+}
+
 pub struct LoadBalancingManager {
     upstreams: Arc<RwLock<Vec<UpstreamServer>>>,
     algorithm: LoadBalancingAlgorithm,
     round_robin_counter: Arc<AtomicUsize>,
+    max_tries: Option<usize>,
+    client: reqwest::Client,
+    metrics: Option<Arc<MetricsCollector>>,
+    sticky: Option<StickyConfig>,
+    /// Only built for `consistent_hash`; rebuilt when upstream health changes
+    ring: Arc<RwLock<HashRing>>,
+    hash_header: Option<String>,
 }
 
 impl LoadBalancingManager {
@@ -96,6 +341,8 @@ impl LoadBalancingManager {
             crate::config::LoadBalancingAlgorithm::WeightedRoundRobin => LoadBalancingAlgorithm::WeightedRoundRobin,
             crate::config::LoadBalancingAlgorithm::LeastConn => LoadBalancingAlgorithm::LeastConnections,
             crate::config::LoadBalancingAlgorithm::IpHash => LoadBalancingAlgorithm::Random, // Fallback to random for IpHash
+            crate::config::LoadBalancingAlgorithm::LeastResponseTime => LoadBalancingAlgorithm::LeastResponseTime,
+            crate::config::LoadBalancingAlgorithm::ConsistentHash => LoadBalancingAlgorithm::ConsistentHash,
         };
 
         let upstream_servers = upstreams
@@ -117,23 +364,103 @@ impl LoadBalancingManager {
             algorithm
         );
 
+        // Redirects are the client's business, not the proxy's
+        let client = reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .context("Failed to create HTTP client for upstreams")?;
+
+        let ring = match algorithm {
+            LoadBalancingAlgorithm::ConsistentHash => HashRing::build(&upstream_servers),
+            _ => HashRing::default(),
+        };
+
         Ok(Self {
+            ring: Arc::new(RwLock::new(ring)),
+            hash_header: None,
             upstreams: Arc::new(RwLock::new(upstream_servers)),
             algorithm,
             round_robin_counter: Arc::new(AtomicUsize::new(0)),
+            max_tries: None,
+            client,
+            metrics: None,
+            sticky: None,
         })
     }
 
-    pub async fn select_upstream(&self) -> Result<UpstreamServer> {
+    /// Count requests that find no upstream in `upstream_unavailable_total`, and
+    /// export the `upstream_*` health, connection and request metrics
+    pub fn with_metrics(mut self, metrics: Arc<MetricsCollector>) -> Self {
+        // Nothing else holds the lock while the manager is being built
+        if let Ok(mut upstreams) = self.upstreams.try_write() {
+            for upstream in upstreams.iter_mut() {
+                metrics.set_upstream_healthy(&upstream.name, upstream.is_healthy());
+                metrics.register_upstream_active_connections(&upstream.name);
+                upstream.metrics = Some(metrics.clone());
+            }
+        }
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Give up after trying this many upstreams for one request; all of them when unset
+    pub fn with_max_tries(mut self, max_tries: Option<usize>) -> Self {
+        self.max_tries = max_tries.map(|n| n.max(1));
+        self
+    }
+
+    /// Pin clients to upstreams by the key [`proxy_request`](Self::proxy_request) derives
+    pub fn with_sticky(mut self, sticky: Option<StickyConfig>) -> Self {
+        self.sticky = sticky;
+        self
+    }
+
+    /// Request header whose value `consistent_hash` places on the ring; the
+    /// request URI when unset or absent
+    pub fn with_hash_header(mut self, header: Option<String>) -> Self {
+        self.hash_header = header;
+        self
+    }
+
+    /// Pick a healthy upstream whose circuit breaker would let the request through.
+    /// With sticky sessions, `key.sticky` chooses it when possible; otherwise the
+    /// algorithm does. Fails with [`NoUpstream`].
+    pub async fn select_upstream(&self, key: SelectionKey<'_>) -> Result<UpstreamServer> {
+        self.select_excluding(&[], key).await
+    }
+
+    async fn select_excluding(&self, tried: &[String], key: SelectionKey<'_>) -> Result<UpstreamServer> {
         let upstreams = self.upstreams.read().await;
 
-        let available: Vec<&UpstreamServer> = upstreams
+        let healthy: Vec<&UpstreamServer> = upstreams
             .iter()
-            .filter(|u| u.enabled && u.is_healthy())
+            .filter(|u| u.is_enabled() && u.is_healthy() && !tried.contains(&u.name))
             .collect();
 
+        if healthy.is_empty() {
+            return Err(NoUpstream::AllUnhealthy.into());
+        }
+
+        let mut available = Vec::with_capacity(healthy.len());
+        for upstream in healthy {
+            if !upstream.circuit_open().await {
+                available.push(upstream);
+            }
+        }
+
         if available.is_empty() {
-            anyhow::bail!("No healthy upstreams available");
+            return Err(NoUpstream::AllCircuitsOpen.into());
+        }
+
+        if let (Some(sticky), Some(key)) = (&self.sticky, key.sticky) {
+            let pinned = match sticky.mode {
+                StickyMode::Cookie => available.iter().copied().find(|u| u.name == key),
+                StickyMode::IpHash => select_rendezvous(&available, key),
+            };
+            match pinned {
+                Some(upstream) => return Ok(upstream.clone()),
+                None => debug!("Pinned upstream '{}' is unavailable, selecting another", key),
+            }
         }
 
         let selected = match self.algorithm {
@@ -154,11 +481,235 @@ impl LoadBalancingManager {
                 let index = rand::random::<usize>() % available.len();
                 available[index]
             }
+            LoadBalancingAlgorithm::LeastResponseTime => {
+                // Requests already in flight add to the wait, so a fast upstream
+                // doesn't take every request while it is busy
+                let score = |u: &UpstreamServer| {
+                    u.response_time_ewma_ms() * (u.active_connections.load(Ordering::Relaxed) + 1) as f64
+                };
+                available
+                    .iter()
+                    .min_by(|a, b| score(a).total_cmp(&score(b)))
+                    .unwrap()
+            }
+            LoadBalancingAlgorithm::ConsistentHash => {
+                // Points of upstreams that can't take the request pass it clockwise
+                let ring = self.ring.read().await;
+                let owner = key.hash.and_then(|hash| {
+                    ring.walk(hash)
+                        .find_map(|name| available.iter().copied().find(|u| u.name == name))
+                });
+                owner.unwrap_or_else(|| {
+                    let index = self.round_robin_counter.fetch_add(1, Ordering::Relaxed) % available.len();
+                    available[index]
+                })
+            }
         };
 
         Ok(selected.clone())
     }
 
+    /// Forward `req` to a selected upstream and return its response. Upstreams
+    /// that cannot be reached are skipped in favour of the next one, up to
+    /// `max_tries`; other failures are only retried for idempotent methods.
+    /// The whole exchange, retries included, ends at `deadline`. `scheme` is the
+    /// client's (`http` or `https`), sent as `X-Forwarded-Proto`. A `5xx` from the
+    /// upstream is relayed as is but counts as a failure for its circuit breaker.
+    /// Fails with [`NoUpstream`] when no upstream could take the request.
+    pub async fn proxy_request(&self, req: PhpRequest, deadline: Option<Deadline>, scheme: &str) -> Result<PhpResponse> {
+        let retry_any = is_idempotent(&req.method);
+        let affinity = self.sticky.as_ref().and_then(|sticky| match sticky.mode {
+            StickyMode::Cookie => request_cookie(&req.headers, &sticky.cookie_name),
+            StickyMode::IpHash => Some(client_ip(&req.remote_addr)),
+        });
+        let hash = self
+            .hash_header
+            .as_ref()
+            .and_then(|header| req.headers.iter().find(|(name, _)| name.eq_ignore_ascii_case(header)))
+            .map_or(req.uri.as_str(), |(_, value)| value.as_str());
+        let key = SelectionKey {
+            sticky: affinity.as_deref(),
+            hash: Some(hash),
+        };
+        self.failover(
+            self.metrics.as_deref(),
+            key,
+            |upstream| self.forward(upstream, &req, deadline, scheme),
+            |e| {
+                // Another upstream would only time out as well
+                !deadline.is_some_and(|d| d.is_expired())
+                    && (retry_any || e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_connect()))
+            },
+            |response| response.status_code >= 500,
+        )
+        .await
+    }
+
+    async fn forward(
+        &self,
+        upstream: UpstreamServer,
+        req: &PhpRequest,
+        deadline: Option<Deadline>,
+        scheme: &str,
+    ) -> Result<PhpResponse> {
+        let _active = ActiveConnection::new(&upstream);
+        let start = Instant::now();
+
+        let method = reqwest::Method::from_bytes(req.method.as_bytes())
+            .with_context(|| format!("Invalid request method {}", req.method))?;
+        let url = format!("{}{}", upstream.url.trim_end_matches('/'), req.uri);
+
+        let mut request = self.client.request(method, &url);
+        if let Some(deadline) = deadline {
+            request = request.timeout(deadline.remaining());
+        }
+        for (name, value) in &req.headers {
+            if is_hop_by_hop(name)
+                || name.eq_ignore_ascii_case("host")
+                || name.eq_ignore_ascii_case("content-length")
+                || name.eq_ignore_ascii_case("x-forwarded-for")
+                || name.eq_ignore_ascii_case("x-forwarded-proto")
+                || name.eq_ignore_ascii_case("x-real-ip")
+            {
+                continue;
+            }
+            request = request.header(name.as_str(), value.as_str());
+        }
+        if let Some(host) = req.headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("host")) {
+            request = request.header("X-Forwarded-Host", host.1.as_str());
+        }
+        let client_ip = client_ip(&req.remote_addr);
+        let forwarded_for = match req.headers.iter().find(|(name, _)| name.eq_ignore_ascii_case("x-forwarded-for")) {
+            Some((_, earlier)) => format!("{}, {}", earlier, client_ip),
+            None => client_ip.clone(),
+        };
+        request = request
+            .header("X-Forwarded-For", forwarded_for)
+            .header("X-Forwarded-Proto", scheme)
+            .header("X-Real-IP", client_ip);
+
+        let mut response = request.body(req.body.clone()).send().await?;
+
+        let status_code = response.status().as_u16();
+        let mut headers: ResponseHeaders = response
+            .headers()
+            .iter()
+            .filter(|(name, _)| !is_hop_by_hop(name.as_str()) && *name != reqwest::header::CONTENT_LENGTH)
+            .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.as_str(), value)))
+            .collect();
+        let too_large = || ResponseTooLarge { upstream: upstream.name.clone() };
+        if response.content_length().is_some_and(|len| len > MAX_UPSTREAM_RESPONSE_SIZE as u64) {
+            return Err(too_large().into());
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > MAX_UPSTREAM_RESPONSE_SIZE {
+                return Err(too_large().into());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        // (Re-)pin the client when it had no cookie or its upstream was unavailable
+        if let Some(sticky) = self.sticky.as_ref().filter(|s| s.mode == StickyMode::Cookie) {
+            if request_cookie(&req.headers, &sticky.cookie_name).as_deref() != Some(upstream.name.as_str()) {
+                headers.append(
+                    "Set-Cookie",
+                    format!("{}={}; Path=/; HttpOnly; SameSite=Lax", sticky.cookie_name, upstream.name),
+                );
+            }
+        }
+
+        debug!("Proxied {} {} to upstream '{}': {}", req.method, req.uri, upstream.name, status_code);
+
+        Ok(PhpResponse {
+            status_code,
+            headers,
+            body,
+            execution_time_ms: start.elapsed().as_millis() as u64,
+            memory_peak_mb: 0.0,
+        })
+    }
+
+    /// Run `f` against selected upstreams, moving on to another one after a failure,
+    /// until it succeeds or `max_tries` distinct upstreams have been tried
+    pub async fn call_with_failover<F, Fut, T>(&self, metrics: Option<&MetricsCollector>, f: F) -> Result<T>
+    where
+        F: Fn(UpstreamServer) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        self.call_with_failover_if(metrics, SelectionKey::default(), f, |_| true).await
+    }
+
+    /// [`call_with_failover`](Self::call_with_failover) starting from the
+    /// upstream `key` selects, giving up at the first error `retryable` rejects
+    pub async fn call_with_failover_if<F, Fut, T, R>(
+        &self,
+        metrics: Option<&MetricsCollector>,
+        key: SelectionKey<'_>,
+        f: F,
+        retryable: R,
+    ) -> Result<T>
+    where
+        F: Fn(UpstreamServer) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+        R: Fn(&anyhow::Error) -> bool,
+    {
+        self.failover(metrics, key, f, retryable, |_| false).await
+    }
+
+    /// [`call_with_failover_if`](Self::call_with_failover_if) where a result
+    /// `failed` accepts is returned but counts as a failure of its upstream
+    async fn failover<F, Fut, T, R, D>(
+        &self,
+        metrics: Option<&MetricsCollector>,
+        key: SelectionKey<'_>,
+        f: F,
+        retryable: R,
+        failed: D,
+    ) -> Result<T>
+    where
+        F: Fn(UpstreamServer) -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+        R: Fn(&anyhow::Error) -> bool,
+        D: Fn(&T) -> bool,
+    {
+        let max_tries = self.max_tries.unwrap_or(usize::MAX);
+        let mut tried = Vec::new();
+        let mut last_error = None;
+
+        while tried.len() < max_tries {
+            let upstream = match self.select_excluding(&tried, key).await {
+                Ok(upstream) => upstream,
+                // Nothing left to fail over to; the last upstream's error says more
+                Err(e) => match last_error {
+                    Some(last_error) => return Err(last_error),
+                    None => {
+                        if let (Some(metrics), Some(reason)) = (metrics, e.downcast_ref::<NoUpstream>()) {
+                            metrics.inc_upstream_unavailable(reason.reason());
+                        }
+                        return Err(e);
+                    }
+                },
+            };
+
+            tried.push(upstream.name.clone());
+            let name = upstream.name.clone();
+            let call = upstream.clone();
+            match upstream.call_with_circuit_breaker_if(|| f(call), &failed).await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    debug!("Upstream '{}' failed (try {}): {}", name, tried.len(), e);
+                    if !retryable(&e) {
+                        return Err(e);
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No upstreams tried")))
+    }
+
     fn select_weighted<'a>(&self, available: &[&'a UpstreamServer]) -> &'a UpstreamServer {
         let total_weight: u32 = available.iter().map(|u| u.weight).sum();
         let mut target = (self.round_robin_counter.fetch_add(1, Ordering::Relaxed) as u32) % total_weight;
@@ -176,9 +727,36 @@ impl LoadBalancingManager {
     pub async fn update_health(&self, name: &str, healthy: bool) {
         let mut upstreams = self.upstreams.write().await;
         if let Some(upstream) = upstreams.iter_mut().find(|u| u.name == name) {
+            let changed = upstream.is_healthy() != healthy;
             upstream.set_healthy(healthy);
             debug!("Updated health for upstream '{}': healthy={}", name, healthy);
+            if changed && matches!(self.algorithm, LoadBalancingAlgorithm::ConsistentHash) {
+                *self.ring.write().await = HashRing::build(&upstreams);
+            }
+        }
+    }
+
+    /// Put an upstream back into rotation, or drain it: a disabled upstream
+    /// gets no new requests while the ones in flight finish. Fails when no
+    /// upstream is named `name`.
+    pub async fn set_enabled(&self, name: &str, enabled: bool) -> Result<()> {
+        // A read lock is enough, so draining never queues requests behind a health check round
+        let upstreams = self.upstreams.read().await;
+        let upstream = upstreams
+            .iter()
+            .find(|u| u.name == name)
+            .ok_or_else(|| anyhow::anyhow!("Unknown upstream '{}'", name))?;
+        let changed = upstream.set_enabled(enabled);
+        debug!(
+            "Upstream '{}' {} ({} requests in flight)",
+            name,
+            if enabled { "enabled" } else { "draining" },
+            upstream.active_connections.load(Ordering::Relaxed)
+        );
+        if changed && matches!(self.algorithm, LoadBalancingAlgorithm::ConsistentHash) {
+            *self.ring.write().await = HashRing::build(&upstreams);
         }
+        Ok(())
     }
 
     pub async fn get_upstreams_status(&self) -> Vec<UpstreamStatus> {
@@ -188,42 +766,52 @@ impl LoadBalancingManager {
             .map(|u| UpstreamStatus {
                 name: u.name.clone(),
                 url: u.url.clone(),
-                enabled: u.enabled,
+                enabled: u.is_enabled(),
+                draining: !u.is_enabled() && u.active_connections.load(Ordering::Relaxed) > 0,
                 healthy: u.is_healthy(),
                 active_connections: u.active_connections.load(Ordering::Relaxed),
                 total_requests: u.total_requests.load(Ordering::Relaxed),
                 failed_requests: u.failed_requests.load(Ordering::Relaxed),
+                response_time_ewma_ms: u.response_time_ewma_ms(),
             })
             .collect()
     }
 
+    /// Probe upstreams in the background until `shutdown` fires; a round in
+    /// progress is finished first. `None` when health checks are disabled.
     pub async fn start_health_checks(
         &self,
         health_check_config: crate::config::HealthCheckConfig,
-    ) {
+        mut shutdown: broadcast::Receiver<()>,
+    ) -> Option<JoinHandle<()>> {
         if !health_check_config.enable {
-            return;
+            return None;
         }
 
         let upstreams = self.upstreams.clone();
+        let ring = matches!(self.algorithm, LoadBalancingAlgorithm::ConsistentHash).then(|| self.ring.clone());
         let interval = Duration::from_secs(health_check_config.interval_seconds);
         let timeout = Duration::from_secs(health_check_config.timeout_seconds);
         let path = health_check_config.path.clone();
         let unhealthy_threshold = health_check_config.unhealthy_threshold;
         let healthy_threshold = health_check_config.healthy_threshold;
 
-        tokio::spawn(async move {
+        let handle = tokio::spawn(async move {
             let client = reqwest::Client::builder()
                 .timeout(timeout)
                 .build()
                 .expect("Failed to create HTTP client for health checks");
 
             loop {
-                tokio::time::sleep(interval).await;
+                tokio::select! {
+                    _ = tokio::time::sleep(interval) => {}
+                    _ = shutdown.recv() => break,
+                }
 
                 let upstreams_read = upstreams.read().await;
+                let health_before: Vec<bool> = upstreams_read.iter().map(|u| u.is_healthy()).collect();
                 for upstream in upstreams_read.iter() {
-                    if !upstream.enabled {
+                    if !upstream.is_enabled() {
                         continue;
                     }
 
@@ -254,10 +842,18 @@ impl LoadBalancingManager {
                         }
                     }
                 }
+
+                if let Some(ring) = &ring {
+                    if upstreams_read.iter().map(|u| u.is_healthy()).ne(health_before) {
+                        *ring.write().await = HashRing::build(&upstreams_read);
+                    }
+                }
             }
+            debug!("Upstream health checks stopped");
         });
 
         debug!("Started health check background task");
+        Some(handle)
     }
 }
 
@@ -267,6 +863,8 @@ pub enum LoadBalancingAlgorithm {
     WeightedRoundRobin,
     LeastConnections,
     Random,
+    LeastResponseTime,
+    ConsistentHash,
 }
 
 #[derive(Clone)]
@@ -274,7 +872,8 @@ pub struct UpstreamServer {
     pub name: String,
     pub url: String,
     pub weight: u32,
-    pub enabled: bool,
+    /// Cleared while the upstream is drained; see [`LoadBalancingManager::set_enabled`]
+    enabled: Arc<AtomicBool>,
     healthy: Arc<AtomicBool>,
     circuit_breaker: Arc<SimpleCircuitBreaker>,
     active_connections: Arc<AtomicUsize>,
@@ -282,9 +881,16 @@ pub struct UpstreamServer {
     failed_requests: Arc<AtomicUsize>,
     consecutive_successes: Arc<AtomicUsize>,
     consecutive_failures: Arc<AtomicUsize>,
+    response_time: Arc<parking_lot::Mutex<ResponseTime>>,
+    metrics: Option<Arc<MetricsCollector>>,
 }
 
 impl UpstreamServer {
+    /// Weight of the newest sample in the response time average
+    const EWMA_ALPHA: f64 = 0.3;
+    /// Smallest sample a failed request counts as, so quick failures don't look fast
+    const FAILURE_PENALTY_MS: f64 = 1000.0;
+
     pub fn new(
         name: String,
         url: String,
@@ -296,13 +902,14 @@ impl UpstreamServer {
             cb_config.failure_threshold as u32,
             cb_config.success_threshold as u32,
             Duration::from_secs(cb_config.timeout_seconds),
+            cb_config.half_open_max_requests,
         );
 
         Ok(Self {
             name,
             url,
             weight,
-            enabled,
+            enabled: Arc::new(AtomicBool::new(enabled)),
             healthy: Arc::new(AtomicBool::new(true)),
             circuit_breaker: Arc::new(circuit_breaker),
             active_connections: Arc::new(AtomicUsize::new(0)),
@@ -310,30 +917,92 @@ impl UpstreamServer {
             failed_requests: Arc::new(AtomicUsize::new(0)),
             consecutive_successes: Arc::new(AtomicUsize::new(0)),
             consecutive_failures: Arc::new(AtomicUsize::new(0)),
+            // Unmeasured upstreams look fastest, so they get probed first
+            response_time: Arc::new(parking_lot::Mutex::new(ResponseTime {
+                average_ms: 0.0,
+                updated: Instant::now(),
+            })),
+            metrics: None,
         })
     }
 
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether the state changed
+    pub fn set_enabled(&self, enabled: bool) -> bool {
+        self.enabled.swap(enabled, Ordering::Relaxed) != enabled
+    }
+
     pub fn is_healthy(&self) -> bool {
         self.healthy.load(Ordering::Relaxed)
     }
 
     pub fn set_healthy(&self, healthy: bool) {
         self.healthy.store(healthy, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.set_upstream_healthy(&self.name, healthy);
+        }
     }
 
     pub fn increment_connections(&self) {
         self.active_connections.fetch_add(1, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.inc_upstream_active_connections(&self.name);
+        }
     }
 
     pub fn decrement_connections(&self) {
         self.active_connections.fetch_sub(1, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.dec_upstream_active_connections(&self.name);
+        }
     }
 
-    pub fn record_request(&self, success: bool) {
+    /// Count a request; `duration` feeds the response time average. A failure counts
+    /// as at least twice the current average and [`Self::FAILURE_PENALTY_MS`]. Requests
+    /// turned away before reaching the upstream have no `duration`.
+    pub fn record_request(&self, success: bool, duration: Option<Duration>) {
         self.total_requests.fetch_add(1, Ordering::Relaxed);
+        if let Some(metrics) = &self.metrics {
+            metrics.inc_upstream_request(&self.name, if success { "success" } else { "failure" });
+        }
         if !success {
             self.failed_requests.fetch_add(1, Ordering::Relaxed);
         }
+        let Some(duration) = duration else {
+            return;
+        };
+
+        let mut response_time = self.response_time.lock();
+        let average = response_time.current_ms();
+        let mut sample = duration.as_secs_f64() * 1000.0;
+        if !success {
+            sample = sample.max(average * 2.0).max(Self::FAILURE_PENALTY_MS);
+        }
+        response_time.average_ms = if average == 0.0 {
+            sample
+        } else {
+            average + Self::EWMA_ALPHA * (sample - average)
+        };
+        response_time.updated = Instant::now();
+    }
+
+    /// Moving average of response time in milliseconds; `0` until measured
+    pub fn response_time_ewma_ms(&self) -> f64 {
+        self.response_time.lock().current_ms()
+    }
+
+    /// Whether the circuit breaker would turn a request away right now
+    pub async fn circuit_open(&self) -> bool {
+        self.time_until_probe().await.is_some_and(|wait| !wait.is_zero())
+    }
+
+    /// Time until the circuit breaker allows the next probe, suitable for `Retry-After`;
+    /// `None` when the breaker is not open
+    pub async fn time_until_probe(&self) -> Option<Duration> {
+        self.circuit_breaker.time_until_probe().await
     }
 
     pub async fn call_with_circuit_breaker<F, Fut, T>(
@@ -344,37 +1013,87 @@ impl UpstreamServer {
         F: FnOnce() -> Fut,
         Fut: std::future::Future<Output = Result<T>>,
     {
-        self.circuit_breaker.try_reset().await;
+        self.call_with_circuit_breaker_if(f, |_| false).await
+    }
 
-        if self.circuit_breaker.is_open().await {
-            self.record_request(false);
-            anyhow::bail!("Circuit breaker is open for upstream '{}'", self.name);
-        }
+    /// [`call_with_circuit_breaker`](Self::call_with_circuit_breaker) where an
+    /// `Ok` result that `failed` accepts is still recorded as a failure
+    async fn call_with_circuit_breaker_if<F, Fut, T, D>(&self, f: F, failed: D) -> Result<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+        D: Fn(&T) -> bool,
+    {
+        let _permit = match self.circuit_breaker.acquire().await {
+            Ok(permit) => permit,
+            Err(rejection) => {
+                self.record_request(false, None);
+                match rejection {
+                    Rejection::Open(wait) => anyhow::bail!(
+                        "Circuit breaker is open for upstream '{}', next probe in {}s",
+                        self.name,
+                        wait.as_secs_f64().ceil() as u64
+                    ),
+                    Rejection::ProbesBusy => anyhow::bail!(
+                        "Circuit breaker is half-open for upstream '{}' and all probe slots are busy",
+                        self.name
+                    ),
+                }
+            }
+        };
 
+        let start = Instant::now();
         match f().await {
+            Ok(result) if failed(&result) => {
+                self.circuit_breaker.record_failure().await;
+                self.record_request(false, Some(start.elapsed()));
+                Ok(result)
+            }
             Ok(result) => {
                 self.circuit_breaker.record_success().await;
-                self.record_request(true);
+                self.record_request(true, Some(start.elapsed()));
                 Ok(result)
             }
             Err(e) => {
                 self.circuit_breaker.record_failure().await;
-                self.record_request(false);
+                self.record_request(false, Some(start.elapsed()));
                 Err(e)
             }
         }
     }
 }
 
+/// Moving average of an upstream's response time
+#[derive(Debug, Clone, Copy)]
+struct ResponseTime {
+    average_ms: f64,
+    updated: Instant,
+}
+
+impl ResponseTime {
+    /// Time for the average to halve without new samples, so an upstream that lost
+    /// its traffic to a faster one is eventually tried again
+    const HALF_LIFE: Duration = Duration::from_secs(30);
+
+    fn current_ms(&self) -> f64 {
+        let half_lives = self.updated.elapsed().as_secs_f64() / Self::HALF_LIFE.as_secs_f64();
+        self.average_ms * 0.5f64.powf(half_lives)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct UpstreamStatus {
     pub name: String,
     pub url: String,
     pub enabled: bool,
+    /// Disabled, with requests still in flight
+    pub draining: bool,
     pub healthy: bool,
     pub active_connections: usize,
     pub total_requests: usize,
     pub failed_requests: usize,
+    /// Moving average of successful response times in milliseconds
+    pub response_time_ewma_ms: f64,
 }
 
 #[cfg(test)]
@@ -385,4 +1104,579 @@ mod tests {
     async fn test_round_robin_selection() {
 
     }
+
+    #[tokio::test]
+    async fn test_half_open_limits_concurrent_probes() {
+        let config = crate::config::CircuitBreakerConfig {
+            enable: true,
+            failure_threshold: 1,
+            success_threshold: 1,
+            timeout_seconds: 1,
+            half_open_max_requests: 2,
+        };
+        let upstream = UpstreamServer::new(
+            "app".to_string(),
+            "http://127.0.0.1:1".to_string(),
+            1,
+            true,
+            &config,
+        )
+        .unwrap();
+
+        let failed: Result<()> = upstream
+            .call_with_circuit_breaker(|| async { anyhow::bail!("down") })
+            .await;
+        assert!(failed.is_err());
+
+        let wait = upstream.time_until_probe().await.unwrap();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+        let err = upstream
+            .call_with_circuit_breaker(|| async { Ok(()) })
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("next probe in 1s"));
+
+        tokio::time::sleep(wait + Duration::from_millis(50)).await;
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let calls: Vec<_> = (0..5)
+            .map(|_| {
+                let upstream = upstream.clone();
+                let running = Arc::clone(&running);
+                let peak = Arc::clone(&peak);
+                tokio::spawn(async move {
+                    upstream
+                        .call_with_circuit_breaker(|| async move {
+                            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                            peak.fetch_max(now, Ordering::SeqCst);
+                            tokio::time::sleep(Duration::from_millis(200)).await;
+                            running.fetch_sub(1, Ordering::SeqCst);
+                            Ok(())
+                        })
+                        .await
+                })
+            })
+            .collect();
+
+        let mut probed = 0;
+        let mut rejected = 0;
+        for call in calls {
+            match call.await.unwrap() {
+                Ok(()) => probed += 1,
+                Err(e) => {
+                    assert!(e.to_string().contains("probe slots are busy"));
+                    rejected += 1;
+                }
+            }
+        }
+        assert_eq!((probed, rejected), (2, 3));
+        assert_eq!(peak.load(Ordering::SeqCst), 2);
+
+        // The successful probes closed the breaker again
+        assert!(upstream.time_until_probe().await.is_none());
+        assert!(upstream.call_with_circuit_breaker(|| async { Ok(()) }).await.is_ok());
+    }
+
+    fn manager(names: &[&str]) -> LoadBalancingManager {
+        let upstreams = names
+            .iter()
+            .map(|name| crate::config::UpstreamConfig {
+                name: name.to_string(),
+                url: format!("http://{}.invalid", name),
+                weight: 1,
+                enabled: true,
+            })
+            .collect();
+        let breaker = crate::config::CircuitBreakerConfig {
+            enable: true,
+            failure_threshold: 1,
+            success_threshold: 1,
+            timeout_seconds: 60,
+            half_open_max_requests: 1,
+        };
+        LoadBalancingManager::new(upstreams, crate::config::LoadBalancingAlgorithm::RoundRobin, &breaker).unwrap()
+    }
+
+    async fn trip(manager: &LoadBalancingManager, name: &str) {
+        let upstream = manager.upstreams.read().await.iter().find(|u| u.name == name).unwrap().clone();
+        let _ = upstream.call_with_circuit_breaker(|| async { anyhow::bail!("down") as Result<()> }).await;
+        assert!(upstream.circuit_open().await);
+    }
+
+    #[tokio::test]
+    async fn test_selection_skips_open_circuits() {
+        let manager = manager(&["tripped", "healthy"]);
+        trip(&manager, "tripped").await;
+
+        // Still passes health checks, but its breaker would refuse the request
+        for _ in 0..4 {
+            assert_eq!(manager.select_upstream(SelectionKey::default()).await.unwrap().name, "healthy");
+        }
+
+        trip(&manager, "healthy").await;
+        let err = manager.select_upstream(SelectionKey::default()).await.map(|u| u.name).unwrap_err();
+        assert_eq!(err.downcast_ref::<NoUpstream>(), Some(&NoUpstream::AllCircuitsOpen));
+
+        manager.update_health("tripped", false).await;
+        manager.update_health("healthy", false).await;
+        let err = manager.select_upstream(SelectionKey::default()).await.map(|u| u.name).unwrap_err();
+        assert_eq!(err.downcast_ref::<NoUpstream>(), Some(&NoUpstream::AllUnhealthy));
+    }
+
+    /// Answers one request with `204` and an `X-Upstream` header, returning what it received
+    async fn upstream_once() -> (String, tokio::task::JoinHandle<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !received.ends_with(b"hello") {
+                let n = stream.read(&mut buf).await.unwrap();
+                assert!(n > 0);
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\nX-Upstream: live\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(received).unwrap()
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_proxy_request_fails_over_unreachable_upstream() {
+        let dead = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let dead_url = format!("http://{}", dead.local_addr().unwrap());
+        drop(dead);
+        let (live_url, received) = upstream_once().await;
+
+        let upstreams = [("dead", dead_url), ("live", live_url)]
+            .into_iter()
+            .map(|(name, url)| crate::config::UpstreamConfig {
+                name: name.to_string(),
+                url,
+                weight: 1,
+                enabled: true,
+            })
+            .collect();
+        let manager = LoadBalancingManager::new(
+            upstreams,
+            crate::config::LoadBalancingAlgorithm::RoundRobin,
+            &crate::config::CircuitBreakerConfig::default(),
+        )
+        .unwrap();
+
+        // Not idempotent, but a refused connection is still safe to retry
+        let request = PhpRequest {
+            method: "POST".to_string(),
+            uri: "/submit?x=1".to_string(),
+            headers: [("Host", "example.com"), ("Connection", "keep-alive"), ("Content-Type", "text/plain")]
+                .into_iter()
+                .map(|(n, v)| (n.to_string(), v.to_string()))
+                .collect(),
+            body: b"hello".to_vec(),
+            query_string: "x=1".to_string(),
+            remote_addr: "192.0.2.7:51000".to_string(),
+        };
+        let response = manager.proxy_request(request, None, "http").await.unwrap();
+        assert_eq!(response.status_code, 204);
+        assert_eq!(response.headers.get("x-upstream"), Some("live"));
+        assert!(response.headers.get("connection").is_none());
+
+        let received = received.await.unwrap().to_ascii_lowercase();
+        assert!(received.starts_with("post /submit?x=1 http/1.1\r\n"));
+        assert!(received.contains("x-forwarded-for: 192.0.2.7\r\n"));
+        assert!(received.contains("x-forwarded-host: example.com\r\n"));
+        assert!(received.contains("x-forwarded-proto: http\r\n"));
+        assert!(received.contains("x-real-ip: 192.0.2.7\r\n"));
+        assert!(!received.contains("keep-alive"));
+
+        let status = manager.get_upstreams_status().await;
+        let dead = status.iter().find(|u| u.name == "dead").unwrap();
+        let live = status.iter().find(|u| u.name == "live").unwrap();
+        assert_eq!((dead.total_requests, dead.failed_requests), (1, 1));
+        assert_eq!((live.total_requests, live.failed_requests, live.active_connections), (1, 0, 0));
+    }
+
+    fn sticky(mode: StickyMode) -> Option<StickyConfig> {
+        Some(StickyConfig { mode, cookie_name: "upstream".to_string() })
+    }
+
+    fn sticky_key(key: &str) -> SelectionKey<'_> {
+        SelectionKey { sticky: Some(key), hash: None }
+    }
+
+    #[tokio::test]
+    async fn test_ip_hash_is_consistent() {
+        let names = ["a", "b", "c", "d", "e"];
+        let manager = manager(&names).with_sticky(sticky(StickyMode::IpHash));
+        let clients: Vec<String> = (0..500).map(|i| format!("10.0.{}.{}", i / 256, i % 256)).collect();
+
+        let mut pinned = Vec::new();
+        for client in &clients {
+            let first = manager.select_upstream(sticky_key(client)).await.unwrap().name;
+            assert_eq!(manager.select_upstream(sticky_key(client)).await.unwrap().name, first);
+            pinned.push(first);
+        }
+        for name in names {
+            let share = pinned.iter().filter(|p| *p == name).count();
+            assert!(share > 50, "{} got {} of 500 clients", name, share);
+        }
+
+        // Only the clients of the failed upstream move, and they return once it recovers
+        manager.update_health("c", false).await;
+        for (client, before) in clients.iter().zip(&pinned) {
+            let now = manager.select_upstream(sticky_key(client)).await.unwrap().name;
+            if before == "c" {
+                assert_ne!(now, "c");
+            } else {
+                assert_eq!(&now, before);
+            }
+        }
+        manager.update_health("c", true).await;
+        for (client, before) in clients.iter().zip(&pinned) {
+            assert_eq!(&manager.select_upstream(sticky_key(client)).await.unwrap().name, before);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cookie_affinity_repins() {
+        let manager = manager(&["a", "b"]).with_sticky(sticky(StickyMode::Cookie));
+        for _ in 0..4 {
+            assert_eq!(manager.select_upstream(sticky_key("b")).await.unwrap().name, "b");
+        }
+        manager.update_health("b", false).await;
+        assert_eq!(manager.select_upstream(sticky_key("b")).await.unwrap().name, "a");
+
+        let (url, _received) = upstream_once().await;
+        let upstreams = vec![crate::config::UpstreamConfig {
+            name: "live".to_string(),
+            url,
+            weight: 1,
+            enabled: true,
+        }];
+        let manager = LoadBalancingManager::new(
+            upstreams,
+            crate::config::LoadBalancingAlgorithm::RoundRobin,
+            &crate::config::CircuitBreakerConfig::default(),
+        )
+        .unwrap()
+        .with_sticky(sticky(StickyMode::Cookie));
+        let request = PhpRequest {
+            method: "GET".to_string(),
+            uri: "/".to_string(),
+            headers: [("cookie".to_string(), "theme=dark; upstream=gone".to_string())].into(),
+            body: b"hello".to_vec(),
+            query_string: String::new(),
+            remote_addr: "192.0.2.7:51000".to_string(),
+        };
+        let response = manager.proxy_request(request, None, "http").await.unwrap();
+        assert_eq!(
+            response.headers.get("set-cookie"),
+            Some("upstream=live; Path=/; HttpOnly; SameSite=Lax")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_least_response_time() {
+        let upstreams = ["fast", "slow", "new"]
+            .iter()
+            .map(|name| crate::config::UpstreamConfig {
+                name: name.to_string(),
+                url: format!("http://{}.invalid", name),
+                weight: 1,
+                enabled: true,
+            })
+            .collect();
+        let manager = LoadBalancingManager::new(
+            upstreams,
+            crate::config::LoadBalancingAlgorithm::LeastResponseTime,
+            &crate::config::CircuitBreakerConfig::default(),
+        )
+        .unwrap();
+        let upstream = |name: &str| {
+            let upstreams = manager.upstreams.try_read().unwrap();
+            upstreams.iter().find(|u| u.name == name).unwrap().clone()
+        };
+        let ewma = |name: &str| upstream(name).response_time_ewma_ms();
+
+        upstream("fast").record_request(true, Some(Duration::from_millis(20)));
+        upstream("slow").record_request(true, Some(Duration::from_millis(200)));
+        // Unmeasured upstreams are tried before measured ones
+        assert_eq!(manager.select_upstream(SelectionKey::default()).await.unwrap().name, "new");
+
+        upstream("new").record_request(true, Some(Duration::from_millis(100)));
+        assert_eq!(manager.select_upstream(SelectionKey::default()).await.unwrap().name, "fast");
+
+        // Requests in flight count against an upstream: 20ms with 9 waiting loses to 100ms
+        for _ in 0..9 {
+            upstream("fast").increment_connections();
+        }
+        assert_eq!(manager.select_upstream(SelectionKey::default()).await.unwrap().name, "new");
+        for _ in 0..9 {
+            upstream("fast").decrement_connections();
+        }
+
+        // A run of slow responses pulls the average up until another upstream wins
+        for _ in 0..5 {
+            upstream("fast").record_request(true, Some(Duration::from_millis(300)));
+        }
+        assert!(ewma("fast") > 200.0 && ewma("fast") < 300.0);
+        assert_eq!(manager.select_upstream(SelectionKey::default()).await.unwrap().name, "new");
+
+        // A failure, however quick, makes an upstream look slower rather than faster
+        upstream("new").record_request(false, Some(Duration::from_millis(1)));
+        assert!(ewma("new") > 300.0, "{}", ewma("new"));
+        assert_eq!(manager.select_upstream(SelectionKey::default()).await.unwrap().name, "slow");
+        // Requests the circuit breaker turned away leave the average alone
+        let before = ewma("slow");
+        upstream("slow").record_request(false, None);
+        assert!((ewma("slow") - before).abs() < 1.0);
+
+        // Without new samples an average decays, so an upstream left idle gets retried
+        upstream("new").response_time.lock().updated -= ResponseTime::HALF_LIFE * 4;
+        assert!(ewma("new") < 30.0, "{}", ewma("new"));
+        assert_eq!(manager.select_upstream(SelectionKey::default()).await.unwrap().name, "new");
+
+        let status = manager.get_upstreams_status().await;
+        let reported = status.iter().find(|u| u.name == "slow").unwrap().response_time_ewma_ms;
+        assert!((reported - 200.0).abs() < 1.0, "{}", reported);
+    }
+
+    #[tokio::test]
+    async fn test_consistent_hash_remaps_only_removed_upstream() {
+        let upstreams = ["a", "b", "c", "d"]
+            .iter()
+            .map(|name| crate::config::UpstreamConfig {
+                name: name.to_string(),
+                url: format!("http://{}.invalid", name),
+                weight: 1,
+                enabled: true,
+            })
+            .collect();
+        let manager = LoadBalancingManager::new(
+            upstreams,
+            crate::config::LoadBalancingAlgorithm::ConsistentHash,
+            &crate::config::CircuitBreakerConfig::default(),
+        )
+        .unwrap();
+        let keys: Vec<String> = (0..4000).map(|i| format!("/assets/{}.css", i)).collect();
+        let owner = |key: &str| {
+            let manager = &manager;
+            let key = key.to_string();
+            async move {
+                let key = SelectionKey { sticky: None, hash: Some(&key) };
+                manager.select_upstream(key).await.unwrap().name
+            }
+        };
+
+        let mut before = Vec::new();
+        for key in &keys {
+            before.push(owner(key).await);
+        }
+        for name in ["a", "b", "c", "d"] {
+            let share = before.iter().filter(|owner| *owner == name).count();
+            assert!((600..1400).contains(&share), "{} owns {} of 4000 keys", name, share);
+        }
+
+        manager.update_health("c", false).await;
+        let mut moved = 0;
+        for (key, previous) in keys.iter().zip(&before) {
+            let now = owner(key).await;
+            if &now != previous {
+                assert_eq!(previous, "c", "{} moved from a remaining upstream", key);
+                moved += 1;
+            }
+        }
+        // Roughly a quarter: exactly the keys "c" owned
+        assert_eq!(moved, before.iter().filter(|owner| *owner == "c").count());
+        assert!((600..1400).contains(&moved));
+
+        manager.update_health("c", true).await;
+        for (key, previous) in keys.iter().zip(&before) {
+            assert_eq!(&owner(key).await, previous);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drained_upstream_finishes_in_flight_requests() {
+        let manager = manager(&["a", "b"]);
+        let a = manager.select_upstream(SelectionKey::default()).await.unwrap();
+        assert_eq!(a.name, "a");
+        let in_flight = ActiveConnection::new(&a);
+
+        manager.set_enabled("a", false).await.unwrap();
+        for _ in 0..4 {
+            assert_eq!(manager.select_upstream(SelectionKey::default()).await.unwrap().name, "b");
+        }
+        let drained = manager.get_upstreams_status().await.remove(0);
+        assert!(!drained.enabled && drained.draining);
+        assert_eq!(drained.active_connections, 1);
+
+        drop(in_flight);
+        assert!(!manager.get_upstreams_status().await[0].draining);
+
+        manager.set_enabled("a", true).await.unwrap();
+        let mut selected = Vec::new();
+        for _ in 0..2 {
+            selected.push(manager.select_upstream(SelectionKey::default()).await.unwrap().name.clone());
+        }
+        assert!(selected.contains(&"a".to_string()));
+
+        assert!(manager.set_enabled("c", false).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_does_not_wait_for_health_check_round() {
+        let manager = manager(&["a", "b"]);
+        // A health check round holds the read lock while it probes
+        let probing = manager.upstreams.read().await;
+
+        let drain = tokio::time::timeout(Duration::from_secs(1), manager.set_enabled("a", false));
+        drain.await.expect("drain queued behind the health check").unwrap();
+        let select = tokio::time::timeout(Duration::from_secs(1), manager.select_upstream(SelectionKey::default()));
+        assert_eq!(select.await.unwrap().unwrap().name, "b");
+        drop(probing);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_metrics() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let manager = manager(&["metrics-a", "metrics-b"]).with_metrics(metrics.clone());
+        let upstream = manager.select_upstream(SelectionKey::default()).await.unwrap();
+        assert_eq!(upstream.name, "metrics-a");
+        assert!(metrics.is_upstream_healthy("metrics-a"));
+        let in_flight = ActiveConnection::new(&upstream);
+        assert_eq!(metrics.get_upstream_active_connections("metrics-a"), 1);
+        drop(in_flight);
+        assert_eq!(metrics.get_upstream_active_connections("metrics-a"), 0);
+
+        let result = manager
+            .call_with_failover(None, |upstream| async move {
+                // Round robin has moved on to metrics-b
+                anyhow::ensure!(upstream.name == "metrics-a", "down");
+                Ok(())
+            })
+            .await;
+        assert!(result.is_ok());
+        assert_eq!(metrics.get_upstream_requests("metrics-b", "failure"), 1);
+        assert_eq!(metrics.get_upstream_failed_requests("metrics-b"), 1);
+        assert_eq!(metrics.get_upstream_requests("metrics-a", "success"), 1);
+        assert_eq!(metrics.get_upstream_failed_requests("metrics-a"), 0);
+
+        manager.update_health("metrics-a", false).await;
+        assert!(!metrics.is_upstream_healthy("metrics-a"));
+        assert!(metrics.is_upstream_healthy("metrics-b"));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_upstream_active_connections_gauge_under_concurrency() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let manager = manager(&["gauge-a"]).with_metrics(metrics.clone());
+        let upstream = manager.select_upstream(SelectionKey::default()).await.unwrap();
+
+        let tasks: Vec<_> = (0..8)
+            .map(|_| {
+                let upstream = upstream.clone();
+                tokio::spawn(async move {
+                    for _ in 0..1000 {
+                        let in_flight = ActiveConnection::new(&upstream);
+                        tokio::task::yield_now().await;
+                        drop(in_flight);
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(metrics.get_upstream_active_connections("gauge-a"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_5xx_and_timeouts_trip_the_breaker() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        // Answers the first request with 503 and never answers the second
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 4\r\nConnection: close\r\n\r\nbusy")
+                .await
+                .unwrap();
+            let (_hung, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let breaker = crate::config::CircuitBreakerConfig {
+            enable: true,
+            failure_threshold: 2,
+            success_threshold: 1,
+            timeout_seconds: 60,
+            half_open_max_requests: 1,
+        };
+        let upstreams = vec![crate::config::UpstreamConfig { name: "app".to_string(), url, weight: 1, enabled: true }];
+        let manager = LoadBalancingManager::new(upstreams, crate::config::LoadBalancingAlgorithm::RoundRobin, &breaker).unwrap();
+        let request = || PhpRequest {
+            method: "GET".to_string(),
+            uri: "/".to_string(),
+            headers: Default::default(),
+            body: Vec::new(),
+            query_string: String::new(),
+            remote_addr: "192.0.2.7:51000".to_string(),
+        };
+
+        // The 503 reaches the client but counts against the upstream
+        let response = manager.proxy_request(request(), None, "https").await.unwrap();
+        assert_eq!(response.status_code, 503);
+        assert_eq!(response.body, b"busy");
+        assert_eq!(manager.get_upstreams_status().await[0].failed_requests, 1);
+
+        let start = Instant::now();
+        let err = manager
+            .proxy_request(request(), Some(Deadline::after(Duration::from_millis(200))), "https")
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_timeout()), "{:#}", err);
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let err = manager.proxy_request(request(), None, "https").await.unwrap_err();
+        assert_eq!(err.downcast_ref::<NoUpstream>(), Some(&NoUpstream::AllCircuitsOpen));
+    }
+
+    #[tokio::test]
+    async fn test_failover_stops_at_max_tries() {
+        let attempts = AtomicUsize::new(0);
+        let failing = |_: UpstreamServer| async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            anyhow::bail!("connection refused") as Result<()>
+        };
+
+        let capped = manager(&["a", "b", "c"]).with_max_tries(Some(2));
+        let err = capped.call_with_failover(None, failing).await.unwrap_err();
+        assert_eq!(err.to_string(), "connection refused");
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 2);
+
+        // Unlimited tries each upstream once, then reports the open circuits
+        let manager = manager(&["a", "b", "c"]);
+        assert!(manager.call_with_failover(None, failing).await.is_err());
+        assert_eq!(attempts.swap(0, Ordering::SeqCst), 3);
+
+        let metrics = MetricsCollector::new();
+        let before = metrics.get_upstream_unavailable("circuit_open");
+        let err = manager.call_with_failover(Some(&metrics), failing).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<NoUpstream>(), Some(&NoUpstream::AllCircuitsOpen));
+        assert_eq!(attempts.load(Ordering::SeqCst), 0);
+        assert_eq!(metrics.get_upstream_unavailable("circuit_open"), before + 1);
+    }
 }
@@ -1,3 +1,4 @@
+pub mod redaction;
 pub mod structured;
 
 use anyhow::Result;
@@ -1,6 +1,9 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
+use crate::config::LoggingConfig;
+use super::redaction::{redact_header, redact_uri};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RequestLog {
@@ -16,7 +19,13 @@ pub struct RequestLog {
     pub worker_id: Option<usize>,
     pub remote_addr: String,
     pub user_agent: Option<String>,
+    /// Request headers, with values masked per `logging.redact_headers`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
     pub waf_triggered: bool,
+    /// Matched routing rule and backend, when `logging.log_routing` is enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub routing_rule: Option<String>,
 }
 
 impl RequestLog {
@@ -40,10 +49,48 @@ impl RequestLog {
             worker_id: None,
             remote_addr,
             user_agent: None,
+            headers: BTreeMap::new(),
             waf_triggered: false,
+            routing_rule: None,
         }
     }
 
+    /// Like `new`, with the URI masked according to the `[logging]` redaction settings
+    pub fn redacted(
+        method: String,
+        uri: &str,
+        status: u16,
+        duration_ms: u64,
+        remote_addr: String,
+        config: &LoggingConfig,
+    ) -> Self {
+        Self::new(method, redact_uri(uri, config).into_owned(), status, duration_ms, remote_addr)
+    }
+
+    /// Record the client's User-Agent, masked if listed in `logging.redact_headers`
+    pub fn with_user_agent(mut self, user_agent: Option<&str>, config: &LoggingConfig) -> Self {
+        self.user_agent = user_agent.map(|ua| redact_header("user-agent", ua, config).into_owned());
+        self
+    }
+
+    /// Record request headers, masking those listed in `logging.redact_headers`
+    pub fn with_headers<'a>(
+        mut self,
+        headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+        config: &LoggingConfig,
+    ) -> Self {
+        self.headers = headers
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), redact_header(name, value, config).into_owned()))
+            .collect();
+        self
+    }
+
+    pub fn with_routing_rule(mut self, routing_rule: Option<String>) -> Self {
+        self.routing_rule = routing_rule;
+        self
+    }
+
     pub fn to_json(&self) -> String {
         serde_json::to_string(self).unwrap_or_else(|_| "{}".to_string())
     }
@@ -33,6 +33,24 @@ enum Commands {
 
     /// Monitor server status (TUI/JSON/Text)
     Monitor(cli::monitor::MonitorArgs),
+
+    /// Validate and reload the configuration of a running server
+    Reload(cli::control::ReloadArgs),
+
+    /// Restart the PHP workers of a running server
+    RestartWorkers(cli::control::RestartWorkersArgs),
+
+    /// Block an IP address on a running server
+    BlockIp(cli::control::IpArgs),
+
+    /// Unblock an IP address on a running server
+    UnblockIp(cli::control::IpArgs),
+
+    /// Take a load-balanced upstream out of rotation on a running server
+    DrainUpstream(cli::control::UpstreamArgs),
+
+    /// Put a drained upstream back into rotation on a running server
+    EnableUpstream(cli::control::UpstreamArgs),
 }
 
 #[tokio::main]
@@ -47,5 +65,11 @@ async fn main() -> Result<()> {
         Commands::Compare(args) => cli::compare::run(args).await,
         Commands::Waf(args) => cli::waf::run(args).await,
         Commands::Monitor(args) => cli::monitor::run(args).await,
+        Commands::Reload(args) => cli::control::reload(args).await,
+        Commands::RestartWorkers(args) => cli::control::restart_workers(args).await,
+        Commands::BlockIp(args) => cli::control::block_ip(args).await,
+        Commands::UnblockIp(args) => cli::control::unblock_ip(args).await,
+        Commands::DrainUpstream(args) => cli::control::drain_upstream(args).await,
+        Commands::EnableUpstream(args) => cli::control::enable_upstream(args).await,
     }
 }
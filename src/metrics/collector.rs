@@ -1,6 +1,7 @@
+use crate::config::StatusGranularity;
 use lazy_static::lazy_static;
 use prometheus::{
-    Counter, CounterVec, Gauge, GaugeVec, HistogramOpts, HistogramVec, Opts, Registry,
+    Counter, CounterVec, Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, Opts, Registry,
 };
 use std::sync::Arc;
 use std::time::Instant;
@@ -36,6 +37,16 @@ lazy_static! {
         &["backend", "error_type"]
     ).unwrap();
 
+    static ref BACKEND_UP: GaugeVec = GaugeVec::new(
+        Opts::new("backend_up", "Whether the last backend health check passed (1=up, 0=down)"),
+        &["backend"]
+    ).unwrap();
+
+    static ref BACKEND_HEALTH_CHECK_DURATION: GaugeVec = GaugeVec::new(
+        Opts::new("backend_health_check_duration_seconds", "Duration of the last backend health check"),
+        &["backend"]
+    ).unwrap();
+
     static ref PHP_WORKERS: GaugeVec = GaugeVec::new(
         Opts::new("php_workers", "PHP worker pool status"),
         &["status"]
@@ -51,6 +62,10 @@ lazy_static! {
         &["worker_id"]
     ).unwrap();
 
+    static ref REDIS_UP: Gauge = Gauge::new(
+        "redis_up", "Whether the last Redis keepalive ping succeeded (1=up, 0=down)"
+    ).unwrap();
+
     static ref OPCACHE_HIT_RATE: Gauge = Gauge::new(
         "opcache_hit_rate_percent", "OPcache hit rate percentage"
     ).unwrap();
@@ -68,6 +83,11 @@ lazy_static! {
         &["rule_id"]
     ).unwrap();
 
+    static ref WAF_WOULD_BLOCK_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("waf_would_block_total", "Requests WAF learn mode would have blocked"),
+        &["rule_id"]
+    ).unwrap();
+
     static ref RATE_LIMIT_TRIGGERED: Counter = Counter::new(
         "rate_limit_triggered_total", "Rate limit triggers"
     ).unwrap();
@@ -105,10 +125,100 @@ lazy_static! {
         &["backend"]
     ).unwrap();
 
+    static ref REQUEST_TIMEOUTS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("request_timeouts_total", "Requests cancelled by the server request timeout"),
+        &["method"]
+    ).unwrap();
+
+    static ref REQUEST_BODY_TIMEOUTS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("request_body_timeout_total", "Requests rejected because the body arrived too slowly"),
+        &["method"]
+    ).unwrap();
+
+    static ref SESSION_DESERIALIZE_ERRORS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("session_deserialize_errors_total", "Stored sessions that failed to deserialize"),
+        &["policy"]
+    ).unwrap();
+
+    static ref GEOIP_BLOCKED_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("geoip_blocked_total", "Connections rejected by GeoIP filtering"),
+        &["country"]
+    ).unwrap();
+
+    static ref GEOIP_ALLOWED_TOTAL: Counter = Counter::new(
+        "geoip_allowed_total", "Connections let through by GeoIP filtering"
+    ).unwrap();
+
+    static ref GEOIP_LOOKUP_ERRORS_TOTAL: Counter = Counter::new(
+        "geoip_lookup_errors_total", "GeoIP lookups that failed; the connection was allowed"
+    ).unwrap();
+
+    static ref GEOIP_CACHE_HITS_TOTAL: Counter = Counter::new(
+        "geoip_cache_hits_total", "Connection GeoIP checks answered from the lookup cache"
+    ).unwrap();
+
+    static ref GEOIP_CACHE_MISSES_TOTAL: Counter = Counter::new(
+        "geoip_cache_misses_total", "Connection GeoIP checks that had to query the database"
+    ).unwrap();
+
+    static ref TLS_HANDSHAKE_DURATION: Histogram = Histogram::with_opts(
+        HistogramOpts::new("tls_handshake_duration_seconds", "TLS handshake duration")
+    ).unwrap();
+
+    static ref TLS_CERTIFICATE_EXPIRY: GaugeVec = GaugeVec::new(
+        Opts::new("tls_certificate_expiry_timestamp_seconds", "Expiry (notAfter) of the served TLS certificate, Unix time"),
+        &["sni"]
+    ).unwrap();
+
+    static ref TLS_HANDSHAKE_ERRORS: CounterVec = CounterVec::new(
+        Opts::new("tls_handshake_errors_total", "Failed TLS handshakes"),
+        &["reason"]
+    ).unwrap();
+
     static ref CIRCUIT_BREAKER_FAILURES: CounterVec = CounterVec::new(
         Opts::new("circuit_breaker_failures_total", "Circuit breaker failure count"),
         &["backend"]
     ).unwrap();
+
+    static ref UPSTREAM_UNAVAILABLE_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("upstream_unavailable_total", "Requests with no load-balanced upstream to try"),
+        &["reason"]
+    ).unwrap();
+
+    static ref UPSTREAM_HEALTHY: GaugeVec = GaugeVec::new(
+        Opts::new("upstream_healthy", "Whether a load-balanced upstream is healthy (1=healthy, 0=unhealthy)"),
+        &["name"]
+    ).unwrap();
+
+    static ref UPSTREAM_ACTIVE_CONNECTIONS: GaugeVec = GaugeVec::new(
+        Opts::new("upstream_active_connections", "Requests in flight to a load-balanced upstream"),
+        &["name"]
+    ).unwrap();
+
+    static ref UPSTREAM_REQUESTS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("upstream_requests_total", "Requests sent to a load-balanced upstream"),
+        &["name", "status"]
+    ).unwrap();
+
+    static ref UPSTREAM_FAILED_REQUESTS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("upstream_failed_requests_total", "Failed requests to a load-balanced upstream"),
+        &["name"]
+    ).unwrap();
+
+    static ref SLO_REQUESTS_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("slo_requests_total", "Requests evaluated against a latency objective"),
+        &["route"]
+    ).unwrap();
+
+    static ref SLO_BREACHES_TOTAL: CounterVec = CounterVec::new(
+        Opts::new("slo_breaches_total", "Requests slower than their latency objective"),
+        &["route"]
+    ).unwrap();
+
+    static ref BUILD_INFO: GaugeVec = GaugeVec::new(
+        Opts::new("fe_php_build_info", "Build information; always 1"),
+        &["version", "git_commit", "rust_version"]
+    ).unwrap();
 }
 
 pub struct MetricsCollector {
@@ -122,6 +232,9 @@ pub struct MetricsCollector {
     cached_backend_total_time: Arc<parking_lot::RwLock<std::collections::HashMap<String, f64>>>,
     // サーバー起動時刻
     start_time: Instant,
+    // キャッシュカウンターの計測開始時刻 (リセットで更新)
+    window_start: Arc<RwLock<Instant>>,
+    status_granularity: StatusGranularity,
 }
 
 impl MetricsCollector {
@@ -134,13 +247,17 @@ impl MetricsCollector {
         registry.register(Box::new(BACKEND_REQUESTS_TOTAL.clone())).unwrap();
         registry.register(Box::new(BACKEND_REQUEST_DURATION.clone())).unwrap();
         registry.register(Box::new(BACKEND_ERRORS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(BACKEND_UP.clone())).unwrap();
+        registry.register(Box::new(BACKEND_HEALTH_CHECK_DURATION.clone())).unwrap();
         registry.register(Box::new(PHP_WORKERS.clone())).unwrap();
         registry.register(Box::new(PHP_MEMORY_USAGE.clone())).unwrap();
         registry.register(Box::new(PHP_REQUESTS_HANDLED.clone())).unwrap();
+        registry.register(Box::new(REDIS_UP.clone())).unwrap();
         registry.register(Box::new(OPCACHE_HIT_RATE.clone())).unwrap();
         registry.register(Box::new(OPCACHE_MEMORY_USAGE.clone())).unwrap();
         registry.register(Box::new(OPCACHE_CACHED_SCRIPTS.clone())).unwrap();
         registry.register(Box::new(WAF_BLOCKED_TOTAL.clone())).unwrap();
+        registry.register(Box::new(WAF_WOULD_BLOCK_TOTAL.clone())).unwrap();
         registry.register(Box::new(RATE_LIMIT_TRIGGERED.clone())).unwrap();
         registry.register(Box::new(FASTCGI_POOL_SIZE.clone())).unwrap();
         registry.register(Box::new(FASTCGI_POOL_MAX_SIZE.clone())).unwrap();
@@ -150,6 +267,29 @@ impl MetricsCollector {
         registry.register(Box::new(CONNECTION_POOL_ERRORS.clone())).unwrap();
         registry.register(Box::new(CIRCUIT_BREAKER_STATE.clone())).unwrap();
         registry.register(Box::new(CIRCUIT_BREAKER_FAILURES.clone())).unwrap();
+        registry.register(Box::new(UPSTREAM_UNAVAILABLE_TOTAL.clone())).unwrap();
+        registry.register(Box::new(UPSTREAM_HEALTHY.clone())).unwrap();
+        registry.register(Box::new(UPSTREAM_ACTIVE_CONNECTIONS.clone())).unwrap();
+        registry.register(Box::new(UPSTREAM_REQUESTS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(UPSTREAM_FAILED_REQUESTS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(REQUEST_TIMEOUTS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(REQUEST_BODY_TIMEOUTS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(SESSION_DESERIALIZE_ERRORS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(GEOIP_BLOCKED_TOTAL.clone())).unwrap();
+        registry.register(Box::new(GEOIP_ALLOWED_TOTAL.clone())).unwrap();
+        registry.register(Box::new(GEOIP_LOOKUP_ERRORS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(GEOIP_CACHE_HITS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(GEOIP_CACHE_MISSES_TOTAL.clone())).unwrap();
+        registry.register(Box::new(TLS_HANDSHAKE_DURATION.clone())).unwrap();
+        registry.register(Box::new(TLS_HANDSHAKE_ERRORS.clone())).unwrap();
+        registry.register(Box::new(TLS_CERTIFICATE_EXPIRY.clone())).unwrap();
+        registry.register(Box::new(SLO_REQUESTS_TOTAL.clone())).unwrap();
+        registry.register(Box::new(SLO_BREACHES_TOTAL.clone())).unwrap();
+        registry.register(Box::new(BUILD_INFO.clone())).unwrap();
+
+        BUILD_INFO
+            .with_label_values(&[crate::VERSION, crate::GIT_COMMIT, crate::RUSTC_VERSION])
+            .set(1.0);
 
         Self {
             registry: Arc::new(registry),
@@ -159,16 +299,24 @@ impl MetricsCollector {
             cached_backend_errors: Arc::new(RwLock::new(std::collections::HashMap::new())),
             cached_backend_total_time: Arc::new(RwLock::new(std::collections::HashMap::new())),
             start_time: Instant::now(),
+            window_start: Arc::new(RwLock::new(Instant::now())),
+            status_granularity: StatusGranularity::default(),
         }
     }
 
+    /// Bucket the `status` label of `http_requests_total` per `metrics.status_granularity`
+    pub fn with_status_granularity(mut self, granularity: StatusGranularity) -> Self {
+        self.status_granularity = granularity;
+        self
+    }
+
     pub fn registry(&self) -> Arc<Registry> {
         Arc::clone(&self.registry)
     }
 
     pub fn record_request(&self, method: &str, status: u16, duration_secs: f64) {
         HTTP_REQUESTS_TOTAL
-            .with_label_values(&[method, &status.to_string()])
+            .with_label_values(&[method, &self.status_granularity.label(status)])
             .inc();
         HTTP_REQUEST_DURATION
             .with_label_values(&[method])
@@ -187,6 +335,46 @@ impl MetricsCollector {
         self.cached_active_connections.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
     }
 
+    /// Count an active connection until the returned guard is dropped
+    pub fn active_connection_guard(self: &Arc<Self>) -> ActiveConnectionGuard {
+        self.inc_active_connections();
+        ActiveConnectionGuard {
+            metrics: Arc::clone(self),
+        }
+    }
+
+    pub fn inc_request_timeout(&self, method: &str) {
+        REQUEST_TIMEOUTS_TOTAL.with_label_values(&[method]).inc();
+    }
+
+    pub fn inc_request_body_timeout(&self, method: &str) {
+        REQUEST_BODY_TIMEOUTS_TOTAL.with_label_values(&[method]).inc();
+    }
+
+    pub fn inc_session_deserialize_error(&self, policy: &str) {
+        SESSION_DESERIALIZE_ERRORS_TOTAL.with_label_values(&[policy]).inc();
+    }
+
+    pub fn inc_geoip_blocked(&self, country: &str) {
+        GEOIP_BLOCKED_TOTAL.with_label_values(&[country]).inc();
+    }
+
+    pub fn inc_geoip_allowed(&self) {
+        GEOIP_ALLOWED_TOTAL.inc();
+    }
+
+    pub fn inc_geoip_lookup_error(&self) {
+        GEOIP_LOOKUP_ERRORS_TOTAL.inc();
+    }
+
+    pub fn inc_geoip_cache_hit(&self) {
+        GEOIP_CACHE_HITS_TOTAL.inc();
+    }
+
+    pub fn inc_geoip_cache_miss(&self) {
+        GEOIP_CACHE_MISSES_TOTAL.inc();
+    }
+
     pub fn record_backend_request(&self, backend: &str, status: &str, duration_secs: f64) {
         BACKEND_REQUESTS_TOTAL
             .with_label_values(&[backend, status])
@@ -214,6 +402,28 @@ impl MetricsCollector {
         *errors.entry(backend.to_string()).or_insert(0) += 1;
     }
 
+    pub fn record_backend_health(&self, backend: &str, up: bool, duration_secs: f64) {
+        BACKEND_UP
+            .with_label_values(&[backend])
+            .set(if up { 1.0 } else { 0.0 });
+        BACKEND_HEALTH_CHECK_DURATION
+            .with_label_values(&[backend])
+            .set(duration_secs);
+    }
+
+    /// Whether the last health check of `backend` passed
+    pub fn get_backend_up(&self, backend: &str) -> bool {
+        BACKEND_UP.with_label_values(&[backend]).get() == 1.0
+    }
+
+    pub fn set_redis_up(&self, up: bool) {
+        REDIS_UP.set(if up { 1.0 } else { 0.0 });
+    }
+
+    pub fn get_redis_up(&self) -> bool {
+        REDIS_UP.get() == 1.0
+    }
+
     pub fn set_php_workers(&self, status: &str, count: i64) {
         PHP_WORKERS.with_label_values(&[status]).set(count as f64);
     }
@@ -246,6 +456,10 @@ impl MetricsCollector {
         WAF_BLOCKED_TOTAL.with_label_values(&[rule_id]).inc();
     }
 
+    pub fn inc_waf_would_block(&self, rule_id: &str) {
+        WAF_WOULD_BLOCK_TOTAL.with_label_values(&[rule_id]).inc();
+    }
+
     pub fn inc_rate_limit_triggered(&self) {
         RATE_LIMIT_TRIGGERED.inc();
     }
@@ -279,6 +493,10 @@ impl MetricsCollector {
             .inc();
     }
 
+    pub fn get_connection_pool_errors(&self, backend: &str, pool_type: &str, error_type: &str) -> u64 {
+        CONNECTION_POOL_ERRORS.with_label_values(&[backend, pool_type, error_type]).get() as u64
+    }
+
     pub fn set_circuit_breaker_state(&self, backend: &str, state: i64) {
         CIRCUIT_BREAKER_STATE
             .with_label_values(&[backend])
@@ -291,6 +509,137 @@ impl MetricsCollector {
             .inc();
     }
 
+    /// `reason` is `unhealthy` or `circuit_open`
+    pub fn inc_upstream_unavailable(&self, reason: &str) {
+        UPSTREAM_UNAVAILABLE_TOTAL.with_label_values(&[reason]).inc();
+    }
+
+    pub fn get_upstream_unavailable(&self, reason: &str) -> u64 {
+        UPSTREAM_UNAVAILABLE_TOTAL.with_label_values(&[reason]).get() as u64
+    }
+
+    pub fn set_upstream_healthy(&self, name: &str, healthy: bool) {
+        UPSTREAM_HEALTHY
+            .with_label_values(&[name])
+            .set(if healthy { 1.0 } else { 0.0 });
+    }
+
+    pub fn is_upstream_healthy(&self, name: &str) -> bool {
+        UPSTREAM_HEALTHY.with_label_values(&[name]).get() == 1.0
+    }
+
+    /// Export the in-flight gauge of `name` without changing it, so it shows before the first request
+    pub fn register_upstream_active_connections(&self, name: &str) {
+        UPSTREAM_ACTIVE_CONNECTIONS.with_label_values(&[name]);
+    }
+
+    pub fn inc_upstream_active_connections(&self, name: &str) {
+        UPSTREAM_ACTIVE_CONNECTIONS.with_label_values(&[name]).inc();
+    }
+
+    pub fn dec_upstream_active_connections(&self, name: &str) {
+        UPSTREAM_ACTIVE_CONNECTIONS.with_label_values(&[name]).dec();
+    }
+
+    pub fn get_upstream_active_connections(&self, name: &str) -> usize {
+        UPSTREAM_ACTIVE_CONNECTIONS.with_label_values(&[name]).get() as usize
+    }
+
+    /// `status` is `success` or `failure`; failures also count in `upstream_failed_requests_total`
+    pub fn inc_upstream_request(&self, name: &str, status: &str) {
+        UPSTREAM_REQUESTS_TOTAL.with_label_values(&[name, status]).inc();
+        if status == "failure" {
+            UPSTREAM_FAILED_REQUESTS_TOTAL.with_label_values(&[name]).inc();
+        }
+    }
+
+    pub fn get_upstream_requests(&self, name: &str, status: &str) -> u64 {
+        UPSTREAM_REQUESTS_TOTAL.with_label_values(&[name, status]).get() as u64
+    }
+
+    pub fn get_upstream_failed_requests(&self, name: &str) -> u64 {
+        UPSTREAM_FAILED_REQUESTS_TOTAL.with_label_values(&[name]).get() as u64
+    }
+
+    pub fn observe_tls_handshake(&self, duration_secs: f64) {
+        TLS_HANDSHAKE_DURATION.observe(duration_secs);
+    }
+
+    pub fn inc_tls_handshake_error(&self, reason: &str) {
+        TLS_HANDSHAKE_ERRORS.with_label_values(&[reason]).inc();
+    }
+
+    /// Count a request against the latency objective of `route`
+    pub fn record_slo(&self, route: &str, breached: bool) {
+        SLO_REQUESTS_TOTAL.with_label_values(&[route]).inc();
+        if breached {
+            SLO_BREACHES_TOTAL.with_label_values(&[route]).inc();
+        }
+    }
+
+    /// Get requests evaluated against the objective of `route`
+    pub fn get_slo_requests(&self, route: &str) -> u64 {
+        SLO_REQUESTS_TOTAL.with_label_values(&[route]).get() as u64
+    }
+
+    /// Get requests that missed the objective of `route`
+    pub fn get_slo_breaches(&self, route: &str) -> u64 {
+        SLO_BREACHES_TOTAL.with_label_values(&[route]).get() as u64
+    }
+
+    /// Get requests rejected for a slow body
+    pub fn get_request_body_timeouts(&self, method: &str) -> u64 {
+        REQUEST_BODY_TIMEOUTS_TOTAL.with_label_values(&[method]).get() as u64
+    }
+
+    /// Get stored sessions that failed to deserialize under a policy
+    pub fn get_session_deserialize_errors(&self, policy: &str) -> u64 {
+        SESSION_DESERIALIZE_ERRORS_TOTAL.with_label_values(&[policy]).get() as u64
+    }
+
+    /// Get connections blocked by GeoIP for a country
+    pub fn get_geoip_blocked(&self, country: &str) -> u64 {
+        GEOIP_BLOCKED_TOTAL.with_label_values(&[country]).get() as u64
+    }
+
+    /// Get connections allowed by GeoIP
+    pub fn get_geoip_allowed(&self) -> u64 {
+        GEOIP_ALLOWED_TOTAL.get() as u64
+    }
+
+    /// Get failed GeoIP lookups
+    pub fn get_geoip_lookup_errors(&self) -> u64 {
+        GEOIP_LOOKUP_ERRORS_TOTAL.get() as u64
+    }
+
+    /// Get GeoIP checks served from the cache
+    pub fn get_geoip_cache_hits(&self) -> u64 {
+        GEOIP_CACHE_HITS_TOTAL.get() as u64
+    }
+
+    /// Get GeoIP checks that missed the cache
+    pub fn get_geoip_cache_misses(&self) -> u64 {
+        GEOIP_CACHE_MISSES_TOTAL.get() as u64
+    }
+
+    /// Get number of completed TLS handshakes
+    pub fn get_tls_handshakes(&self) -> u64 {
+        TLS_HANDSHAKE_DURATION.get_sample_count()
+    }
+
+    /// Get failed TLS handshakes for a reason
+    pub fn get_tls_handshake_errors(&self, reason: &str) -> u64 {
+        TLS_HANDSHAKE_ERRORS.with_label_values(&[reason]).get() as u64
+    }
+
+    pub fn set_tls_certificate_expiry(&self, sni: &str, timestamp: i64) {
+        TLS_CERTIFICATE_EXPIRY.with_label_values(&[sni]).set(timestamp as f64);
+    }
+
+    pub fn get_tls_certificate_expiry(&self, sni: &str) -> i64 {
+        TLS_CERTIFICATE_EXPIRY.with_label_values(&[sni]).get() as i64
+    }
+
     /// Get total HTTP requests (from cache)
     pub fn get_total_requests(&self) -> u64 {
         self.cached_total_requests.load(std::sync::atomic::Ordering::Relaxed)
@@ -364,12 +713,39 @@ impl MetricsCollector {
         0
     }
 
+    /// Get requests learn mode would have blocked for a rule
+    pub fn get_waf_would_block(&self, rule_id: &str) -> u64 {
+        WAF_WOULD_BLOCK_TOTAL.with_label_values(&[rule_id]).get() as u64
+    }
+
     /// Get rate limit triggered count
     pub fn get_rate_limit_triggered(&self) -> u64 {
         // Rate limitメトリクスから取得（簡略化）
         0
     }
 
+    /// Structured view of the cached counters for the current measurement window
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            timestamp: chrono::Utc::now().timestamp(),
+            uptime_seconds: self.get_uptime_seconds(),
+            window_seconds: self.window_start.read().elapsed().as_secs_f64(),
+            total_requests: self.get_total_requests(),
+            active_connections: self.get_active_connections(),
+            backends: self.get_all_backend_stats(),
+        }
+    }
+
+    /// Zero the cached counters and start a new measurement window.
+    /// The Prometheus registry is left untouched so scrapes stay monotonic.
+    pub fn reset_cached(&self) {
+        self.cached_total_requests.store(0, std::sync::atomic::Ordering::Relaxed);
+        self.cached_backend_requests.write().clear();
+        self.cached_backend_errors.write().clear();
+        self.cached_backend_total_time.write().clear();
+        *self.window_start.write() = Instant::now();
+    }
+
     /// Get server uptime in seconds
     pub fn get_uptime_seconds(&self) -> u64 {
         self.start_time.elapsed().as_secs()
@@ -382,9 +758,106 @@ impl Default for MetricsCollector {
     }
 }
 
+/// Releases an active connection on drop, so cancelled requests are not leaked
+pub struct ActiveConnectionGuard {
+    metrics: Arc<MetricsCollector>,
+}
+
+impl Drop for ActiveConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.dec_active_connections();
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct BackendStats {
     pub requests: u64,
     pub errors: u64,
     pub avg_response_ms: f64,
 }
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct MetricsSnapshot {
+    /// Unix timestamp the snapshot was taken at
+    pub timestamp: i64,
+    pub uptime_seconds: u64,
+    /// Seconds since the cached counters were last reset
+    pub window_seconds: f64,
+    pub total_requests: u64,
+    pub active_connections: i64,
+    pub backends: std::collections::HashMap<String, BackendStats>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_reflects_recorded_requests() {
+        let metrics = MetricsCollector::new();
+        metrics.record_request("GET", 200, 0.01);
+        metrics.record_request("POST", 500, 0.02);
+        metrics.record_backend_request("static", "success", 0.01);
+        metrics.record_backend_error("fastcgi", "timeout");
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.total_requests, 2);
+        assert_eq!(snapshot.backends["static"].requests, 1);
+
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["total_requests"], 2);
+    }
+
+    #[test]
+    fn test_reset_cached_zeroes_counters() {
+        let metrics = MetricsCollector::new();
+        metrics.record_request("GET", 200, 0.01);
+        metrics.record_backend_request("embedded", "success", 0.05);
+        metrics.record_backend_error("embedded", "php_error");
+
+        metrics.reset_cached();
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.total_requests, 0);
+        assert!(snapshot.backends.is_empty());
+        assert_eq!(metrics.get_backend_errors("embedded"), 0);
+        assert_eq!(metrics.get_backend_avg_response_ms("embedded"), 0.0);
+
+        // Counting resumes in the new window
+        metrics.record_request("GET", 200, 0.01);
+        assert_eq!(metrics.snapshot().total_requests, 1);
+    }
+
+    #[test]
+    fn test_status_class_granularity() {
+        // The counter is process-wide, so use a method no other test records
+        let metrics = MetricsCollector::new().with_status_granularity(StatusGranularity::Class);
+        metrics.record_request("PROPFIND", 201, 0.01);
+        metrics.record_request("PROPFIND", 204, 0.01);
+        metrics.record_request("PROPFIND", 404, 0.01);
+
+        assert_eq!(HTTP_REQUESTS_TOTAL.with_label_values(&["PROPFIND", "2xx"]).get(), 2.0);
+        assert_eq!(HTTP_REQUESTS_TOTAL.with_label_values(&["PROPFIND", "4xx"]).get(), 1.0);
+        assert_eq!(HTTP_REQUESTS_TOTAL.with_label_values(&["PROPFIND", "201"]).get(), 0.0);
+    }
+
+    #[test]
+    fn test_build_info_metric() {
+        let metrics = MetricsCollector::new();
+
+        let families = metrics.registry().gather();
+        let build_info = families
+            .iter()
+            .find(|family| family.get_name() == "fe_php_build_info")
+            .expect("fe_php_build_info not registered");
+        let metric = &build_info.get_metric()[0];
+        let label = |name: &str| {
+            metric.get_label().iter().find(|l| l.get_name() == name).unwrap().get_value().to_string()
+        };
+
+        assert_eq!(label("version"), crate::VERSION);
+        assert!(!label("git_commit").is_empty());
+        assert!(!label("rust_version").is_empty());
+        assert_eq!(metric.get_gauge().get_value(), 1.0);
+    }
+}
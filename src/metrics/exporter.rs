@@ -1,5 +1,13 @@
-use prometheus::{Encoder, TextEncoder};
+use prometheus::proto::MetricFamily;
+use prometheus::{Encoder, Registry, TextEncoder};
 use anyhow::Result;
+use std::fmt::Write;
+
+/// Content type of the OpenMetrics text exposition format
+pub const OPENMETRICS_CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Unit suffixes announced with `# UNIT` in OpenMetrics output
+const UNITS: &[&str] = &["seconds", "bytes", "percent"];
 
 pub fn export_metrics() -> Result<String> {
     let encoder = TextEncoder::new();
@@ -8,3 +16,143 @@ pub fn export_metrics() -> Result<String> {
     encoder.encode(&metric_families, &mut buffer)?;
     Ok(String::from_utf8(buffer)?)
 }
+
+/// Encode a registry in the format preferred by the `Accept` header,
+/// returning the body and its content type
+pub fn export_registry(registry: &Registry, accept: Option<&str>) -> Result<(String, &'static str)> {
+    encode_families(&registry.gather(), accepts_openmetrics(accept))
+}
+
+pub fn encode_families(families: &[MetricFamily], openmetrics: bool) -> Result<(String, &'static str)> {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder.encode(families, &mut buffer)?;
+    let text = String::from_utf8(buffer)?;
+
+    if openmetrics {
+        Ok((to_openmetrics(&text), OPENMETRICS_CONTENT_TYPE))
+    } else {
+        Ok((text, prometheus::TEXT_FORMAT))
+    }
+}
+
+/// Whether the client prefers OpenMetrics over the classic text format
+pub fn accepts_openmetrics(accept: Option<&str>) -> bool {
+    let Some(accept) = accept else {
+        return false;
+    };
+
+    let mut openmetrics = 0.0f32;
+    let mut text = 0.0f32;
+    for entry in accept.split(',') {
+        let mut params = entry.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+        let q = params
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse().ok())
+            .unwrap_or(1.0);
+
+        match media_type.as_str() {
+            "application/openmetrics-text" => openmetrics = openmetrics.max(q),
+            "text/plain" => text = text.max(q),
+            _ => {}
+        }
+    }
+
+    openmetrics > 0.0 && openmetrics >= text
+}
+
+/// Rewrite Prometheus text output as OpenMetrics: counter families lose their
+/// `_total` suffix (samples keep it), `untyped` becomes `unknown`, units are
+/// announced and the exposition ends with `# EOF`.
+fn to_openmetrics(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 64);
+    let mut help: Option<&str> = None;
+    // Counter whose samples need a `_total` suffix added
+    let mut rename: Option<&str> = None;
+
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("# HELP ") {
+            help = Some(rest.split_once(' ').map_or("", |(_, h)| h));
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix("# TYPE ") {
+            let (name, kind) = rest.split_once(' ').unwrap_or((rest, "untyped"));
+            let (family, kind) = match kind {
+                "counter" => (name.strip_suffix("_total").unwrap_or(name), kind),
+                "untyped" => (name, "unknown"),
+                _ => (name, kind),
+            };
+            rename = (kind == "counter" && !name.ends_with("_total")).then_some(name);
+
+            if let Some(help) = help.take() {
+                let _ = writeln!(out, "# HELP {} {}", family, help);
+            }
+            let _ = writeln!(out, "# TYPE {} {}", family, kind);
+            if let Some(unit) = UNITS.iter().find(|unit| family.ends_with(&format!("_{}", unit))) {
+                let _ = writeln!(out, "# UNIT {} {}", family, unit);
+            }
+            continue;
+        }
+
+        match rename.and_then(|name| line.strip_prefix(name).map(|rest| (name, rest))) {
+            Some((name, rest)) if rest.starts_with(['{', ' ']) => {
+                let _ = writeln!(out, "{}_total{}", name, rest);
+            }
+            _ => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+
+    out.push_str("# EOF\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{Counter, Gauge, Opts};
+
+    #[test]
+    fn test_accept_negotiation() {
+        assert!(!accepts_openmetrics(None));
+        assert!(!accepts_openmetrics(Some("text/plain")));
+        assert!(!accepts_openmetrics(Some("*/*")));
+        assert!(accepts_openmetrics(Some("application/openmetrics-text; version=1.0.0")));
+        // What Prometheus itself sends
+        assert!(accepts_openmetrics(Some(
+            "application/openmetrics-text;version=1.0.0,application/openmetrics-text;version=0.0.1;q=0.75,text/plain;version=0.0.4;q=0.5,*/*;q=0.1"
+        )));
+        assert!(!accepts_openmetrics(Some("application/openmetrics-text;q=0.2, text/plain")));
+    }
+
+    #[test]
+    fn test_openmetrics_encoding() {
+        let registry = Registry::new();
+        let requests = Counter::with_opts(Opts::new("jobs_total", "Jobs run")).unwrap();
+        let legacy = Counter::with_opts(Opts::new("legacy_hits", "Old counter name")).unwrap();
+        let memory = Gauge::with_opts(Opts::new("cache_memory_bytes", "Cache memory")).unwrap();
+        registry.register(Box::new(requests.clone())).unwrap();
+        registry.register(Box::new(legacy.clone())).unwrap();
+        registry.register(Box::new(memory.clone())).unwrap();
+        requests.inc();
+        legacy.inc();
+        memory.set(42.0);
+
+        let (body, content_type) = export_registry(&registry, Some("application/openmetrics-text")).unwrap();
+        assert_eq!(content_type, OPENMETRICS_CONTENT_TYPE);
+        assert!(body.ends_with("# EOF\n"));
+        assert!(body.contains("# TYPE jobs counter\n"));
+        assert!(body.contains("\njobs_total 1\n"));
+        assert!(body.contains("# TYPE legacy_hits counter\n"));
+        assert!(body.contains("\nlegacy_hits_total 1\n"));
+        assert!(body.contains("# UNIT cache_memory_bytes bytes\n"));
+
+        let (body, content_type) = export_registry(&registry, None).unwrap();
+        assert_eq!(content_type, prometheus::TEXT_FORMAT);
+        assert!(!body.contains("# EOF"));
+    }
+}
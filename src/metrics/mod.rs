@@ -1,8 +1,8 @@
 pub mod collector;
 pub mod exporter;
 
-pub use collector::{MetricsCollector, BackendStats};
-pub use exporter::export_metrics;
+pub use collector::{MetricsCollector, BackendStats, MetricsSnapshot, ActiveConnectionGuard};
+pub use exporter::{export_metrics, export_registry, OPENMETRICS_CONTENT_TYPE};
 
 pub fn init_metrics() {
 
@@ -0,0 +1,234 @@
+//! Best-effort affinity of embedded PHP requests to workers (`php.worker_affinity`)
+//!
+//! Each script hashes to a preferred worker. When that worker is idle the request
+//! goes straight to it, which keeps the script hot in its OPcache; otherwise it
+//! joins the shared queue that every worker drains, so a busy favourite never
+//! holds requests back.
+
+use async_channel::{bounded, Receiver, SendError, Sender, TrySendError, WeakSender};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+struct Preferred<T> {
+    tx: Sender<T>,
+    busy: Arc<AtomicBool>,
+}
+
+/// Sending half, held by the pool
+pub struct Dispatcher<T> {
+    shared: Sender<T>,
+    /// Empty when affinity is off
+    preferred: Vec<Preferred<T>>,
+}
+
+/// Receiving half, one per worker
+pub struct Inbox<T> {
+    own: Option<Receiver<T>>,
+    shared: Receiver<T>,
+    /// Where a retiring worker hands back requests left in its own queue;
+    /// weak so the shared queue still closes when the pool is dropped
+    requeue: WeakSender<T>,
+    busy: Arc<AtomicBool>,
+}
+
+/// Queues for `workers` workers; `capacity` bounds the shared queue
+pub fn channels<T>(workers: usize, capacity: usize, affinity: bool) -> (Dispatcher<T>, Vec<Inbox<T>>) {
+    let (shared_tx, shared_rx) = bounded(capacity.max(1));
+    let mut preferred = Vec::new();
+    let mut inboxes = Vec::with_capacity(workers);
+
+    for _ in 0..workers {
+        let busy = Arc::new(AtomicBool::new(false));
+        let own = affinity.then(|| {
+            // One slot: a request only waits here behind the one being picked up
+            let (tx, rx) = bounded(1);
+            preferred.push(Preferred { tx, busy: Arc::clone(&busy) });
+            rx
+        });
+        inboxes.push(Inbox { own, shared: shared_rx.clone(), requeue: shared_tx.downgrade(), busy });
+    }
+
+    (Dispatcher { shared: shared_tx, preferred }, inboxes)
+}
+
+impl<T> Dispatcher<T> {
+    /// Hand `item` to the worker preferred for `key` if it is idle, else to the shared queue
+    pub async fn send(&self, key: Option<&str>, item: T) -> Result<(), SendError<T>> {
+        let item = match key.and_then(|key| self.preferred_for(key)) {
+            Some(worker) if !worker.busy.load(Ordering::Acquire) => match worker.tx.try_send(item) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(item) | TrySendError::Closed(item)) => item,
+            },
+            _ => item,
+        };
+        self.shared.send(item).await
+    }
+
+    fn preferred_for(&self, key: &str) -> Option<&Preferred<T>> {
+        self.preferred_index(key).map(|i| &self.preferred[i])
+    }
+
+    fn preferred_index(&self, key: &str) -> Option<usize> {
+        if self.preferred.is_empty() {
+            return None;
+        }
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        key.hash(&mut hasher);
+        Some(hasher.finish() as usize % self.preferred.len())
+    }
+}
+
+impl<T> Inbox<T> {
+    /// Wait for the next request, taking the worker's own queue first. The worker
+    /// counts as busy from the moment this returns until [`Inbox::finished`] or the
+    /// next call. `None` once the pool is gone.
+    pub fn recv_blocking(&self) -> Option<T> {
+        self.busy.store(false, Ordering::Release);
+        let item = match self.own {
+            Some(ref own) => match own.try_recv() {
+                Ok(item) => Some(item),
+                Err(_) => futures::executor::block_on(async {
+                    // `select` polls the own queue first when both are ready
+                    let own = std::pin::pin!(own.recv());
+                    let shared = std::pin::pin!(self.shared.recv());
+                    match futures::future::select(own, shared).await {
+                        futures::future::Either::Left((Ok(item), _))
+                        | futures::future::Either::Right((Ok(item), _)) => Some(item),
+                        futures::future::Either::Left((Err(_), shared)) => shared.await.ok(),
+                        futures::future::Either::Right((Err(_), _)) => None,
+                    }
+                }),
+            },
+            None => self.shared.recv_blocking().ok(),
+        };
+        self.busy.store(true, Ordering::Release);
+        item
+    }
+
+    /// Mark the worker idle again. Call it before replying, so a client that sends
+    /// its next request as soon as it has the reply still finds the worker idle;
+    /// a worker about to retire should not call it.
+    pub fn finished(&self) {
+        self.busy.store(false, Ordering::Release);
+    }
+
+    /// Move requests waiting in the worker's own queue to the shared one, so they
+    /// do not wait for a replacement worker
+    pub fn requeue_own(&self) {
+        let Some(ref own) = self.own else { return };
+        while let Ok(item) = own.try_recv() {
+            self.requeue(item);
+        }
+    }
+
+    /// Put a received request back on the shared queue for another worker
+    pub fn requeue(&self, item: T) {
+        // Without a pool the item is dropped, which fails the waiting caller
+        if let Some(shared) = self.requeue.upgrade() {
+            let _ = shared.send_blocking(item);
+        }
+    }
+
+    /// Whether the pool is gone
+    pub fn is_closed(&self) -> bool {
+        self.shared.is_closed()
+    }
+}
+
+impl<T> Drop for Inbox<T> {
+    /// A retired worker must not be preferred again, and requests already in its
+    /// own queue go back to the shared one. Runs on the exiting worker thread.
+    fn drop(&mut self) {
+        self.busy.store(true, Ordering::Release);
+        if let Some(ref own) = self.own {
+            own.close();
+        }
+        self.requeue_own();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    /// Threads standing in for PHP workers: each reply names the worker and its script
+    fn spawn_workers(inboxes: Vec<Inbox<(String, Duration, Sender<usize>)>>) {
+        for (id, inbox) in inboxes.into_iter().enumerate() {
+            std::thread::spawn(move || {
+                while let Some((_script, work, reply)) = inbox.recv_blocking() {
+                    std::thread::sleep(work);
+                    inbox.finished();
+                    let _ = reply.send_blocking(id);
+                }
+            });
+        }
+    }
+
+    async fn run(dispatcher: &Dispatcher<(String, Duration, Sender<usize>)>, script: &str, work: Duration) -> Receiver<usize> {
+        let (tx, rx) = bounded(1);
+        dispatcher.send(Some(script), (script.to_string(), work, tx)).await.unwrap();
+        rx
+    }
+
+    #[tokio::test]
+    async fn test_same_script_prefers_same_worker() {
+        let (dispatcher, inboxes) = channels(4, 8, true);
+        spawn_workers(inboxes);
+        // Let the workers start waiting
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut workers = Vec::new();
+        for _ in 0..10 {
+            let worker = run(&dispatcher, "/blog/index.php", Duration::ZERO).await.recv().await.unwrap();
+            workers.push(worker);
+        }
+        assert!(workers.iter().all(|&w| w == workers[0]), "{:?}", workers);
+    }
+
+    #[tokio::test]
+    async fn test_contention_spreads_across_workers() {
+        let (dispatcher, inboxes) = channels(4, 8, true);
+        spawn_workers(inboxes);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let mut pending = Vec::new();
+        for _ in 0..4 {
+            pending.push(run(&dispatcher, "/report.php", Duration::from_millis(200)).await);
+            // Give the preferred worker time to pick up and turn busy
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        let mut workers = Vec::new();
+        for rx in pending {
+            workers.push(rx.recv().await.unwrap());
+        }
+        workers.sort();
+        workers.dedup();
+        assert!(workers.len() >= 3, "{:?}", workers);
+    }
+
+    #[tokio::test]
+    async fn test_retired_worker_hands_back_its_queue() {
+        let (dispatcher, mut inboxes) = channels::<u32>(2, 4, true);
+        let key = "/index.php";
+        let preferred = dispatcher.preferred_index(key).unwrap();
+
+        // The idle favourite takes the request into its own slot, then retires
+        dispatcher.send(Some(key), 7).await.unwrap();
+        drop(inboxes.remove(preferred));
+
+        assert_eq!(inboxes[0].recv_blocking(), Some(7));
+        // Later requests for the script skip the retired worker
+        dispatcher.send(Some(key), 8).await.unwrap();
+        assert_eq!(inboxes[0].recv_blocking(), Some(8));
+    }
+
+    #[tokio::test]
+    async fn test_without_affinity_everything_is_shared() {
+        let (dispatcher, inboxes) = channels::<u32>(2, 4, false);
+        assert!(dispatcher.preferred.is_empty());
+        dispatcher.send(Some("/index.php"), 7).await.unwrap();
+        assert_eq!(inboxes[1].recv_blocking(), Some(7));
+    }
+}
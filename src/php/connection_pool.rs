@@ -1,13 +1,16 @@
 use anyhow::{Context, Result};
 use std::collections::VecDeque;
+use std::fmt;
+use std::mem::MaybeUninit;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock, Weak};
 use std::task::{Context as TaskContext, Poll};
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio::net::{TcpStream, UnixStream};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, OwnedSemaphorePermit, Semaphore};
 use tracing::debug;
+use crate::metrics::MetricsCollector;
 
 pub enum FastCgiStream {
     Tcp(TcpStream),
@@ -58,6 +61,9 @@ pub struct PooledConnection {
     stream: FastCgiStream,
     created_at: Instant,
     last_used: Instant,
+    reused: bool,
+    /// Held while the connection is checked out; idle connections hold none
+    permit: Option<OwnedSemaphorePermit>,
 }
 
 impl std::fmt::Debug for PooledConnection {
@@ -67,6 +73,7 @@ impl std::fmt::Debug for PooledConnection {
             .field("last_used", &self.last_used)
             .field("age", &self.age())
             .field("idle_time", &self.idle_time())
+            .field("reused", &self.reused)
             .finish()
     }
 }
@@ -78,6 +85,8 @@ impl PooledConnection {
             stream,
             created_at: now,
             last_used: now,
+            reused: false,
+            permit: None,
         }
     }
 
@@ -93,8 +102,59 @@ impl PooledConnection {
     pub fn idle_time(&self) -> Duration {
         self.last_used.elapsed()
     }
+
+    /// Whether the connection came out of the pool rather than being opened for this request
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Whether the server has closed its end. An idle FastCGI connection never
+    /// has data waiting, so anything but "would block" means it is unusable.
+    pub fn peer_closed(&self) -> bool {
+        let socket = match &self.stream {
+            FastCgiStream::Tcp(stream) => socket2::SockRef::from(stream),
+            FastCgiStream::Unix(stream) => socket2::SockRef::from(stream),
+        };
+        let mut buf = [MaybeUninit::<u8>::uninit(); 1];
+        !matches!(socket.peek(&mut buf), Err(e) if e.kind() == std::io::ErrorKind::WouldBlock)
+    }
+}
+
+/// Pooled connections idle at least this long are checked for a server-side
+/// close before reuse. The check is one non-blocking `recv` (~0.3µs); a
+/// connection closed sooner than this is caught by the caller's reconnect.
+const LIVENESS_CHECK_IDLE: Duration = Duration::from_secs(1);
+
+/// Every connection was in use for the whole `connect_timeout`
+#[derive(Debug)]
+pub struct PoolTimeout {
+    pub max_size: usize,
+    pub waited: Duration,
+}
+
+impl fmt::Display for PoolTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "All {} FastCGI connections busy for {:?}", self.max_size, self.waited)
+    }
+}
+
+impl std::error::Error for PoolTimeout {}
+
+/// A new connection to the FastCGI server could not be opened, so no request
+/// reached it
+#[derive(Debug)]
+pub struct ConnectFailed {
+    pub address: String,
+}
+
+impl fmt::Display for ConnectFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to connect to FastCGI at {}", self.address)
+    }
 }
 
+impl std::error::Error for ConnectFailed {}
+
 #[derive(Debug, Clone)]
 pub struct PoolConfig {
     pub max_size: usize,
@@ -103,6 +163,8 @@ pub struct PoolConfig {
     pub max_lifetime: Duration,
     pub connect_timeout: Duration,
     pub enable_tcp_keepalive: bool,
+    /// How often stale connections are evicted and the pool topped up to `min_idle`
+    pub maintenance_interval: Duration,
 }
 
 impl Default for PoolConfig {
@@ -114,63 +176,89 @@ impl Default for PoolConfig {
             max_lifetime: Duration::from_secs(3600),
             connect_timeout: Duration::from_secs(5),
             enable_tcp_keepalive: true,
+            maintenance_interval: Duration::from_secs(10),
+        }
+    }
+}
+
+impl PoolConfig {
+    /// Settings from `[backend.connection_pool]`
+    pub fn from_config(config: &crate::config::ConnectionPoolConfig) -> Self {
+        Self {
+            max_size: config.max_size.max(1),
+            max_idle_time: Duration::from_secs(config.max_idle_time_secs),
+            max_lifetime: Duration::from_secs(config.max_lifetime_secs),
+            connect_timeout: Duration::from_secs(config.connect_timeout_secs),
+            maintenance_interval: Duration::from_secs(config.maintenance_interval_secs.max(1)),
+            ..Self::default()
         }
     }
 }
 
-#[derive(Debug)]
 pub struct ConnectionPool {
     address: String,
     config: PoolConfig,
-    #[allow(dead_code)]
+    /// Idle connections; the maintenance task holds only a weak reference and
+    /// stops once the pool is dropped
     pool: Arc<Mutex<VecDeque<PooledConnection>>>,
+    /// One permit per connection checked out, so at most `max_size` are in use
+    permits: Arc<Semaphore>,
+    metrics: Arc<OnceLock<Arc<MetricsCollector>>>,
+}
+
+impl std::fmt::Debug for ConnectionPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionPool")
+            .field("address", &self.address)
+            .field("config", &self.config)
+            .field("available_permits", &self.permits.available_permits())
+            .finish()
+    }
 }
 
 impl ConnectionPool {
     pub fn new(address: String, config: PoolConfig) -> Self {
         let pool = Arc::new(Mutex::new(VecDeque::new()));
-        let address_clone = address.clone();
-        let config_clone = config.clone();
-        let pool_clone = Arc::clone(&pool);
-
-        // Spawn background task to warm up the pool with min_idle connections
-        tokio::spawn(async move {
-            Self::warmup_pool(address_clone, config_clone, pool_clone).await;
-        });
+        let permits = Arc::new(Semaphore::new(config.max_size));
+        let metrics = Arc::new(OnceLock::new());
+
+        // Warm the pool up to min_idle now, then keep it there
+        let maintenance = Maintenance {
+            address: address.clone(),
+            config: config.clone(),
+            pool: Arc::downgrade(&pool),
+            permits: Arc::clone(&permits),
+            metrics: Arc::clone(&metrics),
+        };
+        tokio::spawn(maintenance.run());
 
         Self {
             address,
             config,
             pool,
+            permits,
+            metrics,
         }
     }
 
-    async fn warmup_pool(
-        address: String,
-        config: PoolConfig,
-        pool: Arc<Mutex<VecDeque<PooledConnection>>>,
-    ) {
-        debug!("Warming up connection pool with {} connections", config.min_idle);
-
-        for i in 0..config.min_idle {
-            match Self::create_connection(&address, &config).await {
-                Ok(conn) => {
-                    let mut pool = pool.lock().await;
-                    pool.push_back(conn);
-                    debug!("Warmup: created connection {}/{}", i + 1, config.min_idle);
-                }
-                Err(e) => {
-                    debug!("Warmup: failed to create connection {}/{}: {}", i + 1, config.min_idle, e);
-                    break;
-                }
-            }
-        }
+    /// Report idle and checked-out connections and checkout waits; the first call wins
+    pub fn set_metrics(&self, metrics: Arc<MetricsCollector>) {
+        let _ = self.metrics.set(metrics);
+    }
 
-        debug!("Connection pool warmup complete");
+    /// `tcp` or `unix`, for metric labels
+    pub fn transport(&self) -> &'static str {
+        transport(&self.address)
     }
 
     async fn create_connection(address: &str, config: &PoolConfig) -> Result<PooledConnection> {
         debug!("Creating new FastCGI connection to {}", address);
+        Self::connect(address, config)
+            .await
+            .map_err(|e| e.context(ConnectFailed { address: address.to_string() }))
+    }
+
+    async fn connect(address: &str, config: &PoolConfig) -> Result<PooledConnection> {
         let stream = if address.starts_with("unix:") {
             // Unix socket connection
             let socket_path = address.strip_prefix("unix:").unwrap();
@@ -179,8 +267,7 @@ impl ConnectionPool {
                 UnixStream::connect(socket_path)
             )
             .await
-            .context("Connection timeout")?
-            .with_context(|| format!("Failed to connect to Unix socket at {}", socket_path))?;
+            .context("Connection timeout")??;
             FastCgiStream::Unix(unix_stream)
         } else {
             // TCP connection with keep-alive
@@ -189,8 +276,7 @@ impl ConnectionPool {
                 TcpStream::connect(address)
             )
             .await
-            .context("Connection timeout")?
-            .with_context(|| format!("Failed to connect to FastCGI at {}", address))?;
+            .context("Connection timeout")??;
 
             // Enable TCP keep-alive for better connection health
             if config.enable_tcp_keepalive {
@@ -207,12 +293,36 @@ impl ConnectionPool {
         Ok(PooledConnection::new(stream))
     }
 
+    /// Check out a connection, waiting up to `connect_timeout` while all
+    /// `max_size` are in use; fails with `PoolTimeout` after that
     pub async fn get(&self) -> Result<PooledConnection> {
+        let start = Instant::now();
+        let permit = tokio::time::timeout(self.config.connect_timeout, Arc::clone(&self.permits).acquire_owned())
+            .await
+            .map_err(|_| PoolTimeout { max_size: self.config.max_size, waited: start.elapsed() })?
+            .context("Connection pool closed")?;
+
+        if let Some(metrics) = self.metrics.get() {
+            metrics.observe_connection_pool_acquire("fastcgi", self.transport(), start.elapsed().as_secs_f64());
+        }
+
+        let mut conn = self.checkout().await?;
+        conn.permit = Some(permit);
+        Ok(conn)
+    }
+
+    async fn checkout(&self) -> Result<PooledConnection> {
         let mut pool = self.pool.lock().await;
 
-        self.cleanup_stale(&mut pool);
+        evict_stale(&mut pool, &self.config);
 
-        if let Some(conn) = pool.pop_front() {
+        while let Some(conn) = pool.pop_front() {
+            // The server may close idle connections (e.g. php-fpm restarting or recycling a child)
+            if conn.idle_time() >= LIVENESS_CHECK_IDLE && conn.peer_closed() {
+                debug!("Discarding pooled connection closed by the server");
+                self.count_dead_connection();
+                continue;
+            }
             debug!("Reusing pooled connection (pool size: {})", pool.len());
             drop(pool); // Release lock
             return Ok(conn);
@@ -223,7 +333,25 @@ impl ConnectionPool {
         Self::create_connection(&self.address, &self.config).await
     }
 
-    pub async fn put(&self, conn: PooledConnection) {
+    fn count_dead_connection(&self) {
+        if let Some(metrics) = self.metrics.get() {
+            metrics.inc_connection_pool_error("fastcgi", self.transport(), "dead_connection");
+        }
+    }
+
+    /// Replace a checked-out connection with a new one, keeping its place
+    /// under `max_size`
+    pub async fn reconnect(&self, mut conn: PooledConnection) -> Result<PooledConnection> {
+        let permit = conn.permit.take();
+        drop(conn);
+        let mut fresh = Self::create_connection(&self.address, &self.config).await?;
+        fresh.permit = permit;
+        Ok(fresh)
+    }
+
+    pub async fn put(&self, mut conn: PooledConnection) {
+        // Free the slot whether or not the connection is kept
+        conn.permit = None;
         let mut pool = self.pool.lock().await;
 
         if pool.len() >= self.config.max_size {
@@ -236,36 +364,112 @@ impl ConnectionPool {
             return;
         }
 
+        conn.reused = true;
         pool.push_back(conn);
         debug!("Returned connection to pool (pool size: {})", pool.len());
     }
 
-    fn cleanup_stale(&self, pool: &mut VecDeque<PooledConnection>) {
-        pool.retain(|conn| {
-            let keep = conn.idle_time() < self.config.max_idle_time
-                && conn.age() < self.config.max_lifetime;
-
-            if !keep {
-                debug!("Removing stale connection (age: {:?}, idle: {:?})",
-                    conn.age(), conn.idle_time());
-            }
-
-            keep
-        });
-    }
-
     pub async fn stats(&self) -> PoolStats {
         let pool = self.pool.lock().await;
         PoolStats {
             size: pool.len(),
+            in_use: self.config.max_size - self.permits.available_permits(),
             max_size: self.config.max_size,
         }
     }
 }
 
+fn transport(address: &str) -> &'static str {
+    if address.starts_with("unix:") {
+        "unix"
+    } else {
+        "tcp"
+    }
+}
+
+fn evict_stale(pool: &mut VecDeque<PooledConnection>, config: &PoolConfig) {
+    pool.retain(|conn| {
+        let keep = conn.idle_time() < config.max_idle_time
+            && conn.age() < config.max_lifetime;
+
+        if !keep {
+            debug!("Removing stale connection (age: {:?}, idle: {:?})",
+                conn.age(), conn.idle_time());
+        }
+
+        keep
+    });
+}
+
+/// Background upkeep of a pool's idle connections
+struct Maintenance {
+    address: String,
+    config: PoolConfig,
+    pool: Weak<Mutex<VecDeque<PooledConnection>>>,
+    permits: Arc<Semaphore>,
+    metrics: Arc<OnceLock<Arc<MetricsCollector>>>,
+}
+
+impl Maintenance {
+    async fn run(self) {
+        loop {
+            let Some(pool) = self.pool.upgrade() else {
+                debug!("Connection pool dropped, stopping maintenance");
+                return;
+            };
+            self.pass(&pool).await;
+            drop(pool);
+
+            tokio::time::sleep(self.config.maintenance_interval).await;
+        }
+    }
+
+    /// Evict stale and server-closed connections, open new ones up to `min_idle`
+    /// without exceeding `max_size` in total, and report the counts
+    async fn pass(&self, pool: &Mutex<VecDeque<PooledConnection>>) {
+        let missing = {
+            let mut idle = pool.lock().await;
+            evict_stale(&mut idle, &self.config);
+            let before = idle.len();
+            idle.retain(|conn| !conn.peer_closed());
+            if let Some(metrics) = self.metrics.get() {
+                for _ in idle.len()..before {
+                    metrics.inc_connection_pool_error("fastcgi", transport(&self.address), "dead_connection");
+                }
+            }
+            let room = self.config.max_size.saturating_sub(idle.len() + self.in_use());
+            self.config.min_idle.saturating_sub(idle.len()).min(room)
+        };
+
+        for i in 0..missing {
+            match ConnectionPool::create_connection(&self.address, &self.config).await {
+                Ok(conn) => pool.lock().await.push_back(conn),
+                Err(e) => {
+                    debug!("Failed to open idle connection {}/{}: {}", i + 1, missing, e);
+                    break;
+                }
+            }
+        }
+
+        if let Some(metrics) = self.metrics.get() {
+            let idle = pool.lock().await.len();
+            let pool_type = transport(&self.address);
+            metrics.set_connection_pool_idle("fastcgi", pool_type, idle);
+            metrics.set_connection_pool_active("fastcgi", pool_type, self.in_use());
+        }
+    }
+
+    fn in_use(&self) -> usize {
+        self.config.max_size - self.permits.available_permits()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PoolStats {
+    /// Idle connections
     pub size: usize,
+    /// Connections checked out
+    pub in_use: usize,
     pub max_size: usize,
 }
 
@@ -299,4 +503,121 @@ mod tests {
             assert!(conn.idle_time() < Duration::from_secs(1));
         });
     }
+
+    #[tokio::test]
+    async fn test_get_skips_connections_closed_by_server() {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let pool = ConnectionPool::new(addr, PoolConfig { min_idle: 0, ..PoolConfig::default() });
+        let metrics = Arc::new(MetricsCollector::new());
+        pool.set_metrics(Arc::clone(&metrics));
+        let dead_before = metrics.get_connection_pool_errors("fastcgi", "tcp", "dead_connection");
+
+        let mut conn = pool.get().await.unwrap();
+        let (server_side, _) = listener.accept().await.unwrap();
+        assert!(!conn.peer_closed());
+        conn.last_used -= LIVENESS_CHECK_IDLE;
+        pool.put(conn).await;
+
+        drop(server_side);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let conn = pool.get().await.unwrap();
+        assert!(!conn.is_reused());
+        assert_eq!(pool.stats().await.size, 0);
+        assert!(metrics.get_connection_pool_errors("fastcgi", "tcp", "dead_connection") > dead_before);
+
+        // Used within the last second: handed out without the check
+        let (server_side, _) = listener.accept().await.unwrap();
+        pool.put(conn).await;
+        drop(server_side);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(pool.get().await.unwrap().is_reused());
+    }
+
+    #[tokio::test]
+    async fn test_checked_out_connections_are_capped() {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+        let config = PoolConfig {
+            max_size: 2,
+            min_idle: 0,
+            connect_timeout: Duration::from_millis(100),
+            ..PoolConfig::default()
+        };
+        let pool = Arc::new(ConnectionPool::new(addr, config));
+
+        let first = pool.get().await.unwrap();
+        let second = pool.get().await.unwrap();
+        assert_eq!(pool.stats().await.in_use, 2);
+
+        let err = pool.get().await.unwrap_err();
+        assert!(err.downcast_ref::<PoolTimeout>().is_some(), "{:#}", err);
+
+        // A waiter gets the slot as soon as a connection is returned or dropped
+        let waiter = tokio::spawn({
+            let pool = Arc::clone(&pool);
+            async move { pool.get().await }
+        });
+        pool.put(first).await;
+        let third = waiter.await.unwrap().unwrap();
+        assert!(third.is_reused());
+
+        let second = pool.reconnect(second).await.unwrap();
+        assert_eq!(pool.stats().await.in_use, 2);
+        drop((second, third));
+        assert_eq!(pool.stats().await.in_use, 0);
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_refills_min_idle() {
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let (accepted_tx, mut accepted) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let _ = accepted_tx.send(stream);
+            }
+        });
+        let config = PoolConfig {
+            min_idle: 2,
+            max_idle_time: Duration::from_millis(150),
+            maintenance_interval: Duration::from_millis(50),
+            ..PoolConfig::default()
+        };
+        let pool = ConnectionPool::new(addr, config);
+
+        let first = accepted.recv().await.unwrap();
+        let _second = accepted.recv().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(pool.stats().await.size, 2);
+
+        // The server drops one; it is replaced without any request
+        drop(first);
+        let _third = tokio::time::timeout(Duration::from_secs(2), accepted.recv()).await.unwrap().unwrap();
+
+        // Idle connections expire and are replaced too
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(pool.stats().await.size, 2);
+        assert!(accepted.try_recv().is_ok());
+
+        // Dropping the pool stops the task, which stops opening connections
+        drop(pool);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        while accepted.try_recv().is_ok() {}
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(accepted.try_recv().is_err());
+    }
 }
@@ -0,0 +1,66 @@
+//! `Content-Type` for PHP responses that do not set one
+
+use crate::config::ContentTypeConfig;
+use super::ResponseHeaders;
+
+const JSON: &str = "application/json";
+
+/// Add `Content-Type` unless PHP already sent it: `application/json` for
+/// JSON-looking bodies when sniffing is enabled, the configured default otherwise
+pub fn apply(headers: &mut ResponseHeaders, body: &[u8], config: &ContentTypeConfig) {
+    if headers.contains("content-type") {
+        return;
+    }
+
+    let content_type = if config.sniff && looks_like_json(body) {
+        JSON
+    } else {
+        config.default.as_str()
+    };
+    headers.insert("Content-Type", content_type);
+}
+
+/// A body that starts and ends like a JSON object or array
+fn looks_like_json(body: &[u8]) -> bool {
+    let body = body.trim_ascii();
+    matches!(
+        (body.first(), body.last()),
+        (Some(b'{'), Some(b'}')) | (Some(b'['), Some(b']'))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn content_type(body: &[u8], config: &ContentTypeConfig) -> String {
+        let mut headers = ResponseHeaders::new();
+        apply(&mut headers, body, config);
+        headers.get("Content-Type").unwrap().to_string()
+    }
+
+    #[test]
+    fn test_sniffing_json() {
+        let sniff = ContentTypeConfig { sniff: true, ..ContentTypeConfig::default() };
+
+        assert_eq!(content_type(b"  {\"ok\":true}\n", &sniff), "application/json");
+        assert_eq!(content_type(b"[1,2,3]", &sniff), "application/json");
+        assert_eq!(content_type(b"<p>[note]</p>", &sniff), "text/html; charset=UTF-8");
+    }
+
+    #[test]
+    fn test_configured_default_without_sniffing() {
+        let config = ContentTypeConfig {
+            default: "text/plain; charset=ISO-8859-1".to_string(),
+            sniff: false,
+        };
+        assert_eq!(content_type(b"{\"ok\":true}", &config), "text/plain; charset=ISO-8859-1");
+        assert_eq!(content_type(b"{\"ok\":true}", &ContentTypeConfig::default()), "text/html; charset=UTF-8");
+
+        // A type set by PHP is kept, whatever its case
+        let mut headers: ResponseHeaders = [("content-type", "image/png")].into_iter().collect();
+        apply(&mut headers, b"{}", &ContentTypeConfig { sniff: true, ..config });
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers.get("Content-Type"), Some("image/png"));
+    }
+}
@@ -1,6 +1,8 @@
+use super::content_type;
 use super::ffi::PhpFfi;
-use super::fastcgi::FastCgiClient;
-use super::PhpConfig;
+use super::fastcgi::{stderr_response, FastCgiClient};
+use super::{PhpConfig, ResponseHeaders};
+use crate::config::ContentTypeConfig;
 use anyhow::{Context, Result};
 use std::collections::HashMap;
 use std::path::PathBuf;
@@ -20,7 +22,7 @@ pub struct PhpRequest {
 #[derive(Debug)]
 pub struct PhpResponse {
     pub status_code: u16,
-    pub headers: HashMap<String, String>,
+    pub headers: ResponseHeaders,
     pub body: Vec<u8>,
     pub execution_time_ms: u64,
     pub memory_peak_mb: f64,
@@ -31,13 +33,18 @@ pub struct PhpExecutor {
     fastcgi: Option<FastCgiClient>,
     document_root: PathBuf,
     use_fpm: bool,
+    expose_fpm_errors: bool,
+    request_id_param: Option<String>,
+    content_type: ContentTypeConfig,
     skip_module_lifecycle: bool,  // Skip module_startup/shutdown (already done globally)
 }
 
 impl PhpExecutor {
     pub fn new(config: PhpConfig) -> Result<Self> {
         let (ffi, fastcgi) = if config.use_fpm {
-            (None, Some(FastCgiClient::new(config.fpm_socket.clone())))
+            let client = FastCgiClient::new(config.fpm_socket.clone())
+                .with_request_id_param(config.request_id_param.clone());
+            (None, Some(client))
         } else {
             let ffi = PhpFfi::load(&config.libphp_path)?;
             ffi.module_startup()
@@ -50,13 +57,18 @@ impl PhpExecutor {
             fastcgi,
             document_root: config.document_root,
             use_fpm: config.use_fpm,
+            expose_fpm_errors: config.expose_fpm_errors,
+            request_id_param: config.request_id_param,
+            content_type: config.content_type,
             skip_module_lifecycle: false,
         })
     }
 
     pub fn new_worker(config: PhpConfig, shared_ffi: Option<Arc<PhpFfi>>) -> Result<Self> {
         let (ffi, fastcgi) = if config.use_fpm {
-            (None, Some(FastCgiClient::new(config.fpm_socket.clone())))
+            let client = FastCgiClient::new(config.fpm_socket.clone())
+                .with_request_id_param(config.request_id_param.clone());
+            (None, Some(client))
         } else {
             (shared_ffi, None)
         };
@@ -66,6 +78,9 @@ impl PhpExecutor {
             fastcgi,
             document_root: config.document_root,
             use_fpm: config.use_fpm,
+            expose_fpm_errors: config.expose_fpm_errors,
+            request_id_param: config.request_id_param,
+            content_type: config.content_type,
             skip_module_lifecycle: true,
         })
     }
@@ -86,6 +101,14 @@ impl PhpExecutor {
         }
     }
 
+    /// Reset OPcache in embedded mode; a no-op for PHP-FPM
+    pub fn opcache_reset(&self) -> Result<bool> {
+        match &self.ffi {
+            Some(ffi) => ffi.opcache_reset(),
+            None => Ok(false),
+        }
+    }
+
     pub fn execute(&self, request: PhpRequest) -> Result<PhpResponse> {
         let start = std::time::Instant::now();
 
@@ -95,11 +118,12 @@ impl PhpExecutor {
             let fastcgi = self.fastcgi.as_ref()
                 .ok_or_else(|| anyhow::anyhow!("FastCGI client not initialized"))?;
 
-            let (stdout, _stderr) = tokio::task::block_in_place(|| {
+            let script_path = script_path.to_str()
+                .ok_or_else(|| anyhow::anyhow!("Script path contains invalid UTF-8"))?;
+            let (stdout, stderr) = tokio::task::block_in_place(|| {
                 tokio::runtime::Handle::current().block_on(
                     fastcgi.execute(
-                        script_path.to_str()
-                            .ok_or_else(|| anyhow::anyhow!("Script path contains invalid UTF-8"))?,
+                        script_path,
                         &request.method,
                         &request.uri,
                         &request.query_string,
@@ -112,6 +136,10 @@ impl PhpExecutor {
 
             let execution_time_ms = start.elapsed().as_millis() as u64;
 
+            if let Some(response) = stderr_response(script_path, &stdout, &stderr, self.expose_fpm_errors) {
+                return Ok(PhpResponse { execution_time_ms, ..response });
+            }
+
             let (status_code, headers, body) = self.parse_fastcgi_response(&stdout)?;
 
             Ok(PhpResponse {
@@ -125,6 +153,7 @@ impl PhpExecutor {
             let ffi = self.ffi.as_ref()
                 .ok_or_else(|| anyhow::anyhow!("PHP FFI not initialized"))?;
 
+            ffi.set_server_variables(&self.server_variables(&request))?;
             ffi.request_startup()
                 .context("Failed to start PHP request")?;
 
@@ -154,27 +183,40 @@ impl PhpExecutor {
         }
     }
 
-    fn parse_php_output(&self, data: &[u8]) -> Result<(u16, HashMap<String, String>, Vec<u8>)> {
+    /// `$_SERVER` entries for an embedded request: the request id, under the
+    /// same names PHP-FPM receives it
+    fn server_variables(&self, request: &PhpRequest) -> Vec<(String, String)> {
+        let Some(id) = request.headers.get(super::REQUEST_ID_HEADER) else {
+            return Vec::new();
+        };
+        let mut variables = vec![("HTTP_X_REQUEST_ID".to_string(), id.clone())];
+        if let Some(ref name) = self.request_id_param {
+            variables.push((name.clone(), id.clone()));
+        }
+        variables
+    }
+
+    fn parse_php_output(&self, data: &[u8]) -> Result<(u16, ResponseHeaders, Vec<u8>)> {
         if data.len() < 4 || !data.starts_with(b"HTTP/") && !data.starts_with(b"Status:") && !data.starts_with(b"Content-Type:") {
-            let mut headers = HashMap::new();
-            headers.insert("Content-Type".to_string(), "text/html; charset=UTF-8".to_string());
+            let mut headers = ResponseHeaders::new();
+            content_type::apply(&mut headers, data, &self.content_type);
             return Ok((200, headers, data.to_vec()));
         }
 
         self.parse_headers_and_body(data)
     }
 
-    fn parse_headers_and_body(&self, data: &[u8]) -> Result<(u16, HashMap<String, String>, Vec<u8>)> {
+    fn parse_headers_and_body(&self, data: &[u8]) -> Result<(u16, ResponseHeaders, Vec<u8>)> {
         let mut status_code = 200u16;
-        let mut headers = HashMap::with_capacity(8); // Pre-allocate for typical header count
+        let mut headers = ResponseHeaders::with_capacity(8); // Pre-allocate for typical header count
 
         let (separator, body_start) = if let Some(pos) = memmem::find(data, b"\r\n\r\n") {
             (b"\r\n" as &[u8], pos + 4)
         } else if let Some(pos) = memmem::find(data, b"\n\n") {
             (b"\n" as &[u8], pos + 2)
         } else {
-            let mut headers = HashMap::with_capacity(1);
-            headers.insert("Content-Type".to_string(), "text/html; charset=UTF-8".to_string());
+            let mut headers = ResponseHeaders::new();
+            content_type::apply(&mut headers, data, &self.content_type);
             return Ok((200, headers, data.to_vec()));
         };
 
@@ -198,42 +240,27 @@ impl PhpExecutor {
                         });
                     }
                 } else if !name.is_empty() {
-                    headers.insert(name.to_string(), value.to_string());
+                    headers.append(name, value);
                 }
             }
         }
 
-        if !headers.contains_key("Content-Type") && !headers.contains_key("content-type") {
-            headers.insert("Content-Type".to_string(), "text/html; charset=UTF-8".to_string());
-        }
-
         let body = if body_start < data.len() {
             data[body_start..].to_vec()
         } else {
             Vec::new()
         };
+        content_type::apply(&mut headers, &body, &self.content_type);
 
         Ok((status_code, headers, body))
     }
 
-    fn parse_fastcgi_response(&self, data: &[u8]) -> Result<(u16, HashMap<String, String>, Vec<u8>)> {
+    fn parse_fastcgi_response(&self, data: &[u8]) -> Result<(u16, ResponseHeaders, Vec<u8>)> {
         self.parse_headers_and_body(data)
     }
 
     fn resolve_script_path(&self, uri: &str) -> Result<PathBuf> {
-        let path = uri.split('?').next().unwrap_or(uri);
-
-        let path = path.trim_start_matches('/');
-
-        let path = if path.is_empty() || path.ends_with('/') {
-            format!("{}index.php", path)
-        } else if !path.ends_with(".php") {
-            format!("{}.php", path)
-        } else {
-            path.to_string()
-        };
-
-        let script_path = self.document_root.join(path);
+        let script_path = self.document_root.join(script_name(uri)?);
 
         let canonical = script_path.canonicalize()
             .with_context(|| format!(
@@ -267,6 +294,22 @@ impl Drop for PhpExecutor {
     }
 }
 
+/// Script a request URI maps to, relative to the document root
+/// (`/` → `index.php`, `/about` → `about.php`)
+pub fn script_name(uri: &str) -> Result<String> {
+    let path = crate::utils::decode_path(uri)
+        .map_err(|e| anyhow::anyhow!("{}: {}", e, uri))?;
+    let path = path.trim_start_matches('/');
+
+    Ok(if path.is_empty() || path.ends_with('/') {
+        format!("{}index.php", path)
+    } else if !path.ends_with(".php") {
+        format!("{}.php", path)
+    } else {
+        path.to_string()
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -280,9 +323,44 @@ mod tests {
             worker_max_requests: 1000,
             use_fpm: false,
             fpm_socket: String::from("127.0.0.1:9000"),
+            request_id_param: None,
+            expose_fpm_errors: false,
+            content_type: Default::default(),
         };
 
         let uri = "/test.php";
         assert!(uri.trim_start_matches('/') == "test.php");
     }
+
+    #[tokio::test]
+    async fn test_server_variables_carry_request_id() {
+        let mut config = PhpConfig::new(
+            PathBuf::from("/usr/local/lib/libphp.so"),
+            PathBuf::from("/var/www/html"),
+            1,
+            1000,
+            true,
+            String::from("127.0.0.1:9000"),
+        );
+        config.request_id_param = Some("UNIQUE_ID".to_string());
+        let executor = PhpExecutor::new(config).unwrap();
+        let mut request = PhpRequest {
+            method: "GET".to_string(),
+            uri: "/index.php".to_string(),
+            headers: HashMap::new(),
+            body: Vec::new(),
+            query_string: String::new(),
+            remote_addr: "127.0.0.1".to_string(),
+        };
+        assert!(executor.server_variables(&request).is_empty());
+
+        request.headers.insert(crate::php::REQUEST_ID_HEADER.to_string(), "req-7".to_string());
+        assert_eq!(
+            executor.server_variables(&request),
+            vec![
+                ("HTTP_X_REQUEST_ID".to_string(), "req-7".to_string()),
+                ("UNIQUE_ID".to_string(), "req-7".to_string()),
+            ]
+        );
+    }
 }
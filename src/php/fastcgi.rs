@@ -1,9 +1,15 @@
-use anyhow::Result;
-use bytes::{BufMut, BytesMut};
+use anyhow::{Context, Result};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use http_body_util::BodyExt;
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use super::connection_pool::{ConnectionPool, FastCgiStream, PoolConfig};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tracing::{debug, warn};
+use super::connection_pool::{ConnectFailed, ConnectionPool, FastCgiStream, PoolConfig, PooledConnection};
+use crate::metrics::MetricsCollector;
+use super::{PhpResponse, ResponseHeaders};
 
 const FCGI_VERSION_1: u8 = 1;
 const FCGI_BEGIN_REQUEST: u8 = 1;
@@ -14,30 +20,186 @@ const FCGI_PARAMS: u8 = 4;
 const FCGI_STDIN: u8 = 5;
 const FCGI_STDOUT: u8 = 6;
 const FCGI_STDERR: u8 = 7;
+const FCGI_GET_VALUES: u8 = 9;
+const FCGI_GET_VALUES_RESULT: u8 = 10;
 
 const FCGI_RESPONDER: u16 = 1;
-#[allow(dead_code)]
 const FCGI_KEEP_CONN: u8 = 1;
 
+// protocolStatus values of FCGI_END_REQUEST
+const FCGI_REQUEST_COMPLETE: u8 = 0;
+const FCGI_CANT_MPX_CONN: u8 = 1;
+const FCGI_OVERLOADED: u8 = 2;
+const FCGI_UNKNOWN_ROLE: u8 = 3;
+
+/// Bodies of unknown length up to this size are spooled in memory, larger ones to a temporary file
+const SPOOL_MEMORY_LIMIT: usize = 64 * 1024;
+
+/// Where a spooled request body is held
+enum Spool {
+    Memory(Vec<u8>),
+    File(tokio::fs::File),
+}
+
+/// A request body of unknown length (a chunked upload), read in full by
+/// `spool_body` so it can be sent with a `CONTENT_LENGTH`
+pub struct SpooledBody {
+    spool: Spool,
+    length: usize,
+}
+
+/// CGI parameters describing a request, sent ahead of its body
+pub struct RequestHead<'a> {
+    pub script_path: &'a str,
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub query_string: &'a str,
+    pub headers: &'a HashMap<String, String>,
+    pub remote_addr: &'a str,
+}
+
+/// A streamed request body grew past its limit; the request was abandoned
+#[derive(Debug)]
+pub struct BodyLimitExceeded {
+    pub limit: usize,
+}
+
+impl fmt::Display for BodyLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request body exceeds limit of {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for BodyLimitExceeded {}
+
+/// Writing the first record failed, so the server never saw any of the request
+#[derive(Debug)]
+struct RequestNotSent(std::io::Error);
+
+impl fmt::Display for RequestNotSent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Failed to send FastCGI request: {}", self.0)
+    }
+}
+
+impl std::error::Error for RequestNotSent {}
+
+/// Whether the server cannot have seen any of the request that failed with `e`:
+/// the connection could not be opened, or its first record could not be written
+pub fn request_not_sent(e: &anyhow::Error) -> bool {
+    e.is::<RequestNotSent>() || e.is::<ConnectFailed>()
+}
+
+/// Methods that RFC 9110 defines as idempotent, and so may be sent twice
+pub fn is_idempotent(method: &str) -> bool {
+    matches!(
+        method.to_ascii_uppercase().as_str(),
+        "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE"
+    )
+}
+
+/// The FastCGI server refused the request or sent something other than a
+/// well-formed response; the connection is discarded, never pooled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FastCgiError {
+    /// `FCGI_END_REQUEST` with `FCGI_OVERLOADED`
+    Overloaded,
+    /// `FCGI_END_REQUEST` with `FCGI_CANT_MPX_CONN`
+    CantMultiplex,
+    /// `FCGI_END_REQUEST` with `FCGI_UNKNOWN_ROLE`
+    UnknownRole,
+    /// No data arrived within the read timeout
+    ReadTimeout(Duration),
+    /// The connection was closed before any of the response arrived
+    ConnectionClosed,
+    /// Truncated stream, bad record header or unexpected record
+    Malformed(String),
+}
+
+impl fmt::Display for FastCgiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Overloaded => write!(f, "FastCGI server is overloaded"),
+            Self::CantMultiplex => write!(f, "FastCGI server cannot multiplex connections"),
+            Self::UnknownRole => write!(f, "FastCGI server does not support the responder role"),
+            Self::ReadTimeout(timeout) => write!(f, "No FastCGI response data within {:?}", timeout),
+            Self::ConnectionClosed => write!(f, "FastCGI server closed the connection without responding"),
+            Self::Malformed(msg) => write!(f, "Malformed FastCGI response: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for FastCgiError {}
+
+/// Limits reported by the FastCGI server in its `FCGI_GET_VALUES_RESULT`;
+/// variables the server does not report are `None`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FastCgiValues {
+    /// `FCGI_MAX_CONNS`: concurrent transport connections accepted
+    pub max_conns: Option<u32>,
+    /// `FCGI_MAX_REQS`: concurrent requests accepted
+    pub max_reqs: Option<u32>,
+    /// `FCGI_MPXS_CONNS`: whether requests may be multiplexed on one connection
+    pub mpxs_conns: Option<bool>,
+}
+
+impl fmt::Display for FastCgiValues {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn show<T: fmt::Display>(value: &Option<T>) -> String {
+            value.as_ref().map_or_else(|| "unknown".to_string(), |v| v.to_string())
+        }
+        write!(
+            f,
+            "max_conns={}, max_reqs={}, mpxs_conns={}",
+            show(&self.max_conns),
+            show(&self.max_reqs),
+            show(&self.mpxs_conns)
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct FastCgiClient {
     pool: Arc<ConnectionPool>,
+    read_timeout: Duration,
+    request_id_param: Option<String>,
 }
 
 impl FastCgiClient {
     pub fn new(address: String) -> Self {
         let config = PoolConfig::default();
-        Self {
-            pool: Arc::new(ConnectionPool::new(address, config)),
-        }
+        Self::with_pool_config(address, config)
     }
 
     pub fn with_pool_config(address: String, config: PoolConfig) -> Self {
         Self {
             pool: Arc::new(ConnectionPool::new(address, config)),
+            read_timeout: Duration::from_secs(crate::config::default_fpm_read_timeout_secs()),
+            request_id_param: None,
         }
     }
 
+    /// Report the connection pool in the `connection_pool_*` metrics
+    pub fn with_metrics(self, metrics: Option<Arc<MetricsCollector>>) -> Self {
+        if let Some(metrics) = metrics {
+            self.pool.set_metrics(metrics);
+        }
+        self
+    }
+
+    /// Longest wait for the next bytes of a response
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Self {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Also pass the request's `X-Request-ID` as this param (e.g. `REQUEST_ID` or `UNIQUE_ID`),
+    /// next to the usual `HTTP_X_REQUEST_ID`
+    pub fn with_request_id_param(mut self, name: Option<String>) -> Self {
+        self.request_id_param = name;
+        self
+    }
+
     pub async fn execute(
         &self,
         script_path: &str,
@@ -48,22 +210,47 @@ impl FastCgiClient {
         body: &[u8],
         remote_addr: &str,
     ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let head = RequestHead {
+            script_path,
+            method,
+            uri,
+            query_string,
+            headers,
+            remote_addr,
+        };
+
         let mut pooled_conn = self.pool.get().await?;
-        let stream = pooled_conn.stream();
+        if pooled_conn.is_reused() && !is_idempotent(method) && pooled_conn.peer_closed() {
+            // This request cannot be replayed, so catch a close before sending it
+            pooled_conn = self.pool.reconnect(pooled_conn).await?;
+        }
 
-        let request_id = 1u16;
+        let response = match self.send_buffered(&mut pooled_conn, &head, body).await {
+            // The server closed a connection we had pooled. Send the request again
+            // on a new one only if the script cannot have run, or running it twice
+            // is harmless
+            Err(e) if pooled_conn.is_reused() && replay_safe(&e, method) => {
+                debug!("Pooled FastCGI connection was closed by the server, reconnecting: {}", e);
+                pooled_conn = self.pool.reconnect(pooled_conn).await?;
+                self.send_buffered(&mut pooled_conn, &head, body).await
+            }
+            response => response,
+        }?;
 
-        let begin_request = self.build_begin_request(request_id);
-        stream.write_all(&begin_request).await?;
+        self.pool.put(pooled_conn).await;
 
-        let params = self.build_params(script_path, method, uri, query_string, headers, remote_addr);
-        let params_records = self.build_params_records(request_id, &params);
-        for record in params_records {
-            stream.write_all(&record).await?;
-        }
+        Ok(response)
+    }
 
-        let empty_params = self.build_record(FCGI_PARAMS, request_id, &[]);
-        stream.write_all(&empty_params).await?;
+    async fn send_buffered(
+        &self,
+        pooled_conn: &mut PooledConnection,
+        head: &RequestHead<'_>,
+        body: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let stream = pooled_conn.stream();
+        let request_id = 1u16;
+        self.write_head(stream, request_id, head).await?;
 
         if !body.is_empty() {
             let stdin_records = self.build_data_records(FCGI_STDIN, request_id, body);
@@ -75,6 +262,28 @@ impl FastCgiClient {
         let empty_stdin = self.build_record(FCGI_STDIN, request_id, &[]);
         stream.write_all(&empty_stdin).await?;
 
+        self.read_response(stream, request_id).await
+    }
+
+    /// Like `execute`, with a body already read by `spool_body`. Its length is
+    /// sent as `CONTENT_LENGTH`, replacing any `Transfer-Encoding`, since PHP-FPM
+    /// reads stdin only up to `CONTENT_LENGTH`. The spool cannot be replayed, so
+    /// unlike `execute` a pooled connection the server closes mid-request is not retried.
+    pub async fn execute_spooled(&self, head: &RequestHead<'_>, body: SpooledBody) -> Result<(Vec<u8>, Vec<u8>)> {
+        let mut headers = head.headers.clone();
+        headers.retain(|name, _| !name.eq_ignore_ascii_case("transfer-encoding") && !name.eq_ignore_ascii_case("content-length"));
+        headers.insert("content-length".to_string(), body.length.to_string());
+
+        let mut pooled_conn = self.pool.get().await?;
+        let stream = pooled_conn.stream();
+        let request_id = 1u16;
+
+        self.write_head(stream, request_id, &RequestHead { headers: &headers, ..*head }).await?;
+        self.write_spool(stream, request_id, body.spool).await?;
+
+        let empty_stdin = self.build_record(FCGI_STDIN, request_id, &[]);
+        stream.write_all(&empty_stdin).await?;
+
         let (stdout, stderr) = self.read_response(stream, request_id).await?;
 
         self.pool.put(pooled_conn).await;
@@ -82,6 +291,94 @@ impl FastCgiClient {
         Ok((stdout, stderr))
     }
 
+    async fn write_spool(&self, stream: &mut FastCgiStream, request_id: u16, spool: Spool) -> Result<()> {
+        match spool {
+            Spool::Memory(body) => {
+                for record in self.build_data_records(FCGI_STDIN, request_id, &body) {
+                    stream.write_all(&record).await?;
+                }
+            }
+            Spool::File(mut file) => {
+                let mut buf = vec![0u8; SPOOL_MEMORY_LIMIT];
+                loop {
+                    let n = file.read(&mut buf).await.context("Failed to read spooled request body")?;
+                    if n == 0 {
+                        break;
+                    }
+                    for record in self.build_data_records(FCGI_STDIN, request_id, &buf[..n]) {
+                        stream.write_all(&record).await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Ask the server for its connection and request limits with an
+    /// `FCGI_GET_VALUES` management record. The connection is not reused
+    /// afterwards, since some servers close it after a management reply.
+    pub async fn get_values(&self) -> Result<FastCgiValues> {
+        let mut pooled_conn = self.pool.get().await?;
+        let stream = pooled_conn.stream();
+
+        let mut query = BytesMut::new();
+        for name in ["FCGI_MAX_CONNS", "FCGI_MAX_REQS", "FCGI_MPXS_CONNS"] {
+            self.encode_name_value_pair(&mut query, name, "");
+        }
+        stream.write_all(&self.build_record(FCGI_GET_VALUES, 0, &query)).await?;
+
+        loop {
+            let mut header = [0u8; 8];
+            stream.read_exact(&mut header).await?;
+            let record_type = header[1];
+            let request_id = u16::from_be_bytes([header[2], header[3]]);
+            let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+
+            let mut content = vec![0u8; content_length + header[6] as usize];
+            stream.read_exact(&mut content).await?;
+            content.truncate(content_length);
+
+            if record_type != FCGI_GET_VALUES_RESULT || request_id != 0 {
+                continue;
+            }
+
+            let mut values = FastCgiValues::default();
+            for (name, value) in decode_name_value_pairs(&content)? {
+                match name.as_str() {
+                    "FCGI_MAX_CONNS" => values.max_conns = value.trim().parse().ok(),
+                    "FCGI_MAX_REQS" => values.max_reqs = value.trim().parse().ok(),
+                    "FCGI_MPXS_CONNS" => values.mpxs_conns = Some(value.trim() == "1"),
+                    _ => {}
+                }
+            }
+            return Ok(values);
+        }
+    }
+
+    /// Send BEGIN_REQUEST and the complete PARAMS stream
+    async fn write_head(&self, stream: &mut FastCgiStream, request_id: u16, head: &RequestHead<'_>) -> Result<()> {
+        let begin_request = self.build_begin_request(request_id);
+        stream.write_all(&begin_request).await.map_err(RequestNotSent)?;
+
+        let params = self.build_params(
+            head.script_path,
+            head.method,
+            head.uri,
+            head.query_string,
+            head.headers,
+            head.remote_addr,
+        );
+        let params_records = self.build_params_records(request_id, &params);
+        for record in params_records {
+            stream.write_all(&record).await?;
+        }
+
+        let empty_params = self.build_record(FCGI_PARAMS, request_id, &[]);
+        stream.write_all(&empty_params).await?;
+
+        Ok(())
+    }
+
     fn build_begin_request(&self, request_id: u16) -> Vec<u8> {
         let mut buf = BytesMut::with_capacity(16);
 
@@ -93,7 +390,7 @@ impl FastCgiClient {
         buf.put_u8(0);  // reserved
 
         buf.put_u16(FCGI_RESPONDER);
-        buf.put_u8(0);  // flags (no keep-alive for simplicity)
+        buf.put_u8(FCGI_KEEP_CONN); // keep the connection open for the pool
         buf.put(&[0u8; 5][..]); // reserved
 
         buf.to_vec()
@@ -131,6 +428,12 @@ impl FastCgiClient {
             params.insert(param_name, value.clone());
         }
 
+        if let Some(ref name) = self.request_id_param {
+            if let Some(id) = headers.get(super::REQUEST_ID_HEADER) {
+                params.insert(name.clone(), id.clone());
+            }
+        }
+
         params
     }
 
@@ -202,17 +505,23 @@ impl FastCgiClient {
         buf.to_vec()
     }
 
+    /// Collect STDOUT and STDERR until `FCGI_END_REQUEST`. Anything that does not
+    /// fit the protocol is an error, so a desynchronized connection is never reused.
     async fn read_response(&self, stream: &mut FastCgiStream, expected_request_id: u16) -> Result<(Vec<u8>, Vec<u8>)> {
         let mut stdout_data = Vec::new();
         let mut stderr_data = Vec::new();
+        let mut started = false;
 
         loop {
             let mut header = [0u8; 8];
-            match stream.read_exact(&mut header).await {
-                Ok(_) => {},
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => return Err(e.into()),
+            if let Err(e) = self.read_record_part(stream, &mut header).await {
+                // EOF before the first record: the server dropped the connection
+                if !started && matches!(e.downcast_ref::<FastCgiError>(), Some(FastCgiError::Malformed(_))) {
+                    return Err(FastCgiError::ConnectionClosed.into());
+                }
+                return Err(e);
             }
+            started = true;
 
             let version = header[0];
             let record_type = header[1];
@@ -221,47 +530,716 @@ impl FastCgiClient {
             let padding_length = header[6] as usize;
 
             if version != FCGI_VERSION_1 {
-                return Err(anyhow::anyhow!("Unsupported FastCGI version: {}", version));
+                return Err(FastCgiError::Malformed(format!("unsupported version {}", version)).into());
             }
 
-            if request_id != expected_request_id {
-                let total_skip = content_length + padding_length;
-                let mut discard = vec![0u8; total_skip];
-                stream.read_exact(&mut discard).await?;
+            let mut content = vec![0u8; content_length + padding_length];
+            self.read_record_part(stream, &mut content).await?;
+            content.truncate(content_length);
+
+            // Management records (request ID 0) are not part of the response
+            if request_id == 0 {
                 continue;
             }
+            if request_id != expected_request_id {
+                return Err(FastCgiError::Malformed(format!(
+                    "record for request {} while reading request {}",
+                    request_id, expected_request_id
+                )).into());
+            }
+
+            match record_type {
+                FCGI_STDOUT => stdout_data.extend_from_slice(&content),
+                FCGI_STDERR => stderr_data.extend_from_slice(&content),
+                FCGI_END_REQUEST => {
+                    end_request_status(&content)?;
+                    return Ok((stdout_data, stderr_data));
+                }
+                other => {
+                    return Err(FastCgiError::Malformed(format!("unexpected record type {}", other)).into());
+                }
+            }
+        }
+    }
+
+    /// `read_exact` bounded by the read timeout; EOF means the response was cut short
+    async fn read_record_part(&self, stream: &mut FastCgiStream, buf: &mut [u8]) -> Result<()> {
+        match tokio::time::timeout(self.read_timeout, stream.read_exact(buf)).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(FastCgiError::Malformed(
+                "connection closed before FCGI_END_REQUEST".to_string(),
+            ).into()),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err(FastCgiError::ReadTimeout(self.read_timeout).into()),
+        }
+    }
+}
+
+/// Log what PHP-FPM wrote to STDERR (fatal errors, warnings) against the script.
+/// When the script wrote nothing to STDOUT, returns the `502` to send instead of
+/// an empty `200`: carrying the error text if `expose`, a generic message otherwise.
+pub fn stderr_response(script_path: &str, stdout: &[u8], stderr: &[u8], expose: bool) -> Option<PhpResponse> {
+    if stderr.is_empty() {
+        return None;
+    }
+    let errors = String::from_utf8_lossy(stderr);
+    let errors = errors.trim_end();
+    warn!("PHP-FPM error output for {}: {}", script_path, errors);
+
+    if !stdout.is_empty() {
+        return None;
+    }
+    let mut headers = ResponseHeaders::new();
+    headers.insert("Content-Type", "text/plain; charset=utf-8");
+    let body = if expose {
+        format!("{}\n", errors)
+    } else {
+        "PHP-FPM returned no output; see the server log\n".to_string()
+    };
+    Some(PhpResponse {
+        status_code: 502,
+        headers,
+        body: body.into_bytes(),
+        execution_time_ms: 0,
+        memory_peak_mb: 0.0,
+    })
+}
+
+/// Whether a request that failed with `e` on a reused connection may be sent again
+fn replay_safe(e: &anyhow::Error, method: &str) -> bool {
+    e.is::<RequestNotSent>() || (is_idempotent(method) && closed_by_server(e))
+}
+
+/// Whether a request failed because the server had already closed the connection
+fn closed_by_server(e: &anyhow::Error) -> bool {
+    if let Some(FastCgiError::ConnectionClosed) = e.downcast_ref::<FastCgiError>() {
+        return true;
+    }
+    e.downcast_ref::<std::io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::BrokenPipe | std::io::ErrorKind::ConnectionReset | std::io::ErrorKind::ConnectionAborted
+        )
+    })
+}
+
+/// Check an `FCGI_END_REQUEST` body (appStatus, protocolStatus, 3 reserved bytes)
+/// and return the application's exit status
+fn end_request_status(content: &[u8]) -> Result<u32, FastCgiError> {
+    if content.len() != 8 {
+        return Err(FastCgiError::Malformed(format!(
+            "FCGI_END_REQUEST body is {} bytes, expected 8",
+            content.len()
+        )));
+    }
+
+    match content[4] {
+        FCGI_REQUEST_COMPLETE => Ok(u32::from_be_bytes([content[0], content[1], content[2], content[3]])),
+        FCGI_CANT_MPX_CONN => Err(FastCgiError::CantMultiplex),
+        FCGI_OVERLOADED => Err(FastCgiError::Overloaded),
+        FCGI_UNKNOWN_ROLE => Err(FastCgiError::UnknownRole),
+        other => Err(FastCgiError::Malformed(format!("unknown protocolStatus {}", other))),
+    }
+}
+
+/// Next non-empty data chunk of a request body; trailers are skipped
+async fn next_chunk<B>(body: &mut B) -> Result<Option<Bytes>>
+where
+    B: hyper::body::Body + Unpin,
+    B::Error: fmt::Display,
+{
+    while let Some(frame) = body.frame().await {
+        let frame = frame.map_err(|e| anyhow::anyhow!("Failed to read request body: {}", e))?;
+        let Ok(mut data) = frame.into_data() else {
+            continue;
+        };
+        let chunk = data.copy_to_bytes(data.remaining());
+        if !chunk.is_empty() {
+            return Ok(Some(chunk));
+        }
+    }
+    Ok(None)
+}
+
+/// Read a whole body of unknown length, in memory up to 64 KiB and then to a
+/// temporary file. Fails with `BodyLimitExceeded` as soon as more than
+/// `max_body` bytes have arrived, before anything is sent to the server.
+pub async fn spool_body<B>(body: &mut B, max_body: usize) -> Result<SpooledBody>
+where
+    B: hyper::body::Body + Unpin,
+    B::Error: fmt::Display,
+{
+    let mut spool = Spool::Memory(Vec::new());
+    let mut total = 0usize;
+
+    while let Some(chunk) = next_chunk(body).await? {
+        total += chunk.len();
+        if total > max_body {
+            return Err(BodyLimitExceeded { limit: max_body }.into());
+        }
+
+        match &mut spool {
+            Spool::Memory(buf) if total <= SPOOL_MEMORY_LIMIT => buf.extend_from_slice(&chunk),
+            Spool::Memory(buf) => {
+                let file = tempfile::tempfile().context("Failed to create a spool file for the request body")?;
+                let mut file = tokio::fs::File::from_std(file);
+                file.write_all(buf).await?;
+                file.write_all(&chunk).await?;
+                spool = Spool::File(file);
+            }
+            Spool::File(file) => file.write_all(&chunk).await?,
+        }
+    }
 
-            let mut content = vec![0u8; content_length];
-            if content_length > 0 {
-                stream.read_exact(&mut content).await?;
+    if let Spool::File(file) = &mut spool {
+        file.flush().await?;
+        file.rewind().await?;
+    }
+    Ok(SpooledBody { spool, length: total })
+}
+
+/// Decode a FastCGI name-value pair stream (PARAMS / GET_VALUES_RESULT body)
+fn decode_name_value_pairs(mut data: &[u8]) -> Result<Vec<(String, String)>> {
+    fn read_len(data: &mut &[u8]) -> Result<usize> {
+        match data.first() {
+            Some(&b) if b & 0x80 == 0 => {
+                data.advance(1);
+                Ok(b as usize)
             }
+            Some(_) if data.len() >= 4 => Ok((data.get_u32() & 0x7fff_ffff) as usize),
+            _ => Err(anyhow::anyhow!("Truncated FastCGI name-value length")),
+        }
+    }
+
+    let mut pairs = Vec::new();
+    while !data.is_empty() {
+        let name_len = read_len(&mut data)?;
+        let value_len = read_len(&mut data)?;
+        if data.len() < name_len + value_len {
+            return Err(anyhow::anyhow!("Truncated FastCGI name-value pair"));
+        }
+        let name = String::from_utf8_lossy(&data[..name_len]).into_owned();
+        let value = String::from_utf8_lossy(&data[name_len..name_len + value_len]).into_owned();
+        data.advance(name_len + value_len);
+        pairs.push((name, value));
+    }
 
-            if padding_length > 0 {
-                let mut padding = vec![0u8; padding_length];
-                stream.read_exact(&mut padding).await?;
+    Ok(pairs)
+}
+
+/// PHP-FPM stand-in for tests: reads FastCGI requests off a TCP listener, records
+/// them, and answers each one as the test's responder decides
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// One request as the mock received it
+    #[derive(Debug, Clone, Default)]
+    pub struct Received {
+        pub params: HashMap<String, String>,
+        pub stdin: Vec<u8>,
+        /// `FCGI_KEEP_CONN` was set in `FCGI_BEGIN_REQUEST`
+        pub keep_conn: bool,
+    }
+
+    /// What the mock does once a request's STDIN has ended
+    pub enum Reply {
+        /// Write these bytes and wait for the next request on the connection
+        Send(Vec<u8>),
+        /// Write these bytes and close the connection
+        SendAndClose(Vec<u8>),
+        /// Write these bytes, then keep the connection open without sending more
+        SendAndHold(Vec<u8>),
+        /// Close the connection without answering, as php-fpm does when it recycles a child
+        Close,
+        /// Reset the connection without answering
+        Reset,
+    }
+
+    type Responder = dyn Fn(usize, &Received) -> Reply + Send + Sync;
+
+    pub struct MockFpm {
+        respond: Arc<Responder>,
+        values_reply: Option<Vec<u8>>,
+        listen_after: Duration,
+    }
+
+    /// A running [`MockFpm`]
+    pub struct MockFpmServer {
+        pub addr: String,
+        state: Arc<State>,
+    }
+
+    #[derive(Default)]
+    struct State {
+        connections: AtomicUsize,
+        requests: Mutex<Vec<Received>>,
+        values_queries: Mutex<Vec<Vec<String>>>,
+    }
+
+    /// A request-1 record with `content`
+    pub fn record(record_type: u8, content: &[u8]) -> Vec<u8> {
+        let len = (content.len() as u16).to_be_bytes();
+        [&[FCGI_VERSION_1, record_type, 0, 1, len[0], len[1], 0, 0][..], content].concat()
+    }
+
+    /// `FCGI_END_REQUEST` with `protocol_status`
+    pub fn end_request(protocol_status: u8) -> Vec<u8> {
+        record(FCGI_END_REQUEST, &[0, 0, 0, 0, protocol_status, 0, 0, 0])
+    }
+
+    /// A complete response carrying `stdout`
+    pub fn response(stdout: &[u8]) -> Vec<u8> {
+        [record(FCGI_STDOUT, stdout), end_request(FCGI_REQUEST_COMPLETE)].concat()
+    }
+
+    /// The response every plain mock sends
+    pub fn ok() -> Vec<u8> {
+        response(b"Content-Type: text/plain\r\n\r\nok")
+    }
+
+    impl MockFpm {
+        /// `respond` gets the request's index (from 0, across connections) and the request
+        pub fn new(respond: impl Fn(usize, &Received) -> Reply + Send + Sync + 'static) -> Self {
+            Self {
+                respond: Arc::new(respond),
+                values_reply: None,
+                listen_after: Duration::ZERO,
             }
+        }
 
-            match record_type {
-                FCGI_STDOUT => {
-                    if content_length > 0 {
-                        stdout_data.extend_from_slice(&content);
-                    }
+        /// Answers every request with `stdout`
+        pub fn replying(stdout: &[u8]) -> Self {
+            let reply = response(stdout);
+            Self::new(move |_, _| Reply::Send(reply.clone()))
+        }
+
+        /// Answer `FCGI_GET_VALUES` with these bytes
+        pub fn with_values_reply(mut self, reply: Vec<u8>) -> Self {
+            self.values_reply = Some(reply);
+            self
+        }
+
+        /// Start listening only after `delay`, so earlier connections are refused
+        pub fn listen_after(mut self, delay: Duration) -> Self {
+            self.listen_after = delay;
+            self
+        }
+
+        pub async fn start(self) -> MockFpmServer {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let state = Arc::new(State::default());
+
+            let mock = Arc::new(self);
+            let server_state = Arc::clone(&state);
+            let bind_addr = addr.clone();
+            tokio::spawn(async move {
+                let listener = if mock.listen_after.is_zero() {
+                    listener
+                } else {
+                    drop(listener);
+                    tokio::time::sleep(mock.listen_after).await;
+                    TcpListener::bind(bind_addr).await.unwrap()
+                };
+                while let Ok((stream, _)) = listener.accept().await {
+                    server_state.connections.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(serve(stream, Arc::clone(&mock), Arc::clone(&server_state)));
                 }
-                FCGI_STDERR => {
-                    if content_length > 0 {
-                        stderr_data.extend_from_slice(&content);
+            });
+
+            MockFpmServer { addr, state }
+        }
+    }
+
+    impl MockFpmServer {
+        /// Connections accepted so far
+        pub fn connections(&self) -> usize {
+            self.state.connections.load(Ordering::SeqCst)
+        }
+
+        /// Requests received so far, in order
+        pub fn requests(&self) -> Vec<Received> {
+            self.state.requests.lock().clone()
+        }
+
+        /// Names asked for by each `FCGI_GET_VALUES`
+        pub fn values_queries(&self) -> Vec<Vec<String>> {
+            self.state.values_queries.lock().clone()
+        }
+    }
+
+    async fn serve(mut stream: TcpStream, mock: Arc<MockFpm>, state: Arc<State>) {
+        let mut request = Received::default();
+        let mut params = Vec::new();
+        loop {
+            let mut header = [0u8; 8];
+            if stream.read_exact(&mut header).await.is_err() {
+                return;
+            }
+            let content_length = u16::from_be_bytes([header[4], header[5]]) as usize;
+            let mut content = vec![0u8; content_length + header[6] as usize];
+            if stream.read_exact(&mut content).await.is_err() {
+                return;
+            }
+            content.truncate(content_length);
+
+            match header[1] {
+                FCGI_BEGIN_REQUEST => request.keep_conn = content[2] & FCGI_KEEP_CONN != 0,
+                FCGI_PARAMS => params.extend_from_slice(&content),
+                FCGI_STDIN if !content.is_empty() => request.stdin.extend_from_slice(&content),
+                FCGI_STDIN => {
+                    request.params = decode_name_value_pairs(&params).unwrap().into_iter().collect();
+                    params.clear();
+                    let index = {
+                        let mut requests = state.requests.lock();
+                        requests.push(request.clone());
+                        requests.len() - 1
+                    };
+                    let reply = (mock.respond)(index, &request);
+                    request = Received::default();
+                    match reply {
+                        Reply::Send(bytes) => {
+                            if stream.write_all(&bytes).await.is_err() {
+                                return;
+                            }
+                        }
+                        Reply::SendAndClose(bytes) => {
+                            let _ = stream.write_all(&bytes).await;
+                            return;
+                        }
+                        Reply::SendAndHold(bytes) => {
+                            let _ = stream.write_all(&bytes).await;
+                            tokio::time::sleep(Duration::from_secs(10)).await;
+                            return;
+                        }
+                        Reply::Close => return,
+                        Reply::Reset => {
+                            socket2::SockRef::from(&stream).set_linger(Some(Duration::ZERO)).unwrap();
+                            return;
+                        }
                     }
                 }
-                FCGI_END_REQUEST => {
-                    // Request completed
-                    break;
-                }
-                _ => {
-                    // Unknown record type, ignore
+                FCGI_GET_VALUES => {
+                    let names = decode_name_value_pairs(&content).unwrap().into_iter().map(|(name, _)| name).collect();
+                    state.values_queries.lock().push(names);
+                    if let Some(reply) = &mock.values_reply {
+                        if stream.write_all(reply).await.is_err() {
+                            return;
+                        }
+                    }
                 }
+                _ => {}
             }
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::mock::{self, end_request, MockFpm, MockFpmServer, Reply};
+    use bytes::Bytes;
+    use futures::channel::mpsc as body_channel;
+    use http_body_util::StreamBody;
+    use hyper::body::Frame;
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    /// Answers every request with `ok`, except that request `drop_nth` (from 1) is
+    /// read and then dropped with its connection, as php-fpm does when it recycles
+    /// a child
+    async fn keep_alive_fpm(drop_nth: Option<usize>) -> MockFpmServer {
+        MockFpm::new(move |index, _| match drop_nth {
+            Some(n) if index + 1 == n => Reply::Close,
+            _ => Reply::Send(mock::ok()),
+        })
+        .start()
+        .await
+    }
+
+    /// Reads one request and answers with `reply` verbatim, then either closes the
+    /// connection or keeps it open without sending more
+    async fn run_canned(reply: Vec<u8>, hold_open: bool) -> Result<(Vec<u8>, Vec<u8>)> {
+        let fpm = MockFpm::new(move |_, _| match hold_open {
+            true => Reply::SendAndHold(reply.clone()),
+            false => Reply::SendAndClose(reply.clone()),
+        })
+        .start()
+        .await;
+        let client = client(fpm.addr.clone()).with_read_timeout(Duration::from_millis(200));
+        client.execute("/var/www/index.php", "GET", "/", "", &HashMap::new(), b"", "127.0.0.1").await
+    }
+
+    fn client(addr: String) -> FastCgiClient {
+        let config = PoolConfig {
+            min_idle: 0,
+            ..PoolConfig::default()
+        };
+        FastCgiClient::with_pool_config(addr, config)
+    }
+
+    async fn run_spooled(
+        client: FastCgiClient,
+        headers: &HashMap<String, String>,
+        mut body: StreamBody<body_channel::UnboundedReceiver<Result<Frame<Bytes>, Infallible>>>,
+        max_body: usize,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let head = RequestHead {
+            script_path: "/var/www/upload.php",
+            method: "POST",
+            uri: "/upload.php",
+            query_string: "",
+            headers,
+            remote_addr: "127.0.0.1",
+        };
+        let spooled = spool_body(&mut body, max_body).await?;
+        client.execute_spooled(&head, spooled).await
+    }
+
+    #[tokio::test]
+    async fn test_chunked_body_is_sent_with_content_length() {
+        let chunked = HashMap::from([("transfer-encoding".to_string(), "chunked".to_string())]);
+
+        // Small bodies are spooled in memory, large ones to a file
+        for (chunk, count) in [(&b"abc"[..], 3), (&[b'x'; 30_000][..], 4)] {
+            let fpm = MockFpm::new(|_, _| Reply::Send(mock::ok())).start().await;
+            let (body_tx, body_rx) = body_channel::unbounded();
+            for _ in 0..count {
+                body_tx.unbounded_send(Ok(Frame::data(Bytes::copy_from_slice(chunk)))).unwrap();
+            }
+            drop(body_tx);
+
+            let (stdout, _) = run_spooled(client(fpm.addr.clone()), &chunked, StreamBody::new(body_rx), 1 << 20)
+                .await
+                .unwrap();
+            assert!(stdout.ends_with(b"ok"));
+
+            let request = fpm.requests().remove(0);
+            let expected = chunk.repeat(count);
+            assert_eq!(request.params.get("CONTENT_LENGTH"), Some(&expected.len().to_string()));
+            assert!(!request.params.contains_key("HTTP_TRANSFER_ENCODING"));
+            assert_eq!(request.stdin, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chunked_body_limit_is_checked_before_connecting() {
+        let fpm = MockFpm::new(|_, _| Reply::Send(mock::ok())).start().await;
+        let chunked = HashMap::from([("transfer-encoding".to_string(), "chunked".to_string())]);
+        let (body_tx, body_rx) = body_channel::unbounded();
+        for _ in 0..2 {
+            body_tx.unbounded_send(Ok(Frame::data(Bytes::from_static(b"12345678")))).unwrap();
+        }
+        drop(body_tx);
+
+        let err = run_spooled(client(fpm.addr.clone()), &chunked, StreamBody::new(body_rx), 10).await.unwrap_err();
+        assert_eq!(err.downcast_ref::<BodyLimitExceeded>().unwrap().limit, 10);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(fpm.connections(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_values_parses_result() {
+        let encoder = client(String::new());
+        let mut result = BytesMut::new();
+        encoder.encode_name_value_pair(&mut result, "FCGI_MAX_CONNS", "50");
+        encoder.encode_name_value_pair(&mut result, "FCGI_MAX_REQS", "50");
+        encoder.encode_name_value_pair(&mut result, "FCGI_MPXS_CONNS", "0");
+        // An unrelated record first, as a busy server might send
+        let reply = [
+            encoder.build_record(FCGI_STDERR, 7, b"noise"),
+            encoder.build_record(FCGI_GET_VALUES_RESULT, 0, &result),
+        ]
+        .concat();
+        let fpm = MockFpm::new(|_, _| Reply::Close).with_values_reply(reply).start().await;
+
+        let values = client(fpm.addr.clone()).get_values().await.unwrap();
+        assert_eq!(fpm.values_queries(), [["FCGI_MAX_CONNS", "FCGI_MAX_REQS", "FCGI_MPXS_CONNS"]]);
+
+        assert_eq!(values, FastCgiValues {
+            max_conns: Some(50),
+            max_reqs: Some(50),
+            mpxs_conns: Some(false),
+        });
+        assert_eq!(values.to_string(), "max_conns=50, max_reqs=50, mpxs_conns=false");
+    }
+
+    #[tokio::test]
+    async fn test_decode_long_name_value_pair() {
+        let client = client(String::new());
+        let long = "x".repeat(300);
+        let mut buf = BytesMut::new();
+        client.encode_name_value_pair(&mut buf, "LONG", &long);
+        client.encode_name_value_pair(&mut buf, "EMPTY", "");
+
+        let pairs = decode_name_value_pairs(&buf).unwrap();
+        assert_eq!(pairs, [("LONG".to_string(), long), ("EMPTY".to_string(), String::new())]);
+        assert!(decode_name_value_pairs(&buf[..buf.len() - 1]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_end_request_status() {
+        let stdout = client(String::new()).build_record(FCGI_STDOUT, 1, b"Status: 200\r\n\r\nok");
+
+        let (out, _) = run_canned([stdout.clone(), end_request(FCGI_REQUEST_COMPLETE)].concat(), false)
+            .await
+            .unwrap();
+        assert!(out.ends_with(b"ok"));
+
+        for (status, expected) in [
+            (FCGI_OVERLOADED, FastCgiError::Overloaded),
+            (FCGI_CANT_MPX_CONN, FastCgiError::CantMultiplex),
+            (FCGI_UNKNOWN_ROLE, FastCgiError::UnknownRole),
+        ] {
+            let err = run_canned([stdout.clone(), end_request(status)].concat(), false).await.unwrap_err();
+            assert_eq!(err.downcast_ref::<FastCgiError>(), Some(&expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_records_are_rejected() {
+        let client = client(String::new());
+        let stdout = client.build_record(FCGI_STDOUT, 1, b"Content-Type: text/plain\r\n\r\nok");
+
+        let cases = [
+            // Stream ends without FCGI_END_REQUEST
+            (stdout.clone(), false),
+            // A record belonging to some other request
+            ([client.build_record(FCGI_STDOUT, 2, b"stray"), end_request(0)].concat(), false),
+            // Record type that never appears in a response
+            ([client.build_record(FCGI_PARAMS, 1, b"x"), end_request(0)].concat(), false),
+            // END_REQUEST body of the wrong size
+            (client.build_record(FCGI_END_REQUEST, 1, &[0, 0, 0]), false),
+            // Wrong protocol version
+            ([vec![9], stdout[1..].to_vec()].concat(), false),
+        ];
+        for (reply, hold_open) in cases {
+            let err = run_canned(reply, hold_open).await.unwrap_err();
+            assert!(
+                matches!(err.downcast_ref::<FastCgiError>(), Some(FastCgiError::Malformed(_))),
+                "{:#}",
+                err
+            );
+        }
+
+        // A header announcing more content than is sent stalls; the read times out
+        let mut truncated = stdout.clone();
+        truncated[4..6].copy_from_slice(&1000u16.to_be_bytes());
+        let err = run_canned(truncated, true).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<FastCgiError>(), Some(FastCgiError::ReadTimeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_reuses_connection() {
+        let fpm = keep_alive_fpm(None).await;
+        let client = client(fpm.addr.clone());
+
+        for _ in 0..3 {
+            let (stdout, _) = client.execute("/var/www/index.php", "GET", "/", "", &HashMap::new(), b"", "127.0.0.1")
+                .await
+                .unwrap();
+            assert!(stdout.ends_with(b"ok"));
+        }
+        assert_eq!(fpm.connections(), 1);
+        assert!(fpm.requests().iter().all(|request| request.keep_conn));
+    }
+
+    #[tokio::test]
+    async fn test_reconnects_when_server_drops_pooled_connection() {
+        let fpm = keep_alive_fpm(Some(2)).await;
+        let pooled = client(fpm.addr.clone());
+
+        for _ in 0..3 {
+            let (stdout, _) = pooled.execute("/var/www/index.php", "GET", "/", "", &HashMap::new(), b"", "127.0.0.1")
+                .await
+                .unwrap();
+            assert!(stdout.ends_with(b"ok"));
+        }
+        assert_eq!(fpm.connections(), 2);
+
+        // A fresh connection closing is a real failure, not retried
+        let fpm = keep_alive_fpm(Some(1)).await;
+        let err = client(fpm.addr.clone())
+            .execute("/var/www/index.php", "GET", "/", "", &HashMap::new(), b"", "127.0.0.1")
+            .await
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<FastCgiError>(), Some(&FastCgiError::ConnectionClosed));
+    }
+
+    #[tokio::test]
+    async fn test_non_idempotent_request_is_not_replayed() {
+        // The second request is read in full before the server drops the
+        // connection, so the script may already have run
+        let fpm = keep_alive_fpm(Some(2)).await;
+        let pooled = client(fpm.addr.clone());
+        let headers = HashMap::new();
+        let post = || pooled.execute("/var/www/index.php", "POST", "/", "", &headers, b"a=1", "127.0.0.1");
+
+        assert!(post().await.unwrap().0.ends_with(b"ok"));
+        let err = post().await.unwrap_err();
+        assert_eq!(err.downcast_ref::<FastCgiError>(), Some(&FastCgiError::ConnectionClosed));
+        assert_eq!(fpm.connections(), 1);
+
+        assert!(replay_safe(&RequestNotSent(std::io::ErrorKind::BrokenPipe.into()).into(), "POST"));
+        assert!(!replay_safe(&std::io::Error::from(std::io::ErrorKind::ConnectionReset).into(), "PATCH"));
+        assert!(replay_safe(&std::io::Error::from(std::io::ErrorKind::ConnectionReset).into(), "DELETE"));
+    }
+
+    #[tokio::test]
+    #[ignore] // Requires php-fpm listening on 127.0.0.1:9000
+    async fn test_php_fpm_reuses_connection() {
+        let script = std::env::temp_dir().join("fe-php-keepalive-test.php");
+        std::fs::write(&script, "<?php echo 'ok';").unwrap();
+        let script = script.to_str().unwrap();
+        let client = client("127.0.0.1:9000".to_string());
+
+        let mut ports = Vec::new();
+        for _ in 0..2 {
+            let (stdout, _) = client.execute(script, "GET", "/", "", &HashMap::new(), b"", "127.0.0.1").await.unwrap();
+            assert!(stdout.ends_with(b"ok"));
+
+            let mut conn = client.pool.get().await.unwrap();
+            assert!(conn.is_reused());
+            let FastCgiStream::Tcp(stream) = conn.stream() else { unreachable!() };
+            ports.push(stream.local_addr().unwrap().port());
+            client.pool.put(conn).await;
+        }
+        assert_eq!(ports[0], ports[1]);
+    }
+
+    #[test]
+    fn test_stderr_response() {
+        let fatal = b"PHP Fatal error:  Uncaught Error: Call to undefined function f()\n";
+
+        assert!(stderr_response("/srv/index.php", b"", b"", true).is_none());
+        // Warnings next to real output are only logged
+        assert!(stderr_response("/srv/index.php", b"Status: 200\r\n\r\nok", fatal, true).is_none());
+
+        let exposed = stderr_response("/srv/index.php", b"", fatal, true).unwrap();
+        assert_eq!(exposed.status_code, 502);
+        assert_eq!(exposed.body, fatal);
+
+        let hidden = stderr_response("/srv/index.php", b"", fatal, false).unwrap();
+        assert_eq!(hidden.status_code, 502);
+        assert!(!String::from_utf8_lossy(&hidden.body).contains("Fatal"));
+    }
+
+    #[tokio::test]
+    async fn test_request_id_param() {
+        let headers = HashMap::from([(crate::php::REQUEST_ID_HEADER.to_string(), "req-42".to_string())]);
+
+        let named = client(String::new()).with_request_id_param(Some("UNIQUE_ID".to_string()));
+        let params = named.build_params("/srv/index.php", "GET", "/", "", &headers, "127.0.0.1");
+        assert_eq!(params["HTTP_X_REQUEST_ID"], "req-42");
+        assert_eq!(params["UNIQUE_ID"], "req-42");
+        assert!(!params.contains_key("REQUEST_ID"));
 
-        Ok((stdout_data, stderr_data))
+        let params = client(String::new()).build_params("/srv/index.php", "GET", "/", "", &headers, "127.0.0.1");
+        assert_eq!(params["HTTP_X_REQUEST_ID"], "req-42");
+        assert!(!params.contains_key("UNIQUE_ID"));
     }
 }
@@ -3,8 +3,9 @@ use libloading::{Library, Symbol};
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_int, c_void, c_uint};
 use std::path::Path;
+use std::cell::RefCell;
 use std::ptr;
-use std::sync::Mutex;
+use std::sync::{Mutex, OnceLock};
 
 #[cfg(unix)]
 use libloading::os::unix::Library as UnixLibrary;
@@ -136,11 +137,30 @@ extern "C" fn php_sapi_deactivate() -> c_int {
     0 // SUCCESS
 }
 
-/// Stub callback for registering server variables
+/// `php_register_variable_safe(name, value, value_len, track_vars_array)`
+type RegisterVariableFn = unsafe extern "C" fn(*const c_char, *const c_char, usize, *mut c_void);
+
+// Bound once in `PhpFfi::load`; SAPI callbacks have no way to reach `PhpFfi`
+static REGISTER_VARIABLE: OnceLock<RegisterVariableFn> = OnceLock::new();
+
+thread_local! {
+    // $_SERVER entries for the request running on this thread
+    static SERVER_VARIABLES: RefCell<Vec<(CString, Vec<u8>)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Callback for registering server variables
 /// PHP calls this during request startup to populate $_SERVER
-extern "C" fn php_register_variables(_track_vars_array: *mut c_void) {
-    // Stub implementation - in production, this would populate $_SERVER with CGI variables
-    // For now, we just need this to exist so PHP doesn't crash
+extern "C" fn php_register_variables(track_vars_array: *mut c_void) {
+    let Some(register) = REGISTER_VARIABLE.get() else {
+        return;
+    };
+    SERVER_VARIABLES.with(|variables| {
+        for (name, value) in variables.borrow().iter() {
+            unsafe {
+                register(name.as_ptr(), value.as_ptr() as *const c_char, value.len(), track_vars_array);
+            }
+        }
+    });
 }
 
 /// Stub callback for reading POST data
@@ -202,6 +222,8 @@ pub struct PhpFfi {
     php_execute_script: Symbol<'static, unsafe extern "C" fn(*mut ZendFileHandle) -> c_int>,
     zend_stream_init_filename: Symbol<'static, unsafe extern "C" fn(*mut ZendFileHandle, *const c_char)>,
     zend_destroy_file_handle: Symbol<'static, unsafe extern "C" fn(*mut ZendFileHandle)>,
+    // Used for engine-side calls such as opcache_reset()
+    zend_eval_string: Option<Symbol<'static, unsafe extern "C" fn(*const c_char, *mut c_void, *const c_char) -> c_int>>,
     // TSRM functions for ZTS (Zend Thread Safety) support
     php_tsrm_startup_ex: Option<Symbol<'static, unsafe extern "C" fn(c_int) -> c_int>>,
     tsrm_shutdown: Option<Symbol<'static, unsafe extern "C" fn()>>,
@@ -309,6 +331,22 @@ impl PhpFfi {
             std::mem::transmute(symbol)
         };
 
+        let zend_eval_string = unsafe {
+            library.get::<unsafe extern "C" fn(*const c_char, *mut c_void, *const c_char) -> c_int>(b"zend_eval_string\0")
+                .ok()
+                .map(|symbol| std::mem::transmute(symbol))
+        };
+
+        // Without it $_SERVER stays empty in embedded mode
+        match unsafe { library.get::<RegisterVariableFn>(b"php_register_variable_safe\0") } {
+            Ok(symbol) => {
+                let _ = REGISTER_VARIABLE.set(*symbol);
+            }
+            Err(_) => tracing::warn!(
+                "libphp does not export php_register_variable_safe; $_SERVER will not include the request id"
+            ),
+        }
+
         // Get SAPI module pointer
         let sapi_module: *mut SapiModule = unsafe {
             let symbol: Symbol<*mut SapiModule> = library.get(b"sapi_module\0")
@@ -364,6 +402,7 @@ impl PhpFfi {
             php_execute_script,
             zend_stream_init_filename,
             zend_destroy_file_handle,
+            zend_eval_string,
             php_tsrm_startup_ex,
             tsrm_shutdown,
             ts_resource_ex,
@@ -547,6 +586,21 @@ impl PhpFfi {
         unsafe {
             (self.php_request_shutdown)(ptr::null_mut());
         }
+        SERVER_VARIABLES.with(|variables| variables.borrow_mut().clear());
+    }
+
+    /// Entries added to `$_SERVER` by the next `request_startup` on this thread
+    pub fn set_server_variables(&self, variables: &[(String, String)]) -> Result<()> {
+        let variables = variables
+            .iter()
+            .map(|(name, value)| {
+                let name = CString::new(name.as_str())
+                    .with_context(|| format!("Invalid $_SERVER name (contains null byte): {}", name))?;
+                Ok((name, value.as_bytes().to_vec()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        SERVER_VARIABLES.with(|current| *current.borrow_mut() = variables);
+        Ok(())
     }
 
     /// Execute a PHP script using embedded libphp
@@ -622,6 +676,39 @@ impl PhpFfi {
         Ok(output)
     }
 
+    /// Reset OPcache for the calling thread's PHP context
+    ///
+    /// Returns `Ok(false)` when OPcache is not loaded or the library cannot
+    /// evaluate code, so callers can treat it as a no-op.
+    pub fn opcache_reset(&self) -> Result<bool> {
+        let output = self.eval_output(
+            "echo (function_exists('opcache_reset') && opcache_reset()) ? '1' : '0';",
+        )?;
+        Ok(output.as_deref() == Some(b"1".as_slice()))
+    }
+
+    /// Evaluate PHP code in its own request and return what it printed
+    fn eval_output(&self, code: &str) -> Result<Option<Vec<u8>>> {
+        let Some(zend_eval_string) = &self.zend_eval_string else {
+            return Ok(None);
+        };
+
+        let code = CString::new(code).context("PHP code contains null byte")?;
+        let name = CString::new("fe-php eval").context("Failed to create eval name CString")?;
+
+        self.request_startup()?;
+        let result = unsafe { zend_eval_string(code.as_ptr(), ptr::null_mut(), name.as_ptr()) };
+        let output = self.get_output();
+        self.request_shutdown();
+        self.clear_output();
+
+        if result != 0 {
+            return Err(anyhow::anyhow!("zend_eval_string failed with code {}", result));
+        }
+
+        Ok(Some(output))
+    }
+
     /// Get output buffer contents
     pub fn get_output(&self) -> Vec<u8> {
         OUTPUT_BUFFER.with(|buf| {
@@ -672,4 +759,23 @@ mod tests {
             println!("Successfully loaded libphp.so");
         }
     }
+
+    #[test]
+    #[ignore] // This test requires libphp.so built with OPcache
+    fn test_opcache_reset_clears_stats() {
+        let ffi = PhpFfi::load("/usr/local/lib/libphp.so").unwrap();
+        ffi.module_startup().unwrap();
+
+        let cached_scripts = |ffi: &PhpFfi| {
+            let output = ffi.eval_output(
+                "$s = opcache_get_status(false); echo $s ? $s['opcache_statistics']['num_cached_scripts'] : 0;",
+            ).unwrap().unwrap();
+            String::from_utf8(output).unwrap().parse::<u64>().unwrap()
+        };
+
+        assert!(ffi.opcache_reset().unwrap());
+        assert_eq!(cached_scripts(&ffi), 0);
+
+        ffi.module_shutdown().unwrap();
+    }
 }
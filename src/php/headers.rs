@@ -0,0 +1,263 @@
+//! Response headers emitted by PHP, kept in order and with repeated names intact
+
+use crate::config::{DuplicateHeaders, HeaderPolicyMode, OversizedHeaders, ResponseHeaderLimits, ResponseHeaderPolicy};
+
+/// Ordered header list; names compare case-insensitively and may repeat
+/// (several `Set-Cookie` lines, for example)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ResponseHeaders {
+    entries: Vec<(String, String)>,
+}
+
+impl ResponseHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self { entries: Vec::with_capacity(capacity) }
+    }
+
+    /// Set `name` to a single value, dropping any earlier values
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Add another value for `name`, keeping the existing ones
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// First value of `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// Every value of `name`, in the order they were added
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.entries.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Apply `php.duplicate_headers`: with `combine`, repeated headers become one
+    /// comma-separated line at the position of the first. `Set-Cookie` is never
+    /// combined because cookie values may themselves contain commas.
+    pub fn apply_duplicate_policy(&mut self, policy: DuplicateHeaders) {
+        if policy == DuplicateHeaders::Preserve {
+            return;
+        }
+
+        let mut combined: Vec<(String, String)> = Vec::with_capacity(self.entries.len());
+        for (name, value) in self.entries.drain(..) {
+            let existing = (!name.eq_ignore_ascii_case("set-cookie"))
+                .then(|| combined.iter_mut().find(|(n, _)| n.eq_ignore_ascii_case(&name)))
+                .flatten();
+            match existing {
+                Some((_, first)) => {
+                    first.push_str(", ");
+                    first.push_str(&value);
+                }
+                None => combined.push((name, value)),
+            }
+        }
+        self.entries = combined;
+    }
+
+    /// Apply `php.response_header_policy`, returning the names of removed headers.
+    /// `Content-Type` survives an allowlist so the body is never misread.
+    pub fn apply_header_policy(&mut self, policy: &ResponseHeaderPolicy) -> Vec<String> {
+        let listed = |name: &str| policy.headers.iter().any(|h| h.eq_ignore_ascii_case(name));
+        let keep = |name: &str| match policy.mode {
+            HeaderPolicyMode::Off => true,
+            HeaderPolicyMode::Allow => listed(name) || name.eq_ignore_ascii_case("content-type"),
+            HeaderPolicyMode::Deny => !listed(name),
+        };
+
+        let mut removed = Vec::new();
+        self.entries.retain(|(name, _)| {
+            let kept = keep(name);
+            if !kept {
+                removed.push(name.clone());
+            }
+            kept
+        });
+        removed
+    }
+
+    /// Apply `php.response_headers`, returning the names of headers over a limit.
+    /// Headers count against the total in order, so later ones are cut first.
+    /// With `error` nothing is changed; the caller replaces the response.
+    pub fn apply_size_limits(&mut self, limits: &ResponseHeaderLimits) -> Vec<String> {
+        let per_header = limits.max_header_size.unwrap_or(usize::MAX);
+        let total = limits.max_total_size.unwrap_or(usize::MAX);
+        let mut used = 0usize;
+        let mut oversized = Vec::new();
+
+        let mut kept = Vec::with_capacity(self.entries.len());
+        for (name, mut value) in std::mem::take(&mut self.entries) {
+            let size = name.len() + value.len();
+            let mut fits = size <= per_header && used + size <= total;
+            if !fits {
+                oversized.push(name.clone());
+                if limits.on_oversized == OversizedHeaders::Truncate && size > per_header {
+                    let mut cut = per_header.saturating_sub(name.len());
+                    while !value.is_char_boundary(cut) {
+                        cut -= 1;
+                    }
+                    value.truncate(cut);
+                    fits = used + name.len() + value.len() <= total;
+                }
+            }
+            if fits || limits.on_oversized == OversizedHeaders::Error {
+                used += name.len() + value.len();
+                kept.push((name, value));
+            }
+        }
+        self.entries = kept;
+        oversized
+    }
+}
+
+impl<N: Into<String>, V: Into<String>> FromIterator<(N, V)> for ResponseHeaders {
+    fn from_iter<I: IntoIterator<Item = (N, V)>>(iter: I) -> Self {
+        Self {
+            entries: iter.into_iter().map(|(n, v)| (n.into(), v.into())).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeated_and_case_insensitive_names() {
+        let mut headers = ResponseHeaders::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        headers.append("Content-Type", "text/plain");
+
+        assert_eq!(headers.get_all("SET-COOKIE").collect::<Vec<_>>(), ["a=1", "b=2"]);
+        assert_eq!(headers.get("content-type"), Some("text/plain"));
+
+        headers.insert("content-type", "text/html");
+        assert_eq!(headers.get_all("Content-Type").collect::<Vec<_>>(), ["text/html"]);
+        assert_eq!(headers.len(), 3);
+    }
+
+    #[test]
+    fn test_size_limits() {
+        let headers: ResponseHeaders = [
+            ("Content-Type", "text/html"),
+            ("Set-Cookie", "session=abcdefghijklmnopqrstuvwxyz"),
+            ("X-Trace", "short"),
+        ]
+        .into_iter()
+        .collect();
+        let limits = |action| ResponseHeaderLimits {
+            max_header_size: Some(30),
+            max_total_size: Some(60),
+            on_oversized: action,
+        };
+
+        let mut dropped = headers.clone();
+        assert_eq!(dropped.apply_size_limits(&limits(OversizedHeaders::Drop)), ["Set-Cookie"]);
+        assert_eq!(dropped.iter().map(|(n, _)| n).collect::<Vec<_>>(), ["Content-Type", "X-Trace"]);
+
+        // Truncated to the 30 byte per-header cap, then the total pushes X-Trace out
+        let mut truncated = headers.clone();
+        assert_eq!(truncated.apply_size_limits(&limits(OversizedHeaders::Truncate)), ["Set-Cookie", "X-Trace"]);
+        assert_eq!(truncated.get("set-cookie"), Some("session=abcdefghijkl"));
+        assert!(!truncated.contains("x-trace"));
+
+        let mut untouched = headers.clone();
+        assert_eq!(untouched.apply_size_limits(&limits(OversizedHeaders::Error)), ["Set-Cookie", "X-Trace"]);
+        assert_eq!(untouched, headers);
+
+        let mut unlimited = headers.clone();
+        assert!(unlimited.apply_size_limits(&ResponseHeaderLimits::default()).is_empty());
+        assert_eq!(unlimited, headers);
+    }
+
+    #[test]
+    fn test_combine_keeps_cookies_separate() {
+        let mut headers: ResponseHeaders = [
+            ("Set-Cookie", "a=1"),
+            ("Vary", "Accept"),
+            ("Set-Cookie", "b=2"),
+            ("vary", "Cookie"),
+        ]
+        .into_iter()
+        .collect();
+
+        headers.apply_duplicate_policy(DuplicateHeaders::Combine);
+
+        assert_eq!(
+            headers.iter().collect::<Vec<_>>(),
+            [("Set-Cookie", "a=1"), ("Vary", "Accept, Cookie"), ("Set-Cookie", "b=2")]
+        );
+    }
+
+    #[test]
+    fn test_header_policy() {
+        let headers: ResponseHeaders = [
+            ("Content-Type", "text/html"),
+            ("Strict-Transport-Security", "max-age=0"),
+            ("X-Debug-Token", "abc123"),
+            ("Cache-Control", "no-cache"),
+        ]
+        .into_iter()
+        .collect();
+        let policy = |mode, names: &[&str]| ResponseHeaderPolicy {
+            mode,
+            headers: names.iter().map(|n| n.to_string()).collect(),
+        };
+
+        let mut denied = headers.clone();
+        let removed = denied.apply_header_policy(&policy(HeaderPolicyMode::Deny, &["strict-transport-security", "X-Debug-Token"]));
+        assert_eq!(removed, ["Strict-Transport-Security", "X-Debug-Token"]);
+        assert_eq!(
+            denied.iter().collect::<Vec<_>>(),
+            [("Content-Type", "text/html"), ("Cache-Control", "no-cache")]
+        );
+
+        let mut allowed = headers.clone();
+        let removed = allowed.apply_header_policy(&policy(HeaderPolicyMode::Allow, &["cache-control"]));
+        assert_eq!(removed, ["Strict-Transport-Security", "X-Debug-Token"]);
+        assert_eq!(
+            allowed.iter().collect::<Vec<_>>(),
+            [("Content-Type", "text/html"), ("Cache-Control", "no-cache")]
+        );
+
+        let mut untouched = headers.clone();
+        assert!(untouched.apply_header_policy(&policy(HeaderPolicyMode::Off, &["X-Debug-Token"])).is_empty());
+        assert_eq!(untouched, headers);
+    }
+}
@@ -0,0 +1,107 @@
+//! `X-HTTP-Method-Override` / `_method` support for PHP frameworks
+//!
+//! Only the method handed to PHP changes; routing, WAF, metrics and logs keep
+//! the real request method.
+
+use super::PhpRequest;
+use crate::config::MethodOverrideConfig;
+
+/// Methods a POST may be turned into
+const ALLOWED_METHODS: &[&str] = &["PUT", "PATCH", "DELETE"];
+
+pub const HEADER: &str = "x-http-method-override";
+
+/// Rewrite the method of a POST request as requested by the override header or
+/// form field. Unknown or unsafe target methods are ignored.
+pub fn apply(request: &mut PhpRequest, config: &MethodOverrideConfig) {
+    if !config.enable || request.method != "POST" {
+        return;
+    }
+
+    let requested = header(request, HEADER).or_else(|| {
+        config
+            .form_field
+            .as_deref()
+            .and_then(|field| form_value(request, field))
+    });
+
+    if let Some(method) = requested.map(|m| m.trim().to_ascii_uppercase()) {
+        if ALLOWED_METHODS.contains(&method.as_str()) {
+            request.method = method;
+        }
+    }
+}
+
+fn header(request: &PhpRequest, name: &str) -> Option<String> {
+    request
+        .headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+}
+
+/// First value of `field` in an `application/x-www-form-urlencoded` body
+fn form_value(request: &PhpRequest, field: &str) -> Option<String> {
+    let is_form = header(request, "content-type")
+        .is_some_and(|ct| ct.to_ascii_lowercase().starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return None;
+    }
+
+    String::from_utf8_lossy(&request.body)
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| urlencoding::decode(name).is_ok_and(|name| name == field))
+        .and_then(|(_, value)| urlencoding::decode(value).ok().map(|v| v.into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn request(method: &str, headers: &[(&str, &str)], body: &str) -> PhpRequest {
+        PhpRequest {
+            method: method.to_string(),
+            uri: "/posts/1".to_string(),
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>(),
+            body: body.as_bytes().to_vec(),
+            query_string: String::new(),
+            remote_addr: "127.0.0.1".to_string(),
+        }
+    }
+
+    fn config() -> MethodOverrideConfig {
+        MethodOverrideConfig { enable: true, form_field: Some("_method".to_string()) }
+    }
+
+    #[test]
+    fn test_form_field_override() {
+        let form = [("content-type", "application/x-www-form-urlencoded")];
+
+        let mut req = request("POST", &form, "title=x&_method=delete");
+        apply(&mut req, &config());
+        assert_eq!(req.method, "DELETE");
+
+        // Same body without the form content type is left alone
+        let mut req = request("POST", &[], "_method=DELETE");
+        apply(&mut req, &config());
+        assert_eq!(req.method, "POST");
+    }
+
+    #[test]
+    fn test_override_restrictions() {
+        // Only POST can be overridden, and only to PUT/PATCH/DELETE
+        let mut req = request("GET", &[(HEADER, "DELETE")], "");
+        apply(&mut req, &config());
+        assert_eq!(req.method, "GET");
+
+        let mut req = request("POST", &[(HEADER, "CONNECT")], "");
+        apply(&mut req, &config());
+        assert_eq!(req.method, "POST");
+
+        let mut req = request("POST", &[(HEADER, "PATCH")], "");
+        apply(&mut req, &MethodOverrideConfig::default());
+        assert_eq!(req.method, "POST");
+    }
+}
@@ -1,14 +1,25 @@
+pub mod affinity;
 pub mod ffi;
 pub mod worker;
 pub mod executor;
 pub mod fastcgi;
 pub mod connection_pool;
+pub mod method_override;
+pub mod content_type;
+pub mod headers;
 
 pub use worker::{WorkerPool, WorkerPoolConfig};
 pub use executor::{PhpExecutor, PhpRequest, PhpResponse};
+pub use headers::ResponseHeaders;
 
 use std::path::PathBuf;
 
+/// Request header carrying the server-generated request id to PHP
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Request header carrying the subject CN of a verified client certificate to PHP
+pub const CLIENT_CERT_CN_HEADER: &str = "x-client-cert-cn";
+
 #[derive(Debug, Clone)]
 pub struct PhpConfig {
     pub libphp_path: PathBuf,
@@ -17,6 +28,13 @@ pub struct PhpConfig {
     pub worker_max_requests: usize,
     pub use_fpm: bool,
     pub fpm_socket: String,
+    /// FastCGI param, or embedded `$_SERVER` entry, that also carries the request id;
+    /// `None` passes only `HTTP_X_REQUEST_ID`
+    pub request_id_param: Option<String>,
+    /// Show PHP-FPM's STDERR to the client when the script printed nothing
+    pub expose_fpm_errors: bool,
+    /// `Content-Type` the parsers add when PHP sends none
+    pub content_type: crate::config::ContentTypeConfig,
 }
 
 impl PhpConfig {
@@ -35,6 +53,9 @@ impl PhpConfig {
             worker_max_requests,
             use_fpm,
             fpm_socket,
+            request_id_param: None,
+            expose_fpm_errors: false,
+            content_type: Default::default(),
         }
     }
 }
@@ -1,27 +1,67 @@
-use super::executor::{PhpExecutor, PhpRequest, PhpResponse};
+use super::affinity::{self, Dispatcher, Inbox};
+use super::executor::{self as php_executor, PhpExecutor, PhpRequest, PhpResponse};
 use super::ffi::PhpFfi;
 use super::PhpConfig;
 use anyhow::Result;
-use async_channel::{Sender, Receiver, bounded};
+use async_channel::{Sender, bounded};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Barrier};
+use std::time::Duration;
 use tokio::task;
-use tracing::{info, warn, error};
+use tracing::{debug, info, warn, error};
 
 pub struct WorkerPoolConfig {
     pub pool_size: usize,
     pub max_requests: usize,
+    /// Send repeated requests for a script to the same worker while it is idle
+    pub affinity: bool,
+}
+
+type Job = (PhpRequest, Sender<Result<PhpResponse>>);
+
+/// First delay before replacing a worker that panicked; doubles per consecutive panic
+const RESPAWN_BACKOFF_BASE: Duration = Duration::from_millis(100);
+const RESPAWN_BACKOFF_MAX: Duration = Duration::from_secs(10);
+
+/// Why a worker thread stopped taking requests
+enum Exit {
+    /// The pool is gone
+    Closed,
+    /// PHP could not be initialized for this worker
+    InitFailed,
+    /// Reached `max_requests` or asked to restart; replaced right away
+    Recycled,
+    /// The executor panicked; replaced after a backoff. `first_request` is set
+    /// when it panicked before completing a single request.
+    Panicked { first_request: bool },
+}
+
+/// State every worker thread shares with the pool
+#[derive(Clone)]
+struct WorkerContext {
+    max_requests: usize,
+    shared_ffi: Option<Arc<PhpFfi>>,
+    /// Startup barrier; `None` for replacement workers
+    barrier: Option<Arc<Barrier>>,
+    opcache_generation: Arc<AtomicU64>,
+    restart_generation: Arc<AtomicU64>,
+    live_workers: Arc<AtomicUsize>,
 }
 
 pub struct WorkerPool {
-    request_tx: Sender<(PhpRequest, Sender<Result<PhpResponse>>)>,
-    _config: WorkerPoolConfig,
+    request_tx: Dispatcher<Job>,
+    config: WorkerPoolConfig,
     _php_module: Option<PhpExecutor>,  // Keep PHP module initialized for process lifetime
     _shared_ffi: Option<Arc<PhpFfi>>,   // Shared FFI instance for all workers
+    opcache_generation: Arc<AtomicU64>,  // Bumped to make every worker reset OPcache
+    restart_generation: Arc<AtomicU64>,  // Bumped to make every worker hand over to a replacement
+    live_workers: Arc<AtomicUsize>,  // Workers initialized and not yet retired
 }
 
 impl WorkerPool {
     pub fn new(php_config: PhpConfig, config: WorkerPoolConfig) -> Result<Self> {
-        let (request_tx, request_rx) = bounded(config.pool_size * 2);
+        let (request_tx, inboxes) = affinity::channels(config.pool_size, config.pool_size * 2, config.affinity);
 
         // Initialize PHP module ONCE globally (not in worker threads)
         // This prevents "zend_mm_heap corrupted" error when multiple workers
@@ -57,18 +97,22 @@ impl WorkerPool {
         // Create a barrier to synchronize worker thread initialization
         // This ensures all workers are fully initialized before accepting requests
         let barrier = Arc::new(Barrier::new(config.pool_size + 1));
+        let opcache_generation = Arc::new(AtomicU64::new(0));
+        let restart_generation = Arc::new(AtomicU64::new(0));
+        let live_workers = Arc::new(AtomicUsize::new(0));
+
+        let context = WorkerContext {
+            max_requests: config.max_requests,
+            shared_ffi: shared_ffi.clone(),
+            barrier: Some(Arc::clone(&barrier)),
+            opcache_generation: Arc::clone(&opcache_generation),
+            restart_generation: Arc::clone(&restart_generation),
+            live_workers: Arc::clone(&live_workers),
+        };
 
         // Spawn worker threads
-        for worker_id in 0..config.pool_size {
-            let request_rx = request_rx.clone();
-            let php_config = php_config.clone();
-            let max_requests = config.max_requests;
-            let shared_ffi = shared_ffi.clone();
-            let barrier = Arc::clone(&barrier);
-
-            task::spawn_blocking(move || {
-                Self::worker_thread(worker_id, request_rx, php_config, max_requests, shared_ffi, barrier);
-            });
+        for (worker_id, request_rx) in inboxes.into_iter().enumerate() {
+            Self::spawn_worker(worker_id, request_rx, php_config.clone(), context.clone(), 0);
         }
 
         // Wait for all workers to initialize
@@ -78,20 +122,83 @@ impl WorkerPool {
 
         Ok(Self {
             request_tx,
-            _config: config,
+            config,
             _php_module: php_module,  // Kept alive for process lifetime
             _shared_ffi: shared_ffi,  // Kept alive and shared with all workers
+            opcache_generation,
+            restart_generation,
+            live_workers,
         })
     }
 
-    fn worker_thread(
+    /// Configured number of workers
+    pub fn size(&self) -> usize {
+        self.config.pool_size
+    }
+
+    /// Workers currently able to take requests; a worker being replaced is not counted
+    pub fn live_workers(&self) -> usize {
+        self.live_workers.load(Ordering::SeqCst)
+    }
+
+    /// Ask every worker to reset OPcache before it handles its next request
+    pub fn reset_opcache(&self) {
+        self.opcache_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Replace every worker with a fresh thread and executor. A worker hands over
+    /// when it picks up its next request, which goes to the replacement instead;
+    /// requests already running finish first. OPcache is reset too, since the
+    /// process-wide PHP module and its shared OPcache outlive the workers.
+    pub fn restart_workers(&self) {
+        self.restart_generation.fetch_add(1, Ordering::SeqCst);
+        self.reset_opcache();
+    }
+
+    /// Run a worker in its own blocking thread. When it retires after a panic or
+    /// `max_requests`, a replacement takes over the same slot; after a panic the
+    /// replacement waits a backoff that grows with each consecutive panic.
+    fn spawn_worker(
         worker_id: usize,
-        request_rx: Receiver<(PhpRequest, Sender<Result<PhpResponse>>)>,
+        request_rx: Inbox<Job>,
         php_config: PhpConfig,
-        max_requests: usize,
-        shared_ffi: Option<Arc<PhpFfi>>,
-        barrier: Arc<Barrier>,
+        context: WorkerContext,
+        panics: u32,
     ) {
+        task::spawn_blocking(move || {
+            let panics = match Self::worker_thread(worker_id, &request_rx, php_config.clone(), context.clone()) {
+                Exit::Closed | Exit::InitFailed => return,
+                Exit::Recycled => 0,
+                Exit::Panicked { first_request: false } => 1,
+                Exit::Panicked { first_request: true } => panics + 1,
+            };
+
+            // Nothing waits in the slot while it is empty
+            request_rx.requeue_own();
+            if panics > 0 {
+                let delay = RESPAWN_BACKOFF_BASE
+                    .saturating_mul(1 << (panics - 1).min(16))
+                    .min(RESPAWN_BACKOFF_MAX);
+                warn!("Replacing worker {} in {:?}", worker_id, delay);
+                std::thread::sleep(delay);
+            }
+            if request_rx.is_closed() {
+                return;
+            }
+
+            let context = WorkerContext { barrier: None, ..context };
+            Self::spawn_worker(worker_id, request_rx, php_config, context, panics);
+        });
+    }
+
+    fn worker_thread(
+        worker_id: usize,
+        request_rx: &Inbox<Job>,
+        php_config: PhpConfig,
+        context: WorkerContext,
+    ) -> Exit {
+        let WorkerContext { max_requests, shared_ffi, barrier, opcache_generation, restart_generation, live_workers } = context;
+        let restart_seen = restart_generation.load(Ordering::SeqCst);
         info!("Worker {} starting initialization...", worker_id);
 
         // Initialize PHP executor for this worker
@@ -104,53 +211,101 @@ impl WorkerPool {
             Err(e) => {
                 error!("Worker {} failed to initialize PHP: {}", worker_id, e);
                 // Still wait at barrier to avoid deadlock
-                barrier.wait();
-                return;
+                if let Some(barrier) = barrier {
+                    barrier.wait();
+                }
+                return Exit::InitFailed;
             }
         };
 
         // Initialize TSRM thread-local resources for this worker thread (ZTS only)
         // This MUST be done before processing any PHP requests
         executor.thread_init();
+        live_workers.fetch_add(1, Ordering::SeqCst);
 
         // Wait for all workers to initialize before processing requests
         // This prevents race conditions during startup
-        barrier.wait();
+        if let Some(barrier) = barrier {
+            barrier.wait();
+        }
         info!("Worker {} ready to accept requests", worker_id);
 
         let mut requests_handled = 0;
+        let mut exit = Exit::Closed;
+        let mut opcache_seen = opcache_generation.load(Ordering::SeqCst);
 
         // Process requests until max_requests reached or channel closed
-        while let Ok((request, response_tx)) = request_rx.recv_blocking() {
-            let result = executor.execute(request);
+        while let Some((request, response_tx)) = request_rx.recv_blocking() {
+            if restart_generation.load(Ordering::SeqCst) != restart_seen {
+                info!("Worker {} restarting on request", worker_id);
+                request_rx.requeue((request, response_tx));
+                exit = Exit::Recycled;
+                break;
+            }
+
+            let generation = opcache_generation.load(Ordering::SeqCst);
+            if generation != opcache_seen {
+                opcache_seen = generation;
+                match executor.opcache_reset() {
+                    Ok(true) => info!("Worker {} reset OPcache", worker_id),
+                    Ok(false) => debug!("Worker {} skipped OPcache reset (not available)", worker_id),
+                    Err(e) => warn!("Worker {} failed to reset OPcache: {}", worker_id, e),
+                }
+            }
+
+            let (result, panicked) = match panic::catch_unwind(AssertUnwindSafe(|| executor.execute(request))) {
+                Ok(result) => (result, false),
+                Err(payload) => {
+                    let msg = crate::utils::panic_message(&*payload);
+                    error!("Worker {} panicked: {}", worker_id, msg);
+                    (Err(anyhow::anyhow!("PHP worker {} panicked: {}", worker_id, msg)), true)
+                }
+            };
+
+            requests_handled += 1;
+            let max_reached = max_requests > 0 && requests_handled >= max_requests;
+            if !panicked && !max_reached {
+                request_rx.finished();
+            }
 
             if let Err(e) = response_tx.send_blocking(result) {
                 warn!("Worker {} failed to send response: {}", worker_id, e);
             }
 
-            requests_handled += 1;
+            // The executor may be mid-request after a panic, so retire it
+            if panicked {
+                warn!("Worker {} retiring after panic", worker_id);
+                exit = Exit::Panicked { first_request: requests_handled == 1 };
+                break;
+            }
 
             // Restart worker after max_requests (prevent memory leaks)
-            if max_requests > 0 && requests_handled >= max_requests {
+            if max_reached {
                 info!(
                     "Worker {} reached max requests ({}), restarting",
                     worker_id, max_requests
                 );
+                exit = Exit::Recycled;
                 break;
             }
         }
 
+        live_workers.fetch_sub(1, Ordering::SeqCst);
+
         // Free TSRM thread-local resources before thread exits (ZTS only)
         executor.thread_cleanup();
 
         info!("Worker {} shutting down after {} requests", worker_id, requests_handled);
+        exit
     }
 
     pub async fn execute(&self, request: PhpRequest) -> Result<PhpResponse> {
         let (response_tx, response_rx) = bounded(1);
+        // Unresolvable paths fail in the worker as before; they just get no preference
+        let script = self.config.affinity.then(|| php_executor::script_name(&request.uri).ok()).flatten();
 
         self.request_tx
-            .send((request, response_tx))
+            .send(script.as_deref(), (request, response_tx))
             .await
             .map_err(|e| anyhow::anyhow!("Failed to send request to worker pool: {}", e))?;
 
@@ -182,11 +337,15 @@ mod tests {
             worker_max_requests: 1000,
             use_fpm: false,
             fpm_socket: String::from("127.0.0.1:9000"),
+            request_id_param: None,
+            expose_fpm_errors: false,
+            content_type: Default::default(),
         };
 
         let pool_config = WorkerPoolConfig {
             pool_size: 2,
             max_requests: 1000,
+            affinity: false,
         };
 
         let result = WorkerPool::new(php_config, pool_config);
@@ -0,0 +1,292 @@
+//! Response body handed to hyper, and the write side of HTTP/1.1 connections
+//!
+//! Bodies are binary-safe bytes that are already in memory, so they go to hyper
+//! as a single frame. With `server.response_buffer_mode = "streaming-threshold"`,
+//! [`ChunkedWrites`] writes the headers of a large response on their own and then
+//! its body in `response_chunk_size` pieces, instead of one write of everything.
+
+use crate::config::{ResponseBufferMode, ServerConfig};
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::rt::{Read, ReadBufCursor, Write};
+use std::convert::Infallible;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+#[derive(Debug, Default)]
+pub struct ResponseBody {
+    data: Bytes,
+}
+
+impl ResponseBody {
+    /// Whole body in one frame
+    pub fn full(data: impl Into<Bytes>) -> Self {
+        Self { data: data.into() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Body for ResponseBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        if self.data.is_empty() {
+            return Poll::Ready(None);
+        }
+        let data = std::mem::take(&mut self.data);
+        Poll::Ready(Some(Ok(Frame::data(data))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.len() as u64)
+    }
+}
+
+/// Per-connection switch between one write per response and chunked writes,
+/// flipped by the service as each response is handed to hyper
+pub struct WriteChunking {
+    threshold: usize,
+    chunk_size: usize,
+    active: AtomicBool,
+}
+
+impl WriteChunking {
+    /// `None` unless `server.response_buffer_mode` is `streaming-threshold`
+    pub fn from_config(config: &ServerConfig) -> Option<Arc<Self>> {
+        match config.response_buffer_mode {
+            ResponseBufferMode::Full => None,
+            ResponseBufferMode::StreamingThreshold => Some(Arc::new(Self {
+                threshold: config.response_stream_threshold,
+                chunk_size: config.response_chunk_size.max(1),
+                active: AtomicBool::new(false),
+            })),
+        }
+    }
+
+    /// Chunk the writes of the response about to be sent if its body is large
+    pub fn begin(&self, body_len: usize) {
+        self.active.store(body_len > self.threshold, Ordering::Release);
+    }
+
+    fn limit(&self) -> Option<usize> {
+        self.active.load(Ordering::Acquire).then_some(self.chunk_size)
+    }
+}
+
+/// Connection I/O that caps each write while [`WriteChunking`] is active.
+/// A vectored write then only takes its first buffer, so the headers hyper
+/// queued ahead of the body go out before any of it.
+pub struct ChunkedWrites<I> {
+    inner: I,
+    chunking: Option<Arc<WriteChunking>>,
+}
+
+impl<I> ChunkedWrites<I> {
+    /// Pass writes through unchanged when `chunking` is `None`
+    pub fn new(inner: I, chunking: Option<Arc<WriteChunking>>) -> Self {
+        Self { inner, chunking }
+    }
+
+    fn limit(&self) -> Option<usize> {
+        self.chunking.as_ref().and_then(|chunking| chunking.limit())
+    }
+}
+
+impl<I: Read + Unpin> Read for ChunkedWrites<I> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<I: Write + Unpin> Write for ChunkedWrites<I> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let len = this.limit().map_or(buf.len(), |limit| buf.len().min(limit));
+        Pin::new(&mut this.inner).poll_write(cx, &buf[..len])
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        match this.limit() {
+            Some(limit) => {
+                let first = bufs.iter().find(|buf| !buf.is_empty()).map_or(&[][..], |buf| &buf[..]);
+                Pin::new(&mut this.inner).poll_write(cx, &first[..first.len().min(limit)])
+            }
+            None => Pin::new(&mut this.inner).poll_write_vectored(cx, bufs),
+        }
+    }
+}
+
+impl From<String> for ResponseBody {
+    fn from(body: String) -> Self {
+        Self::full(body)
+    }
+}
+
+impl From<Vec<u8>> for ResponseBody {
+    fn from(body: Vec<u8>) -> Self {
+        Self::full(body)
+    }
+}
+
+impl From<&'static str> for ResponseBody {
+    fn from(body: &'static str) -> Self {
+        Self::full(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use parking_lot::Mutex;
+    use std::io::Cursor;
+
+    /// Connection that reads one request and records the size of every write
+    struct Recorder {
+        request: Cursor<Vec<u8>>,
+        writes: Arc<Mutex<Vec<usize>>>,
+    }
+
+    impl Read for Recorder {
+        fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, mut buf: ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            let remaining = &this.request.get_ref()[this.request.position() as usize..];
+            // Keep the connection open; `Connection: close` ends it after the response
+            if remaining.is_empty() {
+                return Poll::Pending;
+            }
+            let n = remaining.len().min(buf.remaining());
+            buf.put_slice(&remaining[..n]);
+            this.request.set_position(this.request.position() + n as u64);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl Write for Recorder {
+        fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.writes.lock().push(buf.len());
+            Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        // Like a TCP socket, so hyper queues the body instead of copying it
+        fn is_write_vectored(&self) -> bool {
+            true
+        }
+
+        fn poll_write_vectored(
+            self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            bufs: &[io::IoSlice<'_>],
+        ) -> Poll<io::Result<usize>> {
+            let len = bufs.iter().map(|buf| buf.len()).sum();
+            self.writes.lock().push(len);
+            Poll::Ready(Ok(len))
+        }
+    }
+
+    fn config(mode: &str) -> ServerConfig {
+        toml::from_str(&format!(
+            "response_buffer_mode = \"{}\"\nresponse_stream_threshold = 1024\nresponse_chunk_size = 256",
+            mode
+        ))
+        .unwrap()
+    }
+
+    /// Sizes of the socket writes that answer one request with `body_len` bytes
+    async fn socket_writes(mode: &str, body_len: usize) -> Vec<usize> {
+        let writes = Arc::new(Mutex::new(Vec::new()));
+        let io = Recorder {
+            request: Cursor::new(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n".to_vec()),
+            writes: Arc::clone(&writes),
+        };
+        let chunking = WriteChunking::from_config(&config(mode));
+        let service_chunking = chunking.clone();
+        let service = hyper::service::service_fn(move |_req| {
+            let body = ResponseBody::from(vec![b'x'; body_len]);
+            if let Some(chunking) = &service_chunking {
+                chunking.begin(body.len());
+            }
+            async move { Ok::<_, Infallible>(hyper::Response::new(body)) }
+        });
+        hyper::server::conn::http1::Builder::new()
+            .serve_connection(ChunkedWrites::new(io, chunking), service)
+            .await
+            .unwrap();
+        let writes = writes.lock().clone();
+        writes
+    }
+
+    #[tokio::test]
+    async fn test_large_body_written_in_chunks() {
+        let writes = socket_writes("streaming-threshold", 2000).await;
+        // Headers alone, then the body in 256-byte writes
+        assert_eq!(writes.len(), 1 + 2000usize.div_ceil(256), "{:?}", writes);
+        assert!(writes[0] < 256);
+        assert!(writes[1..].iter().all(|&n| n <= 256));
+        assert_eq!(writes[1..].iter().sum::<usize>(), 2000);
+    }
+
+    #[tokio::test]
+    async fn test_small_or_buffered_body_is_one_write() {
+        let small = socket_writes("streaming-threshold", 5).await;
+        assert_eq!(small.len(), 1, "{:?}", small);
+
+        let large = socket_writes("full", 4096).await;
+        assert_eq!(large.len(), 1, "{:?}", large);
+        assert!(large[0] > 4096);
+    }
+
+    #[tokio::test]
+    async fn test_body_is_one_frame() {
+        let payload: Vec<u8> = (0..=255u8).cycle().take(4096).collect();
+        let mut body = ResponseBody::from(payload.clone());
+        assert_eq!(body.size_hint().exact(), Some(payload.len() as u64));
+
+        let frame = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(frame, payload);
+        assert!(body.frame().await.is_none());
+    }
+}
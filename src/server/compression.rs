@@ -49,6 +49,14 @@ impl Default for CompressionConfig {
 }
 
 impl CompressionConfig {
+    /// Gzip-only settings for the metrics and admin HTTP endpoints
+    pub fn gzip_only() -> Self {
+        Self {
+            enable_brotli: false,
+            ..Self::default()
+        }
+    }
+
     /// Check if content type should be compressed
     pub fn should_compress(&self, content_type: &str, size: usize) -> bool {
         if size < self.min_size {
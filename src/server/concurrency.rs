@@ -0,0 +1,162 @@
+use super::body::ResponseBody;
+use super::overload::OverloadResponder;
+use anyhow::Result;
+use hyper::Response;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+
+/// Caps simultaneous PHP executions across all connections (`php.max_concurrent`)
+pub struct PhpConcurrencyLimit {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+    wait: Duration,
+    overload: OverloadResponder,
+}
+
+impl PhpConcurrencyLimit {
+    pub fn new(max: usize, wait: Duration) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            max,
+            wait,
+            overload: OverloadResponder::default(),
+        }
+    }
+
+    /// Response used when the limit is saturated (`server.overload`)
+    pub fn with_overload(mut self, overload: OverloadResponder) -> Self {
+        self.overload = overload;
+        self
+    }
+
+    /// Take an execution slot, queuing for at most the configured wait.
+    /// `None` means the limit is saturated and the request should be rejected.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        acquire_within(&self.semaphore, self.wait).await
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Executions currently holding a slot
+    pub fn in_flight(&self) -> usize {
+        self.max - self.semaphore.available_permits()
+    }
+
+    /// Status of the response answered when the limit is saturated
+    pub fn saturated_status(&self) -> u16 {
+        self.overload.status()
+    }
+
+    /// Overload response answered when no slot became free in time
+    pub fn saturated_response(&self, uri: &str) -> Result<Response<ResponseBody>> {
+        warn!(uri = %uri, max_concurrent = self.max, "PHP concurrency limit reached");
+        self.overload.response()
+    }
+}
+
+/// Caps simultaneous TLS handshakes across all listeners (`tls.max_concurrent_handshakes`)
+/// so a handshake flood cannot take all the CPU
+pub struct TlsHandshakeLimit {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+    wait: Duration,
+}
+
+impl TlsHandshakeLimit {
+    pub fn new(max: usize, wait: Duration) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            max,
+            wait,
+        }
+    }
+
+    /// Take a handshake slot, queuing for at most the configured wait.
+    /// `None` means the connection should be dropped.
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        acquire_within(&self.semaphore, self.wait).await
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Handshakes currently holding a slot
+    pub fn in_flight(&self) -> usize {
+        self.max - self.semaphore.available_permits()
+    }
+}
+
+async fn acquire_within(semaphore: &Arc<Semaphore>, wait: Duration) -> Option<OwnedSemaphorePermit> {
+    let semaphore = Arc::clone(semaphore);
+    if wait.is_zero() {
+        return semaphore.try_acquire_owned().ok();
+    }
+
+    tokio::time::timeout(wait, semaphore.acquire_owned())
+        .await
+        .ok()
+        .and_then(Result::ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_limit_rejects_when_saturated() {
+        let limit = PhpConcurrencyLimit::new(2, Duration::ZERO);
+
+        let first = limit.acquire().await.unwrap();
+        let _second = limit.acquire().await.unwrap();
+        assert_eq!(limit.in_flight(), 2);
+        assert!(limit.acquire().await.is_none());
+
+        drop(first);
+        assert!(limit.acquire().await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_limit_queues_up_to_wait() {
+        let limit = Arc::new(PhpConcurrencyLimit::new(1, Duration::from_millis(500)));
+        let held = limit.acquire().await.unwrap();
+
+        let waiter = tokio::spawn({
+            let limit = Arc::clone(&limit);
+            async move { limit.acquire().await.is_some() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(held);
+        assert!(waiter.await.unwrap());
+
+        let short = PhpConcurrencyLimit::new(1, Duration::from_millis(20));
+        let _held = short.acquire().await.unwrap();
+        assert!(short.acquire().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_limit_bounds_concurrency() {
+        let limit = Arc::new(TlsHandshakeLimit::new(2, Duration::from_millis(500)));
+        let first = limit.acquire().await.unwrap();
+        let _second = limit.acquire().await.unwrap();
+        assert_eq!(limit.in_flight(), 2);
+
+        // A third handshake waits for a slot rather than running alongside
+        let waiter = tokio::spawn({
+            let limit = Arc::clone(&limit);
+            async move { limit.acquire().await.is_some() }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+        drop(first);
+        assert!(waiter.await.unwrap());
+
+        let dropping = TlsHandshakeLimit::new(1, Duration::ZERO);
+        let _held = dropping.acquire().await.unwrap();
+        assert!(dropping.acquire().await.is_none());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::config::Config;
 use anyhow::{Context, Result};
 use parking_lot::RwLock;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::signal::unix::{signal, SignalKind};
 use tracing::{error, info};
@@ -9,6 +9,7 @@ use tracing::{error, info};
 /// Configuration reload manager
 pub struct ConfigReloadManager {
     config_path: PathBuf,
+    overrides: Vec<PathBuf>,
     current_config: Arc<RwLock<Config>>,
 }
 
@@ -16,10 +17,22 @@ impl ConfigReloadManager {
     pub fn new(config_path: PathBuf, initial_config: Config) -> Self {
         Self {
             config_path,
+            overrides: Vec::new(),
             current_config: Arc::new(RwLock::new(initial_config)),
         }
     }
 
+    /// Override files re-applied over the base configuration on every reload
+    pub fn with_overrides(mut self, overrides: Vec<PathBuf>) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Base configuration file re-read on every reload
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
     /// Get current configuration (read-only access)
     pub fn config(&self) -> Arc<RwLock<Config>> {
         Arc::clone(&self.current_config)
@@ -30,7 +43,7 @@ impl ConfigReloadManager {
         info!("Reloading configuration from {:?}", self.config_path);
 
         // Load new configuration
-        let new_config = Config::from_file(&self.config_path)
+        let new_config = Config::from_files(&self.config_path, &self.overrides)
             .with_context(|| format!("Failed to load config from {:?}", self.config_path))?;
 
         // Validate using built-in validation
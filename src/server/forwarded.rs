@@ -0,0 +1,265 @@
+//! Client address, scheme and host as reported by trusted reverse proxies
+//!
+//! The standard `Forwarded` header (RFC 7239) is preferred; `X-Forwarded-For`,
+//! `X-Forwarded-Proto` and `X-Forwarded-Host` are used when it is absent.
+
+use anyhow::{Context, Result};
+use hyper::header::HeaderMap;
+use ipnetwork::IpNetwork;
+use std::net::{IpAddr, SocketAddr};
+use super::peer_addr::PeerAddr;
+
+/// Client details derived from the proxy headers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ClientInfo {
+    pub ip: Option<IpAddr>,
+    pub scheme: Option<String>,
+    pub host: Option<String>,
+}
+
+/// One `Forwarded` element, i.e. the hop added by a single proxy
+#[derive(Debug, Default)]
+struct Element {
+    for_ip: Option<IpAddr>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+/// Resolves the original client behind `security.trusted_proxies`
+pub struct ForwardedResolver {
+    trusted: Vec<IpNetwork>,
+    trust_unix: bool,
+}
+
+impl ForwardedResolver {
+    pub fn new(cidrs: &[String]) -> Result<Self> {
+        let trusted = cidrs
+            .iter()
+            .map(|cidr| {
+                cidr.parse::<IpNetwork>()
+                    .with_context(|| format!("Invalid trusted proxy '{}'", cidr))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { trusted, trust_unix: false })
+    }
+
+    /// Treat every Unix socket peer as a trusted proxy
+    pub fn with_trusted_unix_peers(mut self, trust: bool) -> Self {
+        self.trust_unix = trust;
+        self
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.trusted.iter().any(|net| net.contains(ip))
+    }
+
+    /// Client info for a request received from `peer`; headers are ignored unless
+    /// `peer` itself is a trusted proxy
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> ClientInfo {
+        if !self.is_trusted(peer) {
+            return ClientInfo::default();
+        }
+        self.resolve_trusted(headers)
+    }
+
+    /// Like [`resolve`](Self::resolve) for any kind of peer; Unix socket peers
+    /// are trusted only with [`with_trusted_unix_peers`](Self::with_trusted_unix_peers)
+    pub fn resolve_peer(&self, peer: &PeerAddr, headers: &HeaderMap) -> ClientInfo {
+        match peer.ip() {
+            Some(ip) => self.resolve(ip, headers),
+            None if self.trust_unix => self.resolve_trusted(headers),
+            None => ClientInfo::default(),
+        }
+    }
+
+    fn resolve_trusted(&self, headers: &HeaderMap) -> ClientInfo {
+        let elements = match header_values(headers, "forwarded") {
+            Some(value) => parse_forwarded(&value),
+            None => x_forwarded_elements(headers),
+        };
+
+        // Walk back from the nearest hop, skipping our own proxies; the first
+        // untrusted (or unidentifiable) address is the client
+        let mut selected = None;
+        for element in elements.iter().rev() {
+            selected = Some(element);
+            match element.for_ip {
+                Some(ip) if self.is_trusted(ip) => continue,
+                _ => break,
+            }
+        }
+
+        selected
+            .map(|element| ClientInfo {
+                ip: element.for_ip,
+                scheme: element.proto.clone(),
+                host: element.host.clone(),
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// All values of a header joined into one comma-separated list
+fn header_values(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+    (!values.is_empty()).then(|| values.join(","))
+}
+
+/// Parse `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"` into elements
+fn parse_forwarded(value: &str) -> Vec<Element> {
+    value
+        .split(',')
+        .map(|element| {
+            let mut parsed = Element::default();
+            for pair in element.split(';') {
+                let Some((name, value)) = pair.split_once('=') else {
+                    continue;
+                };
+                let value = value.trim().trim_matches('"');
+                match name.trim().to_ascii_lowercase().as_str() {
+                    "for" => parsed.for_ip = parse_node(value),
+                    "proto" => parsed.proto = Some(value.to_ascii_lowercase()),
+                    "host" => parsed.host = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            parsed
+        })
+        .collect()
+}
+
+/// Node identifier with optional port; `unknown` and obfuscated names yield `None`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            node.strip_prefix('[')
+                .and_then(|rest| rest.split_once(']'))
+                .and_then(|(ip, _)| ip.parse().ok())
+        })
+}
+
+/// Build elements from `X-Forwarded-For`; proto and host apply to the whole chain
+fn x_forwarded_elements(headers: &HeaderMap) -> Vec<Element> {
+    let first = |name: &str| {
+        header_values(headers, name)
+            .and_then(|v| v.split(',').next().map(|s| s.trim().to_string()))
+            .filter(|s| !s.is_empty())
+    };
+    let proto = first("x-forwarded-proto").map(|p| p.to_ascii_lowercase());
+    let host = first("x-forwarded-host");
+
+    match header_values(headers, "x-forwarded-for") {
+        Some(value) => value
+            .split(',')
+            .map(|node| Element {
+                for_ip: parse_node(node.trim()),
+                proto: proto.clone(),
+                host: host.clone(),
+            })
+            .collect(),
+        None if proto.is_some() || host.is_some() => vec![Element { for_ip: None, proto, host }],
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver() -> ForwardedResolver {
+        ForwardedResolver::new(&["10.0.0.0/8".to_string(), "2001:db8::/32".to_string()]).unwrap()
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_unix_socket_peers_trusted_only_when_configured() {
+        let headers = headers(&[("x-forwarded-for", "198.51.100.17"), ("x-forwarded-proto", "HTTPS")]);
+        let peer = PeerAddr::from_unix("/run/fe-php.sock");
+
+        assert_eq!(resolver().resolve_peer(&peer, &headers), ClientInfo::default());
+
+        let info = resolver().with_trusted_unix_peers(true).resolve_peer(&peer, &headers);
+        assert_eq!(info.ip, Some("198.51.100.17".parse().unwrap()));
+        assert_eq!(info.scheme.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn test_forwarded_multiple_elements() {
+        let headers = headers(&[(
+            "forwarded",
+            "for=198.51.100.17;proto=https;host=shop.example, for=203.0.113.5;proto=http, for=\"[2001:db8::7]:4711\"",
+        )]);
+        let info = resolver().resolve("10.0.0.2".parse().unwrap(), &headers);
+
+        // 2001:db8::7 is our own proxy, so the nearest untrusted hop is the client
+        assert_eq!(info.ip, Some("203.0.113.5".parse().unwrap()));
+        assert_eq!(info.scheme.as_deref(), Some("http"));
+        assert_eq!(info.host, None);
+    }
+
+    #[test]
+    fn test_forwarded_all_trusted_uses_first_element() {
+        let headers = headers(&[
+            ("forwarded", "for=198.51.100.17;proto=HTTPS;host=shop.example"),
+            ("forwarded", "for=10.1.1.1"),
+        ]);
+        let info = resolver().resolve("10.0.0.2".parse().unwrap(), &headers);
+
+        assert_eq!(info.ip, Some("198.51.100.17".parse().unwrap()));
+        assert_eq!(info.scheme.as_deref(), Some("https"));
+        assert_eq!(info.host.as_deref(), Some("shop.example"));
+    }
+
+    #[test]
+    fn test_forwarded_preferred_over_x_forwarded() {
+        let headers = headers(&[
+            ("x-forwarded-for", "192.0.2.99"),
+            ("x-forwarded-proto", "http"),
+            ("forwarded", "for=192.0.2.1;proto=https"),
+        ]);
+        let info = resolver().resolve("10.0.0.2".parse().unwrap(), &headers);
+
+        assert_eq!(info.ip, Some("192.0.2.1".parse().unwrap()));
+        assert_eq!(info.scheme.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn test_x_forwarded_fallback_and_untrusted_peer() {
+        let headers = headers(&[
+            ("x-forwarded-for", "192.0.2.10, 10.9.9.9"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "app.example"),
+        ]);
+
+        let info = resolver().resolve("10.0.0.2".parse().unwrap(), &headers);
+        assert_eq!(info.ip, Some("192.0.2.10".parse().unwrap()));
+        assert_eq!(info.scheme.as_deref(), Some("https"));
+        assert_eq!(info.host.as_deref(), Some("app.example"));
+
+        // Headers from a client that is not a trusted proxy are ignored
+        let info = resolver().resolve("203.0.113.9".parse().unwrap(), &headers);
+        assert_eq!(info, ClientInfo::default());
+    }
+
+    #[test]
+    fn test_unknown_node_stops_chain() {
+        let headers = headers(&[("forwarded", "for=192.0.2.1, for=unknown;proto=https, for=10.0.0.5")]);
+        let info = resolver().resolve("10.0.0.2".parse().unwrap(), &headers);
+
+        assert_eq!(info.ip, None);
+        assert_eq!(info.scheme.as_deref(), Some("https"));
+    }
+}
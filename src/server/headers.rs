@@ -0,0 +1,137 @@
+use crate::config::{OversizedHeaders, ResponseHeaderLimits, ResponseHeaderPolicy};
+use crate::geoip::LocationInfo;
+use crate::php::{PhpResponse, ResponseHeaders};
+use chrono::{DateTime, Utc};
+use hyper::header::{HeaderMap, HeaderValue, DATE, SERVER};
+use std::collections::HashMap;
+use tracing::{debug, warn};
+
+/// Request headers PHP sees as `$_SERVER['HTTP_GEOIP_COUNTRY_CODE']` and so on
+pub const GEOIP_HEADERS: [&str; 3] = ["geoip-country-code", "geoip-city", "geoip-continent"];
+
+/// Replace whatever GeoIP headers the client sent with the looked-up `location`.
+/// Parts the database does not know are left out rather than sent empty.
+pub fn insert_geoip_headers(headers: &mut HashMap<String, String>, location: Option<&LocationInfo>) {
+    headers.retain(|name, _| !GEOIP_HEADERS.iter().any(|geoip| name.eq_ignore_ascii_case(geoip)));
+    let Some(location) = location else {
+        return;
+    };
+    let values = [&location.country, &location.city, &location.continent];
+    for (name, value) in GEOIP_HEADERS.iter().zip(values) {
+        if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+            headers.insert(name.to_string(), value.to_string());
+        }
+    }
+}
+
+/// IMF-fixdate as required for the HTTP `Date` header (RFC 9110)
+pub fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Ensure a well-formed `Date` header and set `Server` on an outgoing response
+///
+/// A `Date` supplied by the backend is kept when it parses; anything else is
+/// replaced. `server` overrides whatever the backend sent (e.g. PHP's own).
+pub fn apply_standard_headers(headers: &mut HeaderMap, server: Option<&str>) {
+    let valid_date = headers
+        .get(DATE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| DateTime::parse_from_rfc2822(v).is_ok());
+    if !valid_date {
+        if let Ok(value) = HeaderValue::from_str(&http_date(Utc::now())) {
+            headers.insert(DATE, value);
+        }
+    }
+
+    if let Some(value) = server.and_then(|s| HeaderValue::from_str(s).ok()) {
+        headers.insert(SERVER, value);
+    }
+}
+
+/// Strip headers PHP may not send under `php.response_header_policy`
+pub fn filter_php_headers(response: &mut PhpResponse, policy: &ResponseHeaderPolicy, request_id: &str) {
+    let removed = response.headers.apply_header_policy(policy);
+    if !removed.is_empty() {
+        debug!(request_id = %request_id, "Removed PHP response headers by policy: {}", removed.join(", "));
+    }
+}
+
+/// Hold PHP's headers to `php.response_headers`, replacing the whole response with
+/// a 500 when the policy is `error`
+pub fn limit_php_headers(response: &mut PhpResponse, limits: &ResponseHeaderLimits, request_id: &str) {
+    let oversized = response.headers.apply_size_limits(limits);
+    if oversized.is_empty() {
+        return;
+    }
+
+    warn!(
+        request_id = %request_id,
+        action = ?limits.on_oversized,
+        "PHP response headers over the size limit: {}",
+        oversized.join(", ")
+    );
+    if limits.on_oversized == OversizedHeaders::Error {
+        response.status_code = 500;
+        response.headers = ResponseHeaders::new();
+        response.body = b"Internal Server Error".to_vec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_geoip_headers() {
+        let spoofed = || HashMap::from([
+            ("geoip-country-code".to_string(), "XX".to_string()),
+            ("geoip-city".to_string(), "Nowhere".to_string()),
+            ("accept".to_string(), "*/*".to_string()),
+        ]);
+        let location = LocationInfo {
+            country: Some("JP".to_string()),
+            city: None,
+            continent: Some("AS".to_string()),
+        };
+
+        let mut headers = spoofed();
+        insert_geoip_headers(&mut headers, Some(&location));
+        assert_eq!(headers["geoip-country-code"], "JP");
+        assert_eq!(headers["geoip-continent"], "AS");
+        // No city: no header, and not the client's either
+        assert!(!headers.contains_key("geoip-city"));
+        assert_eq!(headers["accept"], "*/*");
+
+        let mut headers = spoofed();
+        insert_geoip_headers(&mut headers, None);
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn test_http_date_format() {
+        let time = Utc.with_ymd_and_hms(1994, 11, 6, 8, 49, 37).unwrap();
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+    }
+
+    #[test]
+    fn test_invalid_date_is_replaced() {
+        let mut headers = HeaderMap::new();
+        headers.insert(DATE, HeaderValue::from_static("yesterday"));
+        headers.insert(SERVER, HeaderValue::from_static("Apache"));
+
+        apply_standard_headers(&mut headers, Some("fe-php"));
+
+        let date = headers.get(DATE).unwrap().to_str().unwrap();
+        assert!(DateTime::parse_from_rfc2822(date).is_ok());
+        assert_eq!(headers.get(SERVER).unwrap(), "fe-php");
+
+        // A valid backend date is left alone, and no Server header is forced when disabled
+        let mut headers = HeaderMap::new();
+        headers.insert(DATE, HeaderValue::from_static("Sun, 06 Nov 1994 08:49:37 GMT"));
+        apply_standard_headers(&mut headers, None);
+        assert_eq!(headers.get(DATE).unwrap(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert!(headers.get(SERVER).is_none());
+    }
+}
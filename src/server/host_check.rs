@@ -0,0 +1,158 @@
+//! `Host` header validation against `server.host_check`
+
+use crate::config::HostCheckConfig;
+use anyhow::{Context, Result};
+use hyper::header::{HeaderValue, HOST};
+use hyper::Request;
+
+/// What to do with a request after checking its host
+#[derive(Debug, PartialEq, Eq)]
+pub enum HostDecision {
+    /// Host is listed (or no list is configured); route unchanged
+    Accept,
+    /// Missing or unlisted host; route as the default host
+    Default,
+    /// Missing or unlisted host in strict mode; answer 400
+    Reject,
+}
+
+pub struct HostCheck {
+    strict: bool,
+    allowed_hosts: Vec<String>,
+    default_host: Option<HeaderValue>,
+}
+
+impl HostCheck {
+    pub fn from_config(config: &HostCheckConfig) -> Result<Self> {
+        if config.strict && config.allowed_hosts.is_empty() {
+            anyhow::bail!("server.host_check.strict requires allowed_hosts");
+        }
+
+        let default_host = config
+            .default_host
+            .as_deref()
+            .map(HeaderValue::from_str)
+            .transpose()
+            .context("Invalid server.host_check.default_host")?;
+
+        Ok(Self {
+            strict: config.strict,
+            allowed_hosts: config.allowed_hosts.iter().map(|h| h.to_ascii_lowercase()).collect(),
+            default_host,
+        })
+    }
+
+    /// Whether `host` (port ignored) matches `allowed_hosts`; everything matches an empty list
+    pub fn is_allowed(&self, host: &str) -> bool {
+        if self.allowed_hosts.is_empty() {
+            return true;
+        }
+
+        let host = strip_port(host).to_ascii_lowercase();
+        self.allowed_hosts.iter().any(|allowed| match allowed.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => *allowed == host,
+        })
+    }
+
+    /// Check the request's host, rewriting `Host` to the default host when routed there
+    pub fn apply<B>(&self, req: &mut Request<B>) -> HostDecision {
+        let host = req
+            .headers()
+            .get(HOST)
+            .and_then(|v| v.to_str().ok())
+            .or_else(|| req.uri().authority().map(|a| a.as_str()));
+
+        if host.is_some_and(|h| self.is_allowed(h)) {
+            return HostDecision::Accept;
+        }
+        if self.strict {
+            return HostDecision::Reject;
+        }
+
+        match &self.default_host {
+            Some(default_host) => {
+                req.headers_mut().insert(HOST, default_host.clone());
+                HostDecision::Default
+            }
+            None => HostDecision::Accept,
+        }
+    }
+}
+
+/// `example.com:8080` -> `example.com`, `[::1]:8080` -> `[::1]`
+pub(super) fn strip_port(host: &str) -> &str {
+    match host.rfind(':') {
+        Some(i) if !host[i..].contains(']') => &host[..i],
+        _ => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(strict: bool, default_host: Option<&str>) -> HostCheck {
+        HostCheck::from_config(&HostCheckConfig {
+            strict,
+            allowed_hosts: vec!["example.com".to_string(), "*.example.org".to_string()],
+            default_host: default_host.map(String::from),
+        })
+        .unwrap()
+    }
+
+    fn request(host: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri("/");
+        if let Some(host) = host {
+            builder = builder.header(HOST, host);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_allowed_hosts() {
+        let check = check(true, None);
+
+        assert!(check.is_allowed("example.com"));
+        assert!(check.is_allowed("EXAMPLE.com:8443"));
+        assert!(check.is_allowed("api.example.org"));
+        assert!(!check.is_allowed("example.org"));
+        assert!(!check.is_allowed("evilexample.org"));
+        assert!(!check.is_allowed("example.com.evil"));
+    }
+
+    #[test]
+    fn test_strict_mode() {
+        let check = check(true, None);
+
+        assert_eq!(check.apply(&mut request(Some("example.com"))), HostDecision::Accept);
+        assert_eq!(check.apply(&mut request(Some("attacker.test"))), HostDecision::Reject);
+        assert_eq!(check.apply(&mut request(None)), HostDecision::Reject);
+    }
+
+    #[test]
+    fn test_default_host_routing() {
+        let check = check(false, Some("example.com"));
+
+        let mut req = request(None);
+        assert_eq!(check.apply(&mut req), HostDecision::Default);
+        assert_eq!(req.headers()[HOST], "example.com");
+
+        let mut req = request(Some("attacker.test"));
+        assert_eq!(check.apply(&mut req), HostDecision::Default);
+        assert_eq!(req.headers()[HOST], "example.com");
+
+        // Without a default host, unknown hosts are routed untouched
+        let check = HostCheck::from_config(&HostCheckConfig::default()).unwrap();
+        let mut req = request(Some("anything.test"));
+        assert_eq!(check.apply(&mut req), HostDecision::Accept);
+    }
+
+    #[test]
+    fn test_strict_without_hosts_is_rejected() {
+        let config = HostCheckConfig { strict: true, ..Default::default() };
+        assert!(HostCheck::from_config(&config).is_err());
+    }
+}
@@ -3,11 +3,16 @@ use hyper::{Request, Response, StatusCode, body::Incoming};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
 use tracing::{info, debug};
+use crate::config::RequireHttps;
+use crate::tls::acme::Http01Challenges;
+use super::body::ResponseBody;
+use super::host_check::strip_port;
 
 /// HTTP to HTTPS redirect server
 pub struct HttpRedirectServer {
     http_port: u16,
     https_port: u16,
+    acme_challenges: Option<Http01Challenges>,
 }
 
 impl HttpRedirectServer {
@@ -15,9 +20,16 @@ impl HttpRedirectServer {
         Self {
             http_port,
             https_port,
+            acme_challenges: None,
         }
     }
 
+    /// Answer pending ACME HTTP-01 challenges instead of redirecting them
+    pub fn with_acme_challenges(mut self, challenges: Option<Http01Challenges>) -> Self {
+        self.acme_challenges = challenges;
+        self
+    }
+
     pub async fn serve(self) -> Result<()> {
         let addr: SocketAddr = ([0, 0, 0, 0], self.http_port).into();
         let listener = TcpListener::bind(addr).await?;
@@ -29,13 +41,15 @@ impl HttpRedirectServer {
             match listener.accept().await {
                 Ok((stream, remote_addr)) => {
                     let https_port = self.https_port;
+                    let acme_challenges = self.acme_challenges.clone();
 
                     tokio::spawn(async move {
                         let io = hyper_util::rt::TokioIo::new(stream);
 
                         let service = hyper::service::service_fn(move |req: Request<Incoming>| {
+                            let acme_challenges = acme_challenges.clone();
                             async move {
-                                handle_redirect(req, https_port, remote_addr).await
+                                handle_redirect(req, https_port, remote_addr, acme_challenges.as_ref()).await
                             }
                         });
 
@@ -55,11 +69,27 @@ impl HttpRedirectServer {
     }
 }
 
-async fn handle_redirect(
-    req: Request<Incoming>,
+async fn handle_redirect<B>(
+    req: Request<B>,
     https_port: u16,
     remote_addr: SocketAddr,
+    acme_challenges: Option<&Http01Challenges>,
 ) -> Result<Response<String>> {
+    // The CA fetches challenges over plain HTTP, so they are never redirected
+    if let Some(challenges) = acme_challenges {
+        if req.uri().path().starts_with(crate::tls::acme::CHALLENGE_PATH) {
+            let response = match challenges.respond(req.uri().path()) {
+                Some(key_authorization) => Response::builder()
+                    .header("Content-Type", "application/octet-stream")
+                    .body(key_authorization)?,
+                None => Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(String::new())?,
+            };
+            return Ok(response);
+        }
+    }
+
     let host = req.headers()
         .get("host")
         .and_then(|h| h.to_str().ok())
@@ -83,13 +113,82 @@ async fn handle_redirect(
         .body(String::new())?)
 }
 
+/// Apply `server.require_https` to a request that arrived over plain HTTP on a main
+/// listener; `None` lets it through. Redirects drop the port since the plaintext port
+/// says nothing about where HTTPS is served.
+pub fn enforce_https<B>(mode: RequireHttps, req: &Request<B>) -> Result<Option<Response<ResponseBody>>> {
+    let host = req
+        .headers()
+        .get(hyper::header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| req.uri().authority().map(|a| a.as_str()))
+        .filter(|h| !h.is_empty());
+
+    let response = match (mode, host) {
+        (RequireHttps::Off, _) => return Ok(None),
+        (RequireHttps::Redirect, Some(host)) => {
+            let target = req.uri().path_and_query().map(|p| p.as_str()).unwrap_or("/");
+            Response::builder()
+                .status(StatusCode::MOVED_PERMANENTLY)
+                .header(hyper::header::LOCATION, format!("https://{}{}", strip_port(host), target))
+                .body(ResponseBody::default())?
+        }
+        // Nowhere to redirect to without a host
+        (RequireHttps::Redirect, None) | (RequireHttps::Reject, _) => Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .body("Forbidden: HTTPS required".into())?,
+    };
+    Ok(Some(response))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn request(host: Option<&str>) -> Request<()> {
+        let mut builder = Request::builder().uri("/cart.php?item=1");
+        if let Some(host) = host {
+            builder = builder.header("host", host);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_enforce_https_modes() {
+        assert!(enforce_https(RequireHttps::Off, &request(Some("shop.example"))).unwrap().is_none());
+
+        let redirect = enforce_https(RequireHttps::Redirect, &request(Some("shop.example:8080"))).unwrap().unwrap();
+        assert_eq!(redirect.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(redirect.headers()["location"], "https://shop.example/cart.php?item=1");
+
+        let no_host = enforce_https(RequireHttps::Redirect, &request(None)).unwrap().unwrap();
+        assert_eq!(no_host.status(), StatusCode::FORBIDDEN);
+
+        let reject = enforce_https(RequireHttps::Reject, &request(Some("shop.example"))).unwrap().unwrap();
+        assert_eq!(reject.status(), StatusCode::FORBIDDEN);
+    }
+
     #[tokio::test]
     async fn test_redirect_url_construction() {
         // Test would need actual request objects
         // Placeholder for future implementation
     }
+
+    #[tokio::test]
+    async fn test_acme_challenges_are_not_redirected() {
+        let challenges = Http01Challenges::default();
+        let remote: SocketAddr = ([192, 0, 2, 1], 40000).into();
+        let request = |path: &str| Request::builder().uri(path).header("host", "shop.example").body(()).unwrap();
+
+        let response = handle_redirect(request("/.well-known/acme-challenge/unknown"), 443, remote, Some(&challenges)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = handle_redirect(request("/cart.php"), 443, remote, Some(&challenges)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers()["location"], "https://shop.example/cart.php");
+
+        // Without ACME the path is redirected like any other
+        let response = handle_redirect(request("/.well-known/acme-challenge/unknown"), 443, remote, None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+    }
 }
@@ -0,0 +1,52 @@
+use crate::config::MaintenanceConfig;
+use anyhow::{Context, Result};
+use hyper::Response;
+use super::body::ResponseBody;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+const DEFAULT_PAGE: &str = "<!DOCTYPE html>\n<html><head><title>Maintenance</title></head>\n<body><h1>Service under maintenance</h1><p>Please try again shortly.</p></body></html>\n";
+
+/// Runtime maintenance switch; while on, clients get a 503 maintenance page
+pub struct MaintenanceMode {
+    enabled: AtomicBool,
+    retry_after: AtomicU64,
+    default_retry_after: u64,
+    page: String,
+}
+
+impl MaintenanceMode {
+    pub fn from_config(config: &MaintenanceConfig) -> Result<Self> {
+        let page = match config.page_path {
+            Some(ref path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read maintenance page: {}", path.display()))?,
+            None => DEFAULT_PAGE.to_string(),
+        };
+
+        Ok(Self {
+            enabled: AtomicBool::new(config.enabled),
+            retry_after: AtomicU64::new(config.retry_after_secs),
+            default_retry_after: config.retry_after_secs,
+            page,
+        })
+    }
+
+    /// Turn maintenance on or off; `retry_after` falls back to `maintenance.retry_after_secs`
+    pub fn set(&self, enabled: bool, retry_after: Option<u64>) {
+        self.retry_after
+            .store(retry_after.unwrap_or(self.default_retry_after), Ordering::Relaxed);
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn response(&self) -> Result<Response<ResponseBody>> {
+        Ok(Response::builder()
+            .status(503)
+            .header("Content-Type", "text/html; charset=utf-8")
+            .header("Retry-After", self.retry_after.load(Ordering::Relaxed))
+            .header("Cache-Control", "no-store")
+            .body(self.page.clone().into())?)
+    }
+}
@@ -10,11 +10,27 @@ pub mod compression;
 pub mod range;
 pub mod config_reload;
 pub mod peer_addr;
+pub mod path_policy;
+pub mod headers;
+pub mod concurrency;
+pub mod rapid_reset;
+pub mod pipelining;
+pub mod maintenance;
+pub mod socket_options;
+pub mod forwarded;
+pub mod host_check;
+pub mod overload;
+pub mod body;
+pub mod startup_summary;
+pub mod request_context;
 
 use peer_addr::PeerAddr;
+use request_context::RequestContext;
+use body::ResponseBody;
 
-use crate::config::{Config, ListenType};
+use crate::config::{Config, ConnectionFilter, ListenType};
 use crate::php::{WorkerPool, WorkerPoolConfig, PhpConfig};
+use crate::php::connection_pool::PoolConfig;
 use crate::metrics::MetricsCollector;
 use crate::tls::TlsManager;
 use crate::geoip::GeoIpManager;
@@ -23,6 +39,7 @@ use crate::tracing_telemetry::TracingManager;
 use crate::load_balancing::LoadBalancingManager;
 use crate::deployment::DeploymentManager;
 use crate::utils::parse_headers;
+use crate::logging::redaction::redact_uri;
 use anyhow::{Context, Result};
 use http_body_util::BodyExt;
 use hyper::server::conn::{http1, http2};
@@ -30,11 +47,27 @@ use hyper::service::service_fn;
 use hyper::{Request, Response, body::Incoming};
 use hyper_util::rt::TokioIo;
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::net::{TcpListener, UnixListener};
 use tokio_rustls::TlsAcceptor;
 use tracing::{info, error, warn, debug};
 
+/// A bound listening socket plus the per-listener TLS acceptor
+enum BoundListener {
+    Tcp {
+        listener: TcpListener,
+        tls_acceptor: Option<TlsAcceptor>,
+        http2: bool,
+    },
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+        tls_acceptor: Option<TlsAcceptor>,
+        http2: bool,
+    },
+}
+
 #[derive(Clone)]
 pub struct Server {
     config: Arc<Config>,
@@ -42,13 +75,23 @@ pub struct Server {
     backend_router: Option<Arc<crate::backend::router::BackendRouter>>,
     metrics: Arc<MetricsCollector>,
     tls_manager: Option<Arc<TlsManager>>,
+    acme_manager: Option<Arc<crate::tls::acme::AcmeManager>>,
     geoip_manager: Option<Arc<GeoIpManager>>,
-    _redis_manager: Option<Arc<tokio::sync::RwLock<RedisSessionManager>>>,
-    _load_balancer: Option<Arc<LoadBalancingManager>>,
+    _redis_manager: Option<Arc<RedisSessionManager>>,
+    load_balancer: Option<Arc<LoadBalancingManager>>,
     _deployment_manager: Option<Arc<DeploymentManager>>,
     waf_engine: Option<Arc<crate::waf::WafEngine>>,
     shutdown_coordinator: Arc<shutdown::ShutdownCoordinator>,
     ip_blocker: Arc<ip_blocker::IpBlocker>,
+    allowlist: Option<Arc<ip_filter::IpFilter>>,
+    forwarded: Option<Arc<forwarded::ForwardedResolver>>,
+    host_check: Arc<host_check::HostCheck>,
+    path_policy: Arc<path_policy::PathPolicy>,
+    php_limit: Option<Arc<concurrency::PhpConcurrencyLimit>>,
+    /// `server.overload` response, also sent when a backend reports it is overloaded
+    overload: overload::OverloadResponder,
+    tls_handshake_limit: Option<Arc<concurrency::TlsHandshakeLimit>>,
+    maintenance: Arc<maintenance::MaintenanceMode>,
     admin_api: Option<Arc<crate::admin::AdminApi>>,
 }
 
@@ -60,37 +103,84 @@ impl Server {
 
         info!("Configuring {} PHP worker(s)", actual_worker_count);
 
+        for (backend, root) in [
+            ("embedded", &config.backend.embedded.document_root),
+            ("fastcgi", &config.backend.fastcgi.document_root),
+        ] {
+            if let Some(root) = root.as_ref().filter(|root| !root.is_dir()) {
+                anyhow::bail!("Document root for {} backend not found: {}", backend, root.display());
+            }
+        }
+
+        // The worker pool runs libphp, or talks to PHP-FPM directly when hybrid mode is off
         let php_config = PhpConfig {
             libphp_path: config.php.libphp_path.clone(),
-            document_root: config.php.document_root.clone(),
+            document_root: if config.php.use_fpm {
+                config.fastcgi_document_root().to_path_buf()
+            } else {
+                config.embedded_document_root().to_path_buf()
+            },
             worker_pool_size: actual_worker_count,  // Use server.workers
             worker_max_requests: config.php.worker_max_requests,
             use_fpm: config.php.use_fpm,
             fpm_socket: config.php.fpm_socket.clone(),
+            request_id_param: config.php.request_id_param(),
+            expose_fpm_errors: config.php.expose_fpm_errors,
+            content_type: config.php.content_type.clone(),
         };
 
         let pool_config = WorkerPoolConfig {
             pool_size: actual_worker_count,  // Use server.workers
             max_requests: config.php.worker_max_requests,
+            affinity: config.php.worker_affinity,
         };
 
         let worker_pool = Arc::new(WorkerPool::new(php_config.clone(), pool_config)?);
-        let metrics = Arc::new(MetricsCollector::new());
-        let shutdown_coordinator = Arc::new(shutdown::ShutdownCoordinator::new(30));
+        let metrics = Arc::new(
+            MetricsCollector::new().with_status_granularity(config.metrics.status_granularity),
+        );
+        let shutdown_coordinator = Arc::new(shutdown::ShutdownCoordinator::new(config.server.shutdown_timeout_secs));
 
         // Initialize TLS if enabled
-        let tls_manager = if config.tls.enable {
-            let cert_path = config.tls.cert_path.as_ref()
-                .context("TLS enabled but cert_path not specified")?;
-            let key_path = config.tls.key_path.as_ref()
-                .context("TLS enabled but key_path not specified")?;
-
-            let tls = TlsManager::new(cert_path, key_path)
+        let (tls_manager, acme_manager) = if config.tls.enable {
+            let (cert_path, key_path) = if config.tls.acme.enable {
+                crate::tls::acme::ensure_placeholder(&config.tls.acme)?
+            } else {
+                (
+                    config.tls.cert_path.clone().context("TLS enabled but cert_path not specified")?,
+                    config.tls.key_path.clone().context("TLS enabled but key_path not specified")?,
+                )
+            };
+
+            let tls = TlsManager::with_sni(
+                &cert_path,
+                &key_path,
+                config.tls.ca_cert_path.as_deref(),
+                config.tls.require_client_cert,
+                &config.tls.sni,
+            )
                 .context("Failed to initialize TLS")?;
-            info!("TLS/SSL termination enabled");
-            Some(Arc::new(tls))
+            info!("TLS/SSL termination enabled ({} SNI certificate(s))", config.tls.sni.len());
+
+            // The ACME task reports the default certificate it keeps replacing
+            let leaves = tls.leaf_certificates().iter().filter(|(sni, _)| !(config.tls.acme.enable && sni == "default"));
+            for (sni, leaf) in leaves {
+                shutdown_coordinator.register("certificate expiry checks", crate::tls::expiry::spawn_checks(
+                    sni.clone(),
+                    leaf.0.clone(),
+                    std::time::Duration::from_secs(config.tls.expiry_warning_days * 86_400),
+                    Arc::clone(&metrics),
+                    shutdown_coordinator.subscribe(),
+                ));
+            }
+            let tls = Arc::new(tls);
+            let acme = match config.tls.acme.enable {
+                true => Some(Arc::new(crate::tls::acme::AcmeManager::new(config.tls.acme.clone(), Arc::clone(&tls))?)),
+                false => None,
+            };
+            (Some(tls), acme)
         } else {
-            None
+            (None, None)
         };
 
         // Initialize GeoIP if enabled
@@ -102,22 +192,40 @@ impl Server {
                 db_path,
                 config.geoip.allowed_countries.clone(),
                 config.geoip.blocked_countries.clone(),
-            ).context("Failed to initialize GeoIP")?;
+            ).context("Failed to initialize GeoIP")?
+            .with_cache(config.geoip.cache_size, std::time::Duration::from_secs(config.geoip.cache_ttl_secs));
             info!("GeoIP filtering enabled");
-            Some(Arc::new(geoip))
+            let geoip = Arc::new(geoip);
+            if config.geoip.reload_interval_secs > 0 {
+                let watcher = Arc::clone(&geoip).spawn_reload_watcher(
+                    std::time::Duration::from_secs(config.geoip.reload_interval_secs),
+                    shutdown_coordinator.subscribe(),
+                );
+                if let Some(watcher) = watcher {
+                    shutdown_coordinator.register("GeoIP reload watcher", watcher);
+                }
+            }
+            Some(geoip)
         } else {
             None
         };
 
         // Initialize Redis if enabled
         let redis_manager = if config.redis.enable {
-            let redis = RedisSessionManager::new(
-                &config.redis.url,
-                config.redis.key_prefix.clone(),
-                config.redis.timeout_ms,
-            ).await.context("Failed to initialize Redis")?;
+            let redis = RedisSessionManager::from_config(&config.redis)
+                .await
+                .context("Failed to initialize Redis")?
+                .with_decode_error_policy(config.redis.on_deserialize_error, Arc::clone(&metrics));
+            metrics.set_redis_up(true);
+            if config.redis.keepalive_interval_secs > 0 {
+                shutdown_coordinator.register("Redis keepalive", redis.spawn_keepalive(
+                    Arc::clone(&metrics),
+                    std::time::Duration::from_secs(config.redis.keepalive_interval_secs),
+                    shutdown_coordinator.subscribe(),
+                ));
+            }
             info!("Redis session storage enabled");
-            Some(Arc::new(tokio::sync::RwLock::new(redis)))
+            Some(Arc::new(redis))
         } else {
             None
         };
@@ -138,10 +246,19 @@ impl Server {
                 config.load_balancing.upstreams.clone(),
                 config.load_balancing.algorithm,
                 &config.load_balancing.circuit_breaker,
-            ).context("Failed to initialize load balancing")?;
+            ).context("Failed to initialize load balancing")?
+            .with_max_tries(config.load_balancing.max_tries)
+            .with_sticky(config.load_balancing.sticky.clone())
+            .with_hash_header(config.load_balancing.hash_header.clone())
+            .with_metrics(Arc::clone(&metrics));
 
             // Start health checks
-            lb.start_health_checks(config.load_balancing.health_check.clone()).await;
+            let health_checks = lb
+                .start_health_checks(config.load_balancing.health_check.clone(), shutdown_coordinator.subscribe())
+                .await;
+            if let Some(handle) = health_checks {
+                shutdown_coordinator.register("upstream health checks", handle);
+            }
 
             info!("Load balancing enabled with {} upstreams", config.load_balancing.upstreams.len());
             Some(Arc::new(lb))
@@ -160,27 +277,130 @@ impl Server {
                 config.deployment.variants.len()
             );
 
-            Some(Arc::new(dm))
+            let dm = Arc::new(dm);
+            if let Some(handle) = Arc::clone(&dm).start_background_tasks(shutdown_coordinator.subscribe()).await {
+                shutdown_coordinator.register("deployment", handle);
+            }
+            Some(dm)
         } else {
             None
         };
 
         // Initialize WAF if enabled
         let waf_engine = if config.waf.enable {
-            let rules = crate::waf::rules::default_rules();
-
-            let waf = crate::waf::WafEngine::new(
+            // An external rules file replaces or extends the built-in set
+            let rules = crate::waf::rules::load_ruleset(
+                config.waf.rules_path.as_deref(),
+                config.waf.rules_mode,
+            )
+            .context("Failed to load WAF rules")?;
+
+            let mut waf = crate::waf::WafEngine::new(
                 rules,
                 config.waf.mode.to_string(),
                 Arc::clone(&metrics),
+            )
+            .with_anomaly_threshold(config.waf.anomaly_threshold)
+            .with_redaction(config.logging.clone())
+            .with_body_inspection(
+                config.waf.max_body_inspect_bytes,
+                config.waf.body_exclude_content_types.clone(),
             );
 
+            if !config.waf.exclusions.is_empty() {
+                let exclusions = config
+                    .waf
+                    .exclusions
+                    .iter()
+                    .map(|exclusion| crate::waf::WafExclusion::from_config(
+                        exclusion,
+                        config.server.case_insensitive_paths,
+                    ))
+                    .collect::<Result<Vec<_>>>()
+                    .context("Invalid WAF exclusion")?;
+                waf = waf.with_exclusions(exclusions);
+            }
+
+            if let Some(ref path) = config.waf.rules_path {
+                waf = waf
+                    .with_rules_path(path.clone())
+                    .with_rules_mode(config.waf.rules_mode);
+            }
+
+            if let Some(threshold) = config.waf.anomaly_threshold {
+                info!("WAF anomaly scoring enabled with threshold {}", threshold);
+            }
+
             info!("WAF enabled in '{}' mode with {} rules", config.waf.mode, waf.rules_count());
             Some(Arc::new(waf))
         } else {
             None
         };
 
+        // Trusted clients that skip the WAF and IP blocking
+        let allowlist = if config.security.allowlist.is_empty() {
+            None
+        } else {
+            let filter = ip_filter::IpFilter::whitelist(config.security.allowlist.clone())
+                .map_err(|e| anyhow::anyhow!("Invalid security.allowlist: {}", e))?;
+            info!("Security allowlist enabled with {} entries", config.security.allowlist.len());
+            Some(Arc::new(filter))
+        };
+
+        // Proxies whose Forwarded / X-Forwarded-* headers are believed
+        let forwarded = if config.security.trusted_proxies.is_empty() && !config.security.trust_unix_socket_peers {
+            None
+        } else {
+            let resolver = forwarded::ForwardedResolver::new(&config.security.trusted_proxies)
+                .context("Invalid security.trusted_proxies")?
+                .with_trusted_unix_peers(config.security.trust_unix_socket_peers);
+            info!("Trusting forwarding headers from {} proxy entries", config.security.trusted_proxies.len());
+            Some(Arc::new(resolver))
+        };
+
+        let host_check = host_check::HostCheck::from_config(&config.server.host_check)?;
+
+        let path_policy = path_policy::PathPolicy::from_config(&config)
+            .context("Invalid security.denied_patterns")?;
+
+        if config.server.http1.max_pipelined == Some(0) {
+            anyhow::bail!("server.http1.max_pipelined cannot be 0");
+        }
+        if config.php.fpm_read_timeout_secs == 0 {
+            anyhow::bail!("php.fpm_read_timeout_secs cannot be 0");
+        }
+
+        let overload = overload::OverloadResponder::from_config(&config.server.overload)?;
+
+        let php_limit = match config.php.max_concurrent {
+            Some(0) => anyhow::bail!("php.max_concurrent cannot be 0"),
+            Some(max) => {
+                info!("Limiting concurrent PHP executions to {}", max);
+                Some(Arc::new(concurrency::PhpConcurrencyLimit::new(
+                    max,
+                    std::time::Duration::from_millis(config.php.max_concurrent_wait_ms),
+                ).with_overload(overload.clone())))
+            }
+            None => None,
+        };
+
+        let tls_handshake_limit = match config.tls.max_concurrent_handshakes {
+            Some(0) => anyhow::bail!("tls.max_concurrent_handshakes cannot be 0"),
+            Some(max) => {
+                info!("Limiting concurrent TLS handshakes to {}", max);
+                Some(Arc::new(concurrency::TlsHandshakeLimit::new(
+                    max,
+                    std::time::Duration::from_millis(config.tls.handshake_queue_wait_ms),
+                )))
+            }
+            None => None,
+        };
+
+        let maintenance = maintenance::MaintenanceMode::from_config(&config.maintenance)?;
+        if maintenance.is_enabled() {
+            warn!("Starting in maintenance mode");
+        }
+
         // Initialize hybrid backend system if enabled
         let backend_router = if config.backend.enable_hybrid {
             use crate::backend::{Backend, BackendType, embedded::EmbeddedBackend, fastcgi::FastCGIBackend, static_files::StaticBackend};
@@ -201,22 +421,58 @@ impl Server {
             }
 
             // Add FastCGI backend if FPM is configured
-            if config.php.use_fpm || !config.php.fpm_socket.is_empty() {
-                backends.insert(
-                    BackendType::FastCGI,
-                    Arc::new(FastCGIBackend::new(
-                        config.php.fpm_socket.clone(),
-                        config.php.document_root.clone(),
-                    )),
+            if !config.php.fpm_socket.is_empty() {
+                let fastcgi = Arc::new(FastCGIBackend::with_pool_config(
+                    config.php.fpm_socket.clone(),
+                    config.fastcgi_document_root().to_path_buf(),
+                    PoolConfig::from_config(&config.backend.connection_pool),
+                )
+                .with_metrics(config.backend.connection_pool.enable_metrics.then(|| Arc::clone(&metrics)))
+                .with_read_timeout(std::time::Duration::from_secs(config.php.fpm_read_timeout_secs))
+                .with_request_id_param(config.php.request_id_param())
+                .with_expose_errors(config.php.expose_fpm_errors)
+                .with_content_type(config.php.content_type.clone())
+                .with_retries(
+                    config.backend.fastcgi.max_retries,
+                    std::time::Duration::from_millis(config.backend.fastcgi.retry_backoff_ms),
+                    config.backend.fastcgi.retry_non_idempotent,
+                ));
+                backends.insert(BackendType::FastCGI, Arc::clone(&fastcgi) as Arc<dyn Backend>);
+                info!(
+                    "Registered FastCGI backend (PHP-FPM at {}, root: {})",
+                    config.php.fpm_socket,
+                    config.fastcgi_document_root().display()
                 );
-                info!("Registered FastCGI backend (PHP-FPM at {})", config.php.fpm_socket);
+
+                // Report FPM's limits for pool sizing without delaying startup
+                let fpm_socket = config.php.fpm_socket.clone();
+                let mut shutdown = shutdown_coordinator.subscribe();
+                shutdown_coordinator.register("PHP-FPM limits query", tokio::spawn(async move {
+                    tokio::select! {
+                        result = fastcgi.get_values() => match result {
+                            Ok(values) => info!("PHP-FPM at {} reports {}", fpm_socket, values),
+                            Err(e) => warn!("FCGI_GET_VALUES to PHP-FPM at {} failed: {}", fpm_socket, e),
+                        },
+                        _ = shutdown.recv() => {}
+                    }
+                }));
             }
 
             // Add static file backend if enabled
             if config.backend.static_files.enable {
                 if let Some(ref static_root) = config.backend.static_files.root {
+                    let range_disabled = config.backend.static_files.range_disabled_patterns
+                        .iter()
+                        .map(|p| crate::backend::PathPattern::from_config(p, false))
+                        .collect::<Result<Vec<_>>>()
+                        .context("Invalid backend.static_files.range_disabled_patterns")?;
                     let static_backend = StaticBackend::new(static_root.clone())
-                        .with_index_files(config.backend.static_files.index_files.clone());
+                        .with_index_files(config.backend.static_files.index_files.clone())
+                        .with_ranges(config.backend.static_files.max_ranges, range_disabled)
+                        .with_cache(
+                            config.backend.static_files.cache_entries,
+                            config.backend.static_files.cache_max_file_size,
+                        );
                     backends.insert(BackendType::Static, Arc::new(static_backend));
                     info!("Registered static file backend (root: {})", static_root.display());
                 } else {
@@ -224,6 +480,37 @@ impl Server {
                 }
             }
 
+            // Add the page cache in front of its origin
+            if config.backend.cache.enable {
+                use crate::backend::page_cache::{CacheBackend, DiskStore, PageStore, RedisStore};
+
+                let cache = &config.backend.cache;
+                let origin_type = cache.origin.parse::<BackendType>()
+                    .with_context(|| format!("Invalid page cache origin: {}", cache.origin))?;
+                let origin = backends.get(&origin_type)
+                    .filter(|_| origin_type != BackendType::Cache)
+                    .cloned()
+                    .with_context(|| format!("Page cache origin '{}' is not registered", origin_type))?;
+                let store: Arc<dyn PageStore> = match cache.storage {
+                    crate::config::PageCacheStorage::Disk => Arc::new(DiskStore::new(cache.dir.clone())?),
+                    crate::config::PageCacheStorage::Redis => Arc::new(RedisStore::new(
+                        cache.redis_url.as_deref().unwrap_or(&config.redis.url),
+                        cache.key_prefix.clone(),
+                        std::time::Duration::from_millis(config.redis.timeout_ms),
+                    )?),
+                };
+                backends.insert(
+                    BackendType::Cache,
+                    Arc::new(CacheBackend::new(
+                        origin,
+                        store,
+                        std::time::Duration::from_secs(cache.ttl_secs),
+                        std::time::Duration::from_secs(cache.stale_secs),
+                    ).with_bypass_cookies(cache.bypass_cookies.clone())),
+                );
+                info!("Registered page cache backend (origin: {}, storage: {:?})", origin_type, cache.storage);
+            }
+
             // Parse default backend type
             let default_backend = config.backend.default_backend.parse::<BackendType>()
                 .with_context(|| format!("Invalid default backend type: {}", config.backend.default_backend))?;
@@ -237,12 +524,26 @@ impl Server {
                 ));
             }
 
+            let unhealthy_fallback = config.backend.unhealthy_fallback
+                .as_deref()
+                .map(|name| {
+                    let backend_type = name.parse::<BackendType>()
+                        .with_context(|| format!("Invalid unhealthy fallback backend type: {}", name))?;
+                    if !backends.contains_key(&backend_type) {
+                        anyhow::bail!("Unhealthy fallback backend '{}' is not registered", backend_type);
+                    }
+                    Ok(backend_type)
+                })
+                .transpose()?;
+
             // Create backend router
             let router = crate::backend::router::BackendRouter::new(
                 backends,
                 config.backend.routing_rules.clone(),
                 default_backend,
-            )?;
+            )?
+            .with_unhealthy_policy(config.backend.skip_unhealthy, unhealthy_fallback)
+            .with_default_slo(config.backend.default_slo_ms.map(std::time::Duration::from_millis));
 
             info!(
                 "Backend router initialized with {} rules, default backend: {}",
@@ -250,7 +551,16 @@ impl Server {
                 default_backend
             );
 
-            Some(Arc::new(router))
+            let router = Arc::new(router);
+            if config.backend.health_check_interval_secs > 0 {
+                shutdown_coordinator.register("backend health checks", router.spawn_health_checks(
+                    Arc::clone(&metrics),
+                    std::time::Duration::from_secs(config.backend.health_check_interval_secs),
+                    shutdown_coordinator.subscribe(),
+                ));
+            }
+
+            Some(router)
         } else {
             None
         };
@@ -261,13 +571,22 @@ impl Server {
             backend_router,
             metrics,
             tls_manager,
+            acme_manager,
             geoip_manager,
             _redis_manager: redis_manager,
-            _load_balancer: load_balancer,
+            load_balancer,
             _deployment_manager: deployment_manager,
             waf_engine,
             shutdown_coordinator,
             ip_blocker: Arc::new(ip_blocker::IpBlocker::new()),
+            allowlist,
+            forwarded,
+            host_check: Arc::new(host_check),
+            path_policy: Arc::new(path_policy),
+            php_limit,
+            overload,
+            tls_handshake_limit,
+            maintenance: Arc::new(maintenance),
             admin_api: None,
         })
     }
@@ -282,37 +601,47 @@ impl Server {
         self.admin_api = Some(admin_api);
     }
 
+    /// Get a reference to the embedded PHP worker pool
+    pub fn worker_pool(&self) -> Arc<WorkerPool> {
+        Arc::clone(&self.worker_pool)
+    }
+
+    /// Get a reference to the runtime maintenance switch
+    pub fn maintenance(&self) -> Arc<maintenance::MaintenanceMode> {
+        Arc::clone(&self.maintenance)
+    }
+
     /// Get a reference to the IP blocker
     pub fn ip_blocker(&self) -> Arc<ip_blocker::IpBlocker> {
         Arc::clone(&self.ip_blocker)
     }
 
-    pub async fn serve(self) -> Result<()> {
-        match self.config.server.listen_type {
-            ListenType::Tcp => self.serve_tcp().await,
-            ListenType::Unix => self.serve_unix().await,
-        }
+    /// Load balancer, for draining upstreams at runtime
+    pub fn load_balancer(&self) -> Option<Arc<LoadBalancingManager>> {
+        self.load_balancer.clone()
     }
 
-    async fn serve_tcp(self) -> Result<()> {
-        let addr_str = format!("{}:{}", self.config.server.host, self.config.server.port);
-
-        // Resolve hostname to socket address (supports both IP addresses and hostnames like "localhost")
-        let addr: SocketAddr = addr_str.to_socket_addrs()
-            .with_context(|| format!("Failed to resolve address: '{}' (host: '{}', port: {})",
-                addr_str, self.config.server.host, self.config.server.port))?
-            .next()
-            .with_context(|| format!("No addresses resolved for: '{}'", addr_str))?;
-
-        let listener = TcpListener::bind(addr).await
-            .with_context(|| format!("Failed to bind to address: {}", addr))?;
+    /// WAF engine, for reloading its rules at runtime
+    pub fn waf_engine(&self) -> Option<Arc<crate::waf::WafEngine>> {
+        self.waf_engine.clone()
+    }
 
-        let protocol = if self.tls_manager.is_some() { "https" } else { "http" };
-        info!("Server listening on {}://{}", protocol, addr);
+    /// Configured `Server` header, if any
+    fn server_header(&self) -> Option<&str> {
+        Some(self.config.server.server_header.as_str()).filter(|s| !s.is_empty())
+    }
 
-        if self.config.server.enable_http2 {
-            info!("HTTP/2 support enabled");
+    /// Whether the client is on `security.allowlist`
+    fn is_allowlisted(&self, peer_addr: &PeerAddr) -> bool {
+        match (&self.allowlist, peer_addr.ip()) {
+            (Some(allowlist), Some(ip)) => allowlist.check(ip) == ip_filter::IpFilterDecision::Allow,
+            _ => false,
         }
+    }
+
+    pub async fn serve(self) -> Result<()> {
+        let listeners = self.bind_listeners().await?;
+        startup_summary::StartupSummary::new(&self, &listeners).log();
 
         let server = Arc::new(self);
 
@@ -321,12 +650,20 @@ impl Server {
             Arc::clone(&server.shutdown_coordinator)
         ));
 
-        // Spawn HTTP redirect server if TLS is enabled with http_redirect
-        if server.config.tls.enable && server.config.tls.http_redirect {
+        // Spawn HTTP redirect server if TLS is enabled with http_redirect; ACME needs
+        // it for HTTP-01 challenges
+        if server.config.tls.enable && (server.config.tls.http_redirect || server.acme_manager.is_some()) {
+            let https_port = server.config.server
+                .effective_listeners(true)
+                .iter()
+                .find(|l| l.tls && l.listen_type == ListenType::Tcp)
+                .map(|l| l.port)
+                .unwrap_or(server.config.server.port);
+
             let http_redirect_server = http_redirect::HttpRedirectServer::new(
                 server.config.tls.http_port,
-                server.config.server.port,
-            );
+                https_port,
+            ).with_acme_challenges(server.acme_manager.as_ref().map(|acme| acme.challenges()));
 
             tokio::spawn(async move {
                 if let Err(e) = http_redirect_server.serve().await {
@@ -335,220 +672,549 @@ impl Server {
             });
         }
 
-        // Create TLS acceptor if TLS is enabled
-        let tls_acceptor = server.tls_manager.as_ref().map(|tls| {
-            TlsAcceptor::from(tls.server_config())
-        });
-
-        // Get shutdown receiver
-        let mut shutdown_rx = server.shutdown_coordinator.subscribe();
+        // Started once the challenge server is up, since the first order may run right away
+        if let Some(ref acme) = server.acme_manager {
+            server.shutdown_coordinator.register("ACME renewal", Arc::clone(acme).spawn(
+                std::time::Duration::from_secs(server.config.tls.expiry_warning_days * 86_400),
+                Arc::clone(&server.metrics),
+                server.shutdown_coordinator.subscribe(),
+            ));
+        }
 
-        loop {
-            tokio::select! {
-                result = listener.accept() => {
-                    match result {
-                        Ok((stream, remote_addr)) => {
-                            let peer_addr = PeerAddr::from_tcp(remote_addr);
-
-                            // Check if shutdown has been initiated
-                            if server.shutdown_coordinator.is_shutting_down() {
-                                debug!("Rejecting new connection during shutdown from {}", peer_addr);
-                                continue;
-                            }
+        server.run_listeners(listeners).await;
 
-                            let server = Arc::clone(&server);
-                            let tls_acceptor = tls_acceptor.clone();
+        // Wait for signal handler to complete
+        let _ = shutdown_handle.await;
 
-                                    // Track connection
-                            server.shutdown_coordinator.inc_connections();
+        Ok(())
+    }
 
-                            tokio::spawn(async move {
-                                // Check IP blocker (dynamic runtime blocking)
-                                if let Some(ip) = peer_addr.ip() {
-                                    if server.ip_blocker.is_blocked(&ip) {
-                                        debug!("Blocked connection from {} - IP is in blocklist", peer_addr);
-                                        server.shutdown_coordinator.dec_connections();
-                                        return;
-                                    }
-                                }
+    /// Bind every configured listener before any of them starts accepting
+    async fn bind_listeners(&self) -> Result<Vec<BoundListener>> {
+        let configs = self.config.server.effective_listeners(self.config.tls.enable);
+        let mut bound = Vec::with_capacity(configs.len());
+
+        for listener in configs {
+            let http2 = listener.http2_enabled(self.config.server.enable_http2);
+            let tls_acceptor = if listener.tls {
+                let tls = self.tls_manager.as_ref()
+                    .with_context(|| format!("Listener {} requires TLS but [tls] is not enabled", listener))?;
+                Some(TlsAcceptor::from(tls.server_config_for(http2)))
+            } else {
+                None
+            };
+
+            match listener.listen_type {
+                ListenType::Tcp => {
+                    let addr_str = format!("{}:{}", listener.host, listener.port);
+
+                    // Resolve hostname to socket address (supports both IP addresses and hostnames like "localhost")
+                    let addr: SocketAddr = addr_str.to_socket_addrs()
+                        .with_context(|| format!("Failed to resolve address: '{}' (host: '{}', port: {})",
+                            addr_str, listener.host, listener.port))?
+                        .next()
+                        .with_context(|| format!("No addresses resolved for: '{}'", addr_str))?;
+
+                    let tcp = TcpListener::bind(addr).await
+                        .with_context(|| format!("Failed to bind to address: {}", addr))?;
+
+                    let protocol = if tls_acceptor.is_some() { "https" } else { "http" };
+                    info!(
+                        "Server listening on {}://{} ({})",
+                        protocol,
+                        tcp.local_addr().unwrap_or(addr),
+                        if http2 { "HTTP/2" } else { "HTTP/1.1" }
+                    );
 
-                                // Check GeoIP filtering
-                                if let Some(ref geoip) = server.geoip_manager {
-                                    if let Some(ip) = peer_addr.ip() {
-                                        match geoip.is_allowed(ip) {
-                                            Ok(false) => {
-                                                debug!("Blocked connection from {} due to GeoIP rules", peer_addr);
-                                                server.shutdown_coordinator.dec_connections();
-                                                return;
-                                            }
-                                            Err(e) => {
-                                                warn!("GeoIP check error for {}: {}", peer_addr, e);
-                                                // Continue on error to avoid blocking legitimate traffic
-                                            }
-                                            Ok(true) => {}
-                                        }
-                                    }
-                                }
+                    bound.push(BoundListener::Tcp { listener: tcp, tls_acceptor, http2 });
+                }
+                ListenType::Unix => {
+                    let socket_path = listener.unix_socket_path.clone()
+                        .context("Unix socket path not specified in configuration")?;
+
+                    // Remove existing socket file if it exists
+                    if socket_path.exists() {
+                        std::fs::remove_file(&socket_path)
+                            .with_context(|| format!("Failed to remove existing socket file: {:?}", socket_path))?;
+                    }
 
-                                // Handle TLS handshake if enabled
-                                if let Some(acceptor) = tls_acceptor {
-                                    match acceptor.accept(stream).await {
-                                        Ok(tls_stream) => {
-                                            let io = TokioIo::new(tls_stream);
-                                            server.serve_connection(io, peer_addr).await;
-                                        }
-                                        Err(e) => {
-                                            error!("TLS handshake failed for {}: {}", peer_addr, e);
-                                        }
-                                    }
-                                } else {
-                                    let io = TokioIo::new(stream);
-                                    server.serve_connection(io, peer_addr).await;
-                                }
+                    let unix = UnixListener::bind(&socket_path)
+                        .with_context(|| format!("Failed to bind to Unix socket: {:?}", socket_path))?;
+                    socket_options::apply_unix_permissions(&socket_path, &self.config.server)?;
 
-                                // Decrement connection counter when done
-                                server.shutdown_coordinator.dec_connections();
-                            });
-                        }
-                        Err(e) => {
-                            error!("Failed to accept connection: {}", e);
-                        }
-                    }
-                }
+                    info!(
+                        "Server listening on unix://{} ({})",
+                        socket_path.display(),
+                        if http2 { "HTTP/2" } else { "HTTP/1.1" }
+                    );
 
-                _ = shutdown_rx.recv() => {
-                    info!("Shutdown signal received, stopping listener");
-                    break;
+                    bound.push(BoundListener::Unix { listener: unix, path: socket_path, tls_acceptor, http2 });
                 }
             }
         }
 
-        // Wait for signal handler to complete
-        let _ = shutdown_handle.await;
-
-        Ok(())
+        Ok(bound)
     }
 
-    async fn serve_unix(self) -> Result<()> {
-        let socket_path = self.config.server.unix_socket_path.as_ref()
-            .context("Unix socket path not specified in configuration")?
-            .clone();
+    /// Run one accept loop per listener until shutdown, sharing this server's state
+    async fn run_listeners(self: &Arc<Self>, listeners: Vec<BoundListener>) {
+        let tasks: Vec<_> = listeners
+            .into_iter()
+            .map(|listener| tokio::spawn(Arc::clone(self).accept_loop(listener)))
+            .collect();
 
-        // Remove existing socket file if it exists
-        if socket_path.exists() {
-            std::fs::remove_file(&socket_path)
-                .with_context(|| format!("Failed to remove existing socket file: {:?}", socket_path))?;
+        for task in tasks {
+            if let Err(e) = task.await {
+                error!("Listener task failed: {}", e);
+            }
         }
+    }
 
-        let listener = UnixListener::bind(&socket_path)
-            .with_context(|| format!("Failed to bind to Unix socket: {:?}", socket_path))?;
+    async fn accept_loop(self: Arc<Self>, listener: BoundListener) {
+        // Get shutdown receiver
+        let mut shutdown_rx = self.shutdown_coordinator.subscribe();
+
+        match listener {
+            BoundListener::Tcp { listener, tls_acceptor, http2 } => loop {
+                tokio::select! {
+                    result = listener.accept() => {
+                        match result {
+                            Ok((stream, remote_addr)) => {
+                                socket_options::apply(&stream, &self.config.server);
+                                self.spawn_connection(stream, PeerAddr::from_tcp(remote_addr), tls_acceptor.clone(), http2);
+                            }
+                            Err(e) => {
+                                error!("Failed to accept connection: {}", e);
+                            }
+                        }
+                    }
 
-        info!("Server listening on unix://{}", socket_path.display());
+                    _ = shutdown_rx.recv() => {
+                        info!("Shutdown signal received, stopping listener");
+                        break;
+                    }
+                }
+            },
+            BoundListener::Unix { listener, path, tls_acceptor, http2 } => {
+                let socket_path_str = path.display().to_string();
+
+                loop {
+                    tokio::select! {
+                        result = listener.accept() => {
+                            match result {
+                                Ok((stream, _)) => {
+                                    self.spawn_connection(stream, PeerAddr::from_unix(&socket_path_str), tls_acceptor.clone(), http2);
+                                }
+                                Err(e) => {
+                                    error!("Failed to accept connection: {}", e);
+                                }
+                            }
+                        }
 
-        let socket_path_str = socket_path.display().to_string();
+                        _ = shutdown_rx.recv() => {
+                            info!("Shutdown signal received, stopping listener");
+                            break;
+                        }
+                    }
+                }
 
-        if self.config.server.enable_http2 {
-            info!("HTTP/2 support enabled");
+                // Clean up socket file
+                let _ = std::fs::remove_file(&path);
+            }
         }
+    }
 
-        let server = Arc::new(self);
+    /// Run the connection filters in `security.filter_order`, stopping at the first
+    /// rejection. Allowlisted clients skip them all.
+    fn admit_connection(&self, peer_addr: &PeerAddr) -> bool {
+        let Some(ip) = peer_addr.ip() else {
+            return true;
+        };
+        if self.is_allowlisted(peer_addr) {
+            return true;
+        }
 
-        // Spawn signal handler for graceful shutdown
-        let shutdown_handle = tokio::spawn(shutdown::setup_signal_handler(
-            Arc::clone(&server.shutdown_coordinator)
-        ));
+        for filter in &self.config.security.filter_order {
+            match filter {
+                ConnectionFilter::IpBlocker => {
+                    if self.ip_blocker.is_blocked(&ip) {
+                        debug!("Blocked connection from {} - IP is in blocklist", peer_addr);
+                        return false;
+                    }
+                }
+                ConnectionFilter::Geoip => {
+                    if let Some(ref geoip) = self.geoip_manager {
+                        if !geoip.check(ip, &self.metrics) {
+                            debug!("Blocked connection from {} due to GeoIP rules", peer_addr);
+                            return false;
+                        }
+                    }
+                }
+            }
+        }
 
-        // Get shutdown receiver
-        let mut shutdown_rx = server.shutdown_coordinator.subscribe();
+        true
+    }
 
-        loop {
-            tokio::select! {
-                result = listener.accept() => {
-                    match result {
-                        Ok((stream, _)) => {
-                            let peer_addr = PeerAddr::from_unix(&socket_path_str);
-
-                            // Check if shutdown has been initiated
-                            if server.shutdown_coordinator.is_shutting_down() {
-                                debug!("Rejecting new connection during shutdown from {}", peer_addr);
-                                continue;
-                            }
+    fn spawn_connection<S>(
+        self: &Arc<Self>,
+        stream: S,
+        peer_addr: PeerAddr,
+        tls_acceptor: Option<TlsAcceptor>,
+        http2: bool,
+    )
+    where
+        S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+    {
+        // Check if shutdown has been initiated
+        if self.shutdown_coordinator.is_shutting_down() {
+            debug!("Rejecting new connection during shutdown from {}", peer_addr);
+            return;
+        }
 
-                            let server = Arc::clone(&server);
+        let server = Arc::clone(self);
 
-                            // Track connection
-                            server.shutdown_coordinator.inc_connections();
+        // Track connection
+        server.shutdown_coordinator.inc_connections();
 
-                            tokio::spawn(async move {
-                                let io = TokioIo::new(stream);
-                                server.serve_connection(io, peer_addr).await;
+        tokio::spawn(async move {
+            if !server.admit_connection(&peer_addr) {
+                server.shutdown_coordinator.dec_connections();
+                return;
+            }
 
-                                // Decrement connection counter when done
-                                server.shutdown_coordinator.dec_connections();
-                            });
-                        }
-                        Err(e) => {
-                            error!("Failed to accept connection: {}", e);
+            // Handle TLS handshake if enabled
+            if let Some(acceptor) = tls_acceptor {
+                let handshake_slot = match server.tls_handshake_limit {
+                    Some(ref limit) => match limit.acquire().await {
+                        Some(permit) => Some(permit),
+                        None => {
+                            debug!("Dropping connection from {}: {} TLS handshakes in progress", peer_addr, limit.max());
+                            server.metrics.inc_tls_handshake_error("limit");
+                            server.shutdown_coordinator.dec_connections();
+                            return;
                         }
+                    },
+                    None => None,
+                };
+                let handshake_timeout = std::time::Duration::from_secs(server.config.tls.handshake_timeout_secs);
+                let handshake = crate::tls::accept(&acceptor, stream, &server.metrics, handshake_timeout).await;
+                drop(handshake_slot);
+                match handshake {
+                    Ok(tls_stream) => {
+                        let client_identity = crate::tls::client_identity(&tls_stream);
+                        let io = TokioIo::new(tls_stream);
+                        server.serve_connection(io, peer_addr, client_identity, http2, true).await;
+                    }
+                    Err(e) => {
+                        error!("TLS handshake failed for {}: {}", peer_addr, e);
                     }
                 }
-
-                _ = shutdown_rx.recv() => {
-                    info!("Shutdown signal received, stopping listener");
-                    break;
-                }
+            } else {
+                let io = TokioIo::new(stream);
+                server.serve_connection(io, peer_addr, None, http2, false).await;
             }
-        }
-
-        // Wait for signal handler to complete
-        let _ = shutdown_handle.await;
 
-        // Clean up socket file
-        let _ = std::fs::remove_file(&socket_path);
-
-        Ok(())
+            // Decrement connection counter when done
+            server.shutdown_coordinator.dec_connections();
+        });
     }
 
-    async fn serve_connection<I>(&self, io: I, peer_addr: PeerAddr)
+    /// Serve one connection as HTTP/2 (prior knowledge or ALPN `h2`) when `http2`
+    /// is set for its listener, otherwise as HTTP/1.1. `tls` marks connections that
+    /// completed a TLS handshake.
+    async fn serve_connection<I>(
+        &self,
+        io: I,
+        peer_addr: PeerAddr,
+        client_identity: Option<crate::tls::ClientIdentity>,
+        http2: bool,
+        tls: bool,
+    )
     where
         I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static,
     {
         let server = Arc::new(self.clone());
         let peer_addr_clone = peer_addr.clone();
 
-        let service = service_fn(move |req: Request<Incoming>| {
+        let http2_config = &self.config.server.http2;
+        let reset_tracker = http2.then(|| {
+            rapid_reset::ResetTracker::new(
+                http2_config.max_reset_streams,
+                std::time::Duration::from_secs(http2_config.reset_window_secs),
+            )
+        });
+        let service_tracker = reset_tracker.clone();
+
+        let pipeline_tracker = match self.config.server.http1.max_pipelined {
+            Some(max) if !http2 => Some(pipelining::PipelineTracker::new(max)),
+            _ => None,
+        };
+        let service_pipeline = pipeline_tracker.clone();
+
+        let write_chunking = if http2 { None } else { body::WriteChunking::from_config(&self.config.server) };
+        let service_chunking = write_chunking.clone();
+
+        let service = service_fn(move |mut req: Request<Incoming>| {
             let server = Arc::clone(&server);
             let peer_addr = peer_addr_clone.clone();
+            // Only a verified certificate may set the CN header PHP sees
+            req.headers_mut().remove(crate::php::CLIENT_CERT_CN_HEADER);
+            if let Some(ref identity) = client_identity {
+                let common_name = identity.common_name.as_deref()
+                    .and_then(|cn| hyper::header::HeaderValue::from_str(cn).ok());
+                if let Some(common_name) = common_name {
+                    req.headers_mut().insert(crate::php::CLIENT_CERT_CN_HEADER, common_name);
+                }
+                req.extensions_mut().insert(identity.clone());
+            }
+            // HTTP/2 drops this future when the client resets the stream
+            let stream_guard = service_tracker.as_ref().map(|tracker| tracker.guard());
+            let pipeline = service_pipeline.clone();
+            let chunking = service_chunking.clone();
             async move {
-                server.handle_request(req, peer_addr).await
+                let close = pipeline.as_ref().is_some_and(|pipeline| pipeline.start());
+                let result = server.handle_request_with_timeout(req, peer_addr, tls).await;
+                if let Some(guard) = stream_guard {
+                    guard.finish();
+                }
+                if let Some(pipeline) = pipeline {
+                    pipeline.finish();
+                }
+                let mut response = result?;
+                if let Some(chunking) = chunking {
+                    chunking.begin(response.body().len());
+                }
+                if close {
+                    response.headers_mut().insert(
+                        hyper::header::CONNECTION,
+                        hyper::header::HeaderValue::from_static("close"),
+                    );
+                }
+                headers::apply_standard_headers(response.headers_mut(), server.server_header());
+                if server.config.server.expose_version {
+                    response.headers_mut().insert(
+                        "x-fe-php-version",
+                        hyper::header::HeaderValue::from_static(crate::VERSION),
+                    );
+                }
+                Ok::<_, anyhow::Error>(response)
             }
         });
 
         // Use HTTP/2 if enabled, otherwise HTTP/1.1
-        if self.config.server.enable_http2 {
-            if let Err(err) = http2::Builder::new(hyper_util::rt::TokioExecutor::new())
-                .serve_connection(io, service)
-                .await
-            {
-                error!("Error serving HTTP/2 connection: {}", err);
+        if let Some(reset_tracker) = reset_tracker {
+            let conn = http2::Builder::new(hyper_util::rt::TokioExecutor::new())
+                .max_concurrent_streams(http2_config.max_concurrent_streams)
+                .serve_connection(io, service);
+
+            tokio::select! {
+                result = conn => {
+                    if let Err(err) = result {
+                        error!("Error serving HTTP/2 connection: {}", err);
+                    }
+                }
+                _ = reset_tracker.tripped() => {
+                    warn!(
+                        "Closing HTTP/2 connection from {}: more than {} streams reset within {}s",
+                        peer_addr, reset_tracker.limit(), http2_config.reset_window_secs
+                    );
+                }
             }
         } else {
-            if let Err(err) = http1::Builder::new()
-                .serve_connection(io, service)
-                .await
-            {
+            let io = body::ChunkedWrites::new(io, write_chunking);
+            let result = match pipeline_tracker {
+                Some(tracker) => http1::Builder::new().serve_connection(tracker.wrap(io), service).await,
+                None => http1::Builder::new().serve_connection(io, service).await,
+            };
+            if let Err(err) = result {
                 error!("Error serving HTTP/1.1 connection: {}", err);
             }
         }
     }
 
-    async fn handle_request(
+    /// Replace the peer with the client reported by a trusted proxy, and carry the
+    /// reported host and scheme into the `Host` and `X-Forwarded-Proto` headers.
+    /// Also returns the trusted scheme, if the proxy reported one.
+    fn apply_forwarded(&self, req: &mut Request<Incoming>, peer_addr: PeerAddr) -> (PeerAddr, Option<String>) {
+        let Some(resolver) = &self.forwarded else {
+            return (peer_addr, None);
+        };
+
+        let client = resolver.resolve_peer(&peer_addr, req.headers());
+        if let Some(host) = client.host.and_then(|h| h.parse().ok()) {
+            req.headers_mut().insert(hyper::header::HOST, host);
+        }
+        if let Some(scheme) = client.scheme.as_deref().and_then(|s| s.parse().ok()) {
+            req.headers_mut().insert("x-forwarded-proto", scheme);
+        }
+
+        let peer_addr = match client.ip {
+            Some(ip) => PeerAddr::from_tcp(SocketAddr::new(ip, 0)),
+            None => peer_addr,
+        };
+        (peer_addr, client.scheme)
+    }
+
+    /// Apply `server.request_timeout_ms` to the whole request, answering 504 when it elapses
+    async fn handle_request_with_timeout(
         &self,
-        req: Request<Incoming>,
+        mut req: Request<Incoming>,
         peer_addr: PeerAddr,
-    ) -> Result<Response<String>> {
+        tls: bool,
+    ) -> Result<Response<ResponseBody>> {
+        let (peer_addr, forwarded_scheme) = self.apply_forwarded(&mut req, peer_addr);
+        let timeout = self.config.server.request_timeout_ms.map(std::time::Duration::from_millis);
+        let mut ctx = RequestContext::new(&req, peer_addr, timeout);
+        ctx.secure = match forwarded_scheme {
+            Some(scheme) => scheme.eq_ignore_ascii_case("https"),
+            None => tls,
+        };
+
+        let Some(timeout) = timeout else {
+            let result = self.handle_request(req, ctx.clone()).await;
+            self.record_slo(&ctx, &result);
+            return result;
+        };
+
+        // Dropping the handler future on timeout releases its connection guard and
+        // abandons any pending worker response. A backend call already running on a
+        // blocking thread finishes on its own; backends stop at `ctx.deadline`.
+        let result = match tokio::time::timeout(timeout, self.handle_request(req, ctx.clone())).await {
+            Ok(result) => result,
+            Err(_) => {
+                warn!(
+                    request_id = %ctx.request_id,
+                    method = %ctx.method,
+                    uri = %redact_uri(&ctx.uri, &self.config.logging),
+                    timeout_ms = timeout.as_millis() as u64,
+                    "Request timed out"
+                );
+
+                self.metrics.record_request(&ctx.method, 504, ctx.elapsed().as_secs_f64());
+                self.metrics.inc_request_timeout(&ctx.method);
+
+                if let Some(ref api) = self.admin_api {
+                    let log_analyzer = api.log_analyzer();
+                    let mut analyzer = log_analyzer.write();
+                    analyzer.add_log(ctx.log_entry(504, &self.config.logging));
+                }
+
+                Ok(Response::builder()
+                    .status(504)
+                    .body("Gateway Timeout".into())?)
+            }
+        };
+        self.record_slo(&ctx, &result);
+        result
+    }
+
+    /// Count the request against the objective of its route, if routing assigned one.
+    /// Timeouts, server errors and overload or slow-upload rejections breach it however
+    /// quickly they were answered.
+    fn record_slo(&self, ctx: &RequestContext, result: &Result<Response<ResponseBody>>) {
+        let Some((label, threshold)) = ctx.slo.get() else {
+            return;
+        };
+        let failed = match result {
+            Ok(response) => {
+                let status = response.status();
+                status.is_server_error()
+                    || status == hyper::StatusCode::REQUEST_TIMEOUT
+                    || status == hyper::StatusCode::TOO_MANY_REQUESTS
+            }
+            Err(_) => true,
+        };
+        self.metrics.record_slo(label, failed || ctx.elapsed() > *threshold);
+    }
+
+    async fn handle_request(
+        &self,
+        mut req: Request<Incoming>,
+        mut ctx: RequestContext,
+    ) -> Result<Response<ResponseBody>> {
+        let peer_addr = &ctx.peer_addr;
+
+        if !self.config.server.method_allowed(req.method().as_str()) {
+            debug!("Rejected {} request from {}", req.method(), peer_addr);
+            return Ok(Response::builder()
+                .status(405)
+                .header(hyper::header::ALLOW, self.config.server.allowed_methods.join(", ").to_ascii_uppercase())
+                .body("Method Not Allowed".into())?);
+        }
+
+        let allowlisted = self.is_allowlisted(peer_addr);
+        let path = req.uri().path();
+        let is_probe = path == "/_health"
+            || (self.config.metrics.enable && path == self.config.metrics.endpoint);
+
+        // Maintenance mode spares allowlisted clients, health probes and metrics scrapes
+        if self.maintenance.is_enabled() && !allowlisted && !is_probe {
+            return self.maintenance.response();
+        }
+
+        // Probes are often addressed by IP, so they skip host checking too
+        if !is_probe && self.host_check.apply(&mut req) == host_check::HostDecision::Reject {
+            debug!("Rejected request from {} with unexpected Host {:?}", peer_addr, req.headers().get(hyper::header::HOST));
+            return Ok(Response::builder()
+                .status(400)
+                .body("Bad Request: unknown host".into())?);
+        }
+
+        if !is_probe && !ctx.secure {
+            if let Some(response) = http_redirect::enforce_https(self.config.server.require_https, &req)? {
+                debug!("Refused plaintext request for {} from {}", req.uri().path(), peer_addr);
+                return Ok(response);
+            }
+        }
+
+        // Path checks run before routing and apply to every client
+        let decoded_path = match self.path_policy.decode(req.uri().path()) {
+            Ok(path) => path,
+            Err(e) => {
+                warn!("Rejected request for {} from {}: {}", req.uri().path(), peer_addr, e);
+                return Ok(Response::builder()
+                    .status(400)
+                    .body("Bad Request".into())?);
+            }
+        };
+
+        if self.path_policy.is_denied(&decoded_path) {
+            warn!("Denied request for {} from {}", req.uri().path(), peer_addr);
+            return Ok(Response::builder()
+                .status(403)
+                .body("Forbidden".into())?);
+        }
+
+        if self.path_policy.requires_client_cert(&decoded_path)
+            && req.extensions().get::<crate::tls::ClientIdentity>().is_none()
+        {
+            debug!("Rejected request for {} from {} without a client certificate", req.uri().path(), peer_addr);
+            return Ok(Response::builder()
+                .status(403)
+                .body("Forbidden: client certificate required".into())?);
+        }
+
+        if let Some(location) = self.path_policy.redirect_target(req.uri()) {
+            return Ok(Response::builder()
+                .status(301)
+                .header(hyper::header::LOCATION, location)
+                .body(ResponseBody::default())?);
+        }
+
+        if self.config.geoip.inject_headers {
+            if let (Some(geoip), Some(ip)) = (&self.geoip_manager, ctx.peer_addr.ip()) {
+                ctx.location = geoip.lookup_location(ip).ok().flatten();
+            }
+        }
+
+        // Allowlisted clients skip the WAF but are still routed and logged as usual
+        if allowlisted && self.waf_engine.is_some() {
+            debug!("Skipping WAF for allowlisted client {}", peer_addr);
+        }
+
         // Check WAF if enabled
-        if let Some(ref waf) = self.waf_engine {
+        if let Some(waf) = self.waf_engine.as_ref().filter(|_| !allowlisted) {
 
             use http_body_util::BodyExt;
 
@@ -568,54 +1234,86 @@ impl Server {
                 }
             }
 
-            // Collect body (for POST requests)
-            let body_bytes = body.collect().await
-                .map(|collected| collected.to_bytes())
-                .unwrap_or_default();
+            // Collect body (for POST requests). The whole body is buffered here, so
+            // with the WAF on chunked uploads never take the spooled FastCGI path
+            let body_bytes = match self.read_body_timed(&ctx, body.collect()).await {
+                Ok(collected) => collected.map(|collected| collected.to_bytes()).unwrap_or_default(),
+                Err(response) => return Ok(response),
+            };
+
+            // Exclusions match the strictly decoded path; one that only got this far
+            // through `allow_malformed_paths` matches none of them
+            let exclusion_path = crate::utils::decode_path(parts.uri.path()).ok();
 
             // Check request against WAF rules
-            match waf.check_request(method, &uri, query_string, &headers_map, &body_bytes) {
+            let verdict = waf.inspect_path(
+                exclusion_path.as_deref(),
+                method,
+                &uri,
+                query_string,
+                &headers_map,
+                &body_bytes,
+            );
+
+            let mut response = match verdict.result {
                 crate::waf::WafResult::Block(rule) => {
                     warn!("WAF blocked request from {}: rule {} - {}", peer_addr, rule.id, rule.description);
-                    return Ok(Response::builder()
+                    Response::builder()
                         .status(403)
-                        .body("Forbidden: Request blocked by WAF".to_string())
-                        .unwrap());
+                        .body("Forbidden: Request blocked by WAF".into())
+                        .unwrap()
                 }
-                crate::waf::WafResult::Allow => {
+                crate::waf::WafResult::Allow | crate::waf::WafResult::Detected(_) => {
                     // Reconstruct request from parts and body
                     let req = Request::from_parts(parts, http_body_util::Full::new(body_bytes));
 
-                    // Use hybrid backend router if enabled
-                    if let Some(ref backend_router) = self.backend_router {
-                        return self.handle_with_backend_router(req, peer_addr, backend_router).await;
+                    if let Some(load_balancer) = self.upstream_for(&ctx.uri) {
+                        self.proxy_to_upstream(req, ctx, load_balancer).await?
+                    } else if let Some(ref backend_router) = self.backend_router {
+                        // Use hybrid backend router if enabled
+                        self.handle_with_backend_router(req, ctx, backend_router).await?
+                    } else {
+                        router::handle_request(
+                            req,
+                            ctx,
+                            Arc::clone(&self.worker_pool),
+                            Arc::clone(&self.metrics),
+                            Arc::clone(&self.config),
+                            self.admin_api.clone(),
+                            self.php_limit.clone(),
+                        )
+                        .await?
                     }
-
-                    return router::handle_request(
-                        req,
-                        peer_addr,
-                        Arc::clone(&self.worker_pool),
-                        Arc::clone(&self.metrics),
-                        Arc::clone(&self.config),
-                        self.admin_api.clone(),
-                    )
-                    .await;
                 }
+            };
+
+            if self.config.waf.debug_headers {
+                response.headers_mut().insert(
+                    "X-WAF-Anomaly-Score",
+                    hyper::header::HeaderValue::from(verdict.anomaly_score),
+                );
             }
+
+            return Ok(response);
+        }
+
+        if let Some(load_balancer) = self.upstream_for(&ctx.uri) {
+            return self.proxy_to_upstream(req, ctx, load_balancer).await;
         }
 
         // Use hybrid backend router if enabled
         if let Some(ref backend_router) = self.backend_router {
-            return self.handle_with_backend_router(req, peer_addr, backend_router).await;
+            return self.handle_with_backend_router(req, ctx, backend_router).await;
         }
 
         router::handle_request(
             req,
-            peer_addr,
+            ctx,
             Arc::clone(&self.worker_pool),
             Arc::clone(&self.metrics),
             Arc::clone(&self.config),
             self.admin_api.clone(),
+            self.php_limit.clone(),
         )
         .await
     }
@@ -623,9 +1321,9 @@ impl Server {
     async fn handle_with_backend_router<B>(
         &self,
         req: Request<B>,
-        peer_addr: PeerAddr,
-        backend_router: &crate::backend::router::BackendRouter,
-    ) -> Result<Response<String>>
+        mut ctx: RequestContext,
+        backend_router: &Arc<crate::backend::router::BackendRouter>,
+    ) -> Result<Response<ResponseBody>>
     where
         B: hyper::body::Body + Send + 'static,
         B::Data: Send,
@@ -634,106 +1332,197 @@ impl Server {
         use http_body_util::BodyExt;
         use std::collections::HashMap;
 
-        let start = std::time::Instant::now();
-        let method = req.method().to_string();
-        let uri = req.uri().to_string();
+        let method = ctx.method.clone();
+        let uri = ctx.uri.clone();
 
-        self.metrics.inc_active_connections();
+        let _active = self.metrics.active_connection_guard();
 
         // Handle metrics endpoint
         if self.config.metrics.enable && uri == self.config.metrics.endpoint {
-            self.metrics.dec_active_connections();
-            let metrics_output = crate::metrics::export_metrics()?;
+            let accept = req.headers().get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok());
+            let (metrics_output, content_type) =
+                crate::metrics::export_registry(&self.metrics.registry(), accept)?;
             return Ok(Response::builder()
                 .status(200)
-                .header("Content-Type", "text/plain; version=0.0.4")
-                .body(metrics_output)?);
+                .header("Content-Type", content_type)
+                .body(metrics_output.into())?);
         }
 
         // Handle health check (enhanced with backend status)
         if uri == "/_health" {
-            self.metrics.dec_active_connections();
             return self.handle_health_check(backend_router).await;
         }
 
         // Convert Hyper request to PhpRequest
         let (parts, body) = req.into_parts();
-
-        let body_bytes = match body.collect().await {
-            Ok(collected) => {
-                let bytes = collected.to_bytes();
-                // Check body size limit
-                if bytes.len() > crate::utils::MAX_BODY_SIZE {
-                    error!("Request body too large: {} bytes", bytes.len());
-                    self.metrics.dec_active_connections();
-                    return Ok(Response::builder()
-                        .status(413)
-                        .body("Request body too large".to_string())?);
-                }
-                bytes.to_vec()
-            }
-            Err(e) => {
-                error!("Failed to read request body: {}", e);
-                self.metrics.dec_active_connections();
-                return Ok(Response::builder()
-                    .status(400)
-                    .body(format!("Bad Request: {}", e))?);
-            }
-        };
+        let body_limit = backend_router.body_limit(&uri);
 
         let headers = parse_headers(&parts.headers);
 
         let query_string = parts.uri.query().unwrap_or("").to_string();
 
-        let php_request = crate::php::PhpRequest {
+        let mut php_request = crate::php::PhpRequest {
             method: method.clone(),
             uri: uri.clone(),
             headers,
-            body: body_bytes,
+            body: Vec::new(),
             query_string,
-            remote_addr: peer_addr.to_string(),
+            remote_addr: ctx.peer_addr.to_string(),
+        };
+        // Replaces any client-sent X-Request-ID so PHP logs match ours
+        php_request.headers.insert(crate::php::REQUEST_ID_HEADER.to_string(), ctx.request_id.clone());
+        if self.config.geoip.inject_headers {
+            headers::insert_geoip_headers(&mut php_request.headers, ctx.location.as_ref());
+        }
+
+        let is_chunked = parts.headers
+            .get(hyper::header::TRANSFER_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.to_ascii_lowercase().contains("chunked"));
+        let backend = match backend_router.route(&uri) {
+            Ok(route) => {
+                if self.config.logging.log_routing {
+                    ctx.routing_rule = Some(route.describe());
+                }
+                if let Some(threshold) = route.slo {
+                    let _ = ctx.slo.set((route.label().to_string(), threshold));
+                }
+                route.backend
+            }
+            Err(e) => return self.backend_unavailable(&ctx, &e),
+        };
+
+        let result = match backend.as_fastcgi().filter(|_| is_chunked) {
+            // Chunked uploads are spooled, in memory or to a temporary file, instead
+            // of being collected, and sent to php-fpm with their length
+            Some(fastcgi) => {
+                crate::php::method_override::apply(&mut php_request, &self.config.php.method_override);
+                let backend_start = std::time::Instant::now();
+                let result = match self.read_body_timed(&ctx, fastcgi.spool_body(Box::pin(body), body_limit)).await {
+                    Ok(Ok(spooled)) => {
+                        let php_permit = match self.acquire_php_permit(&ctx, &backend).await {
+                            Ok(permit) => permit,
+                            Err(response) => return response,
+                        };
+                        let result = fastcgi.execute_spooled(&php_request, spooled).await;
+                        drop(php_permit);
+                        result
+                    }
+                    Ok(Err(e)) => Err(e),
+                    Err(response) => return Ok(response),
+                };
+                crate::backend::router::BackendRouter::record_metrics(
+                    &self.metrics,
+                    &backend.backend_type().to_string(),
+                    backend_start.elapsed().as_secs_f64(),
+                    &result,
+                );
+                result
+            }
+            None => {
+                let collected = match self.read_body_timed(&ctx, body.collect()).await {
+                    Ok(collected) => collected,
+                    Err(response) => return Ok(response),
+                };
+
+                php_request.body = match collected {
+                    Ok(collected) => {
+                        let bytes = collected.to_bytes();
+                        // Check body size limit
+                        if bytes.len() > body_limit {
+                            error!("Request body too large: {} bytes", bytes.len());
+                            return Ok(Response::builder()
+                                .status(413)
+                                .body("Request body too large".into())?);
+                        }
+                        bytes.to_vec()
+                    }
+                    Err(e) => {
+                        error!("Failed to read request body: {}", e);
+                        return Ok(Response::builder()
+                            .status(400)
+                            .body(format!("Bad Request: {}", e).into())?);
+                    }
+                };
+                crate::php::method_override::apply(&mut php_request, &self.config.php.method_override);
+
+                let php_permit = match self.acquire_php_permit(&ctx, &backend).await {
+                    Ok(permit) => permit,
+                    Err(response) => return response,
+                };
+
+                // Execute on appropriate backend with metrics. Backends block, so run them off the
+                // async workers; this also lets the request timeout fire while a backend is busy.
+                // A blocking call cannot be cancelled, so the execution slot stays taken until
+                // the backend returns, even after the client got its 504.
+                let router = Arc::clone(backend_router);
+                let metrics = Arc::clone(&self.metrics);
+                let deadline = ctx.deadline;
+                tokio::task::spawn_blocking(move || {
+                    let result = router.execute_with_metrics(php_request, deadline, Some(&metrics));
+                    drop(php_permit);
+                    result
+                })
+                .await
+                .context("Backend task failed")?
+            }
         };
 
-        // Execute on appropriate backend with metrics
-        let php_response = match backend_router.execute_with_metrics(php_request, Some(&self.metrics)) {
+        let mut php_response = match result {
             Ok(response) => response,
+            Err(crate::backend::BackendError::BodyTooLarge(limit)) => {
+                error!("Request body too large: exceeds {} bytes", limit);
+                self.metrics.record_request(&method, 413, ctx.elapsed().as_secs_f64());
+                return Ok(Response::builder()
+                    .status(413)
+                    .body("Request body too large".into())?);
+            }
+            Err(e @ crate::backend::BackendError::Unavailable(_)) => {
+                return self.backend_unavailable(&ctx, &e);
+            }
+            Err(crate::backend::BackendError::Overloaded(msg)) => {
+                warn!("Backend refused {} {}: {}", method, redact_uri(&uri, &self.config.logging), msg);
+                self.metrics.record_request(&method, self.overload.status(), ctx.elapsed().as_secs_f64());
+                return self.overload.response();
+            }
             Err(e) => {
-                error!("Backend execution failed: {}", e);
-                self.metrics.dec_active_connections();
+                self.metrics.record_request(&method, 500, ctx.elapsed().as_secs_f64());
 
-                let duration = start.elapsed().as_secs_f64();
-                let duration_ms = (duration * 1000.0) as u64;
-                self.metrics.record_request(&method, 500, duration);
+                let log = ctx.log_entry(500, &self.config.logging);
+                let request_id = ctx.request_id.clone();
+                error!(request_id = %request_id, rule = ctx.routing_rule.as_deref(), "Backend execution failed: {}", e);
 
                 // Send error log to LogAnalyzer
                 if let Some(ref api) = self.admin_api {
                     let log_analyzer = api.log_analyzer();
                     let mut analyzer = log_analyzer.write();
-                    analyzer.add_log(crate::logging::structured::RequestLog::new(
-                        method.clone(),
-                        uri.clone(),
-                        500,
-                        duration_ms,
-                        peer_addr.to_string(),
-                    ));
+                    analyzer.add_log(log);
                 }
 
+                // Panic details are only written to the log
+                let body = match e {
+                    crate::backend::BackendError::Panic(_) => "Internal Server Error".to_string(),
+                    e => format!("Internal Server Error: {}", e),
+                };
                 return Ok(Response::builder()
                     .status(500)
-                    .body(format!("Internal Server Error: {}", e))?);
+                    .header("X-Request-ID", request_id)
+                    .body(body.into())?);
             }
         };
 
-        let duration = start.elapsed().as_secs_f64();
-        let duration_ms = (duration * 1000.0) as u64;
-        self.metrics.record_request(&method, php_response.status_code, duration);
-        self.metrics.dec_active_connections();
+        headers::filter_php_headers(&mut php_response, &self.config.php.response_header_policy, &ctx.request_id);
+        headers::limit_php_headers(&mut php_response, &self.config.php.response_headers, &ctx.request_id);
+
+        self.metrics.record_request(&method, php_response.status_code, ctx.elapsed().as_secs_f64());
 
         info!(
+            request_id = %ctx.request_id,
             method = %method,
-            uri = %uri,
+            uri = %redact_uri(&uri, &self.config.logging),
             status = php_response.status_code,
             duration_ms = php_response.execution_time_ms,
+            rule = ctx.routing_rule.as_deref(),
             "Request completed"
         );
 
@@ -741,15 +1530,11 @@ impl Server {
         if let Some(ref api) = self.admin_api {
             let log_analyzer = api.log_analyzer();
             let mut analyzer = log_analyzer.write();
-            analyzer.add_log(crate::logging::structured::RequestLog::new(
-                method.clone(),
-                uri.clone(),
-                php_response.status_code,
-                duration_ms,
-                peer_addr.to_string(),
-            ));
+            analyzer.add_log(ctx.log_entry(php_response.status_code, &self.config.logging));
         }
 
+        php_response.headers.apply_duplicate_policy(self.config.php.duplicate_headers);
+
         // Build response
         let mut response = Response::builder().status(php_response.status_code);
 
@@ -757,20 +1542,186 @@ impl Server {
             response = response.header(name, value);
         }
 
-        Ok(response.body(String::from_utf8_lossy(&php_response.body).to_string())?)
+        Ok(response.body(php_response.body.into())?)
+    }
+
+    /// Slot in `php.max_concurrent`, taken once the request body has been read so
+    /// slow uploads don't hold one. Static files don't count against the limit.
+    async fn acquire_php_permit(
+        &self,
+        ctx: &RequestContext,
+        backend: &Arc<dyn crate::backend::Backend>,
+    ) -> Result<Option<tokio::sync::OwnedSemaphorePermit>, Result<Response<ResponseBody>>> {
+        let Some(limit) = self.php_limit.as_ref() else {
+            return Ok(None);
+        };
+        if backend.backend_type() == crate::backend::BackendType::Static {
+            return Ok(None);
+        }
+        match limit.acquire().await {
+            Some(permit) => Ok(Some(permit)),
+            None => {
+                self.metrics.record_request(&ctx.method, limit.saturated_status(), ctx.elapsed().as_secs_f64());
+                Err(limit.saturated_response(&redact_uri(&ctx.uri, &self.config.logging)))
+            }
+        }
+    }
+
+    /// Load balancer for requests that no routing rule sends to a local backend.
+    /// Health and metrics endpoints are always answered locally.
+    fn upstream_for(&self, uri: &str) -> Option<&Arc<LoadBalancingManager>> {
+        let load_balancer = self.load_balancer.as_ref()?;
+        let path = uri.split('?').next().unwrap_or(uri);
+        if path == "/_health" || (self.config.metrics.enable && path == self.config.metrics.endpoint) {
+            return None;
+        }
+        if self.backend_router.as_ref().is_some_and(|router| router.has_rule(path)) {
+            return None;
+        }
+        Some(load_balancer)
+    }
+
+    /// Forward a request to an upstream through the load balancer: 503 when no
+    /// upstream is available, 502 when the chosen upstreams all failed
+    async fn proxy_to_upstream<B>(
+        &self,
+        req: Request<B>,
+        ctx: RequestContext,
+        load_balancer: &Arc<LoadBalancingManager>,
+    ) -> Result<Response<ResponseBody>>
+    where
+        B: hyper::body::Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
+    {
+        let _active = self.metrics.active_connection_guard();
+        let method = ctx.method.clone();
+
+        let (parts, body) = req.into_parts();
+        let collected = self.read_body_timed(&ctx, http_body_util::Limited::new(body, crate::utils::MAX_BODY_SIZE).collect());
+        let body = match collected.await {
+            Err(response) => return Ok(response),
+            Ok(Ok(collected)) => collected.to_bytes().to_vec(),
+            Ok(Err(e)) if e.is::<http_body_util::LengthLimitError>() => {
+                self.metrics.record_request(&method, 413, ctx.elapsed().as_secs_f64());
+                return Ok(Response::builder()
+                    .status(413)
+                    .body("Request body too large".into())?);
+            }
+            Ok(Err(e)) => {
+                error!("Failed to read request body: {}", e);
+                return Ok(Response::builder()
+                    .status(400)
+                    .body(format!("Bad Request: {}", e).into())?);
+            }
+        };
+
+        let mut php_request = crate::php::PhpRequest {
+            method: method.clone(),
+            uri: ctx.uri.clone(),
+            headers: parse_headers(&parts.headers),
+            body,
+            query_string: parts.uri.query().unwrap_or("").to_string(),
+            remote_addr: ctx.peer_addr.to_string(),
+        };
+        php_request.headers.insert(crate::php::REQUEST_ID_HEADER.to_string(), ctx.request_id.clone());
+        if self.config.geoip.inject_headers {
+            headers::insert_geoip_headers(&mut php_request.headers, ctx.location.as_ref());
+        }
+
+        let scheme = if ctx.secure { "https" } else { "http" };
+        let upstream_response = match load_balancer.proxy_request(php_request, ctx.deadline, scheme).await {
+            Ok(response) => response,
+            Err(e) => {
+                let status = if e.downcast_ref::<crate::load_balancing::NoUpstream>().is_some() {
+                    503
+                } else if e.downcast_ref::<reqwest::Error>().is_some_and(|e| e.is_timeout()) {
+                    504
+                } else {
+                    502
+                };
+                warn!(
+                    request_id = %ctx.request_id,
+                    "Proxying {} {} failed: {}",
+                    method,
+                    redact_uri(&ctx.uri, &self.config.logging),
+                    e
+                );
+                self.metrics.record_request(&method, status, ctx.elapsed().as_secs_f64());
+                let body = match status {
+                    503 => "Service Unavailable",
+                    504 => "Gateway Timeout",
+                    _ => "Bad Gateway",
+                };
+                return Ok(Response::builder()
+                    .status(status)
+                    .header("X-Request-ID", ctx.request_id.clone())
+                    .body(body.into())?);
+            }
+        };
+
+        self.metrics.record_request(&method, upstream_response.status_code, ctx.elapsed().as_secs_f64());
+        info!(
+            request_id = %ctx.request_id,
+            method = %method,
+            uri = %redact_uri(&ctx.uri, &self.config.logging),
+            status = upstream_response.status_code,
+            duration_ms = upstream_response.execution_time_ms,
+            "Request completed"
+        );
+        if let Some(ref api) = self.admin_api {
+            let log_analyzer = api.log_analyzer();
+            let mut analyzer = log_analyzer.write();
+            analyzer.add_log(ctx.log_entry(upstream_response.status_code, &self.config.logging));
+        }
+
+        let mut response = Response::builder().status(upstream_response.status_code);
+        for (name, value) in upstream_response.headers.iter() {
+            response = response.header(name, value);
+        }
+        Ok(response.body(upstream_response.body.into())?)
+    }
+
+    /// Await a read of the request body, bounded by `server.body_read_timeout_ms`.
+    /// See [`read_body_timed`].
+    async fn read_body_timed<F>(&self, ctx: &RequestContext, read: F) -> Result<F::Output, Response<ResponseBody>>
+    where
+        F: std::future::Future,
+    {
+        read_body_timed(&self.config, &self.metrics, ctx, read).await
+    }
+
+    /// 503 for a request whose backend failed its last health check
+    fn backend_unavailable(
+        &self,
+        ctx: &RequestContext,
+        error: &crate::backend::BackendError,
+    ) -> Result<Response<ResponseBody>> {
+        warn!("Not routing {} {}: {}", ctx.method, redact_uri(&ctx.uri, &self.config.logging), error);
+        self.metrics.record_request(&ctx.method, 503, ctx.elapsed().as_secs_f64());
+        Ok(Response::builder()
+            .status(503)
+            .body("Service Unavailable".into())?)
     }
 
     async fn handle_health_check(
         &self,
-        backend_router: &crate::backend::router::BackendRouter,
-    ) -> Result<Response<String>> {
+        backend_router: &Arc<crate::backend::router::BackendRouter>,
+    ) -> Result<Response<ResponseBody>> {
         use serde_json::json;
 
         let mut backend_statuses = serde_json::Map::new();
         let mut all_healthy = true;
 
-        for (backend_type, backend) in backend_router.backends() {
-            match backend.health_check() {
+        // Health checks may block on backend I/O
+        let router = Arc::clone(backend_router);
+        let metrics = Arc::clone(&self.metrics);
+        let results = tokio::task::spawn_blocking(move || router.check_health(Some(&metrics)))
+            .await
+            .context("Health check task failed")?;
+
+        for (backend_type, result) in results {
+            match result {
                 Ok(status) => {
                     backend_statuses.insert(
                         backend_type.to_string(),
@@ -807,6 +1758,1545 @@ impl Server {
         Ok(Response::builder()
             .status(status_code)
             .header("Content-Type", "application/json")
-            .body(response_body.to_string())?)
+            .body(response_body.to_string().into())?)
+    }
+}
+
+/// Await a read of the request body, bounded by `server.body_read_timeout_ms`.
+/// When the time runs out, the timeout is logged and counted and the `408`
+/// to send is returned instead.
+pub(crate) async fn read_body_timed<F>(
+    config: &Config,
+    metrics: &MetricsCollector,
+    ctx: &RequestContext,
+    read: F,
+) -> Result<F::Output, Response<ResponseBody>>
+where
+    F: std::future::Future,
+{
+    let Some(timeout_ms) = config.server.body_read_timeout_ms else {
+        return Ok(read.await);
+    };
+
+    match tokio::time::timeout(std::time::Duration::from_millis(timeout_ms), read).await {
+        Ok(output) => Ok(output),
+        Err(_) => {
+            warn!(
+                request_id = %ctx.request_id,
+                method = %ctx.method,
+                uri = %redact_uri(&ctx.uri, &config.logging),
+                timeout_ms = timeout_ms,
+                "Request body read timed out"
+            );
+            metrics.inc_request_body_timeout(&ctx.method);
+            metrics.record_request(&ctx.method, 408, ctx.elapsed().as_secs_f64());
+
+            let mut response = Response::new(ResponseBody::from("Request Timeout"));
+            *response.status_mut() = hyper::StatusCode::REQUEST_TIMEOUT;
+            response.headers_mut().insert(hyper::header::CONNECTION, hyper::header::HeaderValue::from_static("close"));
+            Err(response)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::php::fastcgi::mock::{self as fastcgi_mock, MockFpm, Reply};
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    fn static_config(root: &std::path::Path, extra_server: &str) -> Config {
+        let content = format!(r#"
+[server]
+host = "127.0.0.1"
+port = 0
+workers = 1
+{extra_server}
+
+[php]
+libphp_path = "/nonexistent/libphp.so"
+document_root = "{root}"
+use_fpm = true
+fpm_socket = ""
+
+[logging]
+level = "info"
+
+[metrics]
+enable = false
+
+[backend]
+enable_hybrid = true
+default_backend = "static"
+
+[backend.static_files]
+enable = true
+root = "{root}"
+"#, root = root.display());
+
+        toml::from_str(&content).unwrap()
+    }
+
+    async fn get<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, path: &str) -> String {
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    /// Bind the configured TCP listener and serve it in the background
+    async fn start(server: Server) -> SocketAddr {
+        let bound = server.bind_listeners().await.unwrap();
+        let addr = match &bound[0] {
+            BoundListener::Tcp { listener, .. } => listener.local_addr().unwrap(),
+            BoundListener::Unix { .. } => panic!("expected TCP listener"),
+        };
+
+        let server = Arc::new(server);
+        tokio::spawn(async move { server.run_listeners(bound).await });
+        addr
+    }
+
+    struct SlowBackend {
+        delay: std::time::Duration,
+    }
+
+    impl crate::backend::Backend for SlowBackend {
+        fn execute(&self, _request: crate::php::PhpRequest) -> Result<crate::php::PhpResponse, crate::backend::BackendError> {
+            std::thread::sleep(self.delay);
+            Ok(crate::php::PhpResponse {
+                status_code: 200,
+                headers: Default::default(),
+                body: b"slow".to_vec(),
+                execution_time_ms: self.delay.as_millis() as u64,
+                memory_peak_mb: 0.0,
+            })
+        }
+
+        fn health_check(&self) -> Result<crate::backend::HealthStatus> {
+            Ok(crate::backend::HealthStatus::healthy("Slow backend"))
+        }
+
+        fn backend_type(&self) -> crate::backend::BackendType {
+            crate::backend::BackendType::Embedded
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout_returns_504() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = static_config(dir.path(), "request_timeout_ms = 100");
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend {
+            delay: std::time::Duration::from_secs(1),
+        }));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let metrics = server.metrics_collector();
+        let active_before = metrics.get_active_connections();
+
+        let addr = start(server).await;
+        let started = std::time::Instant::now();
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let response = get(stream, "/slow.php").await;
+
+        assert!(response.starts_with("HTTP/1.1 504"), "{}", response);
+        assert!(started.elapsed() < std::time::Duration::from_millis(800));
+        assert_eq!(metrics.get_active_connections(), active_before);
+    }
+
+    #[tokio::test]
+    async fn test_slow_upload_does_not_hold_a_php_slot() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "");
+        config.php.max_concurrent = Some(1);
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend {
+            delay: std::time::Duration::from_millis(10),
+        }));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let php_limit = server.php_limit.clone().unwrap();
+        let addr = start(server).await;
+
+        // Headers and half the body, then nothing
+        let mut upload = tokio::net::TcpStream::connect(addr).await.unwrap();
+        upload
+            .write_all(b"POST /upload.php HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: 10\r\n\r\n12345")
+            .await
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        assert_eq!(php_limit.in_flight(), 0);
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/index.php").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        upload.write_all(b"67890").await.unwrap();
+        let mut response = String::new();
+        upload.read_to_string(&mut response).await.ok();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_execution_holds_its_slot_until_it_returns() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "request_timeout_ms = 100");
+        config.php.max_concurrent = Some(1);
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        // Ignores the deadline, like a PHP script that cannot be interrupted
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend {
+            delay: std::time::Duration::from_millis(400),
+        }));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let php_limit = server.php_limit.clone().unwrap();
+        let addr = start(server).await;
+
+        let first = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/a.php").await;
+        assert!(first.starts_with("HTTP/1.1 504"), "{}", first);
+
+        // The execution is still running, so its slot is not handed out again
+        assert_eq!(php_limit.in_flight(), 1);
+        let second = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/b.php").await;
+        assert!(second.starts_with("HTTP/1.1 503"), "{}", second);
+
+        // Capacity comes back once the backend returns
+        tokio::time::timeout(std::time::Duration::from_secs(2), async {
+            while php_limit.in_flight() > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("execution slot was never released");
+    }
+
+    #[tokio::test]
+    async fn test_max_concurrent_php_executions() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "");
+        config.php.max_concurrent = Some(1);
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend {
+            delay: std::time::Duration::from_millis(300),
+        }));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let addr = start(server).await;
+
+        // The first request holds the only slot while the second arrives
+        let first = tokio::spawn(async move {
+            get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/a.php").await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let second = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/b.php").await;
+
+        assert!(second.starts_with("HTTP/1.1 503"), "{}", second);
+        assert!(second.to_ascii_lowercase().contains("retry-after: 5"), "{}", second);
+        assert!(first.await.unwrap().starts_with("HTTP/1.1 200"));
+
+        // The slot is released once the first execution finishes
+        let third = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/c.php").await;
+        assert!(third.starts_with("HTTP/1.1 200"), "{}", third);
+    }
+
+    #[tokio::test]
+    async fn test_overload_status_is_configurable() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "\n[server.overload]\nstatus = 429\nretry_after_secs = 2\n");
+        config.php.max_concurrent = Some(1);
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend {
+            delay: std::time::Duration::from_millis(300),
+        }));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let addr = start(server).await;
+
+        let first = tokio::spawn(async move {
+            get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/a.php").await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let second = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/b.php").await;
+
+        assert!(second.starts_with("HTTP/1.1 429"), "{}", second);
+        assert!(second.to_ascii_lowercase().contains("retry-after: 2"), "{}", second);
+        assert!(first.await.unwrap().starts_with("HTTP/1.1 200"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_overload_status_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = static_config(dir.path(), "\n[server.overload]\nstatus = 500\n");
+        assert!(Server::new(config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_multiple_listeners_serve_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hello.txt"), "hello").unwrap();
+        let socket_path = dir.path().join("fe-php.sock");
+
+        let listeners = format!(r#"
+unix_socket_mode = "0660"
+
+[[server.listeners]]
+listen_type = "tcp"
+host = "127.0.0.1"
+port = 0
+
+[[server.listeners]]
+listen_type = "unix"
+unix_socket_path = "{}"
+"#, socket_path.display());
+
+        let server = Server::new(static_config(dir.path(), &listeners)).await.unwrap();
+        let bound = server.bind_listeners().await.unwrap();
+        assert_eq!(bound.len(), 2);
+
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&socket_path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        let tcp_addr = match &bound[0] {
+            BoundListener::Tcp { listener, .. } => listener.local_addr().unwrap(),
+            BoundListener::Unix { .. } => panic!("expected TCP listener first"),
+        };
+
+        let server = Arc::new(server);
+        let handle = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.run_listeners(bound).await }
+        });
+
+        let tcp = tokio::net::TcpStream::connect(tcp_addr).await.unwrap();
+        let response = get(tcp, "/hello.txt").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("hello"));
+
+        let unix = tokio::net::UnixStream::connect(&socket_path).await.unwrap();
+        let response = get(unix, "/hello.txt").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("hello"));
+
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn test_allowlist_bypasses_waf_and_ip_blocker() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hello.txt"), "hello").unwrap();
+        let attack = "/hello.txt?id=1%20UNION%20SELECT%20password%20FROM%20users";
+
+        // Extra tables after the [server] keys
+        let waf = "\n[waf]\nenable = true\nmode = \"block\"\n";
+
+        let server = Server::new(static_config(dir.path(), waf)).await.unwrap();
+        let addr = start(server).await;
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), attack).await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+
+        let allowlisted = format!("{}\n[security]\nallowlist = [\"10.0.0.0/8\", \"127.0.0.1\"]\n", waf);
+        let server = Server::new(static_config(dir.path(), &allowlisted)).await.unwrap();
+        server.ip_blocker().block("127.0.0.1").unwrap();
+        let addr = start(server).await;
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), attack).await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("hello"));
+
+        // Clients outside the allowlist are still blocked
+        let other = format!("{}\n[security]\nallowlist = [\"10.0.0.0/8\"]\n", waf);
+        let server = Server::new(static_config(dir.path(), &other)).await.unwrap();
+        let addr = start(server).await;
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), attack).await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_trailing_slash_redirect_and_denied_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("about")).unwrap();
+        std::fs::write(dir.path().join("about/index.html"), "about").unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/config"), "secret").unwrap();
+
+        let extra = r#"trailing_slash = "add"
+case_insensitive_paths = true
+
+[security]
+denied_patterns = [{ type = "prefix", value = "/.git" }]
+"#;
+        let server = Server::new(static_config(dir.path(), extra)).await.unwrap();
+        let addr = start(server).await;
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/about?tab=1").await;
+        assert!(response.starts_with("HTTP/1.1 301"), "{}", response);
+        assert!(response.to_lowercase().contains("location: /about/?tab=1"), "{}", response);
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/.GiT/config").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        assert!(!response.contains("secret"));
+    }
+
+    /// FastCGI responder that answers every request with its SCRIPT_FILENAME
+    async fn script_filename_responder() -> String {
+        let fpm = MockFpm::new(|_, request| {
+            let script = request.params.get("SCRIPT_FILENAME").cloned().unwrap_or_default();
+            Reply::Send(fastcgi_mock::response(format!("Content-Type: text/plain\r\n\r\n{}", script).as_bytes()))
+        });
+        fpm.start().await.addr
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_fastcgi_backend_uses_its_own_document_root() {
+        let embedded_root = tempfile::tempdir().unwrap();
+        let legacy_root = tempfile::tempdir().unwrap();
+        for root in [embedded_root.path(), legacy_root.path()] {
+            std::fs::create_dir(root.join("legacy")).unwrap();
+            std::fs::write(root.join("legacy/index.php"), "<?php").unwrap();
+        }
+
+        let mut config = static_config(embedded_root.path(), "");
+        config.php.fpm_socket = script_filename_responder().await;
+        config.backend.fastcgi.document_root = Some(legacy_root.path().to_path_buf());
+        config.backend.routing_rules.push(crate::config::RoutingRule {
+            pattern: crate::config::PathPatternConfig::Prefix("/legacy/".to_string()),
+            backend: "fastcgi".to_string(),
+            priority: 100,
+            max_body_size: None,
+            slo_ms: None,
+        });
+        assert_eq!(config.embedded_document_root(), embedded_root.path());
+
+        let server = Server::new(config).await.unwrap();
+        let addr = start(server).await;
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/legacy/index.php").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let expected = legacy_root.path().canonicalize().unwrap().join("legacy/index.php");
+        assert!(response.ends_with(&expected.display().to_string()), "{}", response);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_date_and_server_headers_on_all_responses() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "home").unwrap();
+        std::fs::create_dir(dir.path().join("legacy")).unwrap();
+        std::fs::write(dir.path().join("legacy/index.php"), "<?php").unwrap();
+
+        let extra = "server_header = \"fe-php-test\"\n\n[security]\ndenied_patterns = [{ type = \"prefix\", value = \"/private\" }]\n";
+        let mut config = static_config(dir.path(), extra);
+        config.php.fpm_socket = script_filename_responder().await;
+        config.backend.routing_rules.push(crate::config::RoutingRule {
+            pattern: crate::config::PathPatternConfig::Prefix("/legacy/".to_string()),
+            backend: "fastcgi".to_string(),
+            priority: 100,
+            max_body_size: None,
+            slo_ms: None,
+        });
+        let addr = start(Server::new(config).await.unwrap()).await;
+
+        // static file, PHP via FastCGI, a backend error and a policy error
+        for (path, status) in [
+            ("/index.html", "200"),
+            ("/legacy/index.php", "200"),
+            ("/missing.html", "500"),
+            ("/private/x", "403"),
+        ] {
+            let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), path).await;
+            assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}: {}", path, response);
+
+            let header = |name: &str| {
+                response
+                    .lines()
+                    .take_while(|line| !line.is_empty())
+                    .filter_map(|line| line.split_once(": "))
+                    .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.to_string())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(header("server"), vec!["fe-php-test"], "{}", path);
+            let date = header("date");
+            assert_eq!(date.len(), 1, "{}: {}", path, response);
+            assert!(date[0].ends_with(" GMT"), "{}", date[0]);
+            assert!(chrono::DateTime::parse_from_rfc2822(&date[0]).is_ok(), "{}", date[0]);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_http2_rapid_reset_closes_connection() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "enable_http2 = true");
+        config.server.http2.max_reset_streams = 5;
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend {
+            delay: std::time::Duration::from_millis(300),
+        }));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let addr = start(server).await;
+
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let (mut client, connection) = h2::client::handshake(stream).await.unwrap();
+        let connection = tokio::spawn(connection);
+
+        // Open streams, let the server start on them, then cancel them all
+        let mut streams = Vec::new();
+        for _ in 0..20 {
+            let request = Request::get(format!("http://{}/slow.php", addr)).body(()).unwrap();
+            let (_response, send) = client.send_request(request, true).unwrap();
+            streams.push(send);
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        for send in &mut streams {
+            send.send_reset(h2::Reason::CANCEL);
+        }
+
+        let closed = tokio::time::timeout(std::time::Duration::from_secs(2), connection).await;
+        assert!(closed.is_ok(), "connection still open after rapid resets");
+    }
+
+    /// Records how many requests it is executing at once
+    struct CountingBackend {
+        in_flight: Arc<std::sync::atomic::AtomicUsize>,
+        peak: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    impl crate::backend::Backend for CountingBackend {
+        fn execute(&self, _request: crate::php::PhpRequest) -> Result<crate::php::PhpResponse, crate::backend::BackendError> {
+            use std::sync::atomic::Ordering;
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(crate::php::PhpResponse {
+                status_code: 200,
+                headers: Default::default(),
+                body: b"counted".to_vec(),
+                execution_time_ms: 50,
+                memory_peak_mb: 0.0,
+            })
+        }
+
+        fn health_check(&self) -> Result<crate::backend::HealthStatus> {
+            Ok(crate::backend::HealthStatus::healthy("Counting backend"))
+        }
+
+        fn backend_type(&self) -> crate::backend::BackendType {
+            crate::backend::BackendType::Embedded
+        }
+    }
+
+    #[tokio::test]
+    async fn test_http1_pipelined_requests_are_handled_in_sequence() {
+        use crate::backend::{Backend, BackendType};
+
+        // hyper reads the next request on a connection only after the previous
+        // response is written, so pipelining cannot occupy more than one worker
+        let dir = tempfile::tempdir().unwrap();
+        let config = static_config(dir.path(), "");
+
+        let mut server = Server::new(config).await.unwrap();
+        let peak = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(CountingBackend {
+            in_flight: Default::default(),
+            peak: Arc::clone(&peak),
+        }));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let addr = start(server).await;
+
+        // All requests are written before any response is read
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let pipelined = "GET /a.php HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(4);
+        stream.write_all(pipelined.as_bytes()).await.unwrap();
+
+        let mut received = String::new();
+        let mut buf = [0u8; 4096];
+        while received.matches("counted").count() < 4 {
+            let n = tokio::time::timeout(std::time::Duration::from_secs(5), stream.read(&mut buf))
+                .await
+                .expect("pipelined responses did not arrive")
+                .unwrap();
+            assert!(n > 0, "connection closed early: {}", received);
+            received.push_str(&String::from_utf8_lossy(&buf[..n]));
+        }
+
+        assert_eq!(received.matches("HTTP/1.1 200").count(), 4);
+        assert_eq!(peak.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_http1_max_pipelined_closes_long_queues() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), "a").unwrap();
+        let config = static_config(dir.path(), "\n[server.http1]\nmax_pipelined = 2\n");
+        let addr = start(Server::new(config).await.unwrap()).await;
+
+        // Five requests in one write: the first plus four queued behind it
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let pipelined = "GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n".repeat(5);
+        stream.write_all(pipelined.as_bytes()).await.unwrap();
+
+        let mut received = Vec::new();
+        tokio::time::timeout(std::time::Duration::from_secs(5), stream.read_to_end(&mut received))
+            .await
+            .expect("connection was not closed")
+            .unwrap();
+        let received = String::from_utf8_lossy(&received).to_ascii_lowercase();
+
+        // The second queued request reaches the limit and closes the connection
+        assert_eq!(received.matches("http/1.1 200").count(), 3, "{}", received);
+        assert_eq!(received.matches("connection: close").count(), 1, "{}", received);
+
+        // Clients that wait for each response are not affected
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        for _ in 0..5 {
+            stream.write_all(b"GET /a.txt HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).await.unwrap();
+            let response = String::from_utf8_lossy(&buf[..n]).to_ascii_lowercase();
+            assert!(response.starts_with("http/1.1 200"), "{}", response);
+            assert!(!response.contains("connection: close"), "{}", response);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_zero_max_pipelined_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = static_config(dir.path(), "\n[server.http1]\nmax_pipelined = 0\n");
+        assert!(Server::new(config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_zero_fpm_read_timeout_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "");
+        config.php.fpm_read_timeout_secs = 0;
+        let warnings = crate::config::validator::validate_config(&config).unwrap();
+        assert!(warnings.iter().any(|w| w.contains("fpm_read_timeout_secs")));
+        assert!(Server::new(config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_mode_spares_allowlist_and_health() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "home").unwrap();
+
+        let config = static_config(dir.path(), "\n[security]\nallowlist = [\"127.0.0.2\"]\n");
+        let server = Server::new(config).await.unwrap();
+        let maintenance = server.maintenance();
+        let addr = start(server).await;
+
+        let from = |source: &'static str| async move {
+            let socket = tokio::net::TcpSocket::new_v4().unwrap();
+            socket.bind(format!("{}:0", source).parse().unwrap()).unwrap();
+            socket.connect(addr).await.unwrap()
+        };
+
+        maintenance.set(true, Some(120));
+        let response = get(from("127.0.0.1").await, "/index.html").await;
+        assert!(response.starts_with("HTTP/1.1 503"), "{}", response);
+        assert!(response.to_lowercase().contains("retry-after: 120"), "{}", response);
+
+        let response = get(from("127.0.0.1").await, "/_health").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        let response = get(from("127.0.0.2").await, "/index.html").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        maintenance.set(false, None);
+        let response = get(from("127.0.0.1").await, "/index.html").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_and_encoded_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("my file.txt"), "spaced").unwrap();
+        std::fs::create_dir(dir.path().join(".git")).unwrap();
+        std::fs::write(dir.path().join(".git/config"), "secret").unwrap();
+
+        let extra = "\n[security]\ndenied_patterns = [{ type = \"prefix\", value = \"/.git\" }]\n";
+        let addr = start(Server::new(static_config(dir.path(), extra)).await.unwrap()).await;
+
+        for (path, status) in [
+            ("/my%20file.txt", "200"),
+            ("/%2e%2e%2fetc/passwd", "400"),
+            ("/my%20file.txt%00.php", "400"),
+            ("/bad%zz", "400"),
+            // Encoded dots must not slip past denied_patterns
+            ("/%2egit/config", "403"),
+        ] {
+            let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), path).await;
+            assert!(response.starts_with(&format!("HTTP/1.1 {}", status)), "{}: {}", path, response);
+        }
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/my%20file.txt").await;
+        assert!(response.ends_with("spaced"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_missing_backend_document_root_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "");
+        config.backend.fastcgi.document_root = Some(dir.path().join("missing"));
+        assert!(Server::new(config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_allowlist_entry_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = static_config(dir.path(), "\n[security]\nallowlist = [\"not-an-ip\"]\n");
+        assert!(Server::new(config).await.is_err());
+    }
+
+    #[test]
+    fn test_legacy_listener_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = static_config(dir.path(), "");
+
+        let listeners = config.server.effective_listeners(true);
+        assert_eq!(listeners.len(), 1);
+        assert_eq!(listeners[0].listen_type, ListenType::Tcp);
+        assert_eq!(listeners[0].host, "127.0.0.1");
+        assert!(listeners[0].tls);
+    }
+
+    /// Echoes the client address, scheme and method the backend was handed
+    struct ClientEchoBackend;
+
+    impl crate::backend::Backend for ClientEchoBackend {
+        fn execute(&self, request: crate::php::PhpRequest) -> Result<crate::php::PhpResponse, crate::backend::BackendError> {
+            let proto = request.headers.get("x-forwarded-proto").cloned().unwrap_or_default();
+            Ok(crate::php::PhpResponse {
+                status_code: 200,
+                headers: Default::default(),
+                body: format!("client={} proto={} method={}", request.remote_addr, proto, request.method).into_bytes(),
+                execution_time_ms: 0,
+                memory_peak_mb: 0.0,
+            })
+        }
+
+        fn health_check(&self) -> Result<crate::backend::HealthStatus> {
+            Ok(crate::backend::HealthStatus::healthy("Echo backend"))
+        }
+
+        fn backend_type(&self) -> crate::backend::BackendType {
+            crate::backend::BackendType::Embedded
+        }
+    }
+
+    #[tokio::test]
+    async fn test_forwarded_header_from_trusted_proxy() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = static_config(dir.path(), "\n[security]\ntrusted_proxies = [\"127.0.0.1\"]\n");
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(ClientEchoBackend));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let addr = start(server).await;
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream.write_all(
+            b"GET /a.php HTTP/1.1\r\nHost: localhost\r\nForwarded: for=198.51.100.17;proto=https, for=127.0.0.1\r\nConnection: close\r\n\r\n",
+        ).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.contains("client=198.51.100.17:0 proto=https"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_strict_host_check() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "home").unwrap();
+
+        let config = static_config(
+            dir.path(),
+            "\n[server.host_check]\nstrict = true\nallowed_hosts = [\"localhost\"]\n",
+        );
+        let addr = start(Server::new(config).await.unwrap()).await;
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/index.html").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        for request in [
+            "GET /index.html HTTP/1.1\r\nHost: attacker.test\r\nConnection: close\r\n\r\n",
+            "GET /index.html HTTP/1.1\r\nConnection: close\r\n\r\n",
+        ] {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+        }
+    }
+
+    struct PanickingBackend;
+
+    impl crate::backend::Backend for PanickingBackend {
+        fn execute(&self, _request: crate::php::PhpRequest) -> Result<crate::php::PhpResponse, crate::backend::BackendError> {
+            panic!("embedded FFI bug");
+        }
+
+        fn health_check(&self) -> Result<crate::backend::HealthStatus> {
+            Ok(crate::backend::HealthStatus::healthy("Panicking backend"))
+        }
+
+        fn backend_type(&self) -> crate::backend::BackendType {
+            crate::backend::BackendType::Embedded
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backend_panic_returns_500() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let config = static_config(dir.path(), "");
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(PanickingBackend));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let addr = start(server).await;
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/a.php").await;
+        assert!(response.starts_with("HTTP/1.1 500"), "{}", response);
+        assert!(response.to_ascii_lowercase().contains("x-request-id: "), "{}", response);
+        assert!(!response.contains("embedded FFI bug"), "{}", response);
+
+        // The server keeps serving after the panic
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/a.php").await;
+        assert!(response.starts_with("HTTP/1.1 500"), "{}", response);
+    }
+
+    async fn get_range(addr: SocketAddr, path: &str, range: &str) -> String {
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: localhost\r\nRange: {}\r\nConnection: close\r\n\r\n",
+            path, range
+        );
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_static_range_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("data.txt"), "0123456789").unwrap();
+        std::fs::create_dir(dir.path().join("dynamic")).unwrap();
+        std::fs::write(dir.path().join("dynamic/data.txt"), "0123456789").unwrap();
+
+        let mut config = static_config(dir.path(), "");
+        config.backend.static_files.max_ranges = 2;
+        config.backend.static_files.range_disabled_patterns =
+            vec![crate::config::PathPatternConfig::Prefix("/dynamic/".to_string())];
+        let addr = start(Server::new(config).await.unwrap()).await;
+
+        let response = get_range(addr, "/data.txt", "bytes=2-5").await;
+        assert!(response.starts_with("HTTP/1.1 206"), "{}", response);
+        assert!(response.to_ascii_lowercase().contains("content-range: bytes 2-5/10"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n2345"), "{}", response);
+
+        let response = get_range(addr, "/data.txt", "bytes=0-0,2-2").await;
+        assert!(response.starts_with("HTTP/1.1 206"), "{}", response);
+        assert!(response.contains("multipart/byteranges"), "{}", response);
+
+        // Beyond max_ranges
+        let response = get_range(addr, "/data.txt", "bytes=0-0,2-2,4-4").await;
+        assert!(response.starts_with("HTTP/1.1 416"), "{}", response);
+
+        // Disabled pattern: no Accept-Ranges and the full body
+        let response = get_range(addr, "/dynamic/data.txt", "bytes=2-5").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(!response.to_ascii_lowercase().contains("accept-ranges"), "{}", response);
+        assert!(response.ends_with("0123456789"), "{}", response);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_slow_body_upload_times_out() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("upload.php"), "<?php").unwrap();
+
+        let plain = static_config(dir.path(), "body_read_timeout_ms = 200");
+        let mut waf = plain.clone();
+        waf.waf.enable = true;
+        // Chunked uploads to FastCGI are spooled, not collected
+        let mut fastcgi = plain.clone();
+        fastcgi.php.fpm_socket = "127.0.0.1:9".to_string();
+        fastcgi.backend.default_backend = "fastcgi".to_string();
+        // Without the hybrid router, requests go through `router::handle_request`
+        let mut direct = plain.clone();
+        direct.backend.enable_hybrid = false;
+        let mut proxied = direct.clone();
+        proxied.load_balancing.enable = true;
+        proxied.load_balancing.health_check.enable = false;
+        proxied.load_balancing.upstreams.push(crate::config::UpstreamConfig {
+            name: "app".to_string(),
+            url: "http://127.0.0.1:9".to_string(),
+            weight: 1,
+            enabled: true,
+        });
+
+        for (config, method, framing) in [
+            (plain, "PATCH", "Content-Length: 100"),
+            (waf, "PUT", "Content-Length: 100"),
+            (fastcgi, "POST", "Transfer-Encoding: chunked"),
+            (proxied, "DELETE", "Content-Length: 100"),
+            (direct, "POST", "Content-Length: 100"),
+        ] {
+            let server = Server::new(config).await.unwrap();
+            let metrics = server.metrics_collector();
+            let before = metrics.get_request_body_timeouts(method);
+            let addr = start(server).await;
+
+            // Promise a body but dribble only a few bytes of it
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let head = format!("{} /upload.php HTTP/1.1\r\nHost: localhost\r\n{}\r\n\r\n", method, framing);
+            stream.write_all(head.as_bytes()).await.unwrap();
+            let dribble: &[u8] = if framing.starts_with("Transfer") { b"1\r\nx\r\n" } else { b"x" };
+            for _ in 0..3 {
+                stream.write_all(dribble).await.unwrap();
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+
+            let mut response = String::new();
+            tokio::time::timeout(std::time::Duration::from_secs(2), stream.read_to_string(&mut response))
+                .await
+                .expect("slow upload was not cut off")
+                .unwrap();
+
+            assert!(response.starts_with("HTTP/1.1 408"), "{} {}: {}", method, framing, response);
+            assert_eq!(metrics.get_request_body_timeouts(method), before + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_cert_required_paths() {
+        use crate::tls::tests::{test_client_auth_connector, test_connector, write_test_cert, write_test_client_ca};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("admin")).unwrap();
+        std::fs::write(dir.path().join("admin/secret.txt"), "secret").unwrap();
+        std::fs::write(dir.path().join("public.txt"), "public").unwrap();
+        let (cert_path, key_path, cert) = write_test_cert(dir.path());
+        let (ca_path, client_cert, client_key) = write_test_client_ca(dir.path());
+
+        let mut config = static_config(dir.path(), "");
+        config.tls.enable = true;
+        config.tls.cert_path = Some(cert_path);
+        config.tls.key_path = Some(key_path);
+        config.tls.ca_cert_path = Some(ca_path);
+        config.tls.client_cert_required_paths =
+            vec![crate::config::PathPatternConfig::Prefix("/admin/".to_string())];
+        let addr = start(Server::new(config).await.unwrap()).await;
+
+        let server_name = rustls::ServerName::try_from("localhost").unwrap();
+        let anonymous = test_connector(cert.clone());
+        let authenticated = test_client_auth_connector(cert, client_cert, client_key);
+
+        // Public paths need no certificate
+        let stream = anonymous.connect(server_name.clone(), tokio::net::TcpStream::connect(addr).await.unwrap()).await.unwrap();
+        let response = get(stream, "/public.txt").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        // Protected paths are refused without one, on the same listener
+        let stream = anonymous.connect(server_name.clone(), tokio::net::TcpStream::connect(addr).await.unwrap()).await.unwrap();
+        let response = get(stream, "/admin/secret.txt").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+        assert!(response.contains("client certificate required"));
+
+        let stream = authenticated.connect(server_name, tokio::net::TcpStream::connect(addr).await.unwrap()).await.unwrap();
+        let response = get(stream, "/admin/secret.txt").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_method_override_for_php_only() {
+        use crate::backend::{Backend, BackendType};
+
+        let dir = tempfile::tempdir().unwrap();
+        let rules_path = dir.path().join("rules.toml");
+        std::fs::write(&rules_path, "[[rules]]\nid = \"NO-PUT\"\ndescription = \"No PUT\"\npattern = \"^PUT$\"\nfield = \"Method\"\naction = \"Block\"\nseverity = \"High\"\n").unwrap();
+
+        let mut config = static_config(dir.path(), &format!(
+            "\n[waf]\nenable = true\nmode = \"block\"\nrules_path = \"{}\"\n",
+            rules_path.display()
+        ));
+        config.php.method_override.enable = true;
+
+        let mut server = Server::new(config).await.unwrap();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(ClientEchoBackend));
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, Vec::new(), BackendType::Embedded).unwrap(),
+        ));
+        let addr = start(server).await;
+
+        let send = |request: &'static [u8]| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            stream.write_all(request).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        // The WAF sees the real POST, PHP sees the overridden PUT
+        let response = send(b"POST /posts/1 HTTP/1.1\r\nHost: localhost\r\nX-HTTP-Method-Override: PUT\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("method=PUT"), "{}", response);
+
+        // A real PUT is still subject to the method rule
+        let response = send(b"PUT /posts/1 HTTP/1.1\r\nHost: localhost\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_per_listener_http2() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hello.txt"), "hello").unwrap();
+
+        let listeners = r#"
+[[server.listeners]]
+host = "127.0.0.1"
+port = 0
+enable_http2 = true
+
+[[server.listeners]]
+host = "127.0.0.1"
+port = 0
+"#;
+        let server = Server::new(static_config(dir.path(), listeners)).await.unwrap();
+        let bound = server.bind_listeners().await.unwrap();
+        let addrs: Vec<SocketAddr> = bound
+            .iter()
+            .map(|listener| match listener {
+                BoundListener::Tcp { listener, .. } => listener.local_addr().unwrap(),
+                BoundListener::Unix { .. } => panic!("expected TCP listeners"),
+            })
+            .collect();
+
+        let server = Arc::new(server);
+        let handle = tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.run_listeners(bound).await }
+        });
+
+        async fn h2_get(addr: SocketAddr) -> Result<hyper::StatusCode> {
+            let stream = tokio::net::TcpStream::connect(addr).await?;
+            let (mut client, connection) = h2::client::handshake(stream).await?;
+            tokio::spawn(connection);
+            let request = Request::get(format!("http://{}/hello.txt", addr)).body(()).unwrap();
+            let (response, _) = client.send_request(request, true)?;
+            Ok(response.await?.status())
+        }
+
+        assert_eq!(h2_get(addrs[0]).await.unwrap(), hyper::StatusCode::OK);
+
+        // The second listener inherits server.enable_http2 = false and only speaks HTTP/1.1
+        assert!(h2_get(addrs[1]).await.is_err());
+        let tcp = tokio::net::TcpStream::connect(addrs[1]).await.unwrap();
+        let response = get(tcp, "/hello.txt").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+        handle.abort();
+    }
+
+    /// Every address resolves to the same country
+    struct FixedCountry(&'static str);
+
+    impl crate::geoip::CountryLookup for FixedCountry {
+        fn country(&self, _ip: std::net::IpAddr) -> Result<Option<String>> {
+            Ok(Some(self.0.to_string()))
+        }
+    }
+
+    fn block_country(server: &mut Server, country: &'static str) {
+        server.geoip_manager = Some(Arc::new(GeoIpManager::with_lookup(
+            Arc::new(FixedCountry(country)),
+            Vec::new(),
+            vec![country.to_string()],
+        )));
+    }
+
+    #[tokio::test]
+    async fn test_allowlisted_client_bypasses_geoip() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hello.txt"), "hello").unwrap();
+
+        let allowlisted = "\n[security]\nallowlist = [\"127.0.0.1\"]\n";
+        let mut server = Server::new(static_config(dir.path(), allowlisted)).await.unwrap();
+        block_country(&mut server, "XD");
+        let addr = start(server).await;
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/hello.txt").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.ends_with("hello"));
+    }
+
+    #[tokio::test]
+    async fn test_connection_filter_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let peer = PeerAddr::from_tcp("192.0.2.10:40000".parse().unwrap());
+
+        // Default order: a blocklisted client never reaches the GeoIP lookup
+        let mut server = Server::new(static_config(dir.path(), "")).await.unwrap();
+        block_country(&mut server, "XE");
+        server.ip_blocker().block("192.0.2.10").unwrap();
+        assert!(!server.admit_connection(&peer));
+        assert_eq!(server.metrics.get_geoip_blocked("XE"), 0);
+
+        // GeoIP first: the lookup runs and rejects before the blocklist is consulted
+        let geoip_first = "\n[security]\nfilter_order = [\"geoip\", \"ip_blocker\"]\n";
+        let mut server = Server::new(static_config(dir.path(), geoip_first)).await.unwrap();
+        block_country(&mut server, "XF");
+        server.ip_blocker().block("192.0.2.10").unwrap();
+        assert!(!server.admit_connection(&peer));
+        assert_eq!(server.metrics.get_geoip_blocked("XF"), 1);
+
+        // Filters left out of the order are not applied
+        let blocker_only = "\n[security]\nfilter_order = [\"ip_blocker\"]\n";
+        let mut server = Server::new(static_config(dir.path(), blocker_only)).await.unwrap();
+        block_country(&mut server, "XG");
+        assert!(server.admit_connection(&peer));
+        assert_eq!(server.metrics.get_geoip_blocked("XG"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_response_is_binary_safe() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let payload: Vec<u8> = (0..=255u8).cycle().take(200 * 1024).collect();
+        std::fs::write(dir.path().join("blob.bin"), &payload).unwrap();
+
+        let config = static_config(dir.path(), "response_buffer_mode = \"streaming-threshold\"\nresponse_chunk_size = 4096");
+        let addr = start(Server::new(config).await.unwrap()).await;
+
+        let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /blob.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut received = Vec::new();
+        stream.read_to_end(&mut received).await.unwrap();
+
+        let split = received.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        let head = String::from_utf8_lossy(&received[..split]).to_ascii_lowercase();
+        assert!(head.starts_with("http/1.1 200"), "{}", head);
+        assert!(head.contains(&format!("content-length: {}", payload.len())), "{}", head);
+        assert!(&received[split..] == payload.as_slice());
+    }
+
+    #[tokio::test]
+    async fn test_startup_summary_reflects_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "request_timeout_ms = 3000");
+        config.waf.enable = true;
+        config.waf.mode = crate::config::WafMode::Block;
+        config.php.max_concurrent = Some(4);
+        config.php.fpm_socket = "127.0.0.1:9".to_string();
+        let server = Server::new(config).await.unwrap();
+        let listeners = server.bind_listeners().await.unwrap();
+
+        let summary = startup_summary::StartupSummary::new(&server, &listeners);
+        let json = serde_json::to_value(&summary).unwrap();
+
+        let addr = match &listeners[0] {
+            BoundListener::Tcp { listener, .. } => listener.local_addr().unwrap(),
+            BoundListener::Unix { .. } => panic!("expected TCP listener"),
+        };
+        assert_eq!(json["listeners"][0], format!("http://{} (HTTP/1.1)", addr));
+        assert_eq!(json["workers"], 1);
+        assert_eq!(json["default_backend"], "static");
+        let backends = json["backends"].as_array().unwrap();
+        let static_backend = backends.iter().find(|b| b["name"] == "static").unwrap();
+        assert_eq!(static_backend["root"], dir.path().display().to_string());
+        assert!(backends.iter().any(|b| b["name"] == "fastcgi"));
+        assert_eq!(json["waf"]["mode"], "block");
+        assert!(json["waf"]["rules"].as_u64().unwrap() > 0);
+        assert_eq!(json["tls"], false);
+        assert_eq!(json["geoip"], false);
+        assert_eq!(json["limits"]["request_timeout_ms"], 3000);
+        assert_eq!(json["limits"]["php_max_concurrent"], 4);
+        assert!(json["limits"]["body_read_timeout_ms"].is_null());
+    }
+
+    /// PHP-FPM stand-in answering every request with `stdout`
+    async fn fake_fpm(stdout: &[u8]) -> String {
+        MockFpm::replying(stdout).start().await.addr
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_repeated_php_headers_reach_client() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("login.php"), "<?php").unwrap();
+        let fpm = fake_fpm(
+            b"Set-Cookie: session=abc; HttpOnly\r\nVary: Accept\r\nset-cookie: theme=dark\r\nVary: Cookie\r\n\r\nok",
+        )
+        .await;
+
+        use crate::config::DuplicateHeaders;
+        for (policy, vary) in [
+            (DuplicateHeaders::Preserve, vec!["vary: accept", "vary: cookie"]),
+            (DuplicateHeaders::Combine, vec!["vary: accept, cookie"]),
+        ] {
+            let mut config = static_config(dir.path(), "");
+            config.php.fpm_socket = fpm.clone();
+            config.backend.default_backend = "fastcgi".to_string();
+            config.php.duplicate_headers = policy;
+            let addr = start(Server::new(config).await.unwrap()).await;
+
+            let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/login.php").await;
+            let lower = response.to_ascii_lowercase();
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+            assert!(lower.contains("set-cookie: session=abc; httponly\r\n"), "{}", response);
+            assert!(lower.contains("set-cookie: theme=dark\r\n"), "{}", response);
+            for line in vary {
+                assert!(lower.contains(&format!("{}\r\n", line)), "{:?}: {}", policy, response);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_log_records_matched_routing_rule() {
+        use crate::backend::{Backend, BackendType};
+        use crate::config::{PathPatternConfig, RoutingRule};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("assets")).unwrap();
+        std::fs::write(dir.path().join("assets/app.css"), "body{}").unwrap();
+        let mut config = static_config(dir.path(), "");
+        config.logging.log_routing = true;
+
+        let mut server = Server::new(config).await.unwrap();
+        let static_backend = Arc::clone(&server.backend_router.as_ref().unwrap().backends()[&BackendType::Static]);
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Static, static_backend);
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend { delay: std::time::Duration::ZERO }));
+        let rules = vec![RoutingRule {
+            pattern: PathPatternConfig::Prefix("/assets/*".to_string()),
+            backend: "static".to_string(),
+            priority: 100,
+            max_body_size: None,
+            slo_ms: None,
+        }];
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, rules, BackendType::Embedded).unwrap(),
+        ));
+        let admin_api = Arc::new(crate::admin::AdminApi::new(server.metrics_collector()));
+        server.set_admin_api(Arc::clone(&admin_api));
+
+        let addr = start(server).await;
+        for path in ["/assets/app.css", "/index.php"] {
+            let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), path).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        }
+
+        let logs = admin_api.log_analyzer().read().get_recent_logs(10);
+        let rule_for = |uri: &str| {
+            logs.iter()
+                .find(|log| log.uri == uri)
+                .and_then(|log| log.routing_rule.clone())
+        };
+        assert_eq!(rule_for("/assets/app.css").as_deref(), Some("prefix:/assets/* -> static"));
+        assert_eq!(rule_for("/index.php").as_deref(), Some("default -> embedded"));
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake_limit_drops_excess_connections() {
+        use crate::tls::tests::{test_connector, write_test_cert};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("hello.txt"), "hello").unwrap();
+        let (cert_path, key_path, cert) = write_test_cert(dir.path());
+
+        let mut config = static_config(dir.path(), "");
+        config.tls.enable = true;
+        config.tls.cert_path = Some(cert_path);
+        config.tls.key_path = Some(key_path);
+        config.tls.max_concurrent_handshakes = Some(1);
+        let server = Server::new(config).await.unwrap();
+        let metrics = server.metrics_collector();
+        let addr = start(server).await;
+
+        let server_name = rustls::ServerName::try_from("localhost").unwrap();
+        let connector = test_connector(cert);
+
+        // A client that never sends its ClientHello occupies the only slot
+        let stalled = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        assert!(connector.connect(server_name.clone(), tcp).await.is_err());
+        assert_eq!(metrics.get_tls_handshake_errors("limit"), 1);
+
+        // Once it goes away the slot is free again
+        drop(stalled);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let tcp = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let stream = connector.connect(server_name, tcp).await.unwrap();
+        let response = get(stream, "/hello.txt").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn test_slo_breach_counted_per_route() {
+        use crate::backend::{Backend, BackendType};
+        use crate::config::{PathPatternConfig, RoutingRule};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = Server::new(static_config(dir.path(), "")).await.unwrap();
+        let metrics = server.metrics_collector();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend { delay: std::time::Duration::from_millis(100) }));
+        let rule = |prefix: &str, slo_ms: u64| RoutingRule {
+            pattern: PathPatternConfig::Prefix(prefix.to_string()),
+            backend: "embedded".to_string(),
+            priority: 100,
+            max_body_size: None,
+            slo_ms: Some(slo_ms),
+        };
+        let rules = vec![rule("/slo-tight/*", 20), rule("/slo-loose/*", 5_000)];
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, rules, BackendType::Embedded).unwrap(),
+        ));
+
+        let addr = start(server).await;
+        for path in ["/slo-tight/index.php", "/slo-loose/index.php"] {
+            let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), path).await;
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        }
+
+        assert_eq!(metrics.get_slo_requests("prefix:/slo-tight/*"), 1);
+        assert_eq!(metrics.get_slo_breaches("prefix:/slo-tight/*"), 1);
+        assert_eq!(metrics.get_slo_requests("prefix:/slo-loose/*"), 1);
+        assert_eq!(metrics.get_slo_breaches("prefix:/slo-loose/*"), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slo_counts_timeouts_and_overload_as_breaches() {
+        use crate::backend::{Backend, BackendType};
+        use crate::config::{PathPatternConfig, RoutingRule};
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "\n[server.overload]\nstatus = 429\n");
+        config.server.request_timeout_ms = Some(150);
+        config.php.max_concurrent = Some(1);
+        let mut server = Server::new(config).await.unwrap();
+        let metrics = server.metrics_collector();
+        let mut backends: std::collections::HashMap<BackendType, Arc<dyn Backend>> = Default::default();
+        backends.insert(BackendType::Embedded, Arc::new(SlowBackend { delay: std::time::Duration::from_millis(400) }));
+        let rules = vec![RoutingRule {
+            pattern: PathPatternConfig::Prefix("/slo-errors/*".to_string()),
+            backend: "embedded".to_string(),
+            priority: 100,
+            max_body_size: None,
+            slo_ms: Some(5_000),
+        }];
+        server.backend_router = Some(Arc::new(
+            crate::backend::router::BackendRouter::new(backends, rules, BackendType::Embedded).unwrap(),
+        ));
+        let addr = start(server).await;
+
+        let first = tokio::spawn(async move {
+            get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/slo-errors/a.php").await
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let second = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/slo-errors/b.php").await;
+        assert!(second.starts_with("HTTP/1.1 429"), "{}", second);
+        let first = first.await.unwrap();
+        assert!(first.starts_with("HTTP/1.1 504"), "{}", first);
+
+        // Both were answered well within the objective but neither was served
+        assert_eq!(metrics.get_slo_requests("prefix:/slo-errors/*"), 2);
+        assert_eq!(metrics.get_slo_breaches("prefix:/slo-errors/*"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_require_https_uses_forwarded_proto() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "home").unwrap();
+        let config = static_config(
+            dir.path(),
+            "require_https = \"redirect\"\n[security]\ntrusted_proxies = [\"127.0.0.1\"]\n",
+        );
+        let addr = start(Server::new(config).await.unwrap()).await;
+
+        let send = |forwarded_proto: &'static str, path: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!(
+                "GET {path} HTTP/1.1\r\nHost: shop.example\r\nX-Forwarded-Proto: {forwarded_proto}\r\nConnection: close\r\n\r\n"
+            );
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let plaintext = send("http", "/index.html?ref=1").await;
+        assert!(plaintext.starts_with("HTTP/1.1 301"), "{}", plaintext);
+        assert!(plaintext.contains("location: https://shop.example/index.html?ref=1"), "{}", plaintext);
+
+        let secure = send("https", "/index.html").await;
+        assert!(secure.starts_with("HTTP/1.1 200"), "{}", secure);
+        assert!(secure.ends_with("home"), "{}", secure);
+
+        // Probes from the proxy's health checker are not redirected
+        let probe = send("http", "/_health").await;
+        assert!(!probe.starts_with("HTTP/1.1 301"), "{}", probe);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_oversized_php_header_handling() {
+        use crate::config::OversizedHeaders;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("login.php"), "<?php").unwrap();
+        let stdout = format!("Set-Cookie: blob={}\r\nX-App: ok\r\n\r\nwelcome", "x".repeat(16 * 1024));
+        let fpm = fake_fpm(stdout.as_bytes()).await;
+
+        for action in [OversizedHeaders::Drop, OversizedHeaders::Error] {
+            let mut config = static_config(dir.path(), "");
+            config.php.fpm_socket = fpm.clone();
+            config.backend.default_backend = "fastcgi".to_string();
+            config.php.response_headers.max_header_size = Some(8 * 1024);
+            config.php.response_headers.on_oversized = action;
+            let addr = start(Server::new(config).await.unwrap()).await;
+
+            let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/login.php").await;
+            let lower = response.to_ascii_lowercase();
+            assert!(!lower.contains("set-cookie"), "{:?}: {}", action, response);
+            match action {
+                OversizedHeaders::Drop => {
+                    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+                    assert!(lower.contains("x-app: ok\r\n"), "{}", response);
+                    assert!(response.ends_with("welcome"), "{}", response);
+                }
+                _ => assert!(response.starts_with("HTTP/1.1 500"), "{}", response),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_health_without_backend_router() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = static_config(dir.path(), "");
+        config.backend.enable_hybrid = false;
+
+        let server = Server::new(config).await.unwrap();
+        assert!(server.backend_router.is_none());
+        let addr = start(server).await;
+
+        let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/_health").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("application/json"), "{}", response);
+
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let health: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(health["status"], "healthy");
+        assert_eq!(health["worker_pool"]["live_workers"], 1);
+        assert_eq!(health["worker_pool"]["pool_size"], 1);
+        assert!(health["uptime_seconds"].is_u64());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_php_response_header_policy() {
+        use crate::config::HeaderPolicyMode;
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.php"), "<?php").unwrap();
+        let fpm = fake_fpm(b"Strict-Transport-Security: max-age=0\r\nX-Debug-Token: abc\r\nX-App: ok\r\n\r\nhello").await;
+
+        for mode in [HeaderPolicyMode::Deny, HeaderPolicyMode::Allow] {
+            let mut config = static_config(dir.path(), "");
+            config.php.fpm_socket = fpm.clone();
+            config.backend.default_backend = "fastcgi".to_string();
+            config.php.response_header_policy.mode = mode;
+            config.php.response_header_policy.headers = match mode {
+                HeaderPolicyMode::Deny => vec!["strict-transport-security".to_string(), "X-Debug-Token".to_string()],
+                _ => vec!["X-App".to_string()],
+            };
+            let addr = start(Server::new(config).await.unwrap()).await;
+
+            let response = get(tokio::net::TcpStream::connect(addr).await.unwrap(), "/index.php").await;
+            let lower = response.to_ascii_lowercase();
+            assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+            assert!(!lower.contains("strict-transport-security"), "{:?}: {}", mode, response);
+            assert!(!lower.contains("x-debug-token"), "{:?}: {}", mode, response);
+            assert!(lower.contains("x-app: ok\r\n"), "{:?}: {}", mode, response);
+            assert!(response.ends_with("hello"), "{}", response);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_method_gets_405() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("index.html"), "home").unwrap();
+        let addr = start(Server::new(static_config(dir.path(), "")).await.unwrap()).await;
+
+        let send = |method: &'static str| async move {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let request = format!("{} /index.html HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", method);
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let response = send("TRACE").await;
+        assert!(response.starts_with("HTTP/1.1 405"), "{}", response);
+        assert!(
+            response.to_ascii_lowercase().contains("allow: get, head, post, put, patch, delete, options\r\n"),
+            "{}",
+            response
+        );
+
+        let response = send("GET").await;
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
     }
 }
@@ -0,0 +1,69 @@
+//! Uniform responses for requests rejected by a capacity limit
+
+use crate::config::OverloadConfig;
+use anyhow::Result;
+use hyper::header::RETRY_AFTER;
+use hyper::{Response, StatusCode};
+use super::body::ResponseBody;
+
+/// Builds the `server.overload` response: 503 or 429 with `Retry-After`
+#[derive(Debug, Clone)]
+pub struct OverloadResponder {
+    status: StatusCode,
+    retry_after_secs: u64,
+}
+
+impl OverloadResponder {
+    pub fn from_config(config: &OverloadConfig) -> Result<Self> {
+        let status = match config.status {
+            429 => StatusCode::TOO_MANY_REQUESTS,
+            503 => StatusCode::SERVICE_UNAVAILABLE,
+            other => anyhow::bail!("server.overload.status must be 429 or 503, got {}", other),
+        };
+        Ok(Self {
+            status,
+            retry_after_secs: config.retry_after_secs,
+        })
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status.as_u16()
+    }
+
+    pub fn response(&self) -> Result<Response<ResponseBody>> {
+        let reason = self.status.canonical_reason().unwrap_or("Overloaded");
+        Ok(Response::builder()
+            .status(self.status)
+            .header(RETRY_AFTER, self.retry_after_secs.to_string())
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(format!("{}: server is at capacity, retry later", reason).into())?)
+    }
+}
+
+impl Default for OverloadResponder {
+    fn default() -> Self {
+        Self::from_config(&OverloadConfig::default()).expect("default overload config is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overload_status_and_retry_after() {
+        let responder = OverloadResponder::from_config(&OverloadConfig {
+            status: 429,
+            retry_after_secs: 7,
+        }).unwrap();
+        let response = responder.response().unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "7");
+
+        let response = OverloadResponder::default().response().unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+
+        assert!(OverloadResponder::from_config(&OverloadConfig { status: 500, retry_after_secs: 1 }).is_err());
+    }
+}
@@ -0,0 +1,181 @@
+use crate::backend::PathPattern;
+use crate::config::{Config, PathPatternConfig, TrailingSlash};
+use crate::utils::{decode_path, PathError};
+use anyhow::Result;
+use hyper::Uri;
+
+/// Path checks applied before routing: denied patterns, client certificate
+/// requirements and canonical trailing slashes
+pub struct PathPolicy {
+    trailing_slash: TrailingSlash,
+    case_insensitive: bool,
+    denied_patterns: Vec<PathPattern>,
+    client_cert_patterns: Vec<PathPattern>,
+    allow_malformed: bool,
+}
+
+impl PathPolicy {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let case_insensitive = config.server.case_insensitive_paths;
+        let compile = |patterns: &[PathPatternConfig]| {
+            patterns
+                .iter()
+                .map(|pattern| PathPattern::from_config(pattern, case_insensitive))
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            trailing_slash: config.server.trailing_slash,
+            case_insensitive,
+            denied_patterns: compile(&config.security.denied_patterns)?,
+            client_cert_patterns: compile(&config.tls.client_cert_required_paths)?,
+            allow_malformed: config.security.allow_malformed_paths,
+        })
+    }
+
+    /// Decoded, normalized form of the request path used for policy checks.
+    /// Undecodable paths are an error unless `security.allow_malformed_paths`
+    /// is set, in which case the raw path is checked as-is.
+    pub fn decode(&self, path: &str) -> Result<String, PathError> {
+        match decode_path(path) {
+            Ok(decoded) => Ok(decoded),
+            Err(_) if self.allow_malformed => Ok(path.to_string()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether the path matches one of `security.denied_patterns`
+    pub fn is_denied(&self, path: &str) -> bool {
+        self.matches_any(&self.denied_patterns, path)
+    }
+
+    /// Whether the path matches one of `tls.client_cert_required_paths`
+    pub fn requires_client_cert(&self, path: &str) -> bool {
+        self.matches_any(&self.client_cert_patterns, path)
+    }
+
+    fn matches_any(&self, patterns: &[PathPattern], path: &str) -> bool {
+        if patterns.is_empty() {
+            return false;
+        }
+
+        let path = if self.case_insensitive {
+            path.to_lowercase()
+        } else {
+            path.to_string()
+        };
+
+        patterns.iter().any(|pattern| pattern.matches(&path))
+    }
+
+    /// `Location` for a 301 when the path is not in its canonical trailing-slash form.
+    /// Paths whose last segment looks like a file (contains a dot) are left alone.
+    /// Leading slashes are collapsed so the target can never be protocol-relative
+    /// (`//evil.com/` would send the client off-site).
+    pub fn redirect_target(&self, uri: &Uri) -> Option<String> {
+        let path = format!("/{}", uri.path().trim_start_matches(['/', '\\']));
+        let path = path.as_str();
+        let last_segment = path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+
+        let canonical = match self.trailing_slash {
+            TrailingSlash::Preserve => return None,
+            TrailingSlash::Add if !path.ends_with('/') && !last_segment.contains('.') => {
+                format!("{}/", path)
+            }
+            TrailingSlash::Remove if path.len() > 1 && path.ends_with('/') => {
+                let trimmed = path.trim_end_matches('/');
+                if trimmed.is_empty() { "/".to_string() } else { trimmed.to_string() }
+            }
+            _ => return None,
+        };
+
+        Some(match uri.query() {
+            Some(query) => format!("{}?{}", canonical, query),
+            None => canonical,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(trailing_slash: TrailingSlash, case_insensitive: bool) -> PathPolicy {
+        let denied = [
+            PathPatternConfig::Prefix("/.git".to_string()),
+            PathPatternConfig::Suffix(".env".to_string()),
+            PathPatternConfig::Regex("^/admin/.*\\.bak$".to_string()),
+        ];
+        PathPolicy {
+            trailing_slash,
+            case_insensitive,
+            denied_patterns: denied
+                .iter()
+                .map(|p| PathPattern::from_config(p, case_insensitive).unwrap())
+                .collect(),
+            client_cert_patterns: vec![
+                PathPattern::from_config(&PathPatternConfig::Prefix("/admin/".to_string()), case_insensitive).unwrap(),
+            ],
+            allow_malformed: false,
+        }
+    }
+
+    #[test]
+    fn test_trailing_slash_redirects() {
+        let add = policy(TrailingSlash::Add, false);
+        assert_eq!(add.redirect_target(&"/about?x=1".parse().unwrap()).as_deref(), Some("/about/?x=1"));
+        assert_eq!(add.redirect_target(&"/about/".parse().unwrap()), None);
+        assert_eq!(add.redirect_target(&"/style.css".parse().unwrap()), None);
+
+        let remove = policy(TrailingSlash::Remove, false);
+        assert_eq!(remove.redirect_target(&"/about/".parse().unwrap()).as_deref(), Some("/about"));
+        assert_eq!(remove.redirect_target(&"/".parse().unwrap()), None);
+
+        let preserve = policy(TrailingSlash::Preserve, false);
+        assert_eq!(preserve.redirect_target(&"/about".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_redirect_never_leaves_the_site() {
+        let add = policy(TrailingSlash::Add, false);
+        assert_eq!(add.redirect_target(&"//evil.com/x".parse().unwrap()).as_deref(), Some("/evil.com/x/"));
+        assert_eq!(add.redirect_target(&"///evil".parse().unwrap()).as_deref(), Some("/evil/"));
+        assert_eq!(add.redirect_target(&"/%5Cevil".parse().unwrap()).as_deref(), Some("/%5Cevil/"));
+
+        let remove = policy(TrailingSlash::Remove, false);
+        assert_eq!(remove.redirect_target(&"//evil.com/".parse().unwrap()).as_deref(), Some("/evil.com"));
+        assert_eq!(remove.redirect_target(&"//".parse().unwrap()), None);
+
+        for uri in ["//evil.com/x", "//evil.com/", "///", "/\\evil.com/"] {
+            for policy in [&add, &remove] {
+                if let Some(location) = uri.parse().ok().and_then(|uri| policy.redirect_target(&uri)) {
+                    assert!(!location.starts_with("//") && !location.starts_with("/\\"), "{} -> {}", uri, location);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_denied_patterns_case_sensitivity() {
+        let sensitive = policy(TrailingSlash::Preserve, false);
+        assert!(sensitive.is_denied("/.git/config"));
+        assert!(!sensitive.is_denied("/.GIT/config"));
+
+        let insensitive = policy(TrailingSlash::Preserve, true);
+        assert!(insensitive.is_denied("/.GIT/config"));
+        assert!(insensitive.is_denied("/app/.ENV"));
+        assert!(insensitive.is_denied("/Admin/dump.BAK"));
+        assert!(!insensitive.is_denied("/index.php"));
+    }
+
+    #[test]
+    fn test_client_cert_required_paths() {
+        let sensitive = policy(TrailingSlash::Preserve, false);
+        assert!(sensitive.requires_client_cert("/admin/users"));
+        assert!(!sensitive.requires_client_cert("/public/index.html"));
+
+        // Case-insensitive servers must not let /ADMIN/ skip the check
+        let insensitive = policy(TrailingSlash::Preserve, true);
+        assert!(insensitive.requires_client_cert("/ADMIN/users"));
+    }
+}
@@ -0,0 +1,146 @@
+use hyper::rt::{Read, ReadBuf, ReadBufCursor, Write};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+/// Per-connection count of HTTP/1.1 requests queued behind the one being answered.
+/// hyper answers pipelined requests one at a time from its read buffer, so a long
+/// queue holds the connection, and a PHP worker, until all of them are done.
+///
+/// A request counts as pipelined when hyper starts it without reading from the
+/// socket since the previous request finished, i.e. it had already arrived.
+pub struct PipelineTracker {
+    limit: usize,
+    /// Reads that returned data
+    reads: AtomicU64,
+    /// `reads` when the previous request finished
+    reads_at_finish: AtomicU64,
+    queued: AtomicUsize,
+}
+
+impl PipelineTracker {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            reads: AtomicU64::new(0),
+            // Nothing was read yet, so the first request never counts as pipelined
+            reads_at_finish: AtomicU64::new(u64::MAX),
+            queued: AtomicUsize::new(0),
+        })
+    }
+
+    /// Count a request as it starts. Returns whether its response should close
+    /// the connection because the queue reached the limit; the client then sends
+    /// the requests it is still waiting for again on a new connection.
+    pub fn start(&self) -> bool {
+        let pipelined = self.reads.load(Ordering::Acquire) == self.reads_at_finish.load(Ordering::Acquire);
+        let queued = if pipelined {
+            self.queued.fetch_add(1, Ordering::AcqRel) + 1
+        } else {
+            self.queued.store(0, Ordering::Release);
+            0
+        };
+        queued >= self.limit
+    }
+
+    /// Mark the current request as answered
+    pub fn finish(&self) {
+        self.reads_at_finish.store(self.reads.load(Ordering::Acquire), Ordering::Release);
+    }
+
+    /// Wrap the connection's `io` so its reads are counted
+    pub fn wrap<I>(self: &Arc<Self>, io: I) -> CountingIo<I> {
+        CountingIo {
+            inner: io,
+            tracker: Arc::clone(self),
+        }
+    }
+}
+
+/// Connection I/O that reports each read returning data to a [`PipelineTracker`]
+pub struct CountingIo<I> {
+    inner: I,
+    tracker: Arc<PipelineTracker>,
+}
+
+impl<I: Read + Unpin> Read for CountingIo<I> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, mut buf: ReadBufCursor<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // Read through a buffer of our own over the same memory to learn the length
+        let n = unsafe {
+            let mut own = ReadBuf::uninit(buf.as_mut());
+            ready!(Pin::new(&mut this.inner).poll_read(cx, own.unfilled()))?;
+            own.filled().len()
+        };
+        unsafe { buf.advance(n) };
+        if n > 0 {
+            this.tracker.reads.fetch_add(1, Ordering::AcqRel);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<I: Write + Unpin> Write for CountingIo<I> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write_vectored(cx, bufs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(tracker: &PipelineTracker) {
+        tracker.reads.fetch_add(1, Ordering::AcqRel);
+    }
+
+    #[test]
+    fn test_queued_requests_reach_limit() {
+        let tracker = PipelineTracker::new(2);
+
+        // Three requests arrive in one read
+        read(&tracker);
+        assert!(!tracker.start());
+        tracker.finish();
+        assert!(!tracker.start());
+        tracker.finish();
+        assert!(tracker.start());
+    }
+
+    #[test]
+    fn test_waiting_for_the_response_resets_the_queue() {
+        let tracker = PipelineTracker::new(1);
+
+        read(&tracker);
+        assert!(!tracker.start());
+        tracker.finish();
+        // Each request was read only after the previous response
+        for _ in 0..5 {
+            read(&tracker);
+            assert!(!tracker.start());
+            tracker.finish();
+        }
+    }
+}
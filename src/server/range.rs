@@ -24,6 +24,16 @@ impl ByteRange {
     }
 }
 
+/// How a request's `Range` header should be answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RangeOutcome {
+    /// No usable range; send the whole body
+    Full,
+    Partial(ByteRange),
+    Multipart(Vec<ByteRange>),
+    NotSatisfiable,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RangeSpec {
     /// Single range (e.g., "bytes=0-1023")
@@ -38,7 +48,11 @@ impl RangeHandler {
     /// Parse Range header from request
     pub fn parse_range<T>(request: &Request<T>, total_size: u64) -> Option<RangeSpec> {
         let range_header = request.headers().get(RANGE)?.to_str().ok()?;
+        Self::parse_range_header(range_header, total_size)
+    }
 
+    /// Parse a `Range` header value such as `bytes=0-99,200-`
+    pub fn parse_range_header(range_header: &str, total_size: u64) -> Option<RangeSpec> {
         // Must start with "bytes="
         if !range_header.starts_with("bytes=") {
             return None;
@@ -112,6 +126,51 @@ impl RangeHandler {
         }
     }
 
+    /// Decide how to answer a `Range` header, refusing more than `max_ranges` sub-ranges
+    /// before any of them are parsed
+    pub fn evaluate(range_header: Option<&str>, total_size: u64, max_ranges: usize) -> RangeOutcome {
+        let Some(range_header) = range_header else {
+            return RangeOutcome::Full;
+        };
+        let Some(ranges) = range_header.strip_prefix("bytes=") else {
+            return RangeOutcome::Full;
+        };
+        if ranges.split(',').count() > max_ranges || total_size == 0 {
+            return RangeOutcome::NotSatisfiable;
+        }
+
+        match Self::parse_range_header(range_header, total_size) {
+            Some(RangeSpec::Multiple(ranges)) if ranges.is_empty() => RangeOutcome::NotSatisfiable,
+            Some(RangeSpec::Multiple(ranges)) if ranges.len() == 1 => RangeOutcome::Partial(ranges[0]),
+            Some(RangeSpec::Multiple(ranges)) => RangeOutcome::Multipart(ranges),
+            Some(spec) => match Self::resolve_range(&spec, total_size) {
+                Some(range) if total_size > 0 && !range.is_empty() => RangeOutcome::Partial(range),
+                _ => RangeOutcome::NotSatisfiable,
+            },
+            None => RangeOutcome::NotSatisfiable,
+        }
+    }
+
+    /// `multipart/byteranges` body holding each range of `data`
+    pub fn multipart_body(
+        ranges: &[ByteRange],
+        data: &[u8],
+        content_type: &str,
+        boundary: &str,
+    ) -> Vec<u8> {
+        let total_size = data.len();
+        let mut body = Vec::new();
+        for range in ranges {
+            body.extend_from_slice(format!(
+                "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                boundary, content_type, range.start, range.end, total_size
+            ).as_bytes());
+            body.extend_from_slice(&data[range.start as usize..=range.end as usize]);
+        }
+        body.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+        body
+    }
+
     /// Create a 206 Partial Content response
     pub fn create_partial_response(
         range: ByteRange,
@@ -262,6 +321,31 @@ mod tests {
         assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
     }
 
+    #[test]
+    fn test_evaluate_range_count_cap() {
+        assert_eq!(RangeHandler::evaluate(None, 100, 2), RangeOutcome::Full);
+        assert_eq!(
+            RangeHandler::evaluate(Some("bytes=0-9,20-29"), 100, 2),
+            RangeOutcome::Multipart(vec![ByteRange { start: 0, end: 9 }, ByteRange { start: 20, end: 29 }])
+        );
+        assert_eq!(
+            RangeHandler::evaluate(Some("bytes=0-0,2-2,4-4"), 100, 2),
+            RangeOutcome::NotSatisfiable
+        );
+        assert_eq!(RangeHandler::evaluate(Some("bytes=200-300"), 100, 2), RangeOutcome::NotSatisfiable);
+    }
+
+    #[test]
+    fn test_multipart_body() {
+        let ranges = [ByteRange { start: 0, end: 4 }, ByteRange { start: 7, end: 11 }];
+        let body = RangeHandler::multipart_body(&ranges, b"Hello, World!", "text/plain", "SEP");
+        let body = String::from_utf8(body).unwrap();
+
+        assert!(body.contains("--SEP\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-4/13\r\n\r\nHello"));
+        assert!(body.contains("Content-Range: bytes 7-11/13\r\n\r\nWorld"));
+        assert!(body.ends_with("--SEP--\r\n"));
+    }
+
     #[test]
     fn test_byte_range_len() {
         let range = ByteRange { start: 0, end: 999 };
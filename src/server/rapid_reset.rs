@@ -0,0 +1,113 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Notify;
+
+/// Per-connection record of HTTP/2 streams cancelled by the client before a
+/// response was produced (the Rapid Reset pattern, CVE-2023-44487)
+pub struct ResetTracker {
+    limit: usize,
+    window: Duration,
+    resets: Mutex<VecDeque<Instant>>,
+    tripped: Notify,
+}
+
+impl ResetTracker {
+    pub fn new(limit: usize, window: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            window,
+            resets: Mutex::new(VecDeque::new()),
+            tripped: Notify::new(),
+        })
+    }
+
+    /// Guard for one stream; dropping it without `finish` counts as a reset
+    pub fn guard(self: &Arc<Self>) -> StreamGuard {
+        StreamGuard {
+            tracker: Arc::clone(self),
+            finished: false,
+        }
+    }
+
+    /// Resolves once more than `limit` resets happened within `window`
+    pub async fn tripped(&self) {
+        self.tripped.notified().await
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    fn record(&self) {
+        let now = Instant::now();
+        let mut resets = self.resets.lock();
+        while resets.front().is_some_and(|t| now.duration_since(*t) > self.window) {
+            resets.pop_front();
+        }
+        resets.push_back(now);
+
+        if resets.len() > self.limit {
+            self.tripped.notify_one();
+        }
+    }
+}
+
+pub struct StreamGuard {
+    tracker: Arc<ResetTracker>,
+    finished: bool,
+}
+
+impl StreamGuard {
+    pub fn finish(mut self) {
+        self.finished = true;
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.tracker.record();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_reset_tracker_trips_over_limit() {
+        let tracker = ResetTracker::new(3, Duration::from_secs(10));
+
+        // Completed streams never count
+        for _ in 0..10 {
+            tracker.guard().finish();
+        }
+        for _ in 0..3 {
+            drop(tracker.guard());
+        }
+        let tripped = tokio::time::timeout(Duration::from_millis(50), tracker.tripped()).await;
+        assert!(tripped.is_err());
+
+        drop(tracker.guard());
+        let tripped = tokio::time::timeout(Duration::from_millis(50), tracker.tripped()).await;
+        assert!(tripped.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_reset_tracker_window_expires() {
+        let tracker = ResetTracker::new(2, Duration::from_millis(50));
+
+        for _ in 0..2 {
+            drop(tracker.guard());
+        }
+        tokio::time::sleep(Duration::from_millis(80)).await;
+        for _ in 0..2 {
+            drop(tracker.guard());
+        }
+        let tripped = tokio::time::timeout(Duration::from_millis(50), tracker.tripped()).await;
+        assert!(tripped.is_err());
+    }
+}
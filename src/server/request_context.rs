@@ -0,0 +1,154 @@
+//! Request metadata gathered once when handling starts and shared by every stage
+
+use super::peer_addr::PeerAddr;
+use crate::backend::Deadline;
+use crate::config::LoggingConfig;
+use crate::logging::structured::RequestLog;
+use hyper::Request;
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    /// Shared by the access log, error logs and the `X-Request-ID` of error responses
+    pub request_id: String,
+    pub start: Instant,
+    pub method: String,
+    /// Request target (path and query) as received
+    pub uri: String,
+    pub user_agent: Option<String>,
+    /// Request headers as received, for the access log; non-UTF-8 values are skipped
+    pub headers: Vec<(String, String)>,
+    /// Client address, after resolving trusted forwarding headers
+    pub peer_addr: PeerAddr,
+    /// End of the `server.request_timeout_ms` budget
+    pub deadline: Option<Deadline>,
+    /// Arrived over TLS, directly or as reported by a trusted proxy
+    pub secure: bool,
+    /// Matched routing rule and backend, recorded when `logging.log_routing` is enabled
+    pub routing_rule: Option<String>,
+    /// Client location passed to PHP, looked up when `geoip.inject_headers` is enabled
+    pub location: Option<crate::geoip::LocationInfo>,
+    /// Route label and latency objective, set once routing picks a route with one.
+    /// Shared between clones so the timeout wrapper sees what the handler resolved.
+    pub slo: Arc<OnceLock<(String, Duration)>>,
+}
+
+impl RequestContext {
+    pub fn new<B>(req: &Request<B>, peer_addr: PeerAddr, timeout: Option<Duration>) -> Self {
+        Self {
+            request_id: uuid::Uuid::new_v4().to_string(),
+            start: Instant::now(),
+            method: req.method().to_string(),
+            uri: crate::utils::request_target(req.uri()),
+            user_agent: req
+                .headers()
+                .get(hyper::header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from),
+            headers: req
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+                .collect(),
+            peer_addr,
+            deadline: timeout.map(Deadline::after),
+            secure: false,
+            routing_rule: None,
+            location: None,
+            slo: Arc::default(),
+        }
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Access log entry for this request finishing with `status`, redacted per `[logging]`
+    pub fn log_entry(&self, status: u16, config: &LoggingConfig) -> RequestLog {
+        let mut log = RequestLog::redacted(
+            self.method.clone(),
+            &self.uri,
+            status,
+            self.elapsed().as_millis() as u64,
+            self.peer_addr.to_string(),
+            config,
+        )
+        .with_user_agent(self.user_agent.as_deref(), config)
+        .with_headers(self.headers.iter().map(|(name, value)| (name.as_str(), value.as_str())), config)
+        .with_routing_rule(self.routing_rule.clone());
+        log.request_id = self.request_id.clone();
+        log
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn logging(extra: &str) -> LoggingConfig {
+        toml::from_str(extra).unwrap()
+    }
+
+    #[test]
+    fn test_context_from_request() {
+        let req = Request::builder()
+            .method("POST")
+            .uri("/login.php?token=secret&next=/")
+            .header("user-agent", "curl/8.0")
+            .body(())
+            .unwrap();
+        let peer = PeerAddr::from_tcp("192.0.2.7:51000".parse().unwrap());
+
+        let ctx = RequestContext::new(&req, peer, Some(Duration::from_secs(5)));
+
+        assert_eq!(ctx.method, "POST");
+        assert_eq!(ctx.uri, "/login.php?token=secret&next=/");
+        assert_eq!(ctx.user_agent.as_deref(), Some("curl/8.0"));
+        assert_eq!(ctx.peer_addr.ip(), Some("192.0.2.7".parse().unwrap()));
+        let remaining = ctx.deadline.unwrap().remaining();
+        assert!(remaining > Duration::from_secs(4) && remaining <= Duration::from_secs(5));
+        assert!(ctx.routing_rule.is_none());
+
+        let other = RequestContext::new(&req, PeerAddr::from_unix("/run/fe-php.sock"), None);
+        assert_ne!(ctx.request_id, other.request_id);
+        assert!(other.deadline.is_none());
+        assert_eq!(other.peer_addr.ip(), None);
+    }
+
+    #[test]
+    fn test_log_entry_shares_context() {
+        let req = Request::builder()
+            .uri("/index.php?token=secret")
+            .header("user-agent", "probe")
+            .header("authorization", "Bearer s3cr3t")
+            .header("cookie", "PHPSESSID=abc")
+            .header("x-api-key", "k3y")
+            .header("accept", "text/html")
+            .body(())
+            .unwrap();
+        let mut ctx = RequestContext::new(&req, PeerAddr::from_tcp("127.0.0.1:9000".parse().unwrap()), None);
+        ctx.routing_rule = Some("default -> embedded".to_string());
+
+        let config = logging("redact_query_params = [\"token\"]\nredact_headers = [\"user-agent\"]");
+        let log = ctx.log_entry(404, &config);
+
+        assert_eq!(log.request_id, ctx.request_id);
+        assert_eq!(log.method, "GET");
+        assert_eq!(log.status, 404);
+        assert_eq!(log.uri, "/index.php?token=[REDACTED]");
+        assert_eq!(log.user_agent.as_deref(), Some("[REDACTED]"));
+        // An explicit list replaces the defaults
+        assert_eq!(log.headers["authorization"], "Bearer s3cr3t");
+        assert_eq!(log.headers["user-agent"], "[REDACTED]");
+
+        let log = ctx.log_entry(404, &logging(""));
+        assert_eq!(log.user_agent.as_deref(), Some("probe"));
+        for name in ["authorization", "cookie", "x-api-key"] {
+            assert_eq!(log.headers[name], "[REDACTED]", "{}", name);
+        }
+        assert_eq!(log.headers["accept"], "text/html");
+        assert_eq!(log.remote_addr, "127.0.0.1:9000");
+        assert_eq!(log.routing_rule.as_deref(), Some("default -> embedded"));
+    }
+}
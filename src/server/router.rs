@@ -1,68 +1,74 @@
 use crate::config::Config;
-use crate::php::{WorkerPool, PhpRequest};
+use crate::logging::redaction::redact_uri;
 use crate::metrics::MetricsCollector;
-use crate::server::peer_addr::PeerAddr;
+use crate::php::{PhpRequest, WorkerPool};
+use crate::server::body::ResponseBody;
+use crate::server::request_context::RequestContext;
 use crate::utils::parse_headers;
 use anyhow::Result;
-use hyper::{Request, Response, StatusCode};
 use http_body_util::BodyExt;
+use hyper::{Request, Response, StatusCode};
 use std::sync::Arc;
-use tracing::{info, error};
+use tracing::{error, info};
 
 pub async fn handle_request<B>(
     req: Request<B>,
-    peer_addr: PeerAddr,
+    ctx: RequestContext,
     worker_pool: Arc<WorkerPool>,
     metrics: Arc<MetricsCollector>,
     config: Arc<Config>,
     admin_api: Option<Arc<crate::admin::AdminApi>>,
-) -> Result<Response<String>>
+    php_limit: Option<Arc<super::concurrency::PhpConcurrencyLimit>>,
+) -> Result<Response<ResponseBody>>
 where
     B: hyper::body::Body + Send + 'static,
     B::Data: Send,
     B::Error: Into<Box<dyn std::error::Error + Send + Sync>> + std::fmt::Display,
 {
-    let start = std::time::Instant::now();
-    let method = req.method().to_string();
-    let uri = req.uri().to_string();
-    let remote_addr = peer_addr.to_string();
+    let method = ctx.method.clone();
+    let uri = ctx.uri.clone();
 
-    metrics.inc_active_connections();
+    let _active = metrics.active_connection_guard();
 
     // Handle metrics endpoint
     if config.metrics.enable && uri == config.metrics.endpoint {
-        metrics.dec_active_connections();
-        return handle_metrics().await;
+        let accept = req
+            .headers()
+            .get(hyper::header::ACCEPT)
+            .and_then(|v| v.to_str().ok());
+        return handle_metrics(&metrics, accept).await;
     }
 
     // Handle health check
     if uri == "/_health" {
-        metrics.dec_active_connections();
-        return Ok(Response::new("OK".to_string()));
+        return handle_health_check(&worker_pool, &metrics);
     }
 
     // Convert Hyper request to PhpRequest
     let (parts, body) = req.into_parts();
 
-    let body_bytes = match body.collect().await {
+    let collected = match super::read_body_timed(&config, &metrics, &ctx, body.collect()).await {
+        Ok(collected) => collected,
+        Err(response) => return Ok(response),
+    };
+
+    let body_bytes = match collected {
         Ok(collected) => {
             let bytes = collected.to_bytes();
             // Check body size limit
             if bytes.len() > crate::utils::MAX_BODY_SIZE {
                 error!("Request body too large: {} bytes", bytes.len());
-                metrics.dec_active_connections();
                 return Ok(Response::builder()
                     .status(StatusCode::PAYLOAD_TOO_LARGE)
-                    .body("Request body too large".to_string())?);
+                    .body("Request body too large".into())?);
             }
             bytes.to_vec()
         }
         Err(e) => {
             error!("Failed to read request body: {}", e);
-            metrics.dec_active_connections();
             return Ok(Response::builder()
                 .status(StatusCode::BAD_REQUEST)
-                .body(format!("Bad Request: {}", e))?);
+                .body(format!("Bad Request: {}", e).into())?);
         }
     };
 
@@ -70,53 +76,80 @@ where
 
     let query_string = parts.uri.query().unwrap_or("").to_string();
 
-    let php_request = PhpRequest {
+    let mut php_request = PhpRequest {
         method: method.clone(),
         uri: uri.clone(),
         headers,
         body: body_bytes,
         query_string,
-        remote_addr: peer_addr.to_string(),
+        remote_addr: ctx.peer_addr.to_string(),
+    };
+    php_request.headers.insert(
+        crate::php::REQUEST_ID_HEADER.to_string(),
+        ctx.request_id.clone(),
+    );
+    if config.geoip.inject_headers {
+        super::headers::insert_geoip_headers(&mut php_request.headers, ctx.location.as_ref());
+    }
+    crate::php::method_override::apply(&mut php_request, &config.php.method_override);
+
+    let _php_permit = match php_limit {
+        Some(limit) => match limit.acquire().await {
+            Some(permit) => Some(permit),
+            None => {
+                metrics.record_request(
+                    &method,
+                    limit.saturated_status(),
+                    ctx.elapsed().as_secs_f64(),
+                );
+                return limit.saturated_response(&redact_uri(&uri, &config.logging));
+            }
+        },
+        None => None,
     };
 
     // Execute PHP
-    let php_response = match worker_pool.execute(php_request).await {
+    let mut php_response = match worker_pool.execute(php_request).await {
         Ok(response) => response,
         Err(e) => {
-            error!("PHP execution failed: {}", e);
-            metrics.dec_active_connections();
+            error!(request_id = %ctx.request_id, "PHP execution failed: {}", e);
 
-            let duration = start.elapsed().as_secs_f64();
-            let duration_ms = (duration * 1000.0) as u64;
-            metrics.record_request(&method, 500, duration);
+            metrics.record_request(&method, 500, ctx.elapsed().as_secs_f64());
 
             // Send error log to LogAnalyzer
             if let Some(ref api) = admin_api {
                 let log_analyzer = api.log_analyzer();
                 let mut analyzer = log_analyzer.write();
-                analyzer.add_log(crate::logging::structured::RequestLog::new(
-                    method.clone(),
-                    uri.clone(),
-                    500,
-                    duration_ms,
-                    remote_addr.clone(),
-                ));
+                analyzer.add_log(ctx.log_entry(500, &config.logging));
             }
 
             return Ok(Response::builder()
                 .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body(format!("Internal Server Error: {}", e))?);
+                .body(format!("Internal Server Error: {}", e).into())?);
         }
     };
 
-    let duration = start.elapsed().as_secs_f64();
-    let duration_ms = (duration * 1000.0) as u64;
-    metrics.record_request(&method, php_response.status_code, duration);
-    metrics.dec_active_connections();
+    super::headers::filter_php_headers(
+        &mut php_response,
+        &config.php.response_header_policy,
+        &ctx.request_id,
+    );
+    super::headers::limit_php_headers(
+        &mut php_response,
+        &config.php.response_headers,
+        &ctx.request_id,
+    );
+
+    metrics.record_request(
+        &method,
+        php_response.status_code,
+        ctx.elapsed().as_secs_f64(),
+    );
 
     info!(
+        request_id = %ctx.request_id,
         method = %method,
-        uri = %uri,
+        uri = %redact_uri(&uri, &config.logging),
         status = php_response.status_code,
         duration_ms = php_response.execution_time_ms,
         "Request completed"
@@ -126,15 +159,13 @@ where
     if let Some(ref api) = admin_api {
         let log_analyzer = api.log_analyzer();
         let mut analyzer = log_analyzer.write();
-        analyzer.add_log(crate::logging::structured::RequestLog::new(
-            method.clone(),
-            uri.clone(),
-            php_response.status_code,
-            duration_ms,
-            remote_addr.clone(),
-        ));
+        analyzer.add_log(ctx.log_entry(php_response.status_code, &config.logging));
     }
 
+    php_response
+        .headers
+        .apply_duplicate_policy(config.php.duplicate_headers);
+
     // Build response
     let mut response = Response::builder().status(php_response.status_code);
 
@@ -142,13 +173,45 @@ where
         response = response.header(name, value);
     }
 
-    Ok(response.body(String::from_utf8_lossy(&php_response.body).to_string())?)
+    Ok(response.body(php_response.body.into())?)
 }
 
-async fn handle_metrics() -> Result<Response<String>> {
-    let metrics_output = crate::metrics::export_metrics()?;
+async fn handle_metrics(
+    metrics: &MetricsCollector,
+    accept: Option<&str>,
+) -> Result<Response<ResponseBody>> {
+    let (metrics_output, content_type) =
+        crate::metrics::export_registry(&metrics.registry(), accept)?;
     Ok(Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Type", "text/plain; version=0.0.4")
-        .body(metrics_output)?)
+        .header("Content-Type", content_type)
+        .body(metrics_output.into())?)
+}
+
+/// `/_health` without the backend router: healthy while any PHP worker is alive
+fn handle_health_check(
+    worker_pool: &WorkerPool,
+    metrics: &MetricsCollector,
+) -> Result<Response<ResponseBody>> {
+    let live = worker_pool.live_workers();
+    let healthy = live > 0;
+
+    let response_body = serde_json::json!({
+        "status": if healthy { "healthy" } else { "degraded" },
+        "worker_pool": {
+            "healthy": healthy,
+            "live_workers": live,
+            "pool_size": worker_pool.size(),
+        },
+        "uptime_seconds": metrics.get_uptime_seconds(),
+    });
+
+    Ok(Response::builder()
+        .status(if healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        })
+        .header("Content-Type", "application/json")
+        .body(response_body.to_string().into())?)
 }
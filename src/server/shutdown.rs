@@ -1,8 +1,10 @@
 use anyhow::Result;
+use parking_lot::Mutex;
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
 use tracing::{info, warn};
 
 /// Shutdown coordinator for graceful server shutdown
@@ -13,6 +15,8 @@ pub struct ShutdownCoordinator {
     is_shutting_down: Arc<AtomicBool>,
     /// Number of active connections
     active_connections: Arc<AtomicUsize>,
+    /// Background tasks that stop on the shutdown broadcast and are awaited before exit
+    background_tasks: Mutex<Vec<(&'static str, JoinHandle<()>)>>,
     /// Graceful shutdown timeout
     timeout: Duration,
 }
@@ -25,6 +29,7 @@ impl ShutdownCoordinator {
             shutdown_tx,
             is_shutting_down: Arc::new(AtomicBool::new(false)),
             active_connections: Arc::new(AtomicUsize::new(0)),
+            background_tasks: Mutex::new(Vec::new()),
             timeout: Duration::from_secs(timeout_secs),
         }
     }
@@ -54,6 +59,13 @@ impl ShutdownCoordinator {
         self.active_connections.load(Ordering::SeqCst)
     }
 
+    /// Await `handle` on shutdown; the task should finish once it receives the
+    /// shutdown broadcast (see [`subscribe`](Self::subscribe)) and is aborted if it
+    /// outlives the timeout
+    pub fn register(&self, name: &'static str, handle: JoinHandle<()>) {
+        self.background_tasks.lock().push((name, handle));
+    }
+
     /// Initiate graceful shutdown
     pub async fn shutdown(&self) -> Result<()> {
         info!("Initiating graceful shutdown...");
@@ -64,13 +76,29 @@ impl ShutdownCoordinator {
         // Broadcast shutdown signal to all tasks
         let _ = self.shutdown_tx.send(());
 
-        // Wait for active connections to complete
-        self.wait_for_connections().await
+        // Wait for active connections, then background tasks, within one timeout
+        let start = Instant::now();
+        self.wait_for_connections(start).await?;
+        self.wait_for_background_tasks(start).await;
+        Ok(())
     }
 
-    async fn wait_for_connections(&self) -> Result<()> {
-        let start = Instant::now();
+    async fn wait_for_background_tasks(&self, start: Instant) {
+        let tasks = std::mem::take(&mut *self.background_tasks.lock());
+        for (name, mut handle) in tasks {
+            let remaining = self.timeout.saturating_sub(start.elapsed());
+            match tokio::time::timeout(remaining, &mut handle).await {
+                Ok(Ok(())) => info!("Background task '{}' stopped", name),
+                Ok(Err(e)) => warn!("Background task '{}' failed during shutdown: {}", name, e),
+                Err(_) => {
+                    warn!("Background task '{}' did not stop before the shutdown timeout, aborting", name);
+                    handle.abort();
+                }
+            }
+        }
+    }
 
+    async fn wait_for_connections(&self, start: Instant) -> Result<()> {
         loop {
             let active = self.active_connections.load(Ordering::SeqCst);
 
@@ -149,4 +177,32 @@ mod tests {
         assert!(result.is_ok());
         assert!(coordinator.is_shutting_down());
     }
+
+    #[tokio::test]
+    async fn test_shutdown_waits_for_background_cleanup() {
+        let coordinator = ShutdownCoordinator::new(5);
+        let cleaned_up = Arc::new(AtomicBool::new(false));
+
+        let mut shutdown_rx = coordinator.subscribe();
+        let flag = Arc::clone(&cleaned_up);
+        coordinator.register("worker", tokio::spawn(async move {
+            let _ = shutdown_rx.recv().await;
+            // Stands in for flushing a write in progress
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            flag.store(true, Ordering::SeqCst);
+        }));
+
+        coordinator.shutdown().await.unwrap();
+        assert!(cleaned_up.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn test_stuck_background_task_is_aborted() {
+        let coordinator = ShutdownCoordinator::new(1);
+        coordinator.register("stuck", tokio::spawn(std::future::pending()));
+
+        let start = Instant::now();
+        coordinator.shutdown().await.unwrap();
+        assert!(start.elapsed() < Duration::from_secs(3));
+    }
 }
@@ -0,0 +1,114 @@
+use crate::config::ServerConfig;
+use anyhow::{Context, Result};
+use nix::unistd::{Group, User};
+use socket2::SockRef;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::net::TcpStream;
+use tracing::debug;
+
+/// Apply `server.tcp_nodelay` and the socket buffer sizes to an accepted stream.
+/// Failures are logged and the connection is served with the OS defaults.
+pub fn apply(stream: &TcpStream, config: &ServerConfig) {
+    if let Err(e) = stream.set_nodelay(config.tcp_nodelay) {
+        debug!("Failed to set TCP_NODELAY: {}", e);
+    }
+
+    let socket = SockRef::from(stream);
+    if let Some(size) = config.send_buffer_size {
+        if let Err(e) = socket.set_send_buffer_size(size) {
+            debug!("Failed to set SO_SNDBUF to {}: {}", size, e);
+        }
+    }
+    if let Some(size) = config.recv_buffer_size {
+        if let Err(e) = socket.set_recv_buffer_size(size) {
+            debug!("Failed to set SO_RCVBUF to {}: {}", size, e);
+        }
+    }
+}
+
+/// Apply `server.unix_socket_mode`, `unix_socket_owner` and `unix_socket_group`
+/// to a freshly bound Unix socket so a fronting proxy can connect to it
+pub fn apply_unix_permissions(path: &Path, config: &ServerConfig) -> Result<()> {
+    let owner = config.unix_socket_owner.as_deref().map(resolve_uid).transpose()?;
+    let group = config.unix_socket_group.as_deref().map(resolve_gid).transpose()?;
+    if owner.is_some() || group.is_some() {
+        std::os::unix::fs::chown(path, owner, group)
+            .with_context(|| format!("Failed to change ownership of {:?}", path))?;
+    }
+
+    // After chown, which may clear the setuid/setgid bits
+    if let Some(mode) = config.unix_socket_permissions()? {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+            .with_context(|| format!("Failed to set mode {:o} on {:?}", mode, path))?;
+    }
+    Ok(())
+}
+
+fn resolve_uid(owner: &str) -> Result<u32> {
+    if let Ok(uid) = owner.parse() {
+        return Ok(uid);
+    }
+    User::from_name(owner)
+        .with_context(|| format!("Failed to look up user '{}'", owner))?
+        .map(|user| user.uid.as_raw())
+        .with_context(|| format!("Unknown unix_socket_owner '{}'", owner))
+}
+
+fn resolve_gid(group: &str) -> Result<u32> {
+    if let Ok(gid) = group.parse() {
+        return Ok(gid);
+    }
+    Group::from_name(group)
+        .with_context(|| format!("Failed to look up group '{}'", group))?
+        .map(|group| group.gid.as_raw())
+        .with_context(|| format!("Unknown unix_socket_group '{}'", group))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server_config(extra: &str) -> ServerConfig {
+        toml::from_str(extra).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_options_applied_to_accepted_stream() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let _client = TcpStream::connect(addr).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let config = server_config("send_buffer_size = 262144\nrecv_buffer_size = 131072");
+        assert!(config.tcp_nodelay);
+        apply(&stream, &config);
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        // Linux reports double the requested size to account for bookkeeping
+        assert!(socket.send_buffer_size().unwrap() >= 262144);
+        assert!(socket.recv_buffer_size().unwrap() >= 131072);
+
+        apply(&stream, &server_config("tcp_nodelay = false"));
+        assert!(!socket.nodelay().unwrap());
+    }
+
+    #[test]
+    fn test_unix_socket_mode_and_group_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("fe-php.sock");
+        let _listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let gid = nix::unistd::getgid().as_raw();
+        let config = server_config(&format!("unix_socket_mode = \"0660\"\nunix_socket_group = \"{}\"", gid));
+        apply_unix_permissions(&path, &config).unwrap();
+
+        let metadata = std::fs::metadata(&path).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o660);
+        assert_eq!(std::os::unix::fs::MetadataExt::gid(&metadata), gid);
+
+        assert!(apply_unix_permissions(&path, &server_config("unix_socket_mode = \"rw-rw----\"")).is_err());
+        assert!(apply_unix_permissions(&path, &server_config("unix_socket_owner = \"no-such-user-fe-php\"")).is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! One structured record of what a started server is actually running with
+
+use super::{BoundListener, Server};
+use crate::config::PageCacheStorage;
+use serde::Serialize;
+use std::path::PathBuf;
+use tracing::{info, warn};
+
+#[derive(Debug, Serialize)]
+pub struct StartupSummary {
+    pub version: &'static str,
+    pub listeners: Vec<String>,
+    /// PHP workers that started, which excludes any that failed to initialize
+    pub workers: usize,
+    pub backends: Vec<BackendSummary>,
+    pub default_backend: Option<String>,
+    pub waf: Option<WafSummary>,
+    pub tls: bool,
+    pub geoip: bool,
+    pub redis: bool,
+    pub tracing: bool,
+    pub load_balancing: bool,
+    pub deployment: bool,
+    pub maintenance: bool,
+    pub limits: LimitsSummary,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackendSummary {
+    pub name: String,
+    pub root: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WafSummary {
+    pub mode: String,
+    pub rules: usize,
+}
+
+/// Limits after defaults are applied; `None` means unlimited
+#[derive(Debug, Serialize)]
+pub struct LimitsSummary {
+    pub request_timeout_ms: Option<u64>,
+    pub body_read_timeout_ms: Option<u64>,
+    pub php_max_concurrent: Option<usize>,
+    pub http1_max_pipelined: Option<usize>,
+    pub http2_max_concurrent_streams: u32,
+    pub max_body_size: usize,
+}
+
+impl StartupSummary {
+    pub(super) fn new(server: &Server, listeners: &[BoundListener]) -> Self {
+        let config = &server.config;
+
+        let backends = match server.backend_router {
+            Some(ref router) => {
+                let mut backends: Vec<BackendSummary> = router
+                    .backends()
+                    .keys()
+                    .map(|backend_type| {
+                        use crate::backend::BackendType;
+                        let root = match backend_type {
+                            BackendType::Embedded => Some(config.embedded_document_root().to_path_buf()),
+                            BackendType::FastCGI => Some(config.fastcgi_document_root().to_path_buf()),
+                            BackendType::Static => config.backend.static_files.root.clone(),
+                            BackendType::Cache => (config.backend.cache.storage == PageCacheStorage::Disk)
+                                .then(|| config.backend.cache.dir.clone()),
+                        };
+                        BackendSummary { name: backend_type.to_string(), root }
+                    })
+                    .collect();
+                backends.sort_by(|a, b| a.name.cmp(&b.name));
+                backends
+            }
+            None => vec![BackendSummary {
+                name: if config.php.use_fpm { "fastcgi" } else { "embedded" }.to_string(),
+                root: Some(config.php.document_root.clone()),
+            }],
+        };
+
+        Self {
+            version: crate::VERSION,
+            listeners: listeners.iter().map(describe_listener).collect(),
+            workers: server.worker_pool.live_workers(),
+            backends,
+            default_backend: server
+                .backend_router
+                .as_ref()
+                .map(|_| config.backend.default_backend.clone()),
+            waf: server.waf_engine.as_ref().map(|waf| WafSummary {
+                mode: config.waf.mode.to_string(),
+                rules: waf.rules_count(),
+            }),
+            tls: server.tls_manager.is_some(),
+            geoip: server.geoip_manager.is_some(),
+            redis: server._redis_manager.is_some(),
+            tracing: config.tracing.enable,
+            load_balancing: server.load_balancer.is_some(),
+            deployment: server._deployment_manager.is_some(),
+            maintenance: server.maintenance.is_enabled(),
+            limits: LimitsSummary {
+                request_timeout_ms: config.server.request_timeout_ms,
+                body_read_timeout_ms: config.server.body_read_timeout_ms,
+                php_max_concurrent: config.php.max_concurrent,
+                http1_max_pipelined: config.server.http1.max_pipelined,
+                http2_max_concurrent_streams: config.server.http2.max_concurrent_streams,
+                max_body_size: crate::utils::MAX_BODY_SIZE,
+            },
+        }
+    }
+
+    /// Emit the summary as a single `info` event carrying it as JSON
+    pub fn log(&self) {
+        match serde_json::to_string(self) {
+            Ok(summary) => info!(summary = %summary, "Startup summary"),
+            Err(e) => warn!("Failed to serialize startup summary: {}", e),
+        }
+    }
+}
+
+fn describe_listener(listener: &BoundListener) -> String {
+    let protocol = |http2: bool| if http2 { "HTTP/2" } else { "HTTP/1.1" };
+    match listener {
+        BoundListener::Tcp { listener, tls_acceptor, http2 } => format!(
+            "{}://{} ({})",
+            if tls_acceptor.is_some() { "https" } else { "http" },
+            listener.local_addr().map(|a| a.to_string()).unwrap_or_default(),
+            protocol(*http2)
+        ),
+        BoundListener::Unix { path, tls_acceptor, http2, .. } => format!(
+            "unix://{} ({}{})",
+            path.display(),
+            protocol(*http2),
+            if tls_acceptor.is_some() { ", TLS" } else { "" }
+        ),
+    }
+}
@@ -0,0 +1,483 @@
+//! Certificates from an ACME CA (RFC 8555) via the HTTP-01 challenge (`[tls.acme]`)
+//!
+//! The issued chain and its key are cached in `cache_dir` next to the account key
+//! and installed as the default certificate without a restart. Until the first one
+//! is issued, a short-lived self-signed placeholder for the domains is served.
+
+use super::{expiry, x509, TlsManager};
+use crate::config::AcmeConfig;
+use crate::metrics::MetricsCollector;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{Datelike, Utc};
+use parking_lot::RwLock;
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+const LETS_ENCRYPT: &str = "https://acme-v02.api.letsencrypt.org/directory";
+const LETS_ENCRYPT_STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+
+/// Renew once the certificate expires within this window
+const RENEW_BEFORE: Duration = Duration::from_secs(30 * 86_400);
+/// How often the served certificate is compared against `RENEW_BEFORE`
+const CHECK_INTERVAL: Duration = Duration::from_secs(12 * 3600);
+/// Wait after a failed attempt before asking the CA again
+const RETRY_INTERVAL: Duration = Duration::from_secs(3600);
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const POLL_ATTEMPTS: usize = 30;
+
+/// Path prefix the CA fetches key authorizations from
+pub const CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// Pending HTTP-01 key authorizations by token, answered by the port-80 server
+#[derive(Clone, Default)]
+pub struct Http01Challenges(Arc<RwLock<HashMap<String, String>>>);
+
+impl Http01Challenges {
+    /// Key authorization for a request to `path`, if it names a pending challenge
+    pub fn respond(&self, path: &str) -> Option<String> {
+        let token = path.strip_prefix(CHALLENGE_PATH)?;
+        self.0.read().get(token).cloned()
+    }
+
+    fn insert(&self, token: &str, key_authorization: String) {
+        self.0.write().insert(token.to_string(), key_authorization);
+    }
+
+    fn remove(&self, token: &str) {
+        self.0.write().remove(token);
+    }
+}
+
+/// Cached certificate chain and private key
+pub fn certificate_paths(config: &AcmeConfig) -> (PathBuf, PathBuf) {
+    (config.cache_dir.join("cert.pem"), config.cache_dir.join("key.pem"))
+}
+
+/// Certificate and key to start with: the cached ones, or a self-signed placeholder
+/// that expires tomorrow so the first check replaces it
+pub fn ensure_placeholder(config: &AcmeConfig) -> Result<(PathBuf, PathBuf)> {
+    let (cert_path, key_path) = certificate_paths(config);
+    if cert_path.exists() && key_path.exists() {
+        return Ok((cert_path, key_path));
+    }
+
+    std::fs::create_dir_all(&config.cache_dir)
+        .with_context(|| format!("Failed to create ACME cache directory {}", config.cache_dir.display()))?;
+    let tomorrow = Utc::now() + chrono::Duration::days(1);
+    let mut params = rcgen::CertificateParams::new(config.domains.clone());
+    params.not_after = rcgen::date_time_ymd(tomorrow.year(), tomorrow.month() as u8, tomorrow.day() as u8);
+    let placeholder = rcgen::Certificate::from_params(params)?;
+    write_private(&key_path, placeholder.serialize_private_key_pem().as_bytes())?;
+    std::fs::write(&cert_path, placeholder.serialize_pem()?)
+        .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+    info!("Serving a self-signed placeholder certificate until ACME issues one");
+    Ok((cert_path, key_path))
+}
+
+/// Obtains and renews the default certificate
+pub struct AcmeManager {
+    config: AcmeConfig,
+    directory_url: &'static str,
+    challenges: Http01Challenges,
+    tls: Arc<TlsManager>,
+    http: reqwest::Client,
+}
+
+impl AcmeManager {
+    pub fn new(config: AcmeConfig, tls: Arc<TlsManager>) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(30))
+            .user_agent(format!("fe-php/{}", crate::VERSION))
+            .build()
+            .context("Failed to build ACME HTTP client")?;
+        Ok(Self {
+            directory_url: if config.staging { LETS_ENCRYPT_STAGING } else { LETS_ENCRYPT },
+            config,
+            challenges: Http01Challenges::default(),
+            tls,
+            http,
+        })
+    }
+
+    /// Challenges the port-80 server must answer
+    pub fn challenges(&self) -> Http01Challenges {
+        self.challenges.clone()
+    }
+
+    /// Renew now if due, then check every 12 hours until `shutdown` fires. Also keeps
+    /// the expiry gauge for the default certificate current.
+    pub fn spawn(
+        self: Arc<Self>,
+        warn_within: Duration,
+        metrics: Arc<MetricsCollector>,
+        mut shutdown: broadcast::Receiver<()>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let (cert_path, _) = certificate_paths(&self.config);
+            loop {
+                let wait = match self.renew_if_due().await {
+                    Ok(()) => CHECK_INTERVAL,
+                    Err(e) => {
+                        warn!(domains = ?self.config.domains, "ACME certificate renewal failed: {:#}", e);
+                        RETRY_INTERVAL
+                    }
+                };
+                if let Ok(der) = leaf_der(&cert_path) {
+                    let _ = expiry::check("default", &der, warn_within, Utc::now().timestamp(), &metrics);
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = shutdown.recv() => return,
+                }
+            }
+        })
+    }
+
+    async fn renew_if_due(&self) -> Result<()> {
+        let (cert_path, key_path) = certificate_paths(&self.config);
+        let due = leaf_der(&cert_path)
+            .and_then(|der| x509::not_after(&der))
+            .map_or(true, |expires| expires - Utc::now().timestamp() <= RENEW_BEFORE.as_secs() as i64);
+        if !due {
+            return Ok(());
+        }
+
+        info!(domains = ?self.config.domains, "Requesting certificate from {}", self.directory_url);
+        let (chain, key) = self.order().await?;
+
+        // Swap both files in by rename so a reader never sees half of one
+        let new_key = key_path.with_extension("pem.new");
+        let new_cert = cert_path.with_extension("pem.new");
+        write_private(&new_key, key.as_bytes())?;
+        std::fs::write(&new_cert, chain)
+            .with_context(|| format!("Failed to write {}", new_cert.display()))?;
+        std::fs::rename(&new_key, &key_path)?;
+        std::fs::rename(&new_cert, &cert_path)?;
+
+        self.tls.reload_default_certificate(&cert_path, &key_path)?;
+        info!(domains = ?self.config.domains, "Installed certificate issued via ACME");
+        Ok(())
+    }
+
+    /// Run one order to completion: (PEM chain, PEM private key)
+    async fn order(&self) -> Result<(String, String)> {
+        let key = AccountKey::load_or_create(&self.config.cache_dir.join("account.key"))?;
+        let directory: Value = self.http.get(self.directory_url).send().await?
+            .error_for_status()?
+            .json().await?;
+        let endpoint = |name: &str| {
+            directory[name].as_str().with_context(|| format!("ACME directory has no {}", name))
+        };
+        let mut session = Session {
+            http: &self.http,
+            key: &key,
+            new_nonce_url: endpoint("newNonce")?.to_string(),
+            nonce: None,
+            kid: None,
+        };
+
+        // Returns the existing account when this key already has one
+        let mut account = json!({ "termsOfServiceAgreed": true });
+        if let Some(ref email) = self.config.email {
+            account["contact"] = json!([format!("mailto:{}", email)]);
+        }
+        let response = session.post(endpoint("newAccount")?, Some(&account)).await?;
+        session.kid = Some(location(&response)?);
+
+        let identifiers: Vec<Value> = self.config.domains.iter()
+            .map(|domain| json!({ "type": "dns", "value": domain }))
+            .collect();
+        let response = session.post(endpoint("newOrder")?, Some(&json!({ "identifiers": identifiers }))).await?;
+        let order_url = location(&response)?;
+        let order: Value = response.json().await?;
+
+        let thumbprint = key.thumbprint();
+        for authorization in order["authorizations"].as_array().context("ACME order has no authorizations")? {
+            let url = authorization.as_str().context("Malformed ACME authorization URL")?;
+            self.authorize(&mut session, url, &thumbprint).await?;
+        }
+
+        let mut params = rcgen::CertificateParams::new(self.config.domains.clone());
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.distinguished_name.push(rcgen::DnType::CommonName, self.config.domains[0].clone());
+        let certificate = rcgen::Certificate::from_params(params)?;
+        let csr = certificate.serialize_request_der()?;
+        let finalize = order["finalize"].as_str().context("ACME order has no finalize URL")?;
+        session.post(finalize, Some(&json!({ "csr": URL_SAFE_NO_PAD.encode(csr) }))).await?;
+
+        let order = poll(&mut session, &order_url, "order").await?;
+        let certificate_url = order["certificate"].as_str().context("ACME order has no certificate URL")?;
+        let chain = session.post(certificate_url, None).await?.text().await?;
+        Ok((chain, certificate.serialize_private_key_pem()))
+    }
+
+    /// Complete the HTTP-01 challenge of one authorization
+    async fn authorize(&self, session: &mut Session<'_>, url: &str, thumbprint: &str) -> Result<()> {
+        let authorization: Value = session.post(url, None).await?.json().await?;
+        if authorization["status"] == "valid" {
+            return Ok(());
+        }
+        let challenge = authorization["challenges"].as_array()
+            .and_then(|challenges| challenges.iter().find(|c| c["type"] == "http-01"))
+            .with_context(|| format!("No http-01 challenge offered for {}", authorization["identifier"]["value"]))?;
+        let token = challenge["token"].as_str().context("ACME challenge has no token")?;
+        let challenge_url = challenge["url"].as_str().context("ACME challenge has no URL")?;
+
+        self.challenges.insert(token, format!("{}.{}", token, thumbprint));
+        let result = async {
+            session.post(challenge_url, Some(&json!({}))).await?;
+            poll(session, url, "authorization").await
+        }.await;
+        self.challenges.remove(token);
+        result.map(|_| ())
+    }
+}
+
+/// ES256 account key
+struct AccountKey {
+    pair: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl AccountKey {
+    fn load_or_create(path: &Path) -> Result<Self> {
+        let pkcs8 = match std::fs::read_to_string(path) {
+            Ok(pem) => rcgen::KeyPair::from_pem(&pem)
+                .with_context(|| format!("Invalid ACME account key in {}", path.display()))?
+                .serialize_der(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let generated = rcgen::KeyPair::generate(&rcgen::PKCS_ECDSA_P256_SHA256)?;
+                write_private(path, generated.serialize_pem().as_bytes())?;
+                info!("Created ACME account key {}", path.display());
+                generated.serialize_der()
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Self::from_pkcs8(&pkcs8)
+    }
+
+    fn from_pkcs8(pkcs8: &[u8]) -> Result<Self> {
+        let rng = SystemRandom::new();
+        let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8, &rng)
+            .map_err(|e| anyhow!("Unusable ACME account key: {}", e))?;
+        Ok(Self { pair, rng })
+    }
+
+    /// Public key coordinates, base64url
+    fn coordinates(&self) -> (String, String) {
+        // Uncompressed point: 0x04 || x || y
+        let point = self.pair.public_key().as_ref();
+        (URL_SAFE_NO_PAD.encode(&point[1..33]), URL_SAFE_NO_PAD.encode(&point[33..65]))
+    }
+
+    fn jwk(&self) -> Value {
+        let (x, y) = self.coordinates();
+        json!({ "crv": "P-256", "kty": "EC", "x": x, "y": y })
+    }
+
+    /// RFC 7638 thumbprint, the second half of every key authorization
+    fn thumbprint(&self) -> String {
+        let (x, y) = self.coordinates();
+        // Required members in lexicographic order, no whitespace
+        let canonical = format!(r#"{{"crv":"P-256","kty":"EC","x":"{}","y":"{}"}}"#, x, y);
+        URL_SAFE_NO_PAD.encode(ring::digest::digest(&ring::digest::SHA256, canonical.as_bytes()))
+    }
+
+    /// Flattened JWS for `url`; `payload` `None` is a POST-as-GET. Before the
+    /// account exists (`kid` unset) the public key goes along instead.
+    fn sign(&self, url: &str, nonce: &str, kid: Option<&str>, payload: Option<&Value>) -> Result<String> {
+        let mut protected = json!({ "alg": "ES256", "nonce": nonce, "url": url });
+        match kid {
+            Some(kid) => protected["kid"] = json!(kid),
+            None => protected["jwk"] = self.jwk(),
+        }
+        let protected = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&protected)?);
+        let payload = match payload {
+            Some(payload) => URL_SAFE_NO_PAD.encode(serde_json::to_vec(payload)?),
+            None => String::new(),
+        };
+        let signature = self.pair
+            .sign(&self.rng, format!("{}.{}", protected, payload).as_bytes())
+            .map_err(|_| anyhow!("Failed to sign ACME request"))?;
+        Ok(json!({
+            "protected": protected,
+            "payload": payload,
+            "signature": URL_SAFE_NO_PAD.encode(signature.as_ref()),
+        }).to_string())
+    }
+}
+
+/// Signed requests to the CA, carrying the replay nonce from one to the next
+struct Session<'a> {
+    http: &'a reqwest::Client,
+    key: &'a AccountKey,
+    new_nonce_url: String,
+    nonce: Option<String>,
+    kid: Option<String>,
+}
+
+impl Session<'_> {
+    async fn post(&mut self, url: &str, payload: Option<&Value>) -> Result<reqwest::Response> {
+        let mut retried = false;
+        loop {
+            let nonce = match self.nonce.take() {
+                Some(nonce) => nonce,
+                None => self.fresh_nonce().await?,
+            };
+            let body = self.key.sign(url, &nonce, self.kid.as_deref(), payload)?;
+            let response = self.http.post(url)
+                .header(reqwest::header::CONTENT_TYPE, "application/jose+json")
+                .body(body)
+                .send().await
+                .with_context(|| format!("ACME request to {} failed", url))?;
+            self.nonce = replay_nonce(&response);
+            if response.status().is_success() {
+                return Ok(response);
+            }
+
+            let status = response.status();
+            let problem: Value = response.json().await.unwrap_or_default();
+            // A rejected nonce is worth one retry with the fresh one (RFC 8555 section 6.5)
+            if !retried && problem["type"] == "urn:ietf:params:acme:error:badNonce" {
+                retried = true;
+                continue;
+            }
+            bail!("ACME request to {} failed with {}: {}", url, status, problem["detail"].as_str().unwrap_or("no detail"));
+        }
+    }
+
+    async fn fresh_nonce(&self) -> Result<String> {
+        let response = self.http.head(&self.new_nonce_url).send().await?;
+        replay_nonce(&response).context("ACME server sent no Replay-Nonce")
+    }
+}
+
+/// POST-as-GET `url` until its status settles
+async fn poll(session: &mut Session<'_>, url: &str, what: &str) -> Result<Value> {
+    for _ in 0..POLL_ATTEMPTS {
+        let resource: Value = session.post(url, None).await?.json().await?;
+        match resource["status"].as_str() {
+            Some("valid") => return Ok(resource),
+            Some("invalid") => bail!("ACME {} {} is invalid: {}", what, url, resource["error"]),
+            _ => tokio::time::sleep(POLL_INTERVAL).await,
+        }
+    }
+    bail!("ACME {} {} still pending after {} checks", what, url, POLL_ATTEMPTS)
+}
+
+fn replay_nonce(response: &reqwest::Response) -> Option<String> {
+    response.headers()
+        .get("replay-nonce")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn location(response: &reqwest::Response) -> Result<String> {
+    response.headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+        .context("ACME response has no Location header")
+}
+
+/// First certificate of a PEM chain, DER
+fn leaf_der(cert_path: &Path) -> Result<Vec<u8>> {
+    let pem = std::fs::read(cert_path)?;
+    rustls_pemfile::certs(&mut pem.as_slice())?
+        .into_iter()
+        .next()
+        .with_context(|| format!("No certificate in {}", cert_path.display()))
+}
+
+/// Write a file readable by the owner only
+fn write_private(path: &Path, contents: &[u8]) -> Result<()> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::signature::{UnparsedPublicKey, ECDSA_P256_SHA256_FIXED};
+
+    #[test]
+    fn test_account_key_and_jws() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("account.key");
+        let key = AccountKey::load_or_create(&path).unwrap();
+        // Reloading gives the same account
+        assert_eq!(AccountKey::load_or_create(&path).unwrap().thumbprint(), key.thumbprint());
+
+        let jws: Value = serde_json::from_str(
+            &key.sign("https://ca.test/new-order", "n0nce", Some("https://ca.test/acct/1"), Some(&json!({ "a": 1 }))).unwrap(),
+        ).unwrap();
+        let decode = |field: &str| URL_SAFE_NO_PAD.decode(jws[field].as_str().unwrap()).unwrap();
+        let protected: Value = serde_json::from_slice(&decode("protected")).unwrap();
+        assert_eq!(protected["alg"], "ES256");
+        assert_eq!(protected["nonce"], "n0nce");
+        assert_eq!(protected["kid"], "https://ca.test/acct/1");
+        assert!(protected.get("jwk").is_none());
+        assert_eq!(decode("payload"), br#"{"a":1}"#);
+
+        let signed = format!("{}.{}", jws["protected"].as_str().unwrap(), jws["payload"].as_str().unwrap());
+        UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, key.pair.public_key().as_ref())
+            .verify(signed.as_bytes(), &decode("signature"))
+            .unwrap();
+
+        // Without an account the JWK is embedded; POST-as-GET has an empty payload
+        let jws: Value = serde_json::from_str(&key.sign("https://ca.test/new-acct", "n", None, None).unwrap()).unwrap();
+        let protected: Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(jws["protected"].as_str().unwrap()).unwrap()).unwrap();
+        assert_eq!(protected["jwk"], key.jwk());
+        assert_eq!(jws["payload"], "");
+    }
+
+    #[test]
+    fn test_placeholder_is_due_for_renewal() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = AcmeConfig {
+            enable: true,
+            domains: vec!["shop.example".to_string()],
+            cache_dir: dir.path().join("acme"),
+            ..AcmeConfig::default()
+        };
+        let (cert_path, key_path) = ensure_placeholder(&config).unwrap();
+        assert!(TlsManager::new(&cert_path, &key_path).is_ok());
+        let expires = x509::not_after(&leaf_der(&cert_path).unwrap()).unwrap();
+        assert!(expires - Utc::now().timestamp() < RENEW_BEFORE.as_secs() as i64);
+
+        // An existing certificate is left alone
+        std::fs::write(&cert_path, "cached").unwrap();
+        ensure_placeholder(&config).unwrap();
+        assert_eq!(std::fs::read_to_string(&cert_path).unwrap(), "cached");
+    }
+
+    #[test]
+    fn test_challenge_responses() {
+        let challenges = Http01Challenges::default();
+        challenges.insert("tok3n", "tok3n.thumb".to_string());
+        assert_eq!(challenges.respond("/.well-known/acme-challenge/tok3n").as_deref(), Some("tok3n.thumb"));
+        assert_eq!(challenges.respond("/.well-known/acme-challenge/other"), None);
+        assert_eq!(challenges.respond("/tok3n"), None);
+        challenges.remove("tok3n");
+        assert_eq!(challenges.respond("/.well-known/acme-challenge/tok3n"), None);
+    }
+}
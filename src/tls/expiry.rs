@@ -0,0 +1,91 @@
+//! Certificate expiry tracking (`tls_certificate_expiry_timestamp_seconds`)
+
+use crate::metrics::MetricsCollector;
+use super::x509::not_after;
+use anyhow::Result;
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// How often a loaded certificate is re-checked against the warning window
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Publish the expiry of the certificate served for `sni` and warn when it falls
+/// within `warn_within` of `now`. Returns whether the warning fired.
+pub fn check(sni: &str, der: &[u8], warn_within: Duration, now: i64, metrics: &MetricsCollector) -> Result<bool> {
+    let expires = not_after(der)?;
+    metrics.set_tls_certificate_expiry(sni, expires);
+
+    let remaining = expires - now;
+    if remaining <= warn_within.as_secs() as i64 {
+        if remaining <= 0 {
+            warn!(sni = %sni, "TLS certificate expired {} day(s) ago", -remaining / 86_400);
+        } else {
+            warn!(sni = %sni, "TLS certificate expires in {} day(s)", remaining / 86_400);
+        }
+        return Ok(true);
+    }
+    info!(sni = %sni, "TLS certificate valid for {} more day(s)", remaining / 86_400);
+    Ok(false)
+}
+
+/// Check `der` now and then every hour until `shutdown` fires
+pub fn spawn_checks(
+    sni: String,
+    der: Vec<u8>,
+    warn_within: Duration,
+    metrics: Arc<MetricsCollector>,
+    mut shutdown: broadcast::Receiver<()>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = check(&sni, &der, warn_within, Utc::now().timestamp(), &metrics) {
+                warn!(sni = %sni, "Failed to read TLS certificate expiry: {:#}", e);
+                return;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(CHECK_INTERVAL) => {}
+                _ = shutdown.recv() => return,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn certificate(year: i32, month: u8, day: u8) -> Vec<u8> {
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params.not_after = rcgen::date_time_ymd(year, month, day);
+        rcgen::Certificate::from_params(params).unwrap().serialize_der().unwrap()
+    }
+
+    #[test]
+    fn test_expiry_gauge_and_warning() {
+        let metrics = MetricsCollector::new();
+        let day = 86_400;
+
+        // UTCTime encoding
+        let der = certificate(2031, 3, 15);
+        let expires = Utc.with_ymd_and_hms(2031, 3, 15, 0, 0, 0).unwrap().timestamp();
+        assert_eq!(not_after(&der).unwrap(), expires);
+
+        let window = Duration::from_secs(14 * day as u64);
+        assert!(!check("example.com", &der, window, expires - 30 * day, &metrics).unwrap());
+        assert_eq!(metrics.get_tls_certificate_expiry("example.com"), expires);
+        assert!(check("example.com", &der, window, expires - 3 * day, &metrics).unwrap());
+        assert!(check("example.com", &der, window, expires + day, &metrics).unwrap());
+
+        // GeneralizedTime from 2050 on
+        let der = certificate(2051, 1, 2);
+        let expires = Utc.with_ymd_and_hms(2051, 1, 2, 0, 0, 0).unwrap().timestamp();
+        assert_eq!(not_after(&der).unwrap(), expires);
+
+        assert!(not_after(&der[..40]).is_err());
+    }
+}
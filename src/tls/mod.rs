@@ -1,58 +1,122 @@
+pub mod acme;
+pub mod expiry;
+pub mod sni;
+pub mod x509;
+
+use crate::config::SniCertConfig;
+use crate::metrics::MetricsCollector;
 use anyhow::{Context, Result};
-use rustls::{ServerConfig, Certificate, PrivateKey};
-use rustls_pemfile::{certs, pkcs8_private_keys};
+use rustls::server::{AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient};
+use rustls::sign::{any_supported_type, CertifiedKey};
+use rustls::{ServerConfig, Certificate, PrivateKey, RootCertStore};
+use rustls_pemfile::{certs, ec_private_keys, pkcs8_private_keys, rsa_private_keys};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use sni::SniResolver;
 
 /// TLS configuration manager for handling SSL/TLS termination
 pub struct TlsManager {
     server_config: Arc<ServerConfig>,
+    resolver: Arc<SniResolver>,
+    /// End-entity certificate (DER) of every configured certificate, labelled
+    /// "default" or by its `[[tls.sni]]` hostname
+    leaves: Vec<(String, Certificate)>,
 }
 
 impl TlsManager {
     /// Create a new TLS manager from certificate and key files
     pub fn new(cert_path: &Path, key_path: &Path) -> Result<Self> {
-        // Load certificates
-        let cert_file = File::open(cert_path)
-            .context("Failed to open certificate file")?;
-        let mut cert_reader = BufReader::new(cert_file);
-        let cert_chain = certs(&mut cert_reader)
-            .context("Failed to parse certificates")?
-            .into_iter()
-            .map(Certificate)
-            .collect();
+        Self::with_client_ca(cert_path, key_path, None)
+    }
 
-        // Load private key
-        let key_file = File::open(key_path)
-            .context("Failed to open private key file")?;
-        let mut key_reader = BufReader::new(key_file);
-        let mut keys = pkcs8_private_keys(&mut key_reader)
-            .context("Failed to parse private key")?;
+    /// Like [`TlsManager::new`], additionally asking clients for a certificate signed
+    /// by the CA in `client_ca_path`. Clients without one can still connect.
+    pub fn with_client_ca(cert_path: &Path, key_path: &Path, client_ca_path: Option<&Path>) -> Result<Self> {
+        Self::with_sni(cert_path, key_path, client_ca_path, false, &[])
+    }
 
-        let private_key = keys.remove(0);
+    /// Like [`TlsManager::with_client_ca`], serving the certificates in `sni` to
+    /// clients that ask for their hostname and the default one to everybody else.
+    /// With `require_client_cert`, handshakes without a certificate signed by the
+    /// client CA fail.
+    pub fn with_sni(
+        cert_path: &Path,
+        key_path: &Path,
+        client_ca_path: Option<&Path>,
+        require_client_cert: bool,
+        sni: &[SniCertConfig],
+    ) -> Result<Self> {
+        let default = load_certified_key(cert_path, key_path)?;
+        let mut leaves = vec![("default".to_string(), default.end_entity_cert()?.clone())];
+        let mut resolver = SniResolver::new(default);
+        for entry in sni {
+            let key = load_certified_key(&entry.cert_path, &entry.key_path)
+                .with_context(|| format!("Failed to load TLS certificate for {}", entry.hostname))?;
+            leaves.push((entry.hostname.clone(), key.end_entity_cert()?.clone()));
+            resolver.add(&entry.hostname, key);
+        }
 
         // Build TLS server configuration
-        let mut config = ServerConfig::builder()
-            .with_safe_defaults()
-            .with_no_client_auth()
-            .with_single_cert(cert_chain, PrivateKey(private_key))
-            .context("Failed to build TLS configuration")?;
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match client_ca_path {
+            Some(ca_path) if require_client_cert => {
+                let verifier = AllowAnyAuthenticatedClient::new(load_root_store(ca_path)?);
+                builder.with_client_cert_verifier(verifier.boxed())
+            }
+            Some(ca_path) => {
+                let verifier = AllowAnyAnonymousOrAuthenticatedClient::new(load_root_store(ca_path)?);
+                builder.with_client_cert_verifier(verifier.boxed())
+            }
+            None if require_client_cert => anyhow::bail!("Requiring client certificates needs a client CA"),
+            None => builder.with_no_client_auth(),
+        };
+        let resolver = Arc::new(resolver);
+        let mut config = builder.with_cert_resolver(Arc::clone(&resolver) as Arc<dyn rustls::server::ResolvesServerCert>);
 
         // Enable HTTP/2 and HTTP/1.1 via ALPN
         config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
 
         Ok(Self {
             server_config: Arc::new(config),
+            resolver,
+            leaves,
         })
     }
 
+    /// Certificates presented to clients (the first one of each chain), by label
+    pub fn leaf_certificates(&self) -> &[(String, Certificate)] {
+        &self.leaves
+    }
+
+    /// Replace the default certificate on the running server. New handshakes use
+    /// it at once; [`TlsManager::leaf_certificates`] keeps reporting the original.
+    pub fn reload_default_certificate(&self, cert_path: &Path, key_path: &Path) -> Result<()> {
+        self.resolver.set_default(load_certified_key(cert_path, key_path)?);
+        Ok(())
+    }
+
     /// Get the server configuration
     pub fn server_config(&self) -> Arc<ServerConfig> {
         self.server_config.clone()
     }
 
+    /// Server configuration whose ALPN offers only the protocol a listener serves
+    pub fn server_config_for(&self, http2: bool) -> Arc<ServerConfig> {
+        let mut config = (*self.server_config).clone();
+        config.alpn_protocols = if http2 {
+            vec![b"h2".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        };
+        Arc::new(config)
+    }
+
     /// Check if a certificate is valid
     pub fn validate_certificate(cert_path: &Path) -> Result<()> {
         let cert_file = File::open(cert_path)
@@ -66,23 +130,383 @@ impl TlsManager {
 
     /// Check if a private key is valid
     pub fn validate_private_key(key_path: &Path) -> Result<()> {
-        let key_file = File::open(key_path)
-            .context("Failed to open private key file")?;
-        let mut key_reader = BufReader::new(key_file);
-        let _keys = pkcs8_private_keys(&mut key_reader)
-            .context("Failed to parse private key")?;
+        load_private_key(key_path).map(|_| ())
+    }
+}
 
-        Ok(())
+/// Certificate chain and signing key from a PEM certificate file and key file
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<Arc<CertifiedKey>> {
+    let cert_file = File::open(cert_path)
+        .context("Failed to open certificate file")?;
+    let mut cert_reader = BufReader::new(cert_file);
+    let cert_chain: Vec<Certificate> = certs(&mut cert_reader)
+        .context("Failed to parse certificates")?
+        .into_iter()
+        .map(Certificate)
+        .collect();
+    if cert_chain.is_empty() {
+        anyhow::bail!("No certificates in certificate file");
+    }
+
+    let private_key = load_private_key(key_path)?;
+    let signing_key = any_supported_type(&private_key)
+        .map_err(|_| anyhow::anyhow!("Unsupported private key type in {}", key_path.display()))?;
+    Ok(Arc::new(CertifiedKey::new(cert_chain, signing_key)))
+}
+
+/// Reads every key of one PEM label from a reader
+type PemParser = fn(&mut dyn std::io::BufRead) -> std::io::Result<Vec<Vec<u8>>>;
+
+/// First private key in a PEM file: PKCS#8 (`BEGIN PRIVATE KEY`), then PKCS#1
+/// (`BEGIN RSA PRIVATE KEY`), then SEC1 (`BEGIN EC PRIVATE KEY`)
+fn load_private_key(key_path: &Path) -> Result<PrivateKey> {
+    let pem = std::fs::read(key_path)
+        .context("Failed to open private key file")?;
+
+    let parsers: [PemParser; 3] = [pkcs8_private_keys, rsa_private_keys, ec_private_keys];
+    for parse in parsers {
+        let keys = parse(&mut pem.as_slice()).context("Failed to parse private key")?;
+        if let Some(key) = keys.into_iter().next() {
+            return Ok(PrivateKey(key));
+        }
+    }
+    anyhow::bail!("No private key found in {}", key_path.display())
+}
+
+/// Trust anchors for client certificates, read from a PEM bundle
+fn load_root_store(ca_path: &Path) -> Result<RootCertStore> {
+    let ca_file = File::open(ca_path)
+        .context("Failed to open CA certificate file")?;
+    let mut ca_reader = BufReader::new(ca_file);
+    let ca_certs = certs(&mut ca_reader)
+        .context("Failed to parse CA certificates")?;
+
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(&ca_certs);
+    if added == 0 {
+        anyhow::bail!("No usable CA certificates in {}", ca_path.display());
+    }
+    Ok(roots)
+}
+
+/// Client certificate verified during the handshake, attached to every request
+/// of the connection as a request extension
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    /// DER-encoded end-entity certificate
+    pub certificate: Certificate,
+    /// Subject CN, passed to PHP as `X-Client-Cert-CN`
+    pub common_name: Option<String>,
+}
+
+/// Identity of the client on an established connection, if it presented a certificate.
+/// Only certificates accepted by the configured CA get this far.
+pub fn client_identity<S>(stream: &TlsStream<S>) -> Option<ClientIdentity> {
+    stream
+        .get_ref()
+        .1
+        .peer_certificates()
+        .and_then(|chain| chain.first())
+        .map(|certificate| ClientIdentity {
+            common_name: x509::subject_common_name(&certificate.0).unwrap_or_else(|e| {
+                tracing::debug!("Could not read client certificate subject: {:#}", e);
+                None
+            }),
+            certificate: certificate.clone(),
+        })
+}
+
+/// Perform a server-side TLS handshake, recording its duration or failure reason.
+/// Handshakes that do not complete within `timeout` are abandoned, dropping the stream.
+pub async fn accept<S>(
+    acceptor: &TlsAcceptor,
+    stream: S,
+    metrics: &MetricsCollector,
+    timeout: Duration,
+) -> std::io::Result<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let start = Instant::now();
+
+    let result = match tokio::time::timeout(timeout, acceptor.accept(stream)).await {
+        Ok(result) => result,
+        Err(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("TLS handshake not completed within {:?}", timeout),
+        )),
+    };
+
+    match result {
+        Ok(tls_stream) => {
+            metrics.observe_tls_handshake(start.elapsed().as_secs_f64());
+            Ok(tls_stream)
+        }
+        Err(e) => {
+            metrics.inc_tls_handshake_error(handshake_error_reason(&e));
+            Err(e)
+        }
+    }
+}
+
+/// Coarse reason for a failed handshake, used as a metrics label
+pub fn handshake_error_reason(err: &std::io::Error) -> &'static str {
+    use rustls::AlertDescription;
+
+    if err.kind() == std::io::ErrorKind::TimedOut {
+        return "timeout";
+    }
+
+    match err.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+        Some(rustls::Error::InvalidCertificate(_))
+        | Some(rustls::Error::NoCertificatesPresented)
+        | Some(rustls::Error::AlertReceived(
+            AlertDescription::BadCertificate
+            | AlertDescription::UnsupportedCertificate
+            | AlertDescription::CertificateRevoked
+            | AlertDescription::CertificateExpired
+            | AlertDescription::CertificateUnknown
+            | AlertDescription::UnknownCA,
+        )) => "cert",
+        Some(_) => "protocol",
+        None if err.kind() == std::io::ErrorKind::InvalidData => "protocol",
+        None => "io",
     }
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    /// Self-signed certificate for `localhost`, written as PEM files into `dir`
+    pub(crate) fn write_test_cert(dir: &Path) -> (std::path::PathBuf, std::path::PathBuf, Certificate) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, cert.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&key_path, cert.serialize_private_key_pem()).unwrap();
+        (cert_path, key_path, Certificate(cert.serialize_der().unwrap()))
+    }
+
+    pub(crate) fn test_connector(trusted: Certificate) -> tokio_rustls::TlsConnector {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&trusted).unwrap();
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        tokio_rustls::TlsConnector::from(Arc::new(config))
+    }
+
+    /// Client CA written as PEM into `dir`, plus a client certificate (CN `test-client`) it signed
+    pub(crate) fn write_test_client_ca(dir: &Path) -> (std::path::PathBuf, Certificate, PrivateKey) {
+        let mut ca_params = rcgen::CertificateParams::new(Vec::new());
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+        let ca_path = dir.join("client-ca.pem");
+        std::fs::write(&ca_path, ca.serialize_pem().unwrap()).unwrap();
+
+        let mut client_params = rcgen::CertificateParams::new(vec!["client".to_string()]);
+        client_params.distinguished_name.push(rcgen::DnType::CommonName, "test-client");
+        let client = rcgen::Certificate::from_params(client_params).unwrap();
+        let client_cert = Certificate(client.serialize_der_with_signer(&ca).unwrap());
+        (ca_path, client_cert, PrivateKey(client.serialize_private_key_der()))
+    }
+
+    pub(crate) fn test_client_auth_connector(trusted: Certificate, cert: Certificate, key: PrivateKey) -> tokio_rustls::TlsConnector {
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(&trusted).unwrap();
+        let config = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_client_auth_cert(vec![cert], key)
+            .unwrap();
+        tokio_rustls::TlsConnector::from(Arc::new(config))
+    }
 
     #[test]
     fn test_tls_manager_validates_paths() {
-        // This test would need actual certificate files to work
-        // In a real scenario, you would create test certificates
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path, _) = write_test_cert(dir.path());
+
+        assert!(TlsManager::new(&cert_path, &key_path).is_ok());
+        assert!(TlsManager::validate_certificate(&cert_path).is_ok());
+        assert!(TlsManager::validate_private_key(&key_path).is_ok());
+    }
+
+    #[test]
+    fn test_private_key_formats() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("legacy-key.pem");
+        let pem = |label: &str, body: &str| format!("-----BEGIN {0}-----\n{1}\n-----END {0}-----\n", label, body);
+
+        std::fs::write(&path, pem("RSA PRIVATE KEY", "AAEC")).unwrap();
+        assert_eq!(load_private_key(&path).unwrap(), PrivateKey(vec![0, 1, 2]));
+
+        std::fs::write(&path, pem("EC PRIVATE KEY", "AwQF")).unwrap();
+        assert_eq!(load_private_key(&path).unwrap(), PrivateKey(vec![3, 4, 5]));
+        assert!(TlsManager::validate_private_key(&path).is_ok());
+
+        // PKCS#8 wins over an earlier key in another format
+        std::fs::write(&path, pem("EC PRIVATE KEY", "AwQF") + &pem("PRIVATE KEY", "BgcI")).unwrap();
+        assert_eq!(load_private_key(&path).unwrap(), PrivateKey(vec![6, 7, 8]));
+
+        std::fs::write(&path, "not a key\n").unwrap();
+        let err = TlsManager::validate_private_key(&path).unwrap_err();
+        assert_eq!(err.to_string(), format!("No private key found in {}", path.display()));
+        let cert_path = write_test_cert(dir.path()).0;
+        assert!(TlsManager::new(&cert_path, &path).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sni_certificate_selection() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path, default_cert) = write_test_cert(dir.path());
+        let wildcard = rcgen::generate_simple_self_signed(vec!["*.example.com".to_string()]).unwrap();
+        let sni = SniCertConfig {
+            hostname: "*.example.com".to_string(),
+            cert_path: dir.path().join("wildcard.pem"),
+            key_path: dir.path().join("wildcard-key.pem"),
+        };
+        std::fs::write(&sni.cert_path, wildcard.serialize_pem().unwrap()).unwrap();
+        std::fs::write(&sni.key_path, wildcard.serialize_private_key_pem()).unwrap();
+        let wildcard_cert = Certificate(wildcard.serialize_der().unwrap());
+
+        let manager = TlsManager::with_sni(&cert_path, &key_path, None, false, &[sni]).unwrap();
+        let labels: Vec<_> = manager.leaf_certificates().iter().map(|(label, _)| label.as_str()).collect();
+        assert_eq!(labels, ["default", "*.example.com"]);
+        let acceptor = TlsAcceptor::from(manager.server_config());
+        let metrics = MetricsCollector::new();
+
+        for (name, trusted) in [("www.example.com", wildcard_cert), ("localhost", default_cert)] {
+            let (client, server) = tokio::io::duplex(16 * 1024);
+            let server_name = rustls::ServerName::try_from(name).unwrap();
+            let (client_result, server_result) = tokio::join!(
+                test_connector(trusted).connect(server_name, client),
+                accept(&acceptor, server, &metrics, Duration::from_secs(5)),
+            );
+            assert!(client_result.is_ok(), "{}", name);
+            assert!(server_result.is_ok(), "{}", name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handshake_metrics() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path, cert) = write_test_cert(dir.path());
+        let manager = TlsManager::new(&cert_path, &key_path).unwrap();
+        let acceptor = TlsAcceptor::from(manager.server_config());
+        let metrics = MetricsCollector::new();
+
+        // Successful handshake records a duration sample
+        let handshakes_before = metrics.get_tls_handshakes();
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let connector = test_connector(cert);
+        let server_name = rustls::ServerName::try_from("localhost").unwrap();
+        let (client_result, server_result) = tokio::join!(
+            connector.connect(server_name, client),
+            accept(&acceptor, server, &metrics, Duration::from_secs(5)),
+        );
+        assert!(client_result.is_ok());
+        assert!(server_result.is_ok());
+        assert!(metrics.get_tls_handshakes() > handshakes_before);
+
+        // A plaintext client fails the handshake with a protocol error
+        let errors_before = metrics.get_tls_handshake_errors("protocol");
+        let (mut client, server) = tokio::io::duplex(16 * 1024);
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").await.unwrap();
+        let result = accept(&acceptor, server, &metrics, Duration::from_secs(5)).await;
+        assert!(result.is_err());
+        assert!(metrics.get_tls_handshake_errors("protocol") > errors_before);
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path, _) = write_test_cert(dir.path());
+        let manager = TlsManager::new(&cert_path, &key_path).unwrap();
+        let acceptor = TlsAcceptor::from(manager.server_config());
+        let metrics = MetricsCollector::new();
+        let errors_before = metrics.get_tls_handshake_errors("timeout");
+
+        // Send the start of a handshake record header, then stall
+        let (mut client, server) = tokio::io::duplex(16 * 1024);
+        client.write_all(&[0x16, 0x03, 0x01]).await.unwrap();
+
+        let start = Instant::now();
+        let err = accept(&acceptor, server, &metrics, Duration::from_millis(100)).await.unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(metrics.get_tls_handshake_errors("timeout") > errors_before);
+
+        // The server side of the stream was dropped, so the client sees EOF
+        use tokio::io::AsyncReadExt;
+        let mut buf = [0u8; 1];
+        assert_eq!(client.read(&mut buf).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_optional_client_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path, cert) = write_test_cert(dir.path());
+        let (ca_path, client_cert, client_key) = write_test_client_ca(dir.path());
+        let manager = TlsManager::with_client_ca(&cert_path, &key_path, Some(&ca_path)).unwrap();
+        let acceptor = TlsAcceptor::from(manager.server_config());
+        let metrics = MetricsCollector::new();
+        let server_name = rustls::ServerName::try_from("localhost").unwrap();
+
+        // A client presenting a CA-signed certificate is identified
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let connector = test_client_auth_connector(cert.clone(), client_cert.clone(), client_key);
+        let (client_result, server_result) = tokio::join!(
+            connector.connect(server_name.clone(), client),
+            accept(&acceptor, server, &metrics, Duration::from_secs(5)),
+        );
+        assert!(client_result.is_ok());
+        let identity = client_identity(&server_result.unwrap()).unwrap();
+        assert_eq!(identity.certificate, client_cert);
+
+        // Clients without a certificate still connect, anonymously
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let (client_result, server_result) = tokio::join!(
+            test_connector(cert).connect(server_name, client),
+            accept(&acceptor, server, &metrics, Duration::from_secs(5)),
+        );
+        assert!(client_result.is_ok());
+        assert!(client_identity(&server_result.unwrap()).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_required_client_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let (cert_path, key_path, cert) = write_test_cert(dir.path());
+        let (ca_path, client_cert, client_key) = write_test_client_ca(dir.path());
+        let manager = TlsManager::with_sni(&cert_path, &key_path, Some(&ca_path), true, &[]).unwrap();
+        let acceptor = TlsAcceptor::from(manager.server_config());
+        let metrics = MetricsCollector::new();
+        let server_name = rustls::ServerName::try_from("localhost").unwrap();
+
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let connector = test_client_auth_connector(cert.clone(), client_cert, client_key);
+        let (client_result, server_result) = tokio::join!(
+            connector.connect(server_name.clone(), client),
+            accept(&acceptor, server, &metrics, Duration::from_secs(5)),
+        );
+        assert!(client_result.is_ok());
+        let identity = client_identity(&server_result.unwrap()).unwrap();
+        assert_eq!(identity.common_name.as_deref(), Some("test-client"));
+
+        // Anonymous clients fail the handshake
+        let errors_before = metrics.get_tls_handshake_errors("cert");
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let (_, server_result) = tokio::join!(
+            test_connector(cert).connect(server_name, client),
+            accept(&acceptor, server, &metrics, Duration::from_secs(5)),
+        );
+        assert!(server_result.is_err());
+        assert!(metrics.get_tls_handshake_errors("cert") > errors_before);
+
+        assert!(TlsManager::with_sni(&cert_path, &key_path, None, true, &[]).is_err());
     }
 }
@@ -0,0 +1,106 @@
+//! Certificate selection by the SNI name a client sends (`[[tls.sni]]`)
+
+use arc_swap::ArcSwap;
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Picks the certificate for a handshake from its SNI name. Clients that send no
+/// name, or one nothing matches, get the default certificate rather than a
+/// failed handshake.
+pub struct SniResolver {
+    /// Swappable so renewed certificates apply without a restart
+    default: ArcSwap<CertifiedKey>,
+    /// Lowercased hostname -> certificate
+    exact: HashMap<String, Arc<CertifiedKey>>,
+    /// `*.example.com` stored as `example.com`
+    wildcard: HashMap<String, Arc<CertifiedKey>>,
+}
+
+impl SniResolver {
+    pub fn new(default: Arc<CertifiedKey>) -> Self {
+        Self {
+            default: ArcSwap::new(default),
+            exact: HashMap::new(),
+            wildcard: HashMap::new(),
+        }
+    }
+
+    /// Serve `key` for `hostname`, which may be a `*.` wildcard
+    pub fn add(&mut self, hostname: &str, key: Arc<CertifiedKey>) {
+        let hostname = hostname.trim_end_matches('.').to_ascii_lowercase();
+        match hostname.strip_prefix("*.") {
+            Some(suffix) => self.wildcard.insert(suffix.to_string(), key),
+            None => self.exact.insert(hostname, key),
+        };
+    }
+
+    /// Serve `key` from now on wherever the default certificate was
+    pub fn set_default(&self, key: Arc<CertifiedKey>) {
+        self.default.store(key);
+    }
+
+    /// Certificate for `server_name`. Exact names win over wildcards, and a
+    /// wildcard covers exactly one label: `*.example.com` matches
+    /// `www.example.com` but neither `example.com` nor `a.b.example.com`.
+    pub fn lookup(&self, server_name: Option<&str>) -> Arc<CertifiedKey> {
+        let Some(name) = server_name else {
+            return self.default.load_full();
+        };
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(key) = self.exact.get(&name) {
+            return Arc::clone(key);
+        }
+        name.split_once('.')
+            .and_then(|(_, parent)| self.wildcard.get(parent))
+            .map_or_else(|| self.default.load_full(), Arc::clone)
+    }
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<Arc<CertifiedKey>> {
+        Some(self.lookup(client_hello.server_name()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::{Certificate, PrivateKey};
+
+    fn key_for(name: &str) -> Arc<CertifiedKey> {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        let signing = rustls::sign::any_supported_type(&PrivateKey(cert.serialize_private_key_der())).unwrap();
+        Arc::new(CertifiedKey::new(vec![Certificate(cert.serialize_der().unwrap())], signing))
+    }
+
+    #[test]
+    fn test_sni_lookup() {
+        let default = key_for("localhost");
+        let apex = key_for("example.com");
+        let wildcard = key_for("*.example.com");
+        let api = key_for("api.example.com");
+
+        let mut resolver = SniResolver::new(Arc::clone(&default));
+        resolver.add("example.com", Arc::clone(&apex));
+        resolver.add("*.example.com", Arc::clone(&wildcard));
+        resolver.add("API.example.com", Arc::clone(&api));
+
+        let picks = |name: Option<&str>, expected: &Arc<CertifiedKey>| {
+            assert!(Arc::ptr_eq(&resolver.lookup(name), expected), "{:?}", name);
+        };
+        picks(Some("example.com"), &apex);
+        picks(Some("www.Example.com."), &wildcard);
+        picks(Some("api.example.com"), &api);
+        // One label only
+        picks(Some("a.b.example.com"), &default);
+        picks(Some("other.test"), &default);
+        picks(None, &default);
+
+        let renewed = key_for("localhost");
+        resolver.set_default(Arc::clone(&renewed));
+        assert!(Arc::ptr_eq(&resolver.lookup(Some("other.test")), &renewed));
+        assert!(Arc::ptr_eq(&resolver.lookup(Some("example.com")), &apex));
+    }
+}
@@ -0,0 +1,47 @@
+//! The few X.509 fields the server reads from DER certificates
+
+use anyhow::{Context, Result};
+use x509_parser::certificate::X509Certificate;
+use x509_parser::prelude::FromDer;
+
+/// `notAfter` of a DER-encoded X.509 certificate, as Unix seconds
+pub fn not_after(der: &[u8]) -> Result<i64> {
+    Ok(parse(der)?.validity().not_after.timestamp())
+}
+
+/// First commonName (CN) of the certificate's subject, if it has one
+pub fn subject_common_name(der: &[u8]) -> Result<Option<String>> {
+    let certificate = parse(der)?;
+    let Some(cn) = certificate.subject().iter_common_name().next() else {
+        return Ok(None);
+    };
+    let cn = cn.as_str().context("Unsupported commonName encoding")?;
+    Ok(Some(cn.to_string()))
+}
+
+fn parse(der: &[u8]) -> Result<X509Certificate<'_>> {
+    let (_, certificate) = X509Certificate::from_der(der).context("Malformed certificate")?;
+    Ok(certificate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subject_common_name() {
+        let mut params = rcgen::CertificateParams::new(vec!["billing.internal".to_string()]);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.distinguished_name.push(rcgen::DnType::OrganizationName, "Example");
+        params.distinguished_name.push(rcgen::DnType::CommonName, "billing-service");
+        let der = rcgen::Certificate::from_params(params).unwrap().serialize_der().unwrap();
+        assert_eq!(subject_common_name(&der).unwrap().as_deref(), Some("billing-service"));
+
+        let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        let der = rcgen::Certificate::from_params(params).unwrap().serialize_der().unwrap();
+        assert_eq!(subject_common_name(&der).unwrap(), None);
+
+        assert!(subject_common_name(&der[..40]).is_err());
+    }
+}
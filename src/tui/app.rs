@@ -7,7 +7,30 @@ use ratatui::{
     layout::{Constraint, Direction, Layout},
     Frame,
 };
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Number of refresh samples kept for the trend sparklines
+pub const HISTORY_CAPACITY: usize = 120;
+
+/// Default interval between automatic refreshes
+pub const DEFAULT_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Upper bound for the reconnect delay while the server is unreachable
+pub const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Delay before the next refresh: the base interval, doubled for each
+/// consecutive failure after the first, capped at `max`
+pub fn retry_backoff(base: Duration, failures: u32, max: Duration) -> Duration {
+    if failures <= 1 {
+        return base;
+    }
+    let factor = 1u32.checked_shl(failures - 1).unwrap_or(u32::MAX);
+    base.saturating_mul(factor).min(max.max(base))
+}
 
 pub enum Tab {
     Metrics,
@@ -27,6 +50,75 @@ pub struct App {
     pub client: Option<Arc<TuiClient>>,  // For interactive operations
     pub status_message: Option<String>,  // For showing operation results
     pub blocked_ips: Vec<String>,  // List of blocked IPs
+    pub history: MetricHistory,  // Rolling request/error rate samples
+    pub refresh_interval: Duration,
+    pub failed_attempts: u32,  // Consecutive failed refreshes
+    pub next_refresh: Instant,
+}
+
+/// Everything the TUI currently shows, as written by the export key
+#[derive(Debug, Serialize)]
+pub struct SnapshotExport<'a> {
+    pub exported_at: chrono::DateTime<chrono::Utc>,
+    pub snapshot: Option<&'a MonitorSnapshot>,
+    pub analysis: Option<&'a LogAnalysisResult>,
+    pub blocked_ips: &'a [String],
+}
+
+/// Rolling window of rate samples collected across refreshes
+#[derive(Debug, Clone)]
+pub struct MetricHistory {
+    capacity: usize,
+    request_rate: VecDeque<f64>,
+    error_rate: VecDeque<f64>,
+}
+
+impl MetricHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            request_rate: VecDeque::with_capacity(capacity),
+            error_rate: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Append a sample, dropping the oldest once the window is full
+    pub fn push(&mut self, request_rate: f64, error_rate: f64) {
+        while self.request_rate.len() >= self.capacity {
+            self.request_rate.pop_front();
+            self.error_rate.pop_front();
+        }
+        self.request_rate.push_back(request_rate);
+        self.error_rate.push_back(error_rate);
+    }
+
+    pub fn len(&self) -> usize {
+        self.request_rate.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.request_rate.is_empty()
+    }
+
+    /// Request rate samples (req/s, rounded) for a `Sparkline`
+    pub fn request_rate_data(&self) -> Vec<u64> {
+        self.request_rate.iter().map(|r| r.max(0.0).round() as u64).collect()
+    }
+
+    /// Error rate samples in basis points (0.01%) for a `Sparkline`
+    pub fn error_rate_data(&self) -> Vec<u64> {
+        self.error_rate.iter().map(|r| (r.max(0.0) * 10_000.0).round() as u64).collect()
+    }
+
+    pub fn latest(&self) -> Option<(f64, f64)> {
+        Some((*self.request_rate.back()?, *self.error_rate.back()?))
+    }
+}
+
+impl Default for MetricHistory {
+    fn default() -> Self {
+        Self::new(HISTORY_CAPACITY)
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -50,6 +142,10 @@ impl App {
             client: None,
             status_message: None,
             blocked_ips: Vec::new(),
+            history: MetricHistory::default(),
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            failed_attempts: 0,
+            next_refresh: Instant::now(),
         }
     }
 
@@ -67,9 +163,32 @@ impl App {
             client: Some(client),
             status_message: None,
             blocked_ips: Vec::new(),
+            history: MetricHistory::default(),
+            refresh_interval: DEFAULT_REFRESH_INTERVAL,
+            failed_attempts: 0,
+            next_refresh: Instant::now(),
         }
     }
 
+    /// Set the automatic refresh interval (also the base for retry backoff)
+    pub fn with_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval.max(Duration::from_millis(1));
+        self
+    }
+
+    /// Delay until the next automatic refresh, backing off while disconnected
+    pub fn current_refresh_delay(&self) -> Duration {
+        retry_backoff(self.refresh_interval, self.failed_attempts, MAX_RETRY_BACKOFF)
+    }
+
+    pub fn time_until_refresh(&self) -> Duration {
+        self.next_refresh.saturating_duration_since(Instant::now())
+    }
+
+    pub fn refresh_due(&self) -> bool {
+        Instant::now() >= self.next_refresh
+    }
+
     pub fn next_tab(&mut self) {
         self.current_tab = (self.current_tab + 1) % 7;
         self.scroll_offset = 0;
@@ -102,13 +221,18 @@ impl App {
         // Try to take a snapshot
         match self.monitor.take_snapshot().await {
             Ok(snapshot) => {
+                self.history.push(snapshot.request_rate, snapshot.error_rate);
                 self.snapshot = Some(snapshot);
                 self.connection_status = ConnectionStatus::Connected;
+                self.failed_attempts = 0;
+                self.next_refresh = Instant::now() + self.current_refresh_delay();
             }
             Err(e) => {
                 let error_msg = format!("{}", e);
                 self.error_message = Some(error_msg.clone());
                 self.connection_status = ConnectionStatus::Disconnected(error_msg);
+                self.failed_attempts = self.failed_attempts.saturating_add(1);
+                self.next_refresh = Instant::now() + self.current_refresh_delay();
 
                 // Don't fail the refresh, just show the error
                 // This allows the TUI to continue running and retry
@@ -189,6 +313,41 @@ impl App {
         Ok(())
     }
 
+    /// Serialize the current snapshot, analysis and blocked IPs as pretty JSON
+    pub fn export_json(&self) -> Result<String> {
+        let export = SnapshotExport {
+            exported_at: chrono::Utc::now(),
+            snapshot: self.snapshot.as_ref(),
+            analysis: self.analysis.as_ref(),
+            blocked_ips: &self.blocked_ips,
+        };
+        Ok(serde_json::to_string_pretty(&export)?)
+    }
+
+    /// Write the export to a timestamped file in `dir` and return its path
+    pub fn export_to(&self, dir: &Path) -> Result<PathBuf> {
+        let file_name = format!(
+            "fe-php-snapshot-{}.json",
+            chrono::Local::now().format("%Y%m%d-%H%M%S")
+        );
+        let path = dir.join(file_name);
+        std::fs::write(&path, self.export_json()?)?;
+        Ok(path)
+    }
+
+    /// Export the current view to the working directory (interactive operation)
+    pub fn export_snapshot(&mut self) {
+        if self.snapshot.is_none() {
+            self.status_message = Some("✗ Nothing to export yet (no snapshot received)".to_string());
+            return;
+        }
+
+        self.status_message = Some(match self.export_to(Path::new(".")) {
+            Ok(path) => format!("✓ Snapshot exported to {}", path.display()),
+            Err(e) => format!("✗ Failed to export snapshot: {}", e),
+        });
+    }
+
     pub fn render(&mut self, f: &mut Frame) {
         use ratatui::style::{Color, Style};
         use ratatui::widgets::{Block, Borders, Paragraph};
@@ -225,7 +384,11 @@ impl App {
                 Line::from(error_msg.as_str()),
                 Line::from(""),
                 Line::from(Span::styled(
-                    "Retrying...",
+                    format!(
+                        "Retrying in {}s (attempt {})...",
+                        self.time_until_refresh().as_secs() + 1,
+                        self.failed_attempts + 1
+                    ),
                     Style::default().fg(Color::Yellow),
                 )),
                 Line::from(""),
@@ -242,7 +405,7 @@ impl App {
             // Render selected tab content
             match self.current_tab {
                 0 => super::tabs::overview::render(f, chunks[1], &self.snapshot, self.scroll_offset),
-                1 => super::tabs::metrics::render(f, chunks[1], &self.snapshot, self.scroll_offset),
+                1 => super::tabs::metrics::render(f, chunks[1], &self.snapshot, &self.history, self.scroll_offset),
                 2 => super::tabs::backends::render(f, chunks[1], &self.snapshot, self.scroll_offset),
                 3 => super::tabs::security::render(f, chunks[1], &self.snapshot, &self.client, &self.blocked_ips, self.scroll_offset),
                 4 => super::tabs::logs::render(f, chunks[1], &self.analyzer, self.scroll_offset),
@@ -273,3 +436,77 @@ impl App {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::AdminApi;
+    use crate::metrics::MetricsCollector;
+
+    #[tokio::test]
+    async fn test_export_snapshot_json() {
+        let metrics = Arc::new(MetricsCollector::new());
+        metrics.record_request("GET", 200, 0.01);
+        let mut app = App::new(MonitorCollector::new(AdminApi::new(metrics)));
+        app.blocked_ips = vec!["192.0.2.1".to_string()];
+        app.refresh().await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = app.export_to(dir.path()).unwrap();
+        assert!(path.file_name().unwrap().to_string_lossy().starts_with("fe-php-snapshot-"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(json["exported_at"].is_string());
+        assert_eq!(json["snapshot"]["server_status"]["total_requests"], 1);
+        assert!(json["analysis"].is_object());
+        assert!(json["blocked_ips"].is_array());
+    }
+
+    #[test]
+    fn test_export_to_missing_dir_fails() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let app = App::new(MonitorCollector::new(AdminApi::new(metrics)));
+        assert!(app.export_to(Path::new("/nonexistent/fe-php-export")).is_err());
+    }
+
+    #[test]
+    fn test_retry_backoff_schedule() {
+        let base = Duration::from_millis(500);
+        let max = Duration::from_secs(5);
+
+        let schedule: Vec<u128> = (0..8)
+            .map(|failures| retry_backoff(base, failures, max).as_millis())
+            .collect();
+        assert_eq!(schedule, vec![500, 500, 1000, 2000, 4000, 5000, 5000, 5000]);
+
+        // Huge failure counts stay capped instead of overflowing
+        assert_eq!(retry_backoff(base, u32::MAX, max), max);
+        // The cap never shortens the configured interval
+        assert_eq!(retry_backoff(Duration::from_secs(10), 3, max), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn test_metric_history_push_and_trim() {
+        let mut history = MetricHistory::new(3);
+        assert!(history.is_empty());
+
+        for i in 1..=5 {
+            history.push(i as f64 * 10.0, i as f64 / 100.0);
+        }
+
+        // Only the newest samples survive, oldest first
+        assert_eq!(history.len(), 3);
+        assert_eq!(history.request_rate_data(), vec![30, 40, 50]);
+        assert_eq!(history.error_rate_data(), vec![300, 400, 500]);
+        assert_eq!(history.latest(), Some((50.0, 0.05)));
+    }
+
+    #[test]
+    fn test_metric_history_zero_capacity() {
+        let mut history = MetricHistory::new(0);
+        history.push(1.0, 0.0);
+        history.push(2.0, 0.0);
+        assert_eq!(history.request_rate_data(), vec![2]);
+    }
+}
@@ -1,6 +1,8 @@
+use crate::waf::WafSample;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
 
@@ -10,12 +12,20 @@ pub enum Command {
     Status,
     Health,
     Metrics,
+    MetricsSnapshot,
+    ResetMetrics,
     Analysis,
     BlockedIps,
     ReloadConfig { config_path: Option<String> },
+    ReloadWaf,
+    WafSamples,
     RestartWorkers,
+    ResetOpcache,
+    SetMaintenance { enabled: bool, retry_after: Option<u64> },
     BlockIp { ip: String },
     UnblockIp { ip: String },
+    DrainUpstream { name: String },
+    EnableUpstream { name: String },
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,26 +35,63 @@ pub struct Response {
     pub data: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
+}
+
+/// Turn a non-ok response into an error, explaining protocol mismatches
+fn response_error(response: &Response) -> anyhow::Error {
+    use crate::admin::unix_socket::PROTOCOL_VERSION;
+
+    let message = response.error.as_deref().unwrap_or("unknown error");
+    let outdated = response.status == "unsupported"
+        || message.contains("unknown variant")
+        || message.starts_with("Unknown command");
+
+    match response.version {
+        _ if !outdated => anyhow::anyhow!("Server returned error: {}", message),
+        Some(version) if version < PROTOCOL_VERSION => anyhow::anyhow!(
+            "Command not supported by server (server protocol v{}, client v{}); upgrade the server: {}",
+            version, PROTOCOL_VERSION, message
+        ),
+        Some(_) => anyhow::anyhow!("Command not supported by server: {}", message),
+        None => anyhow::anyhow!(
+            "Command not supported by server (server predates protocol versioning, client v{}); upgrade the server: {}",
+            PROTOCOL_VERSION, message
+        ),
+    }
 }
 
 /// Unix Socket client for TUI to communicate with running server
 pub struct TuiClient {
     socket_path: PathBuf,
+    connect_timeout: Duration,
+    io_timeout: Duration,
 }
 
 impl TuiClient {
     pub fn new(socket_path: PathBuf) -> Self {
-        Self { socket_path }
+        Self {
+            socket_path,
+            connect_timeout: Duration::from_secs(5),
+            io_timeout: Duration::from_secs(3),
+        }
+    }
+
+    /// Use the same timeout for connecting, sending and reading
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self.io_timeout = timeout;
+        self
     }
 
     /// Connect to the Unix socket with timeout
     async fn connect(&self) -> Result<UnixStream> {
-        use tokio::time::{timeout, Duration};
+        use tokio::time::timeout;
 
-        // Try to connect with a 5-second timeout
         let connect_future = UnixStream::connect(&self.socket_path);
 
-        match timeout(Duration::from_secs(5), connect_future).await {
+        match timeout(self.connect_timeout, connect_future).await {
             Ok(Ok(stream)) => Ok(stream),
             Ok(Err(e)) => Err(anyhow::anyhow!(
                 "Failed to connect to Unix socket {:?}: {}. Is the server running?",
@@ -65,7 +112,7 @@ impl TuiClient {
 
     /// Send a command and receive response with retry logic
     async fn send_command(&self, command: Command) -> Result<Response> {
-        use tokio::time::{timeout, Duration};
+        use tokio::time::timeout;
 
         let stream = self.connect().await?;
         let (reader, mut writer) = stream.into_split();
@@ -74,7 +121,7 @@ impl TuiClient {
         // Send command as JSON with timeout
         let command_json = serde_json::to_string(&command)?;
 
-        match timeout(Duration::from_secs(3), async {
+        match timeout(self.io_timeout, async {
             writer.write_all(command_json.as_bytes()).await?;
             writer.write_all(b"\n").await?;
             writer.flush().await?;
@@ -90,7 +137,7 @@ impl TuiClient {
         // Read response with timeout
         let mut line = String::new();
 
-        match timeout(Duration::from_secs(3), reader.read_line(&mut line)).await {
+        match timeout(self.io_timeout, reader.read_line(&mut line)).await {
             Ok(Ok(0)) => {
                 return Err(anyhow::anyhow!("Server closed connection unexpectedly"));
             }
@@ -118,7 +165,7 @@ impl TuiClient {
         let response = self.send_command(Command::Status).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         let status = serde_json::from_value(response.data.unwrap_or_default())
@@ -132,7 +179,7 @@ impl TuiClient {
         let response = self.send_command(Command::Health).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         Ok(response.data.unwrap_or_default())
@@ -143,7 +190,7 @@ impl TuiClient {
         let response = self.send_command(Command::Metrics).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         let metrics = response
@@ -154,12 +201,42 @@ impl TuiClient {
         Ok(metrics)
     }
 
+    /// Get cached metrics as a structured snapshot
+    pub async fn get_metrics_snapshot(&self) -> Result<crate::metrics::MetricsSnapshot> {
+        let response = self.send_command(Command::MetricsSnapshot).await?;
+
+        if response.status != "ok" {
+            return Err(response_error(&response));
+        }
+
+        let snapshot = serde_json::from_value(response.data.unwrap_or_default())
+            .context("Failed to parse metrics snapshot")?;
+
+        Ok(snapshot)
+    }
+
+    /// Reset cached metrics, returning the snapshot of the window that just ended
+    pub async fn reset_metrics(&self) -> Result<crate::metrics::MetricsSnapshot> {
+        let response = self.send_command(Command::ResetMetrics).await?;
+
+        if response.status != "ok" {
+            return Err(response_error(&response));
+        }
+
+        let previous = response
+            .data
+            .and_then(|v| v.get("previous").cloned())
+            .unwrap_or_default();
+
+        serde_json::from_value(previous).context("Failed to parse metrics snapshot")
+    }
+
     /// Get log analysis
     pub async fn get_analysis(&self) -> Result<crate::monitor::analyzer::LogAnalysisResult> {
         let response = self.send_command(Command::Analysis).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         let analysis = serde_json::from_value(response.data.unwrap_or_default())
@@ -173,7 +250,7 @@ impl TuiClient {
         let response = self.send_command(Command::ReloadConfig { config_path }).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         let message = response
@@ -184,12 +261,47 @@ impl TuiClient {
         Ok(message)
     }
 
+    /// Reload WAF rules from their file
+    pub async fn reload_waf(&self) -> Result<String> {
+        let response = self.send_command(Command::ReloadWaf).await?;
+
+        if response.status != "ok" {
+            return Err(response_error(&response));
+        }
+
+        let message = response
+            .data
+            .and_then(|v| v.get("message").and_then(|m| m.as_str().map(String::from)))
+            .unwrap_or_else(|| "WAF rules reloaded".to_string());
+
+        Ok(message)
+    }
+
+    /// Requests the WAF recorded in learn mode
+    pub async fn waf_samples(&self) -> Result<Vec<WafSample>> {
+        let response = self.send_command(Command::WafSamples).await?;
+
+        if response.status != "ok" {
+            return Err(response_error(&response));
+        }
+
+        let samples = response
+            .data
+            .and_then(|mut v| v.get_mut("samples").map(serde_json::Value::take))
+            .map(serde_json::from_value)
+            .transpose()
+            .context("Invalid waf_samples response")?
+            .unwrap_or_default();
+
+        Ok(samples)
+    }
+
     /// Restart workers
     pub async fn restart_workers(&self) -> Result<String> {
         let response = self.send_command(Command::RestartWorkers).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         let message = response
@@ -200,12 +312,46 @@ impl TuiClient {
         Ok(message)
     }
 
+    /// Reset OPcache on all embedded workers
+    pub async fn reset_opcache(&self) -> Result<String> {
+        let response = self.send_command(Command::ResetOpcache).await?;
+
+        if response.status != "ok" {
+            return Err(response_error(&response));
+        }
+
+        let message = response
+            .data
+            .and_then(|v| v.get("message").and_then(|m| m.as_str().map(String::from)))
+            .unwrap_or_else(|| "OPcache reset".to_string());
+
+        Ok(message)
+    }
+
+    /// Turn maintenance mode on or off
+    pub async fn set_maintenance(&self, enabled: bool, retry_after: Option<u64>) -> Result<String> {
+        let response = self
+            .send_command(Command::SetMaintenance { enabled, retry_after })
+            .await?;
+
+        if response.status != "ok" {
+            return Err(response_error(&response));
+        }
+
+        let message = response
+            .data
+            .and_then(|v| v.get("message").and_then(|m| m.as_str().map(String::from)))
+            .unwrap_or_else(|| "Maintenance mode updated".to_string());
+
+        Ok(message)
+    }
+
     /// Block IP address
     pub async fn block_ip(&self, ip: String) -> Result<String> {
         let response = self.send_command(Command::BlockIp { ip: ip.clone() }).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         let message = response
@@ -221,7 +367,7 @@ impl TuiClient {
         let response = self.send_command(Command::UnblockIp { ip: ip.clone() }).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         let message = response
@@ -232,12 +378,44 @@ impl TuiClient {
         Ok(message)
     }
 
+    /// Take an upstream out of load balancing rotation
+    pub async fn drain_upstream(&self, name: String) -> Result<String> {
+        let response = self.send_command(Command::DrainUpstream { name: name.clone() }).await?;
+
+        if response.status != "ok" {
+            return Err(response_error(&response));
+        }
+
+        let message = response
+            .data
+            .and_then(|v| v.get("message").and_then(|m| m.as_str().map(String::from)))
+            .unwrap_or_else(|| format!("Upstream {} draining", name));
+
+        Ok(message)
+    }
+
+    /// Put an upstream back into load balancing rotation
+    pub async fn enable_upstream(&self, name: String) -> Result<String> {
+        let response = self.send_command(Command::EnableUpstream { name: name.clone() }).await?;
+
+        if response.status != "ok" {
+            return Err(response_error(&response));
+        }
+
+        let message = response
+            .data
+            .and_then(|v| v.get("message").and_then(|m| m.as_str().map(String::from)))
+            .unwrap_or_else(|| format!("Upstream {} enabled", name));
+
+        Ok(message)
+    }
+
     /// Get list of blocked IPs
     pub async fn get_blocked_ips(&self) -> Result<Vec<String>> {
         let response = self.send_command(Command::BlockedIps).await?;
 
         if response.status != "ok" {
-            anyhow::bail!("Server returned error: {:?}", response.error);
+            return Err(response_error(&response));
         }
 
         let blocked_ips = response
@@ -269,4 +447,38 @@ mod tests {
         let client = TuiClient::new(PathBuf::from("/tmp/test.sock"));
         assert_eq!(client.socket_path, PathBuf::from("/tmp/test.sock"));
     }
+
+    #[test]
+    fn test_response_error_from_older_server() {
+        // Servers predating versioning reject new commands with a serde error and no version
+        let response: Response = serde_json::from_str(
+            r#"{"status":"error","error":"unknown variant `metrics_snapshot`, expected one of `status`"}"#,
+        )
+        .unwrap();
+        assert_eq!(response.version, None);
+
+        let message = response_error(&response).to_string();
+        assert!(message.contains("predates protocol versioning"));
+    }
+
+    #[test]
+    fn test_response_error_version_mismatch() {
+        let response = Response {
+            status: "unsupported".to_string(),
+            data: None,
+            error: Some("Unsupported command: 'drain_upstream' (server protocol version 1)".to_string()),
+            version: Some(1),
+        };
+        let message = response_error(&response).to_string();
+        assert!(message.contains("server protocol v1"));
+
+        // Ordinary errors pass through unchanged
+        let response = Response {
+            status: "error".to_string(),
+            data: None,
+            error: Some("IP not found".to_string()),
+            version: Some(crate::admin::unix_socket::PROTOCOL_VERSION),
+        };
+        assert_eq!(response_error(&response).to_string(), "Server returned error: IP not found");
+    }
 }
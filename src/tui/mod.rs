@@ -47,8 +47,9 @@ async fn run_app(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, mut app:
     loop {
         terminal.draw(|f| app.render(f))?;
 
-        // Check for events with timeout
-        if event::poll(Duration::from_millis(250))? {
+        // Wait for input, waking up in time for the next scheduled refresh
+        let wait = app.time_until_refresh().min(Duration::from_millis(250));
+        if event::poll(wait)? {
             if let Event::Key(key) = event::read()? {
                 match key.code {
                     KeyCode::Char('q') => return Ok(()),
@@ -68,13 +69,19 @@ async fn run_app(terminal: &mut Terminal<CrosstermBackend<io::Stdout>>, mut app:
                         // Restart workers (interactive)
                         app.restart_workers().await?;
                     }
+                    KeyCode::Char('e') => {
+                        // Export current snapshot to a JSON file
+                        app.export_snapshot();
+                    }
                     KeyCode::Up => app.scroll_up(),
                     KeyCode::Down => app.scroll_down(),
                     _ => {}
                 }
             }
-        } else {
-            // Auto-refresh on timeout
+        }
+
+        // Auto-refresh on schedule (backs off while disconnected)
+        if app.refresh_due() {
             app.refresh().await?;
         }
     }
@@ -42,6 +42,10 @@ pub fn render(f: &mut Frame, area: Rect, _scroll_offset: usize) {
             Span::styled("  r             ", Style::default().fg(Color::Green)),
             Span::raw("Refresh data manually"),
         ]),
+        Line::from(vec![
+            Span::styled("  e             ", Style::default().fg(Color::Green)),
+            Span::raw("Export current snapshot to a JSON file"),
+        ]),
         Line::from(vec![
             Span::styled("  Shift+R       ", Style::default().fg(Color::Magenta)),
             Span::raw("Reload configuration (requires --socket)"),
@@ -1,9 +1,10 @@
 use crate::monitor::collector::MonitorSnapshot;
+use crate::tui::app::MetricHistory;
 use ratatui::{
     layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{Block, Borders, List, ListItem, Paragraph, Sparkline},
     Frame,
 };
 
@@ -11,18 +12,58 @@ pub fn render(
     f: &mut Frame,
     area: Rect,
     snapshot: &Option<MonitorSnapshot>,
+    history: &MetricHistory,
     _scroll_offset: usize,
 ) {
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([Constraint::Length(8), Constraint::Min(0)].as_ref())
+        .constraints([Constraint::Length(8), Constraint::Length(6), Constraint::Min(0)].as_ref())
         .split(area);
 
     // Server status
     render_server_status(f, chunks[0], snapshot);
 
+    // Request/error rate trend
+    render_trends(f, chunks[1], history);
+
     // Backend statistics
-    render_backend_stats(f, chunks[1], snapshot);
+    render_backend_stats(f, chunks[2], snapshot);
+}
+
+fn render_trends(f: &mut Frame, area: Rect, history: &MetricHistory) {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(area);
+
+    let (request_rate, error_rate) = history.latest().unwrap_or((0.0, 0.0));
+
+    // Only the most recent samples that fit inside the borders are drawn
+    let visible = chunks[0].width.saturating_sub(2) as usize;
+    let request_data = history.request_rate_data();
+    let request_data = &request_data[request_data.len().saturating_sub(visible)..];
+    let requests = Sparkline::default()
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Request Rate ({:.1} req/s)", request_rate)),
+        )
+        .data(request_data)
+        .style(Style::default().fg(Color::Cyan));
+    f.render_widget(requests, chunks[0]);
+
+    let visible = chunks[1].width.saturating_sub(2) as usize;
+    let error_data = history.error_rate_data();
+    let error_data = &error_data[error_data.len().saturating_sub(visible)..];
+    let errors = Sparkline::default()
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Error Rate ({:.2}%)", error_rate * 100.0)),
+        )
+        .data(error_data)
+        .style(Style::default().fg(if error_rate > 0.01 { Color::Red } else { Color::Green }));
+    f.render_widget(errors, chunks[1]);
 }
 
 fn render_server_status(
@@ -27,6 +27,16 @@ pub fn parse_headers(headers: &HeaderMap) -> HashMap<String, String> {
     map
 }
 
+/// Origin-form request target (`/path?query`)
+///
+/// HTTP/2 requests carry scheme and authority in the URI, which would otherwise
+/// end up in paths handed to backends.
+pub fn request_target(uri: &hyper::Uri) -> String {
+    uri.path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_else(|| uri.path().to_string())
+}
+
 /// Read request body with size limit
 ///
 /// Reads the entire request body into a Vec<u8>, enforcing a maximum size limit.
@@ -1,5 +1,9 @@
 pub mod signals;
 pub mod http;
+pub mod path;
+pub mod panic;
 
 pub use signals::setup_signal_handlers;
-pub use http::{parse_headers, read_body, read_body_with_limit, MAX_BODY_SIZE};
+pub use http::{parse_headers, request_target, read_body, read_body_with_limit, MAX_BODY_SIZE};
+pub use path::{decode_path, PathError};
+pub use panic::panic_message;
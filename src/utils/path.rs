@@ -0,0 +1,103 @@
+use std::fmt;
+
+/// Why a request path was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// Bad `%XX` escape or decoded bytes that are not UTF-8
+    Malformed,
+    /// Raw or percent-encoded NUL byte
+    NullByte,
+    /// `..` segments climbing above the root
+    Traversal,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "Malformed percent-encoding in path"),
+            Self::NullByte => write!(f, "Null byte in path"),
+            Self::Traversal => write!(f, "Path traversal outside document root"),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// Percent-decode a request path and normalize its `.`/`..` segments
+///
+/// Accepts a path or full request URI (the query string is dropped) and
+/// returns an absolute path such as `/dir/file name.txt`. A trailing slash is
+/// kept so callers can still tell directory requests apart. Decoding happens
+/// before normalization, so `%2e%2e%2f` is treated exactly like `../`.
+pub fn decode_path(uri: &str) -> Result<String, PathError> {
+    let raw = uri.split('?').next().unwrap_or(uri);
+    let decoded = percent_decode(raw.as_bytes())?;
+    if decoded.contains(&0) {
+        return Err(PathError::NullByte);
+    }
+    let decoded = String::from_utf8(decoded).map_err(|_| PathError::Malformed)?;
+
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop().ok_or(PathError::Traversal)?;
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut path = format!("/{}", segments.join("/"));
+    let is_dir = decoded.ends_with('/') || decoded.ends_with("/.") || decoded.ends_with("/..");
+    if is_dir && !segments.is_empty() {
+        path.push('/');
+    }
+    Ok(path)
+}
+
+fn percent_decode(input: &[u8]) -> Result<Vec<u8>, PathError> {
+    let mut out = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' {
+            let hex = input.get(i + 1..i + 3).ok_or(PathError::Malformed)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| PathError::Malformed)?;
+            out.push(u8::from_str_radix(hex, 16).map_err(|_| PathError::Malformed)?);
+            i += 3;
+        } else {
+            out.push(input[i]);
+            i += 1;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoded_traversal_is_rejected() {
+        assert_eq!(decode_path("/%2e%2e%2fetc/passwd"), Err(PathError::Traversal));
+        assert_eq!(decode_path("/a/%2E%2E/%2e%2e/secret"), Err(PathError::Traversal));
+        // Traversal that stays inside the root is just normalized
+        assert_eq!(decode_path("/a/b/%2e%2e/c.php?x=1").unwrap(), "/a/c.php");
+        assert_eq!(decode_path("/./a//./b/").unwrap(), "/a/b/");
+    }
+
+    #[test]
+    fn test_null_bytes_and_malformed_escapes() {
+        assert_eq!(decode_path("/index.php%00.jpg"), Err(PathError::NullByte));
+        assert_eq!(decode_path("/bad%zzescape"), Err(PathError::Malformed));
+        assert_eq!(decode_path("/truncated%2"), Err(PathError::Malformed));
+        assert_eq!(decode_path("/invalid-utf8-%ff"), Err(PathError::Malformed));
+    }
+
+    #[test]
+    fn test_valid_encoded_filename() {
+        assert_eq!(decode_path("/files/my%20report%20%28final%29.pdf").unwrap(), "/files/my report (final).pdf");
+        assert_eq!(decode_path("/%E6%97%A5%E6%9C%AC.html").unwrap(), "/日本.html");
+        assert_eq!(decode_path("/").unwrap(), "/");
+    }
+}
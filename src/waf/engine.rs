@@ -1,27 +1,193 @@
-use super::rules::{WafRule, WafField};
+use super::operators::WafRequest;
+use super::rules::{WafAction, WafField, WafRule, WafSeverity};
+use crate::backend::PathPattern;
+use crate::config::{LoggingConfig, WafExclusionConfig, WafRulesMode};
+use crate::logging::redaction::redact_uri;
 use crate::metrics::MetricsCollector;
-use std::collections::HashMap;
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
 use std::sync::Arc;
-use tracing::{warn, info};
+use tracing::{warn, info, debug};
+
+/// Learn-mode samples kept in memory; the oldest are dropped first
+pub const MAX_LEARNED_SAMPLES: usize = 1000;
+
+/// Rule id that excludes every rule, turning the WAF off for a path
+pub const ALL_RULES: &str = "*";
+
+/// Rules skipped for requests whose path matches a pattern
+#[derive(Debug, Clone)]
+pub struct WafExclusion {
+    pattern: PathPattern,
+    rule_ids: Vec<String>,
+    case_insensitive: bool,
+}
+
+impl WafExclusion {
+    pub fn from_config(config: &WafExclusionConfig, case_insensitive: bool) -> Result<Self> {
+        Ok(Self {
+            pattern: PathPattern::from_config(&config.path_pattern, case_insensitive)?,
+            rule_ids: config.rule_ids.clone(),
+            case_insensitive,
+        })
+    }
+
+    /// Prefixes only match whole segments, so `/cms/` does not cover `/cms-private/`
+    fn matches(&self, path: &str) -> bool {
+        let path = if self.case_insensitive { path.to_lowercase() } else { path.to_string() };
+        match &self.pattern {
+            PathPattern::Prefix(prefix) => {
+                let prefix = prefix.trim_end_matches('*').trim_end_matches('/');
+                path.strip_prefix(prefix)
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            }
+            pattern => pattern.matches(&path),
+        }
+    }
+}
+
+/// Decoded, dot-segment normalized path of a request target (which may be in
+/// absolute form). `None` when the path cannot be decoded cleanly.
+fn request_path(uri: &str) -> Option<String> {
+    let uri: hyper::Uri = uri.parse().ok()?;
+    crate::utils::decode_path(uri.path()).ok()
+}
 
 pub struct WafEngine {
-    rules: Vec<WafRule>,
+    /// Swapped as a whole on reload; in-flight requests keep the set they started with
+    rules: ArcSwap<Vec<WafRule>>,
+    rules_path: Option<PathBuf>,
+    rules_mode: WafRulesMode,
     mode: String,
     metrics: Arc<MetricsCollector>,
+    anomaly_threshold: Option<u32>,
+    max_body_inspect_bytes: usize,
+    body_exclude_content_types: Vec<String>,
+    exclusions: Vec<WafExclusion>,
+    learned: Mutex<VecDeque<WafSample>>,
+    /// Query redaction for learn-mode samples and logs; without it only paths are kept
+    redaction: Option<LoggingConfig>,
 }
 
 impl WafEngine {
     pub fn new(rules: Vec<WafRule>, mode: String, metrics: Arc<MetricsCollector>) -> Self {
         info!("WAF Engine initialized with {} rules in {} mode", rules.len(), mode);
         Self {
-            rules,
+            rules: ArcSwap::from_pointee(rules),
+            rules_path: None,
+            rules_mode: WafRulesMode::default(),
             mode,
             metrics,
+            anomaly_threshold: None,
+            max_body_inspect_bytes: usize::MAX,
+            body_exclude_content_types: Vec::new(),
+            exclusions: Vec::new(),
+            learned: Mutex::new(VecDeque::new()),
+            redaction: None,
         }
     }
 
+    /// Only scan the first `max_bytes` of a body, and none of it for the excluded content types
+    pub fn with_body_inspection(mut self, max_bytes: usize, exclude_content_types: Vec<String>) -> Self {
+        self.max_body_inspect_bytes = max_bytes;
+        self.body_exclude_content_types = exclude_content_types
+            .into_iter()
+            .map(|t| t.to_ascii_lowercase())
+            .collect();
+        self
+    }
+
+    pub fn with_exclusions(mut self, exclusions: Vec<WafExclusion>) -> Self {
+        self.exclusions = exclusions;
+        self
+    }
+
+    /// Rule ids excluded for a normalized request path
+    fn excluded_rules(&self, path: &str) -> Vec<&str> {
+        self.exclusions
+            .iter()
+            .filter(|exclusion| exclusion.matches(path))
+            .flat_map(|exclusion| exclusion.rule_ids.iter().map(String::as_str))
+            .collect()
+    }
+
+    /// Whether a matched rule is excluded; logged so suppressed matches can be audited
+    fn suppressed(&self, rule: &WafRule, excluded: &[&str], uri: &str) -> bool {
+        let suppressed = excluded.contains(&rule.id.as_str());
+        if suppressed {
+            debug!("WAF rule {} matched {} but is excluded for this path", rule.id, uri);
+        }
+        suppressed
+    }
+
+    /// Portion of the body that rules are allowed to see
+    fn inspectable_body<'a>(&self, headers: &HashMap<String, String>, body: &'a [u8]) -> &'a [u8] {
+        let content_type = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        if self.body_exclude_content_types.iter().any(|t| content_type.starts_with(t.as_str())) {
+            debug!("Skipping WAF body inspection for content type {}", content_type);
+            return &[];
+        }
+
+        &body[..body.len().min(self.max_body_inspect_bytes)]
+    }
+
+    /// Switch to anomaly scoring: block only once matched rule scores reach the threshold
+    pub fn with_anomaly_threshold(mut self, threshold: Option<u32>) -> Self {
+        self.anomaly_threshold = threshold;
+        self
+    }
+
+    /// File the rules were loaded from, re-read by [`WafEngine::reload_rules`]
+    pub fn with_rules_path(mut self, path: PathBuf) -> Self {
+        self.rules_path = Some(path);
+        self
+    }
+
+    /// How a reloaded rules file combines with the built-in rules
+    pub fn with_rules_mode(mut self, mode: WafRulesMode) -> Self {
+        self.rules_mode = mode;
+        self
+    }
+
     pub fn rules_count(&self) -> usize {
-        self.rules.len()
+        self.rules.load().len()
+    }
+
+    /// Redact learn-mode samples and logs like the access log (`logging.log_query_string`,
+    /// `logging.redact_query_params`)
+    pub fn with_redaction(mut self, logging: LoggingConfig) -> Self {
+        self.redaction = Some(logging);
+        self
+    }
+
+    /// Requests that learn mode let through but would have blocked, oldest first
+    pub fn learned_samples(&self) -> Vec<WafSample> {
+        self.learned.lock().iter().cloned().collect()
+    }
+
+    /// Re-read and compile the rules file, then swap it in. On any error the
+    /// current rules stay active. Returns the number of rules loaded.
+    pub fn reload_rules(&self) -> Result<usize> {
+        let path = self
+            .rules_path
+            .as_ref()
+            .context("WAF uses the built-in rules; set waf.rules_path to reload from a file")?;
+        let rules = super::rules::load_ruleset(Some(path), self.rules_mode)
+            .context("WAF rules reload failed; keeping the current rules")?;
+
+        let count = rules.len();
+        self.rules.store(Arc::new(rules));
+        info!("Reloaded {} WAF rules from {}", count, path.display());
+        Ok(count)
     }
 
     pub fn check_request(
@@ -32,48 +198,151 @@ impl WafEngine {
         headers: &HashMap<String, String>,
         body: &[u8],
     ) -> WafResult {
+        self.inspect(method, uri, query_string, headers, body).result
+    }
+
+    /// Like `check_request`, but also reports the accumulated anomaly score
+    pub fn inspect(
+        &self,
+        method: &str,
+        uri: &str,
+        query_string: &str,
+        headers: &HashMap<String, String>,
+        body: &[u8],
+    ) -> WafVerdict {
+        let path = request_path(uri);
+        self.inspect_path(path.as_deref(), method, uri, query_string, headers, body)
+    }
+
+    /// Like `inspect`, with exclusions matched against `path`, the decoded and
+    /// normalized request path. Without one, no exclusion applies.
+    pub fn inspect_path(
+        &self,
+        path: Option<&str>,
+        method: &str,
+        uri: &str,
+        query_string: &str,
+        headers: &HashMap<String, String>,
+        body: &[u8],
+    ) -> WafVerdict {
         if self.mode == "off" {
-            return WafResult::Allow;
-        }
-
-        let user_agent = headers
-            .get("user-agent")
-            .or_else(|| headers.get("User-Agent"))
-            .map(|s| s.as_str())
-            .unwrap_or("");
-
-        for rule in &self.rules {
-            let value = match rule.field {
-                WafField::Uri => uri,
-                WafField::QueryString => query_string,
-                WafField::UserAgent => user_agent,
-                WafField::Method => method,
-                WafField::Headers => {
-                    // Check all header values
-                    let headers_str = headers.values()
-                        .map(|v| v.as_str())
-                        .collect::<Vec<_>>()
-                        .join(" ");
-                    if rule.matches(&headers_str) {
-                        return self.handle_match(rule);
-                    }
+            return WafVerdict::allow(0);
+        }
+
+        let excluded = path.map(|path| self.excluded_rules(path)).unwrap_or_default();
+        if excluded.contains(&ALL_RULES) {
+            debug!("WAF skipped for {}: all rules are excluded for this path", uri);
+            return WafVerdict::allow(0);
+        }
+
+        let body = self.inspectable_body(headers, body);
+        let request = WafRequest::new(method, uri, query_string, headers, body);
+        let rules = self.rules.load();
+
+        if self.mode == "learn" {
+            return self.learn(&request, &rules, &excluded);
+        }
+
+        let Some(threshold) = self.anomaly_threshold else {
+            for rule in rules.iter() {
+                if !rule.evaluate(&request) || self.suppressed(rule, &excluded, uri) {
                     continue;
                 }
-                WafField::Body => {
-                    let body_str = String::from_utf8_lossy(body);
-                    if rule.matches(&body_str) {
-                        return self.handle_match(rule);
-                    }
+                // Log-only rules must not end the search before a blocking rule is reached
+                if rule.action == WafAction::Log {
+                    info!("WAF rule {} matched (log-only): {}", rule.id, rule.description);
                     continue;
                 }
-            };
+                return WafVerdict {
+                    result: self.handle_match(rule),
+                    anomaly_score: rule.anomaly_score(),
+                };
+            }
+            return WafVerdict::allow(0);
+        };
+
+        let mut score = 0;
+        let mut matched = Vec::new();
+
+        for rule in rules.iter() {
+            if !rule.evaluate(&request) || self.suppressed(rule, &excluded, uri) {
+                continue;
+            }
+
+            // Log-only rules are reported but never push a request towards blocking
+            if rule.action == WafAction::Log {
+                info!("WAF rule {} matched (log-only): {}", rule.id, rule.description);
+                continue;
+            }
+
+            score += rule.anomaly_score();
+            matched.push(rule.id.as_str());
+            debug!("WAF rule {} matched, anomaly score now {}", rule.id, score);
+        }
+
+        if matched.is_empty() || score < threshold {
+            return WafVerdict::allow(score);
+        }
 
-            if rule.matches(value) {
-                return self.handle_match(rule);
+        let rule = WafRule::new(
+            ANOMALY_RULE_ID.to_string(),
+            format!(
+                "Anomaly score {} reached threshold {} (rules: {})",
+                score,
+                threshold,
+                matched.join(", ")
+            ),
+            String::new(),
+            WafField::default(),
+            WafAction::Block,
+            WafSeverity::Critical,
+        );
+
+        WafVerdict {
+            result: self.handle_match(&rule),
+            anomaly_score: score,
+        }
+    }
+
+    /// Record every blocking rule that matches instead of enforcing any of them.
+    /// With an anomaly threshold, only requests whose score reaches it are recorded.
+    fn learn(&self, request: &WafRequest, rules: &[WafRule], excluded: &[&str]) -> WafVerdict {
+        let matched: Vec<&WafRule> = rules
+            .iter()
+            .filter(|rule| {
+                rule.action != WafAction::Log
+                    && rule.evaluate(request)
+                    && !self.suppressed(rule, excluded, request.uri)
+            })
+            .collect();
+        let score = matched.iter().map(|rule| rule.anomaly_score()).sum();
+
+        let Some(first) = matched.first() else {
+            return WafVerdict::allow(0);
+        };
+        if self.anomaly_threshold.is_some_and(|threshold| score < threshold) {
+            return WafVerdict::allow(score);
+        }
+
+        for rule in &matched {
+            self.metrics.inc_waf_would_block(&rule.id);
+            let sample = WafSample::new(rule, request, self.redaction.as_ref());
+            info!(
+                "WAF Learn mode: rule {} would block {} {} - {}",
+                rule.id, sample.method, sample.uri, rule.description
+            );
+
+            let mut learned = self.learned.lock();
+            if learned.len() >= MAX_LEARNED_SAMPLES {
+                learned.pop_front();
             }
+            learned.push_back(sample);
         }
 
-        WafResult::Allow
+        WafVerdict {
+            result: WafResult::Detected((*first).clone()),
+            anomaly_score: score,
+        }
     }
 
     fn handle_match(&self, rule: &WafRule) -> WafResult {
@@ -85,10 +354,6 @@ impl WafEngine {
         );
 
         match self.mode.as_str() {
-            "learn" => {
-                info!("WAF Learn mode: Would block rule {}", rule.id);
-                WafResult::Allow
-            }
             "detect" => {
                 info!("WAF Detect mode: Detected rule {}", rule.id);
                 WafResult::Allow
@@ -104,11 +369,63 @@ impl WafEngine {
 pub enum WafResult {
     Allow,
     Block(WafRule),
+    /// Learn mode: allowed, but recorded because this rule would have blocked it
+    Detected(WafRule),
+}
+
+/// A request learn mode would have blocked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WafSample {
+    pub rule_id: String,
+    pub description: String,
+    pub method: String,
+    pub uri: String,
+    pub query_string: String,
+    /// Unix timestamp (seconds)
+    pub timestamp: i64,
+}
+
+impl WafSample {
+    /// Query strings are redacted with `redaction`, or dropped without it
+    fn new(rule: &WafRule, request: &WafRequest, redaction: Option<&LoggingConfig>) -> Self {
+        let redact = |uri: &str| match redaction {
+            Some(config) => redact_uri(uri, config).into_owned(),
+            None => uri.split_once('?').map_or(uri, |(path, _)| path).to_string(),
+        };
+        let query = redact(&format!("?{}", request.query_string));
+
+        Self {
+            rule_id: rule.id.clone(),
+            description: rule.description.clone(),
+            method: request.method.to_string(),
+            uri: redact(request.uri),
+            query_string: query.trim_start_matches('?').to_string(),
+            timestamp: chrono::Utc::now().timestamp(),
+        }
+    }
+}
+
+/// Rule id reported when a request is blocked by its cumulative anomaly score
+pub const ANOMALY_RULE_ID: &str = "ANOMALY-SCORE";
+
+pub struct WafVerdict {
+    pub result: WafResult,
+    pub anomaly_score: u32,
+}
+
+impl WafVerdict {
+    fn allow(anomaly_score: u32) -> Self {
+        Self {
+            result: WafResult::Allow,
+            anomaly_score,
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::PathPatternConfig;
     use crate::waf::rules::default_rules;
 
     #[test]
@@ -127,10 +444,8 @@ mod tests {
             &body,
         );
 
-        match result {
-            WafResult::Allow => {}  // In detect mode, it logs but allows
-            WafResult::Block(_) => panic!("Should not block in detect mode"),
-        }
+        // In detect mode, it logs but allows
+        assert!(!matches!(result, WafResult::Block(_)), "Should not block in detect mode");
     }
 
     #[test]
@@ -150,10 +465,328 @@ mod tests {
         );
 
         match result {
-            WafResult::Allow => panic!("Should block XSS"),
             WafResult::Block(rule) => {
                 assert!(rule.id.starts_with("XSS"));
             }
+            _ => panic!("Should block XSS"),
         }
     }
+
+    fn logging_config(redact_query_params: &[&str]) -> LoggingConfig {
+        LoggingConfig {
+            redact_query_params: redact_query_params.iter().map(|p| p.to_string()).collect(),
+            ..toml::from_str("").unwrap()
+        }
+    }
+
+    #[test]
+    fn test_learn_mode_records_would_be_blocks() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let engine = WafEngine::new(default_rules(), "learn".to_string(), Arc::clone(&metrics))
+            .with_redaction(logging_config(&[]));
+        let headers = HashMap::new();
+        let would_block = metrics.get_waf_would_block("SQL-001");
+
+        let result = engine.check_request("GET", "/search", "q=1 UNION SELECT password", &headers, b"");
+        match result {
+            WafResult::Detected(rule) => assert_eq!(rule.id, "SQL-001"),
+            _ => panic!("Learn mode should report the rule without blocking"),
+        }
+        assert!(matches!(engine.check_request("GET", "/", "page=2", &headers, b""), WafResult::Allow));
+
+        let samples = engine.learned_samples();
+        assert!(!samples.is_empty());
+        assert_eq!(samples[0].rule_id, "SQL-001");
+        assert_eq!(samples[0].uri, "/search");
+        assert_eq!(samples[0].query_string, "q=1 UNION SELECT password");
+        assert!(metrics.get_waf_would_block("SQL-001") > would_block);
+
+        // Only the most recent samples are kept
+        for _ in 0..MAX_LEARNED_SAMPLES {
+            engine.check_request("GET", "/x", "comment=<script>alert(1)</script>", &headers, b"");
+        }
+        let samples = engine.learned_samples();
+        assert_eq!(samples.len(), MAX_LEARNED_SAMPLES);
+        assert!(samples.iter().all(|s| s.uri == "/x"));
+    }
+
+    #[test]
+    fn test_learn_mode_samples_are_redacted() {
+        let headers = HashMap::new();
+        let query = "token=s3cret&q=1 UNION SELECT password";
+        let uri = format!("/search?{}", query);
+
+        let engine = WafEngine::new(default_rules(), "learn".to_string(), Arc::new(MetricsCollector::new()))
+            .with_redaction(logging_config(&["token"]));
+        engine.check_request("GET", &uri, query, &headers, b"");
+        let sample = &engine.learned_samples()[0];
+        assert_eq!(sample.uri, "/search?token=[REDACTED]&q=1 UNION SELECT password");
+        assert_eq!(sample.query_string, "token=[REDACTED]&q=1 UNION SELECT password");
+
+        // Without a logging config the query is not kept at all
+        let engine = WafEngine::new(default_rules(), "learn".to_string(), Arc::new(MetricsCollector::new()));
+        engine.check_request("GET", &uri, query, &headers, b"");
+        let sample = &engine.learned_samples()[0];
+        assert_eq!(sample.uri, "/search");
+        assert_eq!(sample.query_string, "");
+    }
+
+    #[test]
+    fn test_learn_mode_respects_anomaly_threshold() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let rules = vec![
+            low_score_rule("LEARN-LOW-001", "select"),
+            low_score_rule("LEARN-LOW-002", "from"),
+            low_score_rule("LEARN-LOW-003", "where"),
+        ];
+        let engine = WafEngine::new(rules, "learn".to_string(), Arc::clone(&metrics))
+            .with_anomaly_threshold(Some(5));
+        let headers = HashMap::new();
+
+        // Below the threshold block mode would let it through, so nothing is learned
+        let verdict = engine.inspect("GET", "/search", "q=select", &headers, b"");
+        assert_eq!(verdict.anomaly_score, 2);
+        assert!(matches!(verdict.result, WafResult::Allow));
+        assert!(engine.learned_samples().is_empty());
+        assert_eq!(metrics.get_waf_would_block("LEARN-LOW-001"), 0);
+
+        let verdict = engine.inspect("GET", "/search", "q=select+from+where", &headers, b"");
+        assert_eq!(verdict.anomaly_score, 6);
+        assert!(matches!(verdict.result, WafResult::Detected(_)));
+        assert_eq!(engine.learned_samples().len(), 3);
+        assert_eq!(metrics.get_waf_would_block("LEARN-LOW-001"), 1);
+    }
+
+    fn exclusion(pattern: PathPatternConfig, rule_ids: &[&str]) -> WafExclusion {
+        WafExclusion::from_config(&WafExclusionConfig {
+            path_pattern: pattern,
+            rule_ids: rule_ids.iter().map(|id| id.to_string()).collect(),
+        }, false)
+        .unwrap()
+    }
+
+    #[test]
+    fn test_exclusions_skip_listed_rules_for_matching_paths() {
+        let engine = WafEngine::new(default_rules(), "block".to_string(), Arc::new(MetricsCollector::new()))
+            .with_exclusions(vec![
+                exclusion(PathPatternConfig::Exact("/editor/save".to_string()), &["SQL-001"]),
+                exclusion(PathPatternConfig::Prefix("/cms/".to_string()), &["SQL-001"]),
+                exclusion(PathPatternConfig::Regex(r"^/api/v\d+/import$".to_string()), &["SQL-001"]),
+            ]);
+        let headers = HashMap::new();
+        let sqli = "q=1 UNION SELECT password";
+        let blocked = |uri: &str| matches!(engine.check_request("GET", uri, sqli, &headers, b""), WafResult::Block(_));
+
+        // Exact
+        assert!(!blocked("/editor/save"));
+        assert!(blocked("/editor/save/draft"));
+        // Prefix
+        assert!(!blocked("/cms/pages/1"));
+        assert!(blocked("/blog/cms/1"));
+        // Regex, matched against the path without the query string
+        assert!(!blocked("/api/v2/import?x=1"));
+        assert!(blocked("/api/v2/export"));
+
+        // Rules that are not listed still apply on excluded paths
+        let xss = engine.check_request("GET", "/cms/pages/1", "c=<script>alert(1)</script>", &headers, b"");
+        assert!(matches!(xss, WafResult::Block(rule) if rule.id.starts_with("XSS")));
+    }
+
+    #[test]
+    fn test_wildcard_exclusion_disables_waf_for_path() {
+        let engine = WafEngine::new(default_rules(), "block".to_string(), Arc::new(MetricsCollector::new()))
+            .with_exclusions(vec![exclusion(PathPatternConfig::Prefix("/webhooks/".to_string()), &[ALL_RULES])]);
+        let headers = HashMap::new();
+
+        let verdict = engine.inspect("POST", "/webhooks/github", "c=<script>alert(1)</script>", &headers, b"");
+        assert!(matches!(verdict.result, WafResult::Allow));
+        assert!(matches!(
+            engine.check_request("POST", "/other", "c=<script>alert(1)</script>", &headers, b""),
+            WafResult::Block(_)
+        ));
+    }
+
+    #[test]
+    fn test_exclusions_match_the_normalized_path() {
+        let engine = WafEngine::new(default_rules(), "block".to_string(), Arc::new(MetricsCollector::new()))
+            .with_exclusions(vec![exclusion(PathPatternConfig::Prefix("/cms/".to_string()), &[ALL_RULES])]);
+        let headers = HashMap::new();
+        let xss = "c=<script>alert(1)</script>";
+        let blocked = |uri: &str| matches!(engine.check_request("GET", uri, xss, &headers, b""), WafResult::Block(_));
+
+        assert!(!blocked("/cms"));
+        assert!(!blocked("/cms/pages/1"));
+        // HTTP/2 requests carry the target in absolute form
+        assert!(!blocked("https://example.com/cms/pages/1"));
+
+        // Traversal out of the excluded prefix, plain or percent-encoded
+        assert!(blocked("/cms/../admin/x.php"));
+        assert!(blocked("/cms%2f..%2fadmin/x.php"));
+        assert!(blocked("https://example.com/cms/%2e%2e/admin/x.php"));
+        // Paths that fail to decode match no exclusion
+        assert!(blocked("/cms/../../admin/x.php"));
+        assert!(blocked("/cms/%zz"));
+        // Siblings sharing the prefix text are not covered
+        assert!(blocked("/cms-private/x.php"));
+        assert!(blocked("/cmsx"));
+        // Exclusions are case-sensitive unless paths are
+        assert!(blocked("/CMS/pages/1"));
+
+        let case_insensitive = WafExclusion::from_config(&WafExclusionConfig {
+            path_pattern: PathPatternConfig::Prefix("/CMS/".to_string()),
+            rule_ids: vec![ALL_RULES.to_string()],
+        }, true).unwrap();
+        let engine = WafEngine::new(default_rules(), "block".to_string(), Arc::new(MetricsCollector::new()))
+            .with_exclusions(vec![case_insensitive]);
+        let verdict = engine.inspect_path(Some("/Cms/pages/1"), "GET", "/Cms/pages/1", xss, &headers, b"");
+        assert!(matches!(verdict.result, WafResult::Allow));
+        let verdict = engine.inspect_path(None, "GET", "/cms/pages/1", xss, &headers, b"");
+        assert!(matches!(verdict.result, WafResult::Block(_)));
+    }
+
+    fn low_score_rule(id: &str, pattern: &str) -> WafRule {
+        WafRule::new(
+            id.to_string(),
+            format!("{} test rule", id),
+            pattern.to_string(),
+            WafField::QueryString,
+            WafAction::Block,
+            WafSeverity::Low,
+        )
+    }
+
+    #[test]
+    fn test_anomaly_scoring() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let rules = vec![
+            low_score_rule("LOW-001", "select"),
+            low_score_rule("LOW-002", "from"),
+            low_score_rule("LOW-003", "where"),
+        ];
+        let engine = WafEngine::new(rules, "block".to_string(), metrics)
+            .with_anomaly_threshold(Some(5));
+
+        let headers = HashMap::new();
+
+        // A single low-severity match stays below the threshold
+        let verdict = engine.inspect("GET", "/search", "q=select", &headers, b"");
+        assert_eq!(verdict.anomaly_score, 2);
+        assert!(matches!(verdict.result, WafResult::Allow));
+
+        // Several matches together cross it
+        let verdict = engine.inspect("GET", "/search", "q=select+from+where", &headers, b"");
+        assert_eq!(verdict.anomaly_score, 6);
+        match verdict.result {
+            WafResult::Block(rule) => {
+                assert_eq!(rule.id, ANOMALY_RULE_ID);
+                assert!(rule.description.contains("LOW-003"));
+            }
+            _ => panic!("Should block once the threshold is reached"),
+        }
+    }
+
+    #[test]
+    fn test_rule_score_override() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let mut rule = low_score_rule("LOW-001", "select");
+        rule.score = Some(10);
+        let engine = WafEngine::new(vec![rule], "block".to_string(), metrics)
+            .with_anomaly_threshold(Some(5));
+
+        let verdict = engine.inspect("GET", "/search", "q=select", &HashMap::new(), b"");
+        assert_eq!(verdict.anomaly_score, 10);
+        assert!(matches!(verdict.result, WafResult::Block(_)));
+    }
+
+    #[test]
+    fn test_log_only_rule_does_not_shadow_block_rule() {
+        let mut log_only = low_score_rule("LOG-001", "select");
+        log_only.action = WafAction::Log;
+
+        let rules = vec![log_only.clone(), low_score_rule("BLOCK-001", "select")];
+        let engine = WafEngine::new(rules, "block".to_string(), Arc::new(MetricsCollector::new()));
+        let verdict = engine.inspect("GET", "/search", "q=select", &HashMap::new(), b"");
+        match verdict.result {
+            WafResult::Block(rule) => assert_eq!(rule.id, "BLOCK-001"),
+            _ => panic!("A matching log-only rule must not stop evaluation"),
+        }
+
+        // A log-only match on its own is allowed
+        let engine = WafEngine::new(vec![log_only], "block".to_string(), Arc::new(MetricsCollector::new()));
+        let verdict = engine.inspect("GET", "/search", "q=select", &HashMap::new(), b"");
+        assert!(matches!(verdict.result, WafResult::Allow));
+    }
+
+    #[test]
+    fn test_body_inspection_limits() {
+        let metrics = Arc::new(MetricsCollector::new());
+        let rule = WafRule::new(
+            "BODY-001".to_string(),
+            "Shell command in body".to_string(),
+            "rm -rf".to_string(),
+            WafField::Body,
+            WafAction::Block,
+            WafSeverity::High,
+        );
+        let engine = WafEngine::new(vec![rule], "block".to_string(), metrics)
+            .with_body_inspection(16, vec!["multipart/form-data".to_string()]);
+
+        let mut headers = HashMap::new();
+        headers.insert("Content-Type".to_string(), "text/plain".to_string());
+
+        // Within the inspected prefix
+        let result = engine.check_request("POST", "/upload", "", &headers, b"rm -rf /");
+        assert!(matches!(result, WafResult::Block(_)));
+
+        // Past the inspected prefix
+        let mut body = vec![b'a'; 32];
+        body.extend_from_slice(b"rm -rf /");
+        let result = engine.check_request("POST", "/upload", "", &headers, &body);
+        assert!(matches!(result, WafResult::Allow));
+
+        // Excluded content types skip body rules entirely
+        headers.insert("Content-Type".to_string(), "multipart/form-data; boundary=x".to_string());
+        let result = engine.check_request("POST", "/upload", "", &headers, b"rm -rf /");
+        assert!(matches!(result, WafResult::Allow));
+    }
+
+    #[test]
+    fn test_reload_rules_swaps_ruleset() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.toml");
+        let rule = |pattern: &str| format!(
+            "[[rules]]\nid = \"T-001\"\ndescription = \"test\"\npattern = \"{}\"\nfield = \"Uri\"\naction = \"Block\"\nseverity = \"High\"\n",
+            pattern
+        );
+        std::fs::write(&path, rule("^/old")).unwrap();
+
+        let metrics = Arc::new(MetricsCollector::new());
+        let engine = WafEngine::new(crate::waf::rules::load_rules(&path).unwrap(), "block".to_string(), metrics)
+            .with_rules_path(path.clone());
+        let headers = HashMap::new();
+        assert!(matches!(engine.check_request("GET", "/old", "", &headers, b""), WafResult::Block(_)));
+        assert!(matches!(engine.check_request("GET", "/new", "", &headers, b""), WafResult::Allow));
+
+        std::fs::write(&path, rule("^/new")).unwrap();
+        assert_eq!(engine.reload_rules().unwrap(), 1);
+        assert!(matches!(engine.check_request("GET", "/old", "", &headers, b""), WafResult::Allow));
+        assert!(matches!(engine.check_request("GET", "/new", "", &headers, b""), WafResult::Block(_)));
+
+        // An invalid file leaves the current rules in place
+        std::fs::write(&path, rule("(unclosed")).unwrap();
+        assert!(engine.reload_rules().is_err());
+        assert!(matches!(engine.check_request("GET", "/new", "", &headers, b""), WafResult::Block(_)));
+
+        // In merge mode the reloaded file is added to the built-in rules
+        std::fs::write(&path, rule("^/new")).unwrap();
+        let merged = WafEngine::new(default_rules(), "block".to_string(), Arc::new(MetricsCollector::new()))
+            .with_rules_path(path.clone())
+            .with_rules_mode(WafRulesMode::Merge);
+        assert_eq!(merged.reload_rules().unwrap(), default_rules().len() + 1);
+        assert!(merged.rules_count() > default_rules().len());
+
+        // Built-in rules have no file to reload from
+        let builtin = WafEngine::new(default_rules(), "block".to_string(), Arc::new(MetricsCollector::new()));
+        assert!(builtin.reload_rules().is_err());
+    }
 }
@@ -1,7 +1,9 @@
 pub mod engine;
+pub mod operators;
 pub mod rules;
 
-pub use engine::{WafEngine, WafResult};
+pub use engine::{WafEngine, WafExclusion, WafResult, WafSample, WafVerdict};
+pub use operators::{Operator, Target, WafRequest};
 pub use rules::{WafRule, WafAction, WafSeverity};
 
 use serde::{Deserialize, Serialize};
@@ -0,0 +1,357 @@
+//! ModSecurity CRS-style rule operators and targets
+
+use anyhow::{Context, Result};
+use ipnetwork::IpNetwork;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Rule operator such as `@rx ^admin` or `!@streq GET`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Operator {
+    kind: OperatorKind,
+    negated: bool,
+}
+
+#[derive(Debug, Clone)]
+enum OperatorKind {
+    Rx(Regex),
+    Contains(String),
+    StrEq(String),
+    IpMatch(Vec<IpNetwork>),
+    Gt(f64),
+}
+
+impl Operator {
+    /// Whether a single target value satisfies the operator (after negation)
+    pub fn matches(&self, value: &str) -> bool {
+        let matched = match &self.kind {
+            OperatorKind::Rx(regex) => regex.is_match(value),
+            OperatorKind::Contains(needle) => value.contains(needle.as_str()),
+            OperatorKind::StrEq(expected) => value == expected,
+            OperatorKind::IpMatch(networks) => value
+                .trim()
+                .parse::<IpAddr>()
+                .map(|ip| networks.iter().any(|net| net.contains(ip)))
+                .unwrap_or(false),
+            OperatorKind::Gt(threshold) => value
+                .trim()
+                .parse::<f64>()
+                .map(|n| n > *threshold)
+                .unwrap_or(false),
+        };
+
+        matched != self.negated
+    }
+}
+
+impl FromStr for Operator {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (negated, s) = match s.strip_prefix('!') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, s),
+        };
+
+        // Like ModSecurity, an operator without `@` is an implicit `@rx`
+        let (name, arg) = match s.strip_prefix('@') {
+            Some(rest) => rest.split_once(char::is_whitespace).unwrap_or((rest, "")),
+            None => ("rx", s),
+        };
+        let arg = arg.trim();
+
+        let kind = match name {
+            "rx" => OperatorKind::Rx(
+                Regex::new(arg).with_context(|| format!("Invalid @rx pattern: {}", arg))?,
+            ),
+            "contains" => OperatorKind::Contains(arg.to_string()),
+            "streq" => OperatorKind::StrEq(arg.to_string()),
+            "ipMatch" => OperatorKind::IpMatch(
+                arg.split(',')
+                    .map(|net| {
+                        net.trim()
+                            .parse::<IpNetwork>()
+                            .with_context(|| format!("Invalid @ipMatch address: {}", net))
+                    })
+                    .collect::<Result<_>>()?,
+            ),
+            "gt" => OperatorKind::Gt(
+                arg.parse().with_context(|| format!("Invalid @gt number: {}", arg))?,
+            ),
+            _ => return Err(anyhow::anyhow!(
+                "Unsupported operator: '@{}'. Valid operators: @rx, @contains, @streq, @ipMatch, @gt",
+                name
+            )),
+        };
+
+        Ok(Self { kind, negated })
+    }
+}
+
+impl TryFrom<String> for Operator {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Operator> for String {
+    fn from(op: Operator) -> Self {
+        op.to_string()
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "!")?;
+        }
+        match &self.kind {
+            OperatorKind::Rx(regex) => write!(f, "@rx {}", regex.as_str()),
+            OperatorKind::Contains(s) => write!(f, "@contains {}", s),
+            OperatorKind::StrEq(s) => write!(f, "@streq {}", s),
+            OperatorKind::IpMatch(networks) => {
+                let list: Vec<String> = networks.iter().map(|n| n.to_string()).collect();
+                write!(f, "@ipMatch {}", list.join(","))
+            }
+            OperatorKind::Gt(n) => write!(f, "@gt {}", n),
+        }
+    }
+}
+
+/// Request variable inspected by a rule, e.g. `ARGS` or `REQUEST_HEADERS:User-Agent`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Target {
+    RequestUri,
+    Args(Option<String>),
+    RequestHeaders(Option<String>),
+    RequestBody,
+}
+
+impl FromStr for Target {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, selector) = match s.trim().split_once(':') {
+            Some((name, selector)) => (name, Some(selector.to_string())),
+            None => (s.trim(), None),
+        };
+
+        match (name, selector) {
+            ("REQUEST_URI", None) => Ok(Self::RequestUri),
+            ("REQUEST_BODY", None) => Ok(Self::RequestBody),
+            ("ARGS", selector) => Ok(Self::Args(selector)),
+            ("REQUEST_HEADERS", selector) => Ok(Self::RequestHeaders(selector)),
+            _ => Err(anyhow::anyhow!(
+                "Unsupported target: '{}'. Valid targets: REQUEST_URI, ARGS[:name], REQUEST_HEADERS[:name], REQUEST_BODY",
+                s
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for Target {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl From<Target> for String {
+    fn from(target: Target) -> Self {
+        target.to_string()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RequestUri => write!(f, "REQUEST_URI"),
+            Self::Args(None) => write!(f, "ARGS"),
+            Self::Args(Some(name)) => write!(f, "ARGS:{}", name),
+            Self::RequestHeaders(None) => write!(f, "REQUEST_HEADERS"),
+            Self::RequestHeaders(Some(name)) => write!(f, "REQUEST_HEADERS:{}", name),
+            Self::RequestBody => write!(f, "REQUEST_BODY"),
+        }
+    }
+}
+
+/// Read-only view of a request as seen by WAF rules
+pub struct WafRequest<'a> {
+    pub method: &'a str,
+    pub uri: &'a str,
+    pub query_string: &'a str,
+    pub headers: &'a HashMap<String, String>,
+    pub body: &'a [u8],
+    args: Vec<(String, String)>,
+}
+
+impl<'a> WafRequest<'a> {
+    pub fn new(
+        method: &'a str,
+        uri: &'a str,
+        query_string: &'a str,
+        headers: &'a HashMap<String, String>,
+        body: &'a [u8],
+    ) -> Self {
+        let mut args = parse_args(query_string);
+
+        let is_form = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("content-type"))
+            .map(|(_, value)| value.starts_with("application/x-www-form-urlencoded"))
+            .unwrap_or(false);
+        if is_form {
+            args.extend(parse_args(&String::from_utf8_lossy(body)));
+        }
+
+        Self {
+            method,
+            uri,
+            query_string,
+            headers,
+            body,
+            args,
+        }
+    }
+
+    /// Header value by case-insensitive name
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        let headers: &'a HashMap<String, String> = self.headers;
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All values a target resolves to for this request
+    pub fn values(&self, target: &Target) -> Vec<Cow<'_, str>> {
+        match target {
+            Target::RequestUri => vec![Cow::Borrowed(self.uri)],
+            Target::RequestBody => vec![String::from_utf8_lossy(self.body)],
+            Target::Args(None) => self.args.iter().map(|(_, v)| Cow::Borrowed(v.as_str())).collect(),
+            Target::Args(Some(name)) => self.args
+                .iter()
+                .filter(|(k, _)| k == name)
+                .map(|(_, v)| Cow::Borrowed(v.as_str()))
+                .collect(),
+            Target::RequestHeaders(None) => self.headers.values().map(|v| Cow::Borrowed(v.as_str())).collect(),
+            Target::RequestHeaders(Some(name)) => self.header(name).map(Cow::Borrowed).into_iter().collect(),
+        }
+    }
+}
+
+/// Decode `a=1&b=2` into name/value pairs
+fn parse_args(input: &str) -> Vec<(String, String)> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_component(name), decode_component(value))
+        })
+        .collect()
+}
+
+fn decode_component(s: &str) -> String {
+    let s = s.replace('+', " ");
+    urlencoding::decode(&s)
+        .map(|decoded| decoded.into_owned())
+        .unwrap_or(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        headers.insert("User-Agent".to_string(), "sqlmap/1.7".to_string());
+        headers.insert("X-Forwarded-For".to_string(), "10.1.2.3".to_string());
+        headers.insert("content-type".to_string(), "application/x-www-form-urlencoded".to_string());
+        headers
+    }
+
+    fn matches(op: &str, target: &str, request: &WafRequest) -> bool {
+        let op: Operator = op.parse().unwrap();
+        let target: Target = target.parse().unwrap();
+        request.values(&target).iter().any(|v| op.matches(v))
+    }
+
+    #[test]
+    fn test_rx_operator() {
+        let headers = headers();
+        let request = WafRequest::new("GET", "/admin/login?id=5", "id=5", &headers, b"");
+
+        assert!(matches("@rx ^/admin", "REQUEST_URI", &request));
+        assert!(!matches("@rx ^/public", "REQUEST_URI", &request));
+        // Bare patterns are treated as @rx
+        assert!(matches("(?i)SQLMAP", "REQUEST_HEADERS:User-Agent", &request));
+    }
+
+    #[test]
+    fn test_contains_operator() {
+        let headers = headers();
+        let request = WafRequest::new("GET", "/search?q=union+select", "q=union+select", &headers, b"");
+
+        assert!(matches("@contains union select", "ARGS", &request));
+        assert!(!matches("@contains drop table", "ARGS", &request));
+    }
+
+    #[test]
+    fn test_streq_operator() {
+        let headers = headers();
+        let request = WafRequest::new("POST", "/login", "", &headers, b"user=admin&pass=x");
+
+        assert!(matches("@streq admin", "ARGS:user", &request));
+        assert!(!matches("@streq admin", "ARGS:pass", &request));
+        assert!(matches("!@streq root", "ARGS:user", &request));
+    }
+
+    #[test]
+    fn test_ip_match_operator() {
+        let headers = headers();
+        let request = WafRequest::new("GET", "/", "", &headers, b"");
+
+        assert!(matches("@ipMatch 10.0.0.0/8,192.168.0.1", "REQUEST_HEADERS:X-Forwarded-For", &request));
+        assert!(!matches("@ipMatch 172.16.0.0/12", "REQUEST_HEADERS:X-Forwarded-For", &request));
+    }
+
+    #[test]
+    fn test_gt_operator() {
+        let headers = headers();
+        let request = WafRequest::new("GET", "/items?limit=500", "limit=500", &headers, b"");
+
+        assert!(matches("@gt 100", "ARGS:limit", &request));
+        assert!(!matches("@gt 1000", "ARGS:limit", &request));
+        // Non-numeric values never match
+        assert!(!matches("@gt 0", "REQUEST_HEADERS:User-Agent", &request));
+    }
+
+    #[test]
+    fn test_request_body_target() {
+        let headers = HashMap::new();
+        let request = WafRequest::new("POST", "/api", "", &headers, b"{\"cmd\":\"rm -rf /\"}");
+
+        assert!(matches("@contains rm -rf", "REQUEST_BODY", &request));
+        assert!(!matches("@contains rm -rf", "ARGS", &request));
+    }
+
+    #[test]
+    fn test_invalid_operator_and_target() {
+        assert!("@beginsWith /".parse::<Operator>().is_err());
+        assert!("@rx (".parse::<Operator>().is_err());
+        assert!("@gt abc".parse::<Operator>().is_err());
+        assert!("REMOTE_ADDR".parse::<Target>().is_err());
+    }
+}
@@ -1,20 +1,35 @@
+use super::operators::{Operator, Target, WafRequest};
+use crate::config::WafRulesMode;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use regex::Regex;
+use std::path::Path;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WafRule {
     pub id: String,
     pub description: String,
+    #[serde(default)]
     pub pattern: String,
     #[serde(skip)]
     pub regex: Option<Regex>,
+    #[serde(default)]
     pub field: WafField,
+    /// CRS-style targets; when set together with `operator`, `field`/`pattern` are ignored
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<Target>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<Operator>,
     pub action: WafAction,
     pub severity: WafSeverity,
+    /// Anomaly score contributed when matched; defaults to the severity's score
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
 pub enum WafField {
+    #[default]
     Uri,
     QueryString,
     Headers,
@@ -38,6 +53,18 @@ pub enum WafSeverity {
     Critical,
 }
 
+impl WafSeverity {
+    /// Default anomaly score, following the CRS critical/error/warning/notice weights
+    pub fn anomaly_score(&self) -> u32 {
+        match self {
+            Self::Critical => 5,
+            Self::High => 4,
+            Self::Medium => 3,
+            Self::Low => 2,
+        }
+    }
+}
+
 impl WafRule {
     pub fn new(
         id: String,
@@ -55,11 +82,56 @@ impl WafRule {
             pattern,
             regex,
             field,
+            targets: Vec::new(),
+            operator: None,
             action,
             severity,
+            score: None,
         }
     }
 
+    /// Build a CRS-style rule from an operator such as `@rx ...` and its targets
+    pub fn with_operator(
+        id: String,
+        description: String,
+        targets: Vec<Target>,
+        operator: Operator,
+        action: WafAction,
+        severity: WafSeverity,
+    ) -> Self {
+        Self {
+            id,
+            description,
+            pattern: String::new(),
+            regex: None,
+            field: WafField::default(),
+            targets,
+            operator: Some(operator),
+            action,
+            severity,
+            score: None,
+        }
+    }
+
+    /// Compile the legacy `pattern` after deserialization, failing on invalid regexes
+    pub fn compile(mut self) -> Result<Self> {
+        if self.operator.is_some() {
+            if self.targets.is_empty() {
+                return Err(anyhow::anyhow!("WAF rule {} has an operator but no targets", self.id));
+            }
+        } else {
+            let regex = Regex::new(&self.pattern)
+                .with_context(|| format!("Invalid pattern in WAF rule {}", self.id))?;
+            self.regex = Some(regex);
+        }
+        Ok(self)
+    }
+
+    /// Score added to the request's anomaly total when this rule matches
+    pub fn anomaly_score(&self) -> u32 {
+        self.score.unwrap_or_else(|| self.severity.anomaly_score())
+    }
+
     pub fn matches(&self, value: &str) -> bool {
         if let Some(ref regex) = self.regex {
             regex.is_match(value)
@@ -67,6 +139,82 @@ impl WafRule {
             false
         }
     }
+
+    /// Evaluate the rule against a whole request
+    pub fn evaluate(&self, request: &WafRequest) -> bool {
+        if let Some(ref operator) = self.operator {
+            return self.targets.iter().any(|target| {
+                request.values(target).iter().any(|value| operator.matches(value))
+            });
+        }
+
+        match self.field {
+            WafField::Uri => self.matches(request.uri),
+            WafField::QueryString => self.matches(request.query_string),
+            WafField::UserAgent => self.matches(request.header("user-agent").unwrap_or("")),
+            WafField::Method => self.matches(request.method),
+            WafField::Headers => {
+                // Check all header values
+                let headers_str = request.headers.values()
+                    .map(|v| v.as_str())
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                self.matches(&headers_str)
+            }
+            WafField::Body => self.matches(&String::from_utf8_lossy(request.body)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RulesFile {
+    #[serde(default)]
+    rules: Vec<WafRule>,
+}
+
+/// Parse `[[rules]]` entries from TOML and compile them
+pub fn parse_rules(content: &str) -> Result<Vec<WafRule>> {
+    let file: RulesFile = toml::from_str(content).context("Failed to parse WAF rules")?;
+    file.rules.into_iter().map(WafRule::compile).collect()
+}
+
+/// Parse a `{"rules": [...]}` document from JSON and compile it
+pub fn parse_rules_json(content: &str) -> Result<Vec<WafRule>> {
+    let file: RulesFile = serde_json::from_str(content).context("Failed to parse WAF rules")?;
+    file.rules.into_iter().map(WafRule::compile).collect()
+}
+
+/// Load WAF rules from an external file; `.json` files are JSON, anything else TOML
+pub fn load_rules(path: &Path) -> Result<Vec<WafRule>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read WAF rules file: {}", path.display()))?;
+    let is_json = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+    let rules = if is_json { parse_rules_json(&content) } else { parse_rules(&content) };
+    rules.with_context(|| format!("Invalid WAF rules file: {}", path.display()))
+}
+
+/// Add `custom` to the built-in rules; a custom rule replaces the built-in one with the same id
+pub fn merge_rules(custom: Vec<WafRule>) -> Vec<WafRule> {
+    let mut rules: Vec<WafRule> = default_rules()
+        .into_iter()
+        .filter(|rule| !custom.iter().any(|c| c.id == rule.id))
+        .collect();
+    rules.extend(custom);
+    rules
+}
+
+/// Build the active ruleset: the built-in rules, or `path` combined with them per `mode`
+pub fn load_ruleset(path: Option<&Path>, mode: WafRulesMode) -> Result<Vec<WafRule>> {
+    let Some(path) = path else {
+        return Ok(default_rules());
+    };
+    let rules = load_rules(path)?;
+    Ok(match mode {
+        WafRulesMode::Replace => rules,
+        WafRulesMode::Merge => merge_rules(rules),
+    })
 }
 
 // OWASP Core Rule Set examples
@@ -126,3 +274,88 @@ pub fn default_rules() -> Vec<WafRule> {
         ),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_parse_crs_style_rules() {
+        let rules = parse_rules(r#"
+[[rules]]
+id = "SQL-001"
+description = "SQL Injection - UNION attack"
+pattern = "(?i)union.+select"
+field = "QueryString"
+action = "Block"
+severity = "Critical"
+
+[[rules]]
+id = "CRS-913100"
+description = "Scanner user agent"
+targets = ["REQUEST_HEADERS:User-Agent"]
+operator = "@contains sqlmap"
+action = "Block"
+severity = "High"
+"#).unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].regex.is_some());
+        assert_eq!(rules[1].targets, vec![Target::RequestHeaders(Some("User-Agent".to_string()))]);
+
+        let mut headers = HashMap::new();
+        headers.insert("user-agent".to_string(), "sqlmap/1.7".to_string());
+        let request = WafRequest::new("GET", "/", "", &headers, b"");
+        assert!(!rules[0].evaluate(&request));
+        assert!(rules[1].evaluate(&request));
+    }
+
+    #[test]
+    fn test_load_example_rules_file() {
+        let rules = load_rules(Path::new("examples/waf_rules.toml")).unwrap();
+        assert!(rules.iter().any(|r| r.operator.is_some()));
+    }
+
+    #[test]
+    fn test_parse_rules_rejects_invalid_regex() {
+        let err = parse_rules(r#"
+[[rules]]
+id = "BAD-001"
+description = "Broken"
+pattern = "(unclosed"
+field = "Uri"
+action = "Block"
+severity = "Low"
+"#).unwrap_err();
+
+        assert!(format!("{:#}", err).contains("BAD-001"));
+    }
+
+    #[test]
+    fn test_load_ruleset_from_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("rules.json");
+        std::fs::write(&path, r#"{"rules": [
+            {"id": "CUSTOM-001", "description": "Admin probe", "pattern": "^/wp-admin",
+             "field": "Uri", "action": "Block", "severity": "High"},
+            {"id": "SQL-001", "description": "Stricter UNION", "pattern": "(?i)union",
+             "field": "QueryString", "action": "Block", "severity": "Critical"}
+        ]}"#).unwrap();
+
+        let defaults = default_rules().len();
+        assert_eq!(load_ruleset(None, WafRulesMode::Merge).unwrap().len(), defaults);
+        assert_eq!(load_ruleset(Some(&path), WafRulesMode::Replace).unwrap().len(), 2);
+
+        // SQL-001 overrides the built-in rule, CUSTOM-001 is added
+        let merged = load_ruleset(Some(&path), WafRulesMode::Merge).unwrap();
+        assert_eq!(merged.len(), defaults + 1);
+        let sql = merged.iter().find(|r| r.id == "SQL-001").unwrap();
+        assert_eq!(sql.pattern, "(?i)union");
+
+        std::fs::write(&path, r#"{"rules": [{"id": "BAD-002", "description": "Broken",
+            "pattern": "[unclosed", "field": "Uri", "action": "Block", "severity": "Low"}]}"#).unwrap();
+        let err = load_ruleset(Some(&path), WafRulesMode::Merge).unwrap_err();
+        assert!(format!("{:#}", err).contains("BAD-002"));
+    }
+}